@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Catches Ctrl-C while it's kept, see [`catch`]
+pub(crate) struct Catch;
+
+/// Catches the first Ctrl-C until the returned [`Catch`] is dropped, so the step
+/// that's running can finish and what was done can be written down. Commands
+/// run in their own process group, so they don't see it. A second Ctrl-C
+/// interrupts them and stops comtrya right away.
+#[cfg(unix)]
+pub(crate) fn catch() -> Catch {
+    extern "C" fn cancel(_: libc::c_int) {
+        // Only atomics, signals and resetting the handler are safe in a signal handler
+        if CANCELLED.swap(true, Ordering::SeqCst) {
+            comtrya_lib::atoms::command::interrupt_running();
+
+            unsafe {
+                libc::signal(libc::SIGINT, libc::SIG_DFL);
+                libc::raise(libc::SIGINT);
+            }
+        }
+    }
+
+    CANCELLED.store(false, Ordering::SeqCst);
+
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            cancel as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+
+    Catch
+}
+
+/// Ctrl-C stops comtrya right away, as there are no unix signals to catch
+#[cfg(not(unix))]
+pub(crate) fn catch() -> Catch {
+    Catch
+}
+
+impl Drop for Catch {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+}
+
+pub(crate) fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
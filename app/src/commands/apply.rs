@@ -1,22 +1,89 @@
+use super::inventory::{Inventory, Target};
+use super::prompt::{Answer, Prompt};
+use super::remote::Remote;
 use super::ComtryaCommand;
-use crate::Runtime;
-use clap::Parser;
+use crate::{cancel, progress, OutputFormat, Runtime};
+use clap::{Parser, ValueEnum};
+use colored::{Color, Colorize};
 use comfy_table::{Cell, ContentArrangement, Table};
-use comtrya_lib::contexts::to_rhai;
+use comtrya_lib::audit::AuditLog;
 use comtrya_lib::manifests::{load, Manifest};
-use core::panic;
-use petgraph::{visit::DfsPostOrder, Graph};
-use rhai::Engine;
-use std::path::PathBuf;
+use comtrya_lib::notify::{notify, Summary};
+use comtrya_lib::report::{write_metrics, ActionReport, ManifestReport, RunReport, Status};
+use comtrya_lib::rollback::{default_runs_dir, Journal};
+use comtrya_lib::session::{
+    resolve_dependency, Observer, Records, RunOptions, RunOutcome, Session,
+};
+use comtrya_lib::state::{
+    checkpoint_path, default_state_path, lock_path, Checkpoint, RunLock, State,
+};
+use petgraph::graph::NodeIndex;
+use petgraph::visit::{depth_first_search, Control, DfsEvent};
+use petgraph::Graph;
+use std::ffi::OsStr;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::time::Instant;
 use std::{collections::HashMap, ops::Deref};
-use tracing::{debug, error, info, instrument, span, trace, warn};
+use tracing::{debug, error, info, instrument, trace, warn};
 
-#[derive(Parser, Debug)]
+/// Shows the progress of a run, the diffs of its steps and asks before applying
+/// them, as the options of `apply` say
+struct Cli {
+    no_color: bool,
+    prompt: Option<Mutex<Prompt>>,
+}
+
+impl Observer for Cli {
+    fn on_manifest_start(&self, manifest: &str, actions: usize) {
+        progress::manifest_started(manifest, actions);
+    }
+
+    fn on_manifest_done(&self, report: &ManifestReport) {
+        progress::manifest_done(&report.name, report.status);
+    }
+
+    fn on_diff(&self, _manifest: &str, diff: &str) {
+        print_diff(diff, self.no_color);
+    }
+
+    fn confirm_step(&self, manifest: &str, action: &str, step: &str) -> bool {
+        match self.prompt.as_ref() {
+            Some(prompt) => prompt.lock().unwrap().ask(manifest, action, step) == Answer::Apply,
+            None => true,
+        }
+    }
+
+    fn on_step_start(&self, manifest: &str, step: &str) {
+        progress::step(manifest, step);
+    }
+
+    fn on_action_done(&self, manifest: &str, report: &ActionReport) {
+        progress::action_done(manifest, report);
+    }
+
+    fn cancelled(&self) -> bool {
+        cancel::is_cancelled()
+    }
+}
+
+#[derive(Parser, Clone, Debug)]
 pub(crate) struct Apply {
-    /// Run a subset of your manifests, comma separated list
+    /// Where to find manifests instead of the manifest directory: a path, a git
+    /// repository, or the URL of a manifest or tarball of manifests
+    source: Option<String>,
+
+    /// Run a subset of your manifests and their dependencies, comma separated list of
+    /// names, globs (apps/*) or labels (label:gui)
     #[arg(short, long, value_delimiter = ',')]
     manifests: Vec<String>,
 
+    /// Leave out manifests, unless another manifest depends on them, same format as --manifests
+    #[arg(long, value_delimiter = ',')]
+    exclude: Vec<String>,
+
     /// Performs a dry-run without changing the system
     #[arg(long)]
     dry_run: bool,
@@ -24,24 +91,266 @@ pub(crate) struct Apply {
     /// Define label selector
     #[arg(short, long)]
     pub label: Option<String>,
+
+    /// Only run actions with one of these tags, comma separated list
+    #[arg(long, value_delimiter = ',')]
+    tags: Vec<String>,
+
+    /// Skip actions with any of these tags, comma separated list
+    #[arg(long, value_delimiter = ',')]
+    skip_tags: Vec<String>,
+
+    /// Show a diff of the changes each step would make
+    #[arg(long)]
+    diff: bool,
+
+    /// Continue with the remaining manifests when one fails
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Number of independent manifests, and actions of unordered manifests, to run at once
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    /// Ask before applying each step: apply, skip, apply all or quit
+    #[arg(short, long, conflicts_with_all = ["dry_run", "jobs"])]
+    interactive: bool,
+
+    /// Apply on these machines over SSH instead, comma separated list of
+    /// destinations such as user@server
+    #[arg(long = "host", value_delimiter = ',')]
+    hosts: Vec<String>,
+
+    /// Install comtrya with the install script on hosts where it's missing and
+    /// this binary doesn't run, rather than failing
+    #[arg(long)]
+    install_remote: bool,
+
+    /// Inventory of hosts and groups, --host then selects hosts and groups by name
+    #[arg(long)]
+    inventory: Option<PathBuf>,
+
+    /// Write a report of the run to this file, as HTML when it ends in .html,
+    /// JSON otherwise
+    #[arg(long, conflicts_with_all = ["hosts", "inventory"])]
+    report: Option<PathBuf>,
+
+    /// Show progress bars of the running manifests instead of logs, when
+    /// stdout is a terminal
+    #[arg(long, conflicts_with_all = ["interactive", "diff", "hosts", "inventory"])]
+    progress: bool,
+
+    /// Exit with 2 when changes were applied, or would be with --dry-run, with 0
+    /// when there were none and with 1 when manifests failed
+    #[arg(long, conflicts_with_all = ["hosts", "inventory"])]
+    detailed_exitcode: bool,
+
+    /// Check for drift, a dry-run with --detailed-exitcode
+    #[arg(long, conflicts_with_all = ["interactive", "hosts", "inventory"])]
+    check: bool,
+
+    /// Take the lock of another run that's stuck, rather than failing
+    #[arg(long)]
+    force_unlock: bool,
+
+    /// Carry on with a run that was cancelled, skipping what it had done
+    #[arg(long, conflicts_with_all = ["dry_run", "check"])]
+    resume: bool,
 }
 
+/// Exit code of --detailed-exitcode when there were changes
+const CHANGES_EXIT_CODE: u8 = 2;
+
 impl Apply {
-    fn manifest_path(&self, runtime: &Runtime) -> anyhow::Result<PathBuf> {
-        let first_manifest_path = runtime.config.manifest_paths.first().ok_or_else(|| {
-            anyhow::anyhow!(
-                "No manifest paths found in config file, please add at least one path to your manifests"
-            )
-        })?;
-
-        let manifest_path = match crate::manifests::resolve(first_manifest_path) {
-            Some(path) => path,
-            None => {
-                return Err(anyhow::anyhow!(
-                    "Manifest location, {:?}, could be resolved",
-                    first_manifest_path
-                ))
+    /// How `comtrya watch` runs: a dry-run, unless every step is confirmed
+    pub(crate) fn for_watch(&self) -> Apply {
+        Apply {
+            dry_run: !self.interactive,
+            detailed_exitcode: false,
+            check: false,
+            ..self.clone()
+        }
+    }
+
+    /// Copies the manifests to every host and applies them there with the same options
+    fn apply_remote(&self, runtime: &Runtime) -> anyhow::Result<()> {
+        let manifest_path = self.manifest_path(runtime)?;
+        let args = self.remote_args(runtime);
+
+        let targets = match self.inventory.as_ref() {
+            Some(inventory) => Inventory::load(inventory)?.targets(&self.hosts)?,
+            None => self.hosts.iter().map(|host| Target::new(host)).collect(),
+        };
+
+        // Prompts need a terminal, and machine readable output can't be interleaved
+        let concurrent =
+            targets.len() > 1 && !self.interactive && runtime.args.output == OutputFormat::Text;
+
+        let apply = |target: &Target| {
+            let started = Instant::now();
+            let remote = Remote::new(&target.address);
+
+            let mut config = runtime.remote_config.clone();
+            config.variables.extend(target.variables.clone());
+
+            // Sent once from here instead, with the results of every host
+            config.notify.clear();
+
+            let result = remote
+                .upload(&manifest_path, &config)
+                .and_then(|_| remote.comtrya(self.install_remote))
+                .and_then(|comtrya| {
+                    remote.run(
+                        &comtrya,
+                        &args,
+                        self.interactive,
+                        concurrent.then_some(target.name.as_str()),
+                    )
+                });
+
+            if let Err(err) = result.as_ref() {
+                error!("{}", err);
             }
+
+            (target.name.clone(), result, started.elapsed())
+        };
+
+        let results: Vec<_> = if concurrent {
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = targets
+                    .iter()
+                    .map(|target| scope.spawn(move || apply(target)))
+                    .collect();
+
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap())
+                    .collect()
+            })
+        } else {
+            targets.iter().map(apply).collect()
+        };
+
+        if runtime.args.output == OutputFormat::Text {
+            let mut table = Table::new();
+            table
+                .set_content_arrangement(ContentArrangement::Dynamic)
+                .set_width(80)
+                .set_header(vec!["Host", "Result", "Duration"]);
+
+            for (name, result, duration) in results.iter() {
+                let outcome = match result {
+                    Ok(()) => String::from("ok"),
+                    Err(err) => format!("failed: {err}"),
+                };
+
+                table.add_row(vec![
+                    Cell::new(name),
+                    Cell::new(outcome),
+                    Cell::new(format!("{:.1}s", duration.as_secs_f64())),
+                ]);
+            }
+
+            println!("{table}");
+        }
+
+        let failed: Vec<&str> = results
+            .iter()
+            .filter(|(_, result, _)| result.is_err())
+            .map(|(name, _, _)| name.as_str())
+            .collect();
+
+        if !self.dry_run {
+            let title = match failed.is_empty() {
+                true => format!("comtrya applied on {} hosts", results.len()),
+                false => format!("comtrya failed on {}", failed.join(", ")),
+            };
+            let message: Vec<String> = results
+                .iter()
+                .map(|(name, result, duration)| {
+                    let outcome = if result.is_ok() { "ok" } else { "failed" };
+                    format!("{name}: {outcome} in {}s", duration.as_secs())
+                })
+                .collect();
+
+            notify(
+                &runtime.config.notify,
+                &Summary::new(&title, &message.join("\n"), failed.is_empty()),
+            );
+        }
+
+        if !failed.is_empty() {
+            return Err(anyhow::anyhow!("Apply failed on {}", failed.join(", ")));
+        }
+
+        Ok(())
+    }
+
+    /// The command line to apply the uploaded manifests with on a host
+    fn remote_args(&self, runtime: &Runtime) -> Vec<String> {
+        let mut args = vec![];
+
+        if runtime.args.no_color {
+            args.push(String::from("--no-color"));
+        }
+
+        if runtime.args.verbose > 0 {
+            args.push(format!("-{}", "v".repeat(runtime.args.verbose.into())));
+        }
+
+        if let Some(output) = runtime.args.output.to_possible_value() {
+            args.push(format!("--output={}", output.get_name()));
+        }
+
+        if runtime.args.sudo_askpass {
+            args.push(String::from("--sudo-askpass"));
+        }
+
+        args.extend([
+            String::from("-d"),
+            String::from("manifests"),
+            String::from("apply"),
+        ]);
+
+        for (flag, values) in [
+            ("--manifests", &self.manifests),
+            ("--exclude", &self.exclude),
+            ("--tags", &self.tags),
+            ("--skip-tags", &self.skip_tags),
+        ] {
+            if !values.is_empty() {
+                args.push(format!("{flag}={}", values.join(",")));
+            }
+        }
+
+        if let Some(label) = self.label.as_ref() {
+            args.push(format!("--label={label}"));
+        }
+
+        for (flag, set) in [
+            ("--dry-run", self.dry_run),
+            ("--diff", self.diff),
+            ("--keep-going", self.keep_going),
+            ("--interactive", self.interactive),
+            ("--force-unlock", self.force_unlock),
+            ("--resume", self.resume),
+        ] {
+            if set {
+                args.push(String::from(flag));
+            }
+        }
+
+        if self.jobs != 1 {
+            args.push(format!("--jobs={}", self.jobs));
+        }
+
+        args
+    }
+
+    pub(crate) fn manifest_path(&self, runtime: &Runtime) -> anyhow::Result<PathBuf> {
+        let manifest_path = match &self.source {
+            Some(source) => resolve_location(source)?,
+            None => manifest_path(runtime)?,
         };
 
         trace!(manifests = self.manifests.join(",").deref(),);
@@ -51,258 +360,503 @@ impl Apply {
     #[instrument(skip(self, runtime))]
     pub fn status(&self, runtime: &Runtime) -> anyhow::Result<()> {
         let contexts = &runtime.contexts;
-        let manifest_path = self.manifest_path(&runtime)?;
+        let manifest_path = self.manifest_path(runtime)?;
 
         println!("Load manifests from path: {:#?}", manifest_path);
 
         let manifests = load(manifest_path, contexts);
 
+        let state = match state_path(runtime) {
+            Some(path) => State::load(&path)?,
+            None => State::default(),
+        };
+
+        let drift = state.drift(&manifests, contexts);
+
         let mut table = Table::new();
         table
             .set_content_arrangement(ContentArrangement::Dynamic)
-            .set_width(40)
-            .set_header(vec!["Manifest", "Count of Actions"]);
+            .set_width(80)
+            .set_header(vec![
+                "Manifest",
+                "Count of Actions",
+                "Last Applied",
+                "Drift",
+            ]);
 
         for (name, manifest) in manifests.iter() {
+            // Manifests that don't apply to this machine have nothing to report
+            if !manifest.is_applicable(contexts).unwrap_or(false) {
+                continue;
+            }
+
+            let last_applied = state
+                .manifests
+                .get(name)
+                .map(|m| format!("{}", m.applied_at))
+                .unwrap_or_else(|| String::from("never"));
+
+            let drifted = drift
+                .iter()
+                .filter(|drift| drift.manifest() == name)
+                .count();
+
             table.add_row(vec![
-                Cell::new(format!("{name}")),
+                Cell::new(name),
                 Cell::new(format!("{}", manifest.actions.len())),
+                Cell::new(last_applied),
+                Cell::new(format!("{drifted}")),
             ]);
         }
         println!("{table}");
+
+        for d in drift.iter() {
+            println!("{d}");
+        }
+
         Ok(())
     }
 }
 
 impl ComtryaCommand for Apply {
-    #[instrument(skip(self, runtime))]
     fn execute(&self, runtime: &Runtime) -> anyhow::Result<()> {
-        let contexts = &runtime.contexts;
-        let manifest_path = self.manifest_path(&runtime)?;
-        let manifests = load(manifest_path, contexts);
+        self.run(runtime).map(|_| ())
+    }
+}
 
-        // Build DAG
-        let mut dag: Graph<Manifest, u32, petgraph::Directed> = Graph::new();
-
-        let manifest_root = Manifest {
-            r#where: None,
-            root_dir: None,
-            dag_index: None,
-            name: None,
-            depends: vec![],
-            actions: vec![],
-            ..Default::default()
-        };
+impl Apply {
+    /// Applies the manifests, returning the exit code for --detailed-exitcode,
+    /// so that main exits once the lock and the rest are dropped
+    #[instrument(skip(self, runtime))]
+    pub(crate) fn run(&self, runtime: &Runtime) -> anyhow::Result<ExitCode> {
+        if self.check {
+            return Apply {
+                dry_run: true,
+                detailed_exitcode: true,
+                check: false,
+                ..self.clone()
+            }
+            .run(runtime);
+        }
 
-        let root_index = dag.add_node(manifest_root);
+        if !self.hosts.is_empty() || self.inventory.is_some() {
+            return self.apply_remote(runtime).map(|_| ExitCode::SUCCESS);
+        }
 
-        let manifests: HashMap<String, Manifest> = manifests
-            .into_iter()
-            .map(|(name, mut manifest)| {
-                let abc = dag.add_node(manifest.clone());
+        // Runs that change nothing can't get in the way of those that do
+        let _lock = match (self.dry_run, state_path(runtime)) {
+            (false, Some(path)) => Some(RunLock::acquire(&lock_path(&path), self.force_unlock)?),
+            _ => None,
+        };
 
-                manifest.dag_index = Some(abc);
-                dag.add_edge(root_index, abc, 0);
+        // The first Ctrl-C lets the running step finish, then stops the run
+        let _catch = cancel::catch();
 
-                (name, manifest)
-            })
-            .collect();
+        if let Some(concurrency) = runtime.config.download_concurrency {
+            comtrya_lib::atoms::http::set_concurrency(concurrency);
+        }
 
-        for (name, manifest) in manifests.iter() {
-            manifest.depends.iter().for_each(|dependency| {
-                let (local_dependency_prefix, _) = name.rsplit_once('.').unwrap_or((name, ""));
-
-                let resolved_dependency_name =
-                    dependency.replace("./", format!("{}.", local_dependency_prefix).as_str());
-
-                let m1 = match manifests.get(&resolved_dependency_name) {
-                    Some(manifest) => manifest,
-                    None => {
-                        error!(
-                            message = "Unresolved dependency",
-                            dependency = resolved_dependency_name.as_str()
-                        );
-
-                        return;
-                    }
-                };
+        comtrya_lib::actions::set_plugin_dirs(runtime.config.plugin_dirs.clone());
 
-                trace!(
-                    message = "Dependency Registered",
-                    from = name.as_str(),
-                    to = m1.name.as_deref().unwrap_or("cannot extract name"),
-                );
+        let manifest_path = self.manifest_path(runtime)?;
 
-                if let (Some(from), Some(to)) = (manifest.dag_index, m1.dag_index) {
-                    dag.add_edge(from, to, 0);
-                } else {
-                    error!(message = "Cannot add dependency, missing dag index");
-                }
-            });
+        let mut session = Session::with_contexts(runtime.config.clone(), runtime.contexts.clone());
+        for err in session.load(manifest_path) {
+            error!("{err}");
         }
 
-        let clone_m = self.manifests.clone();
+        // Without --manifests, the manifests of the config, or of this machine
+        let include = match self.manifests.is_empty() {
+            true => &runtime.config.manifests,
+            false => &self.manifests,
+        };
 
-        let run_manifests = if self.manifests.is_empty() {
-            // No manifests specified on command line, so run everything
-            vec![String::from("")]
-        } else {
-            // Run subset
-            manifests
-                .keys()
-                .filter(|z| clone_m.contains(z))
-                .cloned()
-                .collect::<Vec<String>>()
+        let options = RunOptions {
+            dry_run: self.dry_run,
+            manifests: include.clone(),
+            exclude: self.exclude.clone(),
+            label: self.label.clone(),
+            tags: self.tags.clone(),
+            skip_tags: self.skip_tags.clone(),
+            keep_going: self.keep_going,
+            diff: self.diff,
+            jobs: self.jobs,
+            interactive: self.interactive,
         };
 
+        // Fails on dependency cycles before anything runs
+        let manifests = session.order(&options.manifests, &options.exclude)?.len();
+
         let dry_run = self.dry_run;
 
-        let engine = Engine::new();
-        let mut scope = to_rhai(contexts);
-
-        run_manifests.iter().for_each(|manifest| {
-            let start = if manifest.eq(&String::from("")) {
-                root_index
-            } else if let Some(dag_index) = manifests
-                .get(manifest)
-                .and_then(|manifest| manifest.dag_index)
-            {
-                dag_index
-            } else {
-                // FIXME: Don't panic here. Find a better way to handle this.
-                panic!("Cannot find manifest in DAG");
+        let journal = match (dry_run, default_runs_dir()) {
+            (false, Some(runs_dir)) => Some(Journal::create(&runs_dir)?),
+            _ => None,
+        };
+
+        let audit = match (dry_run, runtime.config.audit_file.as_ref()) {
+            (false, Some(path)) => Some(AuditLog::open(
+                Path::new(path),
+                journal.as_ref().map(|journal| journal.run_id.clone()),
+            )?),
+            _ => None,
+        };
+
+        let state = match state_path(runtime) {
+            Some(path) => State::load(&path)?,
+            None => State::default(),
+        };
+
+        let checkpoint_file = state_path(runtime).map(|path| checkpoint_path(&path));
+        let resumed = match checkpoint_file.as_ref().filter(|_| self.resume) {
+            Some(path) => Checkpoint::load(path)?,
+            None => None,
+        };
+
+        if self.resume && resumed.is_none() {
+            warn!("There's no cancelled run to resume, applying everything");
+        }
+
+        session.observe(Cli {
+            no_color: runtime.args.no_color,
+            prompt: self.interactive.then(|| Mutex::new(Prompt::default())),
+        });
+
+        // The view would be garbled when stdout isn't a terminal
+        let progress = (self.progress
+            && runtime.args.output == OutputFormat::Text
+            && std::io::stdout().is_terminal())
+        .then(|| progress::start(manifests, runtime.args.no_color));
+
+        let RunOutcome {
+            report,
+            state,
+            checkpoint,
+            journal,
+        } = session.run_with(
+            &options,
+            Records {
+                state,
+                resumed,
+                journal,
+                audit,
+            },
+        )?;
+
+        drop(progress);
+
+        if !dry_run {
+            if let Some(state_path) = state_path(runtime) {
+                state.save(&state_path)?;
+            }
+
+            match checkpoint_file.as_ref() {
+                Some(path) if cancel::is_cancelled() => checkpoint.save(path)?,
+                Some(path) => Checkpoint::remove(path)?,
+                None => (),
+            }
+        }
+
+        if let Some(journal) = journal.filter(|journal| !journal.entries.is_empty()) {
+            info!(
+                "Changes can be reverted with `comtrya rollback {}`",
+                journal.run_id
+            );
+        }
+
+        if let Some(path) = self.report.as_ref() {
+            let contents = match path.extension().and_then(OsStr::to_str) {
+                Some("html") | Some("htm") => report.to_html()?,
+                _ => serde_json::to_string_pretty(&report)?,
             };
 
-            let mut dfs = DfsPostOrder::new(&dag, start);
+            std::fs::write(path, contents)?;
+            info!("Report written to {}", path.display());
+        }
 
-            while let Some(visited) = dfs.next(&dag) {
-                if dag.node_weight(visited).is_none() {
-                    info!(
-                        message = "Skipping manifest, not found in DAG",
-                        index = visited.index()
-                    );
-                }
+        match runtime.args.output {
+            OutputFormat::Text => {
+                print_failures(&report, runtime.args.no_color);
+                print_slowest_steps(&report, runtime.args.no_color);
+            }
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+            OutputFormat::Yaml => print!("{}", serde_yml::to_string(&report)?),
+        }
 
-                // .unwrap() is safe here, because we just checked that the node exists
-                let m1 = dag.node_weight(visited).unwrap();
+        if let Some(metrics) = runtime.config.metrics.as_ref() {
+            write_metrics(metrics, &report);
+        }
 
-                // Root manifest, nothing to do.
-                if m1.name.is_none() {
-                    continue;
-                }
+        if !dry_run {
+            notify(
+                &runtime.config.notify,
+                &Summary::of_run(&report, cancel::is_cancelled()),
+            );
 
-                let span_manifest = span!(
-                    tracing::Level::INFO,
-                    "",
-                    manifest = m1.name.as_deref().unwrap_or("Cannot extract name"),
-                )
-                .entered();
-
-                let mut successful = true;
-
-                if let Some(label) = self.label.as_ref() {
-                    if !m1.labels.contains(label) {
-                        info!(
-                            message = "Skipping manifest, label not found",
-                            label = label.as_str()
-                        );
-                        continue;
-                    }
+            collect_cache(&runtime.config.cache);
+        }
+
+        if cancel::is_cancelled() {
+            return Err(match checkpoint_file.filter(|_| !dry_run) {
+                Some(_) => {
+                    anyhow::anyhow!("The run was cancelled, carry on with `comtrya apply --resume`")
                 }
+                None => anyhow::anyhow!("The run was cancelled"),
+            });
+        }
+
+        let failed = report
+            .manifests
+            .iter()
+            .filter(|manifest| matches!(manifest.status, Status::Failed | Status::Unreachable))
+            .count();
+
+        if failed > 0 {
+            return Err(anyhow::anyhow!("{} manifests failed or didn't run", failed));
+        }
+
+        let changed = report
+            .manifests
+            .iter()
+            .any(|manifest| matches!(manifest.status, Status::Applied | Status::Planned));
+
+        if self.detailed_exitcode && changed {
+            return Ok(ExitCode::from(CHANGES_EXIT_CODE));
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
 
-                if let Some(where_condition) = &m1.r#where {
-                    let where_result =
-                        match engine.eval_with_scope::<bool>(&mut scope, where_condition) {
-                            Ok(result) => {
-                                debug!(
-                                    "Result of 'where' condition '{}' -> '{}'",
-                                    where_condition, result
-                                );
-
-                                result
-                            }
-                            Err(err) => {
-                                warn!("'where' condition '{}' failed: {}", where_condition, err);
-                                false
-                            }
-                        };
-
-                    if !where_result {
-                        info!("Skip manifest, because 'where' conditions were false!");
-                        span_manifest.exit();
-                        continue;
-                    }
+pub(crate) fn manifest_path(runtime: &Runtime) -> anyhow::Result<PathBuf> {
+    let first_manifest_path = runtime.config.manifest_paths.first().ok_or_else(|| {
+        anyhow::anyhow!(
+            "No manifest paths found in config file, please add at least one path to your manifests"
+        )
+    })?;
+
+    resolve_location(first_manifest_path)
+}
+
+fn resolve_location(location: &String) -> anyhow::Result<PathBuf> {
+    match crate::manifests::resolve(location) {
+        Some(path) => Ok(path),
+        None => Err(anyhow::anyhow!(
+            "Manifest location, {:?}, could be resolved",
+            location
+        )),
+    }
+}
+
+/// Builds the dependency DAG, with an edge from every manifest to the manifests it depends
+/// on. All manifests are reachable from the returned root node.
+pub(crate) fn dependency_graph(
+    manifests: HashMap<String, Manifest>,
+) -> (Graph<Manifest, u32>, NodeIndex, HashMap<String, Manifest>) {
+    let mut dag: Graph<Manifest, u32, petgraph::Directed> = Graph::new();
+
+    let manifest_root = Manifest {
+        r#where: None,
+        root_dir: None,
+        dag_index: None,
+        name: None,
+        depends: vec![],
+        actions: vec![],
+        ..Default::default()
+    };
+
+    let root_index = dag.add_node(manifest_root);
+
+    let manifests: HashMap<String, Manifest> = manifests
+        .into_iter()
+        .map(|(name, mut manifest)| {
+            let abc = dag.add_node(manifest.clone());
+
+            manifest.dag_index = Some(abc);
+            dag.add_edge(root_index, abc, 0);
+
+            (name, manifest)
+        })
+        .collect();
+
+    for (name, manifest) in manifests.iter() {
+        manifest.depends.iter().for_each(|dependency| {
+            let resolved_dependency_name = resolve_dependency(name, dependency);
+
+            let m1 = match manifests.get(&resolved_dependency_name) {
+                Some(manifest) => manifest,
+                None => {
+                    error!(
+                        message = "Unresolved dependency",
+                        dependency = resolved_dependency_name.as_str()
+                    );
+
+                    return;
                 }
+            };
 
-                for action in m1.actions.iter() {
-                    let span_action = span!(tracing::Level::INFO, "", %action).entered();
+            trace!(
+                message = "Dependency Registered",
+                from = name.as_str(),
+                to = m1.name.as_deref().unwrap_or("cannot extract name"),
+            );
 
-                    let action = action.inner_ref();
+            if let (Some(from), Some(to)) = (manifest.dag_index, m1.dag_index) {
+                dag.add_edge(from, to, 0);
+            } else {
+                error!(message = "Cannot add dependency, missing dag index");
+            }
+        });
+    }
 
-                    let plan = match action.plan(m1, contexts) {
-                        Ok(steps) => steps,
-                        Err(err) => {
-                            info!("Action failed to get plan: {:?}", err);
-                            successful = false;
-                            continue;
-                        }
+    (dag, root_index, manifests)
+}
+
+/// Finds an edge that closes a dependency cycle
+pub(crate) fn find_cycle(dag: &Graph<Manifest, u32>) -> Option<(NodeIndex, NodeIndex)> {
+    depth_first_search(dag, dag.node_indices(), |event| match event {
+        DfsEvent::BackEdge(from, to) => Control::Break((from, to)),
+        _ => Control::Continue,
+    })
+    .break_value()
+}
+
+fn state_path(runtime: &Runtime) -> Option<PathBuf> {
+    runtime
+        .config
+        .state_file
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(default_state_path)
+}
+
+/// Lists what failed, and what didn't run because of it
+fn print_failures(report: &RunReport, no_color: bool) {
+    let mut failed = vec![];
+    let mut unreachable = vec![];
+
+    for manifest in report.manifests.iter() {
+        if manifest.status == Status::Unreachable {
+            unreachable.push(format!(
+                "{} ({})",
+                manifest.name,
+                manifest.reason.as_deref().unwrap_or_default()
+            ));
+        }
+
+        for action in manifest.actions.iter() {
+            let name = format!("{}: {}", manifest.name, action.summary);
+
+            match action.status {
+                Status::Failed | Status::Ignored => {
+                    let error = action
+                        .error
+                        .as_deref()
+                        .or_else(|| action.steps.iter().find_map(|step| step.error.as_deref()))
+                        .unwrap_or_default();
+                    let ignored = if action.status == Status::Ignored {
+                        " (ignored)"
+                    } else {
+                        ""
                     };
 
-                    let mut steps = plan
-                        .into_iter()
-                        .filter(|step| step.do_initializers_allow_us_to_run())
-                        .filter(|step| match step.atom.plan() {
-                            Ok(outcome) => outcome.should_run,
-                            Err(_) => false,
-                        })
-                        .peekable();
-
-                    if steps.peek().is_none() {
-                        info!("nothing to be done to reconcile action");
-                        span_action.exit();
-                        continue;
-                    }
-
-                    for mut step in steps {
-                        if dry_run {
-                            continue;
-                        }
-
-                        match step.atom.execute() {
-                            Ok(_) => (),
-                            Err(err) => {
-                                debug!("Atom failed to execute: {:?}", err);
-                                successful = false;
-                                break;
-                            }
-                        }
-
-                        if !step.do_finalizers_allow_us_to_continue() {
-                            debug!("Finalizers won't allow us to continue with this action");
-                            successful = false;
-                            break;
-                        }
-                    }
-                    info!("{}", action.summarize());
-                    span_action.exit();
+                    failed.push(format!("{name}{ignored}: {error}"));
                 }
+                _ => (),
+            }
 
-                if dry_run {
-                    span_manifest.exit();
-                    continue;
+            for step in action.steps.iter() {
+                if step.status == Status::Unreachable {
+                    unreachable.push(format!("{name}: {}", step.atom));
                 }
+            }
+        }
+    }
 
-                if !successful {
-                    error!("Failed");
-                    span_manifest.exit();
-                    break;
-                }
+    let heading = |heading: &str, color: Color| {
+        if no_color {
+            println!("{heading}");
+        } else {
+            println!("{}", heading.color(color).bold());
+        }
+    };
 
-                info!("Completed");
-                span_manifest.exit();
-            }
-        });
+    if !failed.is_empty() {
+        heading("Failed:", Color::Red);
+        failed.iter().for_each(|line| println!("  {line}"));
+    }
 
-        Ok(())
+    if !unreachable.is_empty() {
+        heading("Skipped due to failure:", Color::Yellow);
+        unreachable.iter().for_each(|line| println!("  {line}"));
+    }
+}
+
+/// Steps that took at least this long are shown after the run
+const SLOW_STEP_MS: u64 = 1000;
+
+/// Keeps the cache within the limits of `Comtrya.yaml`, leaving bundles alone
+fn collect_cache(policy: &comtrya_lib::cache::CachePolicy) {
+    if policy.is_empty() || comtrya_lib::cache::is_bundle() {
+        return;
+    }
+
+    match comtrya_lib::cache::collect(policy) {
+        Ok(removed) if removed.entries > 0 => {
+            debug!(
+                "Removed {} cache entries, {} bytes",
+                removed.entries, removed.bytes
+            );
+        }
+        Ok(_) => {}
+        Err(err) => warn!("Unable to clean up the cache: {}", err),
+    }
+}
+
+fn print_slowest_steps(report: &RunReport, no_color: bool) {
+    let slowest = report
+        .slowest_steps
+        .iter()
+        .filter(|step| step.duration_ms >= SLOW_STEP_MS)
+        .take(5)
+        .collect::<Vec<_>>();
+
+    if slowest.is_empty() {
+        return;
+    }
+
+    if no_color {
+        println!("Slowest steps:");
+    } else {
+        println!("{}", "Slowest steps:".bold());
+    }
+
+    for step in slowest {
+        println!(
+            "  {:>6.1}s  {}: {}: {}",
+            step.duration_ms as f64 / 1000.0,
+            step.manifest,
+            step.action,
+            step.atom
+        );
+    }
+}
+
+fn print_diff(diff: &str, no_color: bool) {
+    if no_color {
+        print!("{diff}");
+        return;
+    }
+
+    for line in diff.lines() {
+        let line = match line.chars().next() {
+            _ if line.starts_with("+++") || line.starts_with("---") => line.bold(),
+            Some('+') => line.green(),
+            Some('-') => line.red(),
+            Some('@') => line.cyan(),
+            _ => line.normal(),
+        };
+
+        println!("{line}");
     }
 }
@@ -0,0 +1,105 @@
+use super::ComtryaCommand;
+use crate::Runtime;
+use comtrya_lib::atoms::http::human_size;
+use comtrya_lib::cache::{self, Area};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+#[command()]
+pub(crate) struct Cache {
+    #[command(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum CacheCommand {
+    /// Show where the cache is and how much space it takes
+    Info,
+
+    /// Remove everything in the cache, or only some of it
+    Clean {
+        /// Only remove these, e.g. `--only downloads`
+        #[arg(long, value_enum)]
+        only: Vec<CacheArea>,
+    },
+
+    /// Remove what `cache` in Comtrya.yaml doesn't keep, like `apply` does
+    Gc {
+        /// Remove entries not used for this many days
+        #[arg(long)]
+        max_age_days: Option<u64>,
+
+        /// Remove the entries used the longest ago until the cache is this small
+        #[arg(long)]
+        max_size_mb: Option<u64>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum CacheArea {
+    Downloads,
+    Api,
+    Manifests,
+    Oci,
+}
+
+impl From<CacheArea> for Area {
+    fn from(area: CacheArea) -> Self {
+        match area {
+            CacheArea::Downloads => Area::Downloads,
+            CacheArea::Api => Area::Api,
+            CacheArea::Manifests => Area::Manifests,
+            CacheArea::Oci => Area::Oci,
+        }
+    }
+}
+
+impl ComtryaCommand for Cache {
+    fn execute(&self, runtime: &Runtime) -> anyhow::Result<()> {
+        let removed = match &self.command {
+            CacheCommand::Info => {
+                println!("{}", cache::dir().display());
+
+                for (area, size) in cache::sizes() {
+                    println!("  {:<10} {}", area.name(), human_size(size));
+                }
+
+                return Ok(());
+            }
+            CacheCommand::Clean { only } => {
+                let areas: Vec<Area> = match only.is_empty() {
+                    true => Area::ALL.to_vec(),
+                    false => only.iter().map(|area| Area::from(*area)).collect(),
+                };
+
+                cache::clean(&areas)?
+            }
+            CacheCommand::Gc {
+                max_age_days,
+                max_size_mb,
+            } => {
+                let mut policy = runtime.config.cache.clone();
+                policy.max_age_days = max_age_days.or(policy.max_age_days);
+                policy.max_size_mb = max_size_mb.or(policy.max_size_mb);
+
+                if policy.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "Nothing to collect without `cache` in Comtrya.yaml, --max-age-days or --max-size-mb"
+                    ));
+                }
+
+                cache::collect(&policy)?
+            }
+        };
+
+        info!(
+            "Removed {} cache entries, {}",
+            removed.entries,
+            human_size(removed.bytes)
+        );
+
+        Ok(())
+    }
+}
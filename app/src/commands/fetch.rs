@@ -0,0 +1,65 @@
+use super::apply::manifest_path;
+use super::validate::all_actions;
+use super::ComtryaCommand;
+use crate::Runtime;
+use anyhow::anyhow;
+use clap::Parser;
+use comtrya_lib::manifests::load;
+use tracing::{info, warn};
+
+#[derive(Parser, Debug)]
+#[command()]
+pub(crate) struct Fetch {}
+
+impl ComtryaCommand for Fetch {
+    fn execute(&self, runtime: &Runtime) -> anyhow::Result<()> {
+        if runtime.args.offline {
+            return Err(anyhow!("Can't fetch anything offline"));
+        }
+
+        if let Some(concurrency) = runtime.config.download_concurrency {
+            comtrya_lib::atoms::http::set_concurrency(concurrency);
+        }
+
+        comtrya_lib::actions::set_plugin_dirs(runtime.config.plugin_dirs.clone());
+
+        let manifests = load(manifest_path(runtime)?, &runtime.contexts);
+
+        let mut names: Vec<&String> = manifests.keys().collect();
+        names.sort();
+
+        let mut failures = vec![];
+
+        for name in names {
+            let manifest = &manifests[name];
+
+            for action in all_actions(manifest) {
+                // Actions plan against this machine, so fetch on one like the target
+                let steps = match action.inner_ref().plan(manifest, &runtime.contexts) {
+                    Ok(steps) => steps,
+                    Err(err) => {
+                        warn!("Action {action} in manifest '{name}' can't be planned: {err}");
+                        continue;
+                    }
+                };
+
+                for step in steps {
+                    if let Err(err) = step.atom.fetch() {
+                        failures.push(format!("Action {action} in manifest '{name}': {err}"));
+                    }
+                }
+            }
+        }
+
+        if failures.is_empty() {
+            info!("Fetched everything {} manifests need", manifests.len());
+            return Ok(());
+        }
+
+        for failure in failures.iter() {
+            println!("{failure}");
+        }
+
+        Err(anyhow!("Failed to fetch {} downloads", failures.len()))
+    }
+}
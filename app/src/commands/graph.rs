@@ -0,0 +1,121 @@
+use super::apply::{dependency_graph, find_cycle, manifest_path};
+use super::ComtryaCommand;
+use crate::Runtime;
+use clap::{Parser, ValueEnum};
+use comtrya_lib::manifests::{load, Manifest};
+use petgraph::graph::NodeIndex;
+use petgraph::Graph;
+use tracing::error;
+
+#[derive(Parser, Debug)]
+#[command()]
+pub(crate) struct DependencyGraph {
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = GraphFormat::Dot)]
+    format: GraphFormat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl ComtryaCommand for DependencyGraph {
+    fn execute(&self, runtime: &Runtime) -> anyhow::Result<()> {
+        let manifests = load(manifest_path(runtime)?, &runtime.contexts);
+        let (dag, root_index, _) = dependency_graph(manifests);
+
+        let cycle = find_cycle(&dag);
+
+        print!("{}", render(&dag, root_index, cycle, self.format));
+
+        if let Some((from, to)) = cycle {
+            error!(
+                "Dependency cycle, closed by '{}' depending on '{}'",
+                name(&dag, from),
+                name(&dag, to)
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn name(dag: &Graph<Manifest, u32>, node: NodeIndex) -> &str {
+    dag[node].name.as_deref().unwrap_or_default()
+}
+
+/// Draws an arrow from every manifest to the manifests it depends on, the edge
+/// closing a cycle is highlighted
+fn render(
+    dag: &Graph<Manifest, u32>,
+    root_index: NodeIndex,
+    cycle: Option<(NodeIndex, NodeIndex)>,
+    format: GraphFormat,
+) -> String {
+    let mut nodes: Vec<NodeIndex> = dag
+        .node_indices()
+        .filter(|node| *node != root_index)
+        .collect();
+    nodes.sort_by_key(|node| name(dag, *node));
+
+    let mut edges: Vec<(NodeIndex, NodeIndex)> = nodes
+        .iter()
+        .flat_map(|from| {
+            let mut to: Vec<NodeIndex> = dag.neighbors(*from).collect();
+            to.sort_by_key(|to| name(dag, *to));
+            to.into_iter().map(|to| (*from, to))
+        })
+        .collect();
+    edges.dedup();
+
+    let mut output = String::new();
+
+    match format {
+        GraphFormat::Dot => {
+            output.push_str("digraph manifests {\n");
+
+            for node in nodes.iter() {
+                output.push_str(&format!("  \"{}\";\n", name(dag, *node)));
+            }
+
+            for edge in edges.iter() {
+                let style = if Some(*edge) == cycle {
+                    " [color=red]"
+                } else {
+                    ""
+                };
+
+                output.push_str(&format!(
+                    "  \"{}\" -> \"{}\"{};\n",
+                    name(dag, edge.0),
+                    name(dag, edge.1),
+                    style
+                ));
+            }
+
+            output.push_str("}\n");
+        }
+        GraphFormat::Mermaid => {
+            // Manifest names contain dots, so they're only used as labels
+            let id = |node: &NodeIndex| format!("m{}", node.index());
+
+            output.push_str("graph TD\n");
+
+            for node in nodes.iter() {
+                output.push_str(&format!("  {}[\"{}\"]\n", id(node), name(dag, *node)));
+            }
+
+            for (index, edge) in edges.iter().enumerate() {
+                output.push_str(&format!("  {} --> {}\n", id(&edge.0), id(&edge.1)));
+
+                if Some(*edge) == cycle {
+                    output.push_str(&format!("  linkStyle {index} stroke:red\n"));
+                }
+            }
+        }
+    }
+
+    output
+}
@@ -0,0 +1,140 @@
+use super::ComtryaCommand;
+use crate::Runtime;
+use clap::Parser;
+use comtrya_lib::actions::installed_packages;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+#[derive(Parser, Debug)]
+#[command()]
+pub(crate) struct Init {
+    /// Directory to create the manifests in
+    #[arg(default_value = ".")]
+    directory: PathBuf,
+
+    /// Add the packages installed on this machine to packages.yaml
+    #[arg(long)]
+    import_packages: bool,
+}
+
+const CONFIG: &str = r#"# Manifests are looked up relative to the directory comtrya runs in
+manifest_paths:
+  - ./manifests
+
+# Values available to manifests as {{ variables.<name> }}
+variables:
+  editor: vim
+"#;
+
+const COMMON: &str = r#"# Runs on every OS, the OS specific manifests depend on it
+actions:
+  - action: directory.create
+    path: "{{ user.config_dir }}"
+"#;
+
+const LINUX: &str = r#"where: os.name == "linux"
+
+depends:
+  - common
+
+actions:
+  - action: package.install
+    list:
+      - git
+      - "{{ variables.editor }}"
+"#;
+
+const MACOS: &str = r#"where: os.name == "macos"
+
+depends:
+  - common
+
+actions:
+  - action: package.install
+    provider: homebrew
+    list:
+      - git
+      - "{{ variables.editor }}"
+"#;
+
+const WINDOWS: &str = r#"where: os.name == "windows"
+
+depends:
+  - common
+
+actions:
+  - action: package.install
+    provider: winget
+    list:
+      - Git.Git
+"#;
+
+const FILES: &str = r#"Files used by file.copy, file.link and directory.copy are resolved relative
+to this directory, e.g. `from: gitconfig` refers to files/gitconfig.
+"#;
+
+impl ComtryaCommand for Init {
+    fn execute(&self, _: &Runtime) -> anyhow::Result<()> {
+        let manifests = self.directory.join("manifests");
+
+        let mut scaffold = vec![
+            (self.directory.join("Comtrya.yaml"), CONFIG.to_string()),
+            (manifests.join("common.yaml"), COMMON.to_string()),
+            (manifests.join("linux.yaml"), LINUX.to_string()),
+            (manifests.join("macos.yaml"), MACOS.to_string()),
+            (manifests.join("windows.yaml"), WINDOWS.to_string()),
+            (manifests.join("files").join("README.md"), FILES.to_string()),
+        ];
+
+        if self.import_packages {
+            match installed_packages() {
+                Ok(packages) => scaffold.push((
+                    manifests.join("packages.yaml"),
+                    packages_manifest(&packages),
+                )),
+                Err(err) => warn!("Not importing installed packages: {}", err),
+            }
+        }
+
+        for (path, contents) in scaffold {
+            create(&path, &contents)?;
+        }
+
+        println!(
+            "Created manifests in {}, run `comtrya apply` there to apply them",
+            self.directory.display()
+        );
+
+        Ok(())
+    }
+}
+
+/// Writes a scaffold file, existing files are left alone
+fn create(path: &Path, contents: &str) -> anyhow::Result<()> {
+    if path.exists() {
+        warn!("Skipping {}, it already exists", path.display());
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    std::fs::write(path, contents)?;
+    info!("Created {}", path.display());
+
+    Ok(())
+}
+
+fn packages_manifest(packages: &[String]) -> String {
+    let mut manifest = format!(
+        "# Packages installed when this was generated with `comtrya init`\nwhere: os.name == \"{}\"\n\nactions:\n  - action: package.install\n    list:\n",
+        std::env::consts::OS
+    );
+
+    for package in packages {
+        manifest.push_str(&format!("      - \"{package}\"\n"));
+    }
+
+    manifest
+}
@@ -0,0 +1,123 @@
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The machines `apply --host` can run on, and the variables they get
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Inventory {
+    #[serde(default)]
+    hosts: BTreeMap<String, Host>,
+
+    #[serde(default)]
+    groups: BTreeMap<String, Group>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Host {
+    /// SSH destination, defaults to the name of the host
+    address: Option<String>,
+
+    #[serde(default)]
+    variables: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Group {
+    #[serde(default)]
+    hosts: Vec<String>,
+
+    #[serde(default)]
+    variables: BTreeMap<String, String>,
+}
+
+/// A host to apply on, with the variables of its groups and its own
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Target {
+    pub name: String,
+    pub address: String,
+    pub variables: BTreeMap<String, String>,
+}
+
+impl Target {
+    /// A host that isn't in an inventory
+    pub(crate) fn new(address: &str) -> Self {
+        Target {
+            name: address.to_string(),
+            address: address.to_string(),
+            variables: BTreeMap::new(),
+        }
+    }
+}
+
+impl Inventory {
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let inventory = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("Unable to read inventory {}: {}", path.display(), err))?;
+
+        let inventory: Inventory = serde_yml::from_str(&inventory)
+            .map_err(|err| anyhow!("Invalid inventory {}: {}", path.display(), err))?;
+
+        for (group, hosts) in inventory.groups.iter() {
+            if let Some(host) = hosts
+                .hosts
+                .iter()
+                .find(|host| !inventory.hosts.contains_key(*host))
+            {
+                return Err(anyhow!("Group '{group}' has unknown host '{host}'"));
+            }
+        }
+
+        Ok(inventory)
+    }
+
+    /// The hosts with these names, or in groups with these names. Every host
+    /// when no names are given.
+    pub(crate) fn targets(&self, names: &[String]) -> anyhow::Result<Vec<Target>> {
+        let mut hosts: Vec<&String> = vec![];
+
+        if names.is_empty() {
+            hosts.extend(self.hosts.keys());
+        }
+
+        for name in names {
+            if let Some(group) = self.groups.get(name) {
+                hosts.extend(group.hosts.iter());
+            } else if self.hosts.contains_key(name) {
+                hosts.push(name);
+            } else {
+                return Err(anyhow!("'{name}' isn't a host or group in the inventory"));
+            }
+        }
+
+        let mut targets: Vec<Target> = vec![];
+
+        for name in hosts {
+            if targets.iter().any(|target| &target.name == name) {
+                continue;
+            }
+
+            let host = &self.hosts[name];
+
+            // Host variables win over those of its groups
+            let mut variables = BTreeMap::new();
+            for group in self.groups.values() {
+                if group.hosts.contains(name) {
+                    variables.extend(group.variables.clone());
+                }
+            }
+            variables.extend(host.variables.clone());
+
+            targets.push(Target {
+                name: name.clone(),
+                address: host.address.clone().unwrap_or_else(|| name.clone()),
+                variables,
+            });
+        }
+
+        Ok(targets)
+    }
+}
@@ -1,15 +1,50 @@
 mod apply;
 pub(crate) use apply::Apply;
 
+mod inventory;
+mod prompt;
+pub(crate) use prompt::ask_variables;
+mod remote;
+
+mod init;
+pub(crate) use init::Init;
+
 mod version;
 pub(crate) use version::Version;
 
 mod contexts;
 pub(crate) use contexts::Contexts;
 
+mod watch;
+pub(crate) use watch::Watch;
+
+mod fetch;
+pub(crate) use fetch::Fetch;
+
+mod cache;
+pub(crate) use cache::Cache;
+
+mod graph;
+pub(crate) use graph::DependencyGraph;
+
+mod oci;
+pub(crate) use oci::{Pull, Push};
+
+mod schema;
+pub(crate) use schema::Schema;
+
 mod gen_completions;
 pub(crate) use gen_completions::GenCompletions;
 
+mod rollback;
+pub(crate) use rollback::Rollback;
+
+mod test;
+pub(crate) use test::Test;
+
+mod validate;
+pub(crate) use validate::Validate;
+
 use crate::Runtime;
 
 pub trait ComtryaCommand {
@@ -0,0 +1,57 @@
+use super::ComtryaCommand;
+use crate::Runtime;
+use clap::Parser;
+use comtrya_lib::oci::{Reference, Registry};
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+#[command()]
+pub(crate) struct Push {
+    /// Registry reference to push to, e.g. oci://ghcr.io/acme/baseline:1.0
+    reference: String,
+
+    /// Directory containing the manifests
+    #[arg(default_value = ".")]
+    directory: PathBuf,
+}
+
+impl ComtryaCommand for Push {
+    fn execute(&self, _runtime: &Runtime) -> anyhow::Result<()> {
+        let reference = Reference::parse(&self.reference)?;
+        let digest = Registry::new(reference)?.push(&self.directory)?;
+
+        info!("Pushed {} to {}", self.directory.display(), self.reference);
+        println!("{digest}");
+
+        Ok(())
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command()]
+pub(crate) struct Pull {
+    /// Registry reference to pull from, e.g. oci://ghcr.io/acme/baseline:1.0
+    reference: String,
+
+    /// Directory to unpack the manifests into
+    #[arg(default_value = ".")]
+    directory: PathBuf,
+}
+
+impl ComtryaCommand for Pull {
+    fn execute(&self, _runtime: &Runtime) -> anyhow::Result<()> {
+        let reference = Reference::parse(&self.reference)?;
+
+        std::fs::create_dir_all(&self.directory)?;
+        Registry::new(reference)?.pull(&self.directory)?;
+
+        info!(
+            "Pulled {} into {}",
+            self.reference,
+            self.directory.display()
+        );
+
+        Ok(())
+    }
+}
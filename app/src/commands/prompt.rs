@@ -0,0 +1,203 @@
+use super::apply::manifest_path;
+use crate::{Commands, Runtime};
+use anyhow::anyhow;
+use comtrya_lib::manifests::{prompts, Prompt as VariablePrompt};
+use std::io::{BufRead, IsTerminal, Read, Write};
+
+/// What to do with a step, answered for every step with `apply --interactive`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Answer {
+    Apply,
+    Skip,
+}
+
+const HELP: &str = "y - apply this step
+n - skip this step
+a - apply this step and all remaining steps
+q - quit, skip this step and all remaining steps
+? - print help";
+
+/// Asks before each step is applied, like `git add -p`. Once answered with
+/// apply-all or quit, the remaining steps aren't asked about.
+#[derive(Debug, Default)]
+pub(crate) struct Prompt {
+    apply_all: bool,
+    quit: bool,
+}
+
+impl Prompt {
+    pub(crate) fn ask(&mut self, manifest: &str, action: &str, step: &str) -> Answer {
+        if self.quit {
+            return Answer::Skip;
+        }
+
+        if self.apply_all {
+            return Answer::Apply;
+        }
+
+        // stdout is kept for the report
+        let mut input = std::io::stdin().lock();
+        let mut output = std::io::stderr();
+
+        let _ = writeln!(output, "[{manifest}] {action}\n  {step}");
+
+        loop {
+            let _ = write!(output, "Apply this step [y,n,a,q,?]? ");
+            let _ = output.flush();
+
+            let mut line = String::new();
+
+            // Nobody left to answer, so nothing more is applied
+            if !matches!(input.read_line(&mut line), Ok(read) if read > 0) {
+                self.quit = true;
+                return Answer::Skip;
+            }
+
+            match line.trim() {
+                "y" => return Answer::Apply,
+                "n" => return Answer::Skip,
+                "a" => {
+                    self.apply_all = true;
+                    return Answer::Apply;
+                }
+                "q" => {
+                    self.quit = true;
+                    return Answer::Skip;
+                }
+                _ => {
+                    let _ = writeln!(output, "{HELP}");
+                }
+            }
+        }
+    }
+}
+
+/// Prompted variables can be set without asking, e.g. for `email` with `COMTRYA_VAR_EMAIL`
+const VARIABLE_PREFIX: &str = "COMTRYA_VAR_";
+
+/// Asks for the variables the manifests prompt for, unless they're set already by
+/// `variables`, `--var` or the environment. Without a terminal, the defaults are used.
+pub(crate) fn ask_variables(runtime: &mut Runtime) -> anyhow::Result<()> {
+    let manifest_path = match &runtime.args.command {
+        Commands::Apply(apply) | Commands::Status(apply) => apply.manifest_path(runtime)?,
+        Commands::Watch(watch) => watch.apply.manifest_path(runtime)?,
+        Commands::Fetch(_) | Commands::Validate(_) | Commands::Graph(_) | Commands::Test(_) => {
+            manifest_path(runtime)?
+        }
+        _ => return Ok(()),
+    };
+
+    let terminal = std::io::stdin().is_terminal();
+
+    for (name, prompt) in prompts(&manifest_path) {
+        if runtime.config.variables.contains_key(&name) {
+            continue;
+        }
+
+        let value = match std::env::var(format!("{VARIABLE_PREFIX}{}", name.to_uppercase())) {
+            Ok(value) => value,
+            Err(_) if terminal => ask_variable(&name, &prompt, read_answer)?,
+            Err(_) => prompt.default.clone().ok_or_else(|| {
+                anyhow!("Variable '{name}' isn't set, and can't be asked for without a terminal. Set it with --var {name}=<value>")
+            })?,
+        };
+
+        runtime.config.variables.insert(name.clone(), value.clone());
+        runtime
+            .remote_config
+            .variables
+            .insert(name.clone(), value.clone());
+        runtime
+            .contexts
+            .entry(String::from("variables"))
+            .or_default()
+            .insert(name, value.into());
+    }
+
+    Ok(())
+}
+
+/// Asks until the answer is one of the choices, when there are any. `read` shows
+/// the question and returns the answer, or nothing when nobody's left to answer.
+fn ask_variable(
+    name: &str,
+    prompt: &VariablePrompt,
+    mut read: impl FnMut(&str, bool) -> std::io::Result<Option<String>>,
+) -> anyhow::Result<String> {
+    let mut question = prompt.message.clone().unwrap_or_else(|| name.to_string());
+    if !prompt.choices.is_empty() {
+        question.push_str(&format!(" ({})", prompt.choices.join(", ")));
+    }
+    if let Some(default) = prompt.default.as_ref().filter(|_| !prompt.secret) {
+        question.push_str(&format!(" [{default}]"));
+    }
+    question.push_str(": ");
+
+    loop {
+        let Some(answer) = read(&question, prompt.secret)? else {
+            return prompt
+                .default
+                .clone()
+                .ok_or_else(|| anyhow!("Variable '{name}' wasn't answered"));
+        };
+
+        let answer = match (answer.trim(), &prompt.default) {
+            ("", Some(default)) => default.clone(),
+            ("", None) => continue,
+            (answer, _) => answer.to_string(),
+        };
+
+        if prompt.choices.is_empty() || prompt.choices.contains(&answer) {
+            return Ok(answer);
+        }
+
+        let _ = writeln!(
+            std::io::stderr(),
+            "Answer with one of {}",
+            prompt.choices.join(", ")
+        );
+    }
+}
+
+/// Reads an answer from the terminal, without showing it when it's secret
+fn read_answer(question: &str, secret: bool) -> std::io::Result<Option<String>> {
+    let mut output = std::io::stderr();
+    write!(output, "{question}")?;
+    output.flush()?;
+
+    if !secret {
+        let mut line = String::new();
+        return Ok((std::io::stdin().lock().read_line(&mut line)? > 0).then_some(line));
+    }
+
+    crossterm::terminal::enable_raw_mode()?;
+
+    let mut answer = vec![];
+    let mut result = Ok(());
+
+    for byte in std::io::stdin().lock().bytes() {
+        match byte {
+            Ok(b'\r' | b'\n') => break,
+            // Ctrl-C, as raw mode doesn't turn it into a signal
+            Ok(3) => {
+                result = Err(std::io::ErrorKind::Interrupted.into());
+                break;
+            }
+            // Backspace
+            Ok(127 | 8) => {
+                answer.pop();
+            }
+            Ok(byte) => answer.push(byte),
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        }
+    }
+
+    crossterm::terminal::disable_raw_mode()?;
+    writeln!(output)?;
+    result?;
+
+    Ok(Some(String::from_utf8_lossy(&answer).to_string()))
+}
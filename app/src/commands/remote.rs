@@ -0,0 +1,251 @@
+use comtrya_lib::config::{Config, Logs};
+use comtrya_lib::oci::pack;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use tracing::{debug, info};
+
+/// Where the manifests, and comtrya when it isn't installed, are copied to
+const REMOTE_DIR: &str = "$HOME/.cache/comtrya/remote";
+
+/// A machine manifests are applied on over SSH, using the system's `ssh` so
+/// that `~/.ssh/config` and agents work as usual
+pub(crate) struct Remote {
+    host: String,
+}
+
+impl Remote {
+    pub(crate) fn new(host: &str) -> Self {
+        Remote {
+            host: host.to_string(),
+        }
+    }
+
+    /// Runs a shell command on the host and returns its stdout
+    fn ssh(&self, command: &str, stdin: Option<&[u8]>) -> anyhow::Result<String> {
+        debug!("ssh {} {}", self.host, command);
+
+        let mut child = Command::new("ssh")
+            .arg(&self.host)
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // .unwrap() is safe here, stdin is piped
+        let mut input = child.stdin.take().unwrap();
+        input.write_all(stdin.unwrap_or_default())?;
+        drop(input);
+
+        let output = child.wait_with_output()?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "'{}' failed on {}: {}",
+                command,
+                self.host,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Copies the manifests and config to the host, replacing those of earlier runs
+    pub(crate) fn upload(&self, manifest_path: &Path, config: &Config) -> anyhow::Result<()> {
+        info!("Copying manifests to {}", self.host);
+
+        self.ssh(
+            &format!(
+                "rm -rf {REMOTE_DIR}/manifests && mkdir -p {REMOTE_DIR}/manifests && tar -xzf - -C {REMOTE_DIR}/manifests"
+            ),
+            Some(&pack(manifest_path)?),
+        )?;
+
+        self.ssh(
+            &format!("cat > {REMOTE_DIR}/Comtrya.yaml"),
+            Some(serde_yml::to_string(&portable_config(config))?.as_bytes()),
+        )?;
+
+        Ok(())
+    }
+
+    /// Finds comtrya on the host. When it's missing, this binary is copied over if
+    /// it runs on the host, otherwise it's installed with `install`, or fails.
+    pub(crate) fn comtrya(&self, install: bool) -> anyhow::Result<String> {
+        if let Ok(path) = self.ssh("command -v comtrya", None) {
+            return Ok(path);
+        }
+
+        let binary = format!("{REMOTE_DIR}/comtrya");
+
+        if self.ssh(&format!("test -x {binary}"), None).is_ok() {
+            return Ok(binary);
+        }
+
+        let target = self
+            .ssh("uname -sm; ldd --version 2>&1 | head -n 1", None)
+            .ok()
+            .and_then(|uname| remote_target(&uname));
+
+        if target.as_deref().is_some_and(runs_on) {
+            info!("Copying comtrya to {}", self.host);
+
+            let executable = std::fs::read(std::env::current_exe()?)?;
+            self.ssh(
+                &format!("mkdir -p {REMOTE_DIR} && cat > {binary} && chmod +x {binary}"),
+                Some(&executable),
+            )?;
+
+            return Ok(binary);
+        }
+
+        let target = target.unwrap_or_else(|| String::from("an unknown target"));
+
+        if !install {
+            return Err(anyhow::anyhow!(
+                "comtrya isn't installed on {}, and this binary doesn't run on {}. Install it there, or pass --install-remote to have the install script do it",
+                self.host,
+                target
+            ));
+        }
+
+        info!("Installing comtrya on {}, {}", self.host, target);
+
+        self.ssh(
+            &format!(
+                "curl -fsSL https://get.comtrya.dev | VERSION=v{} sh",
+                env!("CARGO_PKG_VERSION")
+            ),
+            None,
+        )?;
+
+        self.ssh("command -v comtrya", None)
+    }
+
+    /// Runs comtrya on the host with `args`, its output is streamed back as is,
+    /// or with every line prefixed when several hosts run at once
+    pub(crate) fn run(
+        &self,
+        comtrya: &str,
+        args: &[String],
+        tty: bool,
+        prefix: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let args: Vec<String> = args.iter().map(|arg| quote(arg)).collect();
+        let command = format!("cd {REMOTE_DIR} && {comtrya} {}", args.join(" "));
+
+        debug!("ssh {} {}", self.host, command);
+
+        let mut ssh = Command::new("ssh");
+        if tty {
+            ssh.arg("-t");
+        }
+        ssh.arg(&self.host).arg(command);
+
+        if !stream(&mut ssh, prefix)?.success() {
+            return Err(anyhow::anyhow!("comtrya failed on {}", self.host));
+        }
+
+        Ok(())
+    }
+}
+
+/// The config for another machine. Paths in the config only make sense locally,
+/// so they're left out.
+pub(crate) fn portable_config(config: &Config) -> Config {
+    Config {
+        manifest_paths: vec![],
+        plugin_dirs: vec![],
+        state_file: None,
+        logs: config.logs.clone().map(|logs| Logs {
+            directory: None,
+            ..logs
+        }),
+        ..config.clone()
+    }
+}
+
+/// Runs the command with its output streamed as is, or with every line prefixed
+/// when several run at once
+pub(crate) fn stream(command: &mut Command, prefix: Option<&str>) -> std::io::Result<ExitStatus> {
+    let Some(prefix) = prefix else {
+        return command.status();
+    };
+
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // .unwrap() is safe here, both are piped
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                println!("[{prefix}] {line}");
+            }
+        });
+
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            eprintln!("[{prefix}] {line}");
+        }
+    });
+
+    child.wait()
+}
+
+/// The target triple of this binary, as far as it matters to running it elsewhere
+fn local_target() -> String {
+    let env = if cfg!(target_env = "musl") {
+        "musl"
+    } else {
+        "gnu"
+    };
+
+    match std::env::consts::OS {
+        "linux" => format!("{}-unknown-linux-{env}", std::env::consts::ARCH),
+        "macos" => format!("{}-apple-darwin", std::env::consts::ARCH),
+        os => format!("{}-unknown-{os}", std::env::consts::ARCH),
+    }
+}
+
+/// The target triple of a host, from `uname -sm` and the first line of
+/// `ldd --version`, which names musl on hosts that use it
+fn remote_target(uname: &str) -> Option<String> {
+    let mut lines = uname.lines();
+    let (os, arch) = lines.next()?.trim().split_once(' ')?;
+    let ldd = lines.next().unwrap_or_default().to_lowercase();
+
+    let arch = match arch {
+        "arm64" => "aarch64",
+        "amd64" => "x86_64",
+        arch => arch,
+    };
+
+    match os {
+        "Linux" if ldd.contains("musl") => Some(format!("{arch}-unknown-linux-musl")),
+        "Linux" => Some(format!("{arch}-unknown-linux-gnu")),
+        "Darwin" => Some(format!("{arch}-apple-darwin")),
+        os => Some(format!("{arch}-unknown-{}", os.to_lowercase())),
+    }
+}
+
+/// Whether this binary runs on a host of the target. Binaries linked with musl
+/// are static, so they run on hosts with glibc too.
+fn runs_on(target: &str) -> bool {
+    let local = local_target();
+
+    match local.strip_suffix("-musl") {
+        Some(linux) => target.starts_with(linux),
+        None => target == local,
+    }
+}
+
+/// Quotes an argument for the remote shell
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
@@ -0,0 +1,34 @@
+use super::ComtryaCommand;
+use crate::Runtime;
+use anyhow::anyhow;
+use clap::Parser;
+use comtrya_lib::rollback::{default_runs_dir, Journal};
+use tracing::info;
+
+#[derive(Parser, Debug)]
+#[command()]
+pub(crate) struct Rollback {
+    /// The run to revert, defaults to the most recent run
+    run_id: Option<String>,
+}
+
+impl ComtryaCommand for Rollback {
+    fn execute(&self, _: &Runtime) -> anyhow::Result<()> {
+        let runs_dir =
+            default_runs_dir().ok_or_else(|| anyhow!("Unable to find the data directory"))?;
+
+        let run_id = match &self.run_id {
+            Some(run_id) => run_id.clone(),
+            None => Journal::latest(&runs_dir)?
+                .ok_or_else(|| anyhow!("No runs with changes to roll back"))?,
+        };
+
+        let journal = Journal::open(&runs_dir, &run_id)?;
+
+        info!("Rolling back run {}", run_id);
+        journal.rollback()?;
+        info!("Rolled back run {}", run_id);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use super::ComtryaCommand;
+use crate::Runtime;
+use clap::Parser;
+use comtrya_lib::manifests::Manifest;
+use schemars::schema_for;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command()]
+pub(crate) struct Schema {
+    /// Write the schema to this file, rather than stdout
+    #[arg(short, long)]
+    file: Option<PathBuf>,
+}
+
+impl ComtryaCommand for Schema {
+    fn execute(&self, _: &Runtime) -> anyhow::Result<()> {
+        let schema = serde_json::to_string_pretty(&schema_for!(Manifest))?;
+
+        match &self.file {
+            Some(file) => std::fs::write(file, schema + "\n")?,
+            None => println!("{schema}"),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use super::apply::manifest_path;
+use super::remote::{portable_config, stream};
+use super::ComtryaCommand;
+use crate::{OutputFormat, Runtime};
+use anyhow::anyhow;
+use clap::{Parser, ValueEnum};
+use comfy_table::{Cell, ContentArrangement, Table};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+use tracing::{debug, error, info};
+
+/// Where the manifests, config and comtrya are mounted in the containers
+const CONTAINER_DIR: &str = "/comtrya";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Engine {
+    Docker,
+    Podman,
+}
+
+#[derive(Parser, Clone, Debug)]
+pub(crate) struct Test {
+    /// Images to apply the manifests in, comma separated list such as
+    /// ubuntu:24.04,fedora:40,archlinux
+    #[arg(long = "image", value_delimiter = ',', required = true)]
+    images: Vec<String>,
+
+    /// Run a subset of your manifests and their dependencies, same format as
+    /// `apply --manifests`
+    #[arg(short, long, value_delimiter = ',')]
+    manifests: Vec<String>,
+
+    /// Leave out manifests, unless another manifest depends on them
+    #[arg(long, value_delimiter = ',')]
+    exclude: Vec<String>,
+
+    /// Defaults to docker, or podman when docker isn't installed
+    #[arg(long)]
+    engine: Option<Engine>,
+
+    /// comtrya to run in the containers, which must be built for Linux.
+    /// Defaults to this one on Linux.
+    #[arg(long)]
+    binary: Option<PathBuf>,
+}
+
+impl Engine {
+    fn command(&self) -> &'static str {
+        match self {
+            Engine::Docker => "docker",
+            Engine::Podman => "podman",
+        }
+    }
+
+    fn detect() -> Engine {
+        match Command::new("docker").arg("--version").output() {
+            Ok(output) if output.status.success() => Engine::Docker,
+            _ => Engine::Podman,
+        }
+    }
+}
+
+impl Test {
+    /// Applies the manifests in a throwaway container of `image`
+    fn run(
+        &self,
+        runtime: &Runtime,
+        engine: Engine,
+        image: &str,
+        mounts: &[(&Path, String)],
+        prefix: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let mut command = Command::new(engine.command());
+        command.args(["run", "--rm"]);
+
+        for (source, target) in mounts {
+            command
+                .arg("--volume")
+                .arg(format!("{}:{}:ro", source.display(), target));
+        }
+
+        command
+            .arg("--workdir")
+            .arg(CONTAINER_DIR)
+            .arg("--entrypoint")
+            .arg(format!("{CONTAINER_DIR}/bin/comtrya"))
+            .arg(image);
+
+        if runtime.args.no_color {
+            command.arg("--no-color");
+        }
+
+        if runtime.args.verbose > 0 {
+            command.arg(format!("-{}", "v".repeat(runtime.args.verbose.into())));
+        }
+
+        command.args(["-d", "manifests", "apply"]);
+
+        for (flag, values) in [
+            ("--manifests", &self.manifests),
+            ("--exclude", &self.exclude),
+        ] {
+            if !values.is_empty() {
+                command.arg(format!("{flag}={}", values.join(",")));
+            }
+        }
+
+        debug!("{:?}", command);
+
+        let status = stream(&mut command, prefix).map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => {
+                anyhow!(
+                    "{} needs to be installed to test manifests",
+                    engine.command()
+                )
+            }
+            _ => err.into(),
+        })?;
+
+        if !status.success() {
+            return Err(anyhow!("comtrya failed in {}", image));
+        }
+
+        Ok(())
+    }
+}
+
+impl ComtryaCommand for Test {
+    fn execute(&self, runtime: &Runtime) -> anyhow::Result<()> {
+        let manifest_path = manifest_path(runtime)?.canonicalize()?;
+        let engine = self.engine.unwrap_or_else(Engine::detect);
+
+        let binary = match self.binary.as_ref() {
+            Some(binary) => binary.canonicalize()?,
+            None if cfg!(target_os = "linux") => std::env::current_exe()?,
+            None => {
+                return Err(anyhow!(
+                    "Containers run Linux, use --binary with a Linux build of comtrya"
+                ))
+            }
+        };
+
+        // Containers are thrown away, so there's nothing to report from them
+        let mut config = portable_config(&runtime.remote_config);
+        config.disable_update_check = true;
+        config.notify.clear();
+        config.metrics = None;
+        config.audit_file = None;
+
+        // Only readable by us, as it can hold variables and secrets. Removed when dropped.
+        let mut config_file = tempfile::Builder::new()
+            .prefix("comtrya-test-")
+            .suffix(".yaml")
+            .tempfile()?;
+        config_file.write_all(serde_yml::to_string(&config)?.as_bytes())?;
+        config_file.flush()?;
+        let config_path = config_file.path();
+
+        let mounts = [
+            (
+                manifest_path.as_path(),
+                format!("{CONTAINER_DIR}/manifests"),
+            ),
+            (config_path, format!("{CONTAINER_DIR}/Comtrya.yaml")),
+            (binary.as_path(), format!("{CONTAINER_DIR}/bin/comtrya")),
+        ];
+
+        // Machine readable output can't be interleaved
+        let concurrent = self.images.len() > 1 && runtime.args.output == OutputFormat::Text;
+
+        let test = |image: &String| {
+            info!("Applying manifests in {}", image);
+
+            let started = Instant::now();
+            let result = self.run(
+                runtime,
+                engine,
+                image,
+                &mounts,
+                concurrent.then_some(image.as_str()),
+            );
+
+            if let Err(err) = result.as_ref() {
+                error!("{}", err);
+            }
+
+            (image.clone(), result, started.elapsed())
+        };
+
+        let results: Vec<_> = if concurrent {
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = self
+                    .images
+                    .iter()
+                    .map(|image| scope.spawn(move || test(image)))
+                    .collect();
+
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap())
+                    .collect()
+            })
+        } else {
+            self.images.iter().map(test).collect()
+        };
+
+        if runtime.args.output == OutputFormat::Text {
+            let mut table = Table::new();
+            table
+                .set_content_arrangement(ContentArrangement::Dynamic)
+                .set_width(80)
+                .set_header(vec!["Image", "Result", "Duration"]);
+
+            for (image, result, duration) in results.iter() {
+                table.add_row(vec![
+                    Cell::new(image),
+                    Cell::new(if result.is_ok() { "passed" } else { "failed" }),
+                    Cell::new(format!("{:.1}s", duration.as_secs_f64())),
+                ]);
+            }
+
+            println!("{table}");
+        }
+
+        let failed: Vec<&str> = results
+            .iter()
+            .filter(|(_, result, _)| result.is_err())
+            .map(|(image, _, _)| image.as_str())
+            .collect();
+
+        if !failed.is_empty() {
+            return Err(anyhow!("Manifests failed in {}", failed.join(", ")));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use super::apply::{dependency_graph, find_cycle, manifest_path};
+use super::ComtryaCommand;
+use crate::Runtime;
+use anyhow::anyhow;
+use clap::Parser;
+use comtrya_lib::actions::Actions;
+use comtrya_lib::manifests::{deprecations, load_with_errors, Manifest};
+use comtrya_lib::rhai_functions;
+use comtrya_lib::session::resolve_dependency;
+use std::collections::HashSet;
+
+#[derive(Parser, Debug)]
+#[command()]
+pub(crate) struct Validate {
+    /// Rewrite deprecated action and field names to their new names
+    #[arg(long)]
+    fix: bool,
+}
+
+impl ComtryaCommand for Validate {
+    fn execute(&self, runtime: &Runtime) -> anyhow::Result<()> {
+        let manifest_path = manifest_path(runtime)?;
+
+        if self.fix {
+            for name in deprecations::fix_files(&manifest_path)? {
+                println!(
+                    "{}:{}: renamed {} to {}",
+                    name.path.display(),
+                    name.line,
+                    name.old,
+                    name.new
+                );
+            }
+        }
+
+        let deprecated = deprecations::find_files(&manifest_path).len();
+        let (manifests, errors) = load_with_errors(manifest_path, &runtime.contexts);
+
+        let mut problems: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+
+        let mut names: Vec<&String> = manifests.keys().collect();
+        names.sort();
+
+        let ids: HashSet<&str> = manifests
+            .values()
+            .flat_map(all_actions)
+            .filter_map(|action| action.inner_ref().id())
+            .collect();
+
+        for name in names {
+            let manifest = &manifests[name];
+
+            for dependency in manifest.depends.iter() {
+                let resolved = resolve_dependency(name, dependency);
+
+                if !manifests.contains_key(&resolved) {
+                    problems.push(format!(
+                        "Manifest '{name}' depends on '{resolved}', which doesn't exist"
+                    ));
+                }
+            }
+
+            problems.extend(check_manifest(name, manifest, &ids));
+        }
+
+        let count = manifests.len();
+        let (dag, _, _) = dependency_graph(manifests);
+
+        if let Some((from, to)) = find_cycle(&dag) {
+            problems.push(format!(
+                "Dependency cycle, closed by '{}' depending on '{}'",
+                dag[from].name.as_deref().unwrap_or_default(),
+                dag[to].name.as_deref().unwrap_or_default()
+            ));
+        }
+
+        if deprecated > 0 {
+            println!(
+                "{deprecated} deprecated names can be rewritten with `comtrya validate --fix`"
+            );
+        }
+
+        if problems.is_empty() {
+            println!("{count} manifests are valid");
+            return Ok(());
+        }
+
+        for problem in problems.iter() {
+            println!("{problem}");
+        }
+
+        Err(anyhow!(
+            "Found {} problems in the manifests",
+            problems.len()
+        ))
+    }
+}
+
+/// Checks that the `where` expressions of the manifest and its actions compile,
+/// and that the files its actions need exist
+fn check_manifest(name: &str, manifest: &Manifest, ids: &HashSet<&str>) -> Vec<String> {
+    let engine = rhai_functions::engine();
+    let mut problems = vec![];
+
+    let mut check_condition = |condition: &str, location: String| {
+        if let Err(err) = engine.compile_expression(condition) {
+            problems.push(format!(
+                "{location} has an invalid where condition '{condition}': {err}"
+            ));
+        }
+    };
+
+    if let Some(condition) = manifest.r#where.as_deref() {
+        check_condition(condition, format!("Manifest '{name}'"));
+    }
+
+    for action in all_actions(manifest) {
+        for condition in action.inner_ref().conditions() {
+            check_condition(condition, format!("Action {action} in manifest '{name}'"));
+        }
+    }
+
+    for action in all_actions(manifest) {
+        if let Some(id) = action.inner_ref().when_changed() {
+            if !ids.contains(id) {
+                problems.push(format!(
+                    "Action {action} in manifest '{name}' runs when '{id}' changed, but no action has that id"
+                ));
+            }
+        }
+    }
+
+    // Also finds actions depending on ids that aren't in their block
+    for definitions in [
+        &manifest.before,
+        &manifest.actions,
+        &manifest.after,
+        &manifest.on_failure,
+        &manifest.always,
+    ] {
+        if let Err(err) = manifest.batches(definitions) {
+            problems.push(format!("Manifest '{name}': {err}"));
+        }
+    }
+
+    for action in all_actions(manifest) {
+        for file in action.inner_ref().files(manifest) {
+            if !file.exists() {
+                problems.push(format!(
+                    "Action {action} in manifest '{name}' needs {}, which doesn't exist",
+                    file.display()
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+/// The actions of every block of the manifest
+pub(super) fn all_actions(manifest: &Manifest) -> impl Iterator<Item = &Actions> {
+    manifest
+        .before
+        .iter()
+        .chain(manifest.actions.iter())
+        .chain(manifest.after.iter())
+        .chain(manifest.on_failure.iter())
+        .chain(manifest.always.iter())
+}
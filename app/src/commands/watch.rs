@@ -0,0 +1,73 @@
+use super::{Apply, ComtryaCommand};
+use crate::Runtime;
+use clap::Parser;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{error, info};
+
+#[derive(Parser, Debug)]
+#[command()]
+pub(crate) struct Watch {
+    #[command(flatten)]
+    pub(crate) apply: Apply,
+
+    /// Milliseconds between checks for changes
+    #[arg(long, default_value_t = 500)]
+    interval: u64,
+}
+
+impl ComtryaCommand for Watch {
+    fn execute(&self, runtime: &Runtime) -> anyhow::Result<()> {
+        let manifest_path = self.apply.manifest_path(runtime)?;
+        let apply = self.apply.for_watch();
+
+        let mut modified = snapshot(&manifest_path);
+
+        loop {
+            if let Err(err) = apply.execute(runtime) {
+                error!("{}", err);
+            }
+
+            info!("Watching {} for changes", manifest_path.display());
+
+            loop {
+                std::thread::sleep(Duration::from_millis(self.interval));
+
+                let current = snapshot(&manifest_path);
+                if current != modified {
+                    modified = current;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// When every file below `path` was last modified, hidden files and
+/// directories such as `.git` are left out
+fn snapshot(path: &Path) -> BTreeMap<PathBuf, SystemTime> {
+    let mut modified = BTreeMap::new();
+
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return modified;
+    };
+
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let path = entry.path();
+
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => modified.extend(snapshot(&path)),
+            Ok(metadata) => {
+                modified.insert(path, metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+            }
+            Err(_) => (),
+        }
+    }
+
+    modified
+}
@@ -4,16 +4,28 @@ use comtrya_lib::config::load_config as lib_config;
 pub use comtrya_lib::config::Config;
 use tracing::instrument;
 
+/// The config as written, with the command line on top
 #[instrument(name = "load_config", level = "info")]
 pub(crate) fn load_config(args: &GlobalArgs) -> Result<Config> {
-    match lib_config() {
-        Ok(config) => match args.manifest_directory.clone() {
-            Some(manifest_path) => Ok(Config {
-                manifest_paths: vec![manifest_path],
-                ..config
-            }),
-            None => Ok(Config { ..config }),
-        },
-        Err(error) => Err(error),
+    Ok(with_args(lib_config()?, args))
+}
+
+/// The config of this machine, with the `machines` matching its hostname. The
+/// command line still wins over them.
+pub(crate) fn machine_config(config: &Config, args: &GlobalArgs) -> Config {
+    with_args(config.for_this_machine(), args)
+}
+
+fn with_args(mut config: Config, args: &GlobalArgs) -> Config {
+    if let Some(manifest_path) = args.manifest_directory.clone() {
+        config.manifest_paths = vec![manifest_path];
     }
+
+    config.variables.extend(args.variables.iter().cloned());
+
+    if args.profile.is_some() {
+        config.profile = args.profile.clone();
+    }
+
+    config
 }
@@ -0,0 +1,211 @@
+use anyhow::anyhow;
+use comtrya_lib::config::{default_logs_dir, Logs};
+use comtrya_lib::state::now;
+use serde_json::{json, Map, Value};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tracing::field::{Field, Visit};
+use tracing::span::Record;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::{DefaultFields, Format, Full, Writer};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Creates the log file of this run, named after the time like run ids, and
+/// removes the oldest log files so that `keep` are left
+pub(crate) fn create_log_file(logs: &Logs) -> anyhow::Result<File> {
+    let dir = logs
+        .directory
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(default_logs_dir)
+        .ok_or_else(|| anyhow!("No directory for log files"))?;
+
+    std::fs::create_dir_all(&dir)?;
+
+    if logs.keep > 0 {
+        let mut files: Vec<(SystemTime, PathBuf)> = std::fs::read_dir(&dir)?
+            .flatten()
+            .filter(|entry| entry.path().extension() == Some(OsStr::new("log")))
+            .map(|entry| {
+                let modified = entry
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+
+                (modified, entry.path())
+            })
+            .collect();
+        files.sort();
+
+        let remove = (files.len() + 1).saturating_sub(logs.keep);
+        for (_, file) in files.into_iter().take(remove) {
+            std::fs::remove_file(file)?;
+        }
+    }
+
+    let mut name = now().to_string();
+    let mut suffix = 0;
+
+    while dir.join(format!("{name}.log")).exists() {
+        suffix += 1;
+        name = format!("{}-{}", now(), suffix);
+    }
+
+    Ok(OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(dir.join(format!("{name}.log")))?)
+}
+
+/// Fields of events and spans as a JSON object when logs are JSON, and as usual
+/// otherwise
+pub(crate) struct LogFields {
+    json: bool,
+}
+
+/// Formats each event as a single line JSON object for `--log-format json`,
+/// so that logs can be shipped to the likes of Loki or Elastic
+pub(crate) struct LogFormat {
+    json: bool,
+    text: Format<Full, ()>,
+}
+
+impl LogFormat {
+    pub(crate) fn new(json: bool, text: Format<Full, ()>) -> Self {
+        LogFormat { json, text }
+    }
+}
+
+impl LogFields {
+    pub(crate) fn new(json: bool) -> Self {
+        LogFields { json }
+    }
+}
+
+#[derive(Default)]
+struct JsonVisitor(Map<String, Value>);
+
+impl Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), Value::from(format!("{value:?}")));
+    }
+}
+
+impl<'writer> FormatFields<'writer> for LogFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        if !self.json {
+            return DefaultFields::new().format_fields(writer, fields);
+        }
+
+        let mut visitor = JsonVisitor::default();
+        fields.record(&mut visitor);
+        write!(writer, "{}", Value::Object(visitor.0))
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &Record<'_>,
+    ) -> fmt::Result {
+        if !self.json {
+            if !current.fields.is_empty() {
+                current.fields.push(' ');
+            }
+            return DefaultFields::new().format_fields(current.as_writer(), fields);
+        }
+
+        let mut visitor = JsonVisitor(serde_json::from_str(&current.fields).unwrap_or_default());
+        fields.record(&mut visitor);
+        current.fields = Value::Object(visitor.0).to_string();
+
+        Ok(())
+    }
+}
+
+impl<S> FormatEvent<S, LogFields> for LogFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, LogFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        if !self.json {
+            return self.text.format_event(ctx, writer, event);
+        }
+
+        let metadata = event.metadata();
+
+        let mut fields = JsonVisitor::default();
+        event.record(&mut fields);
+
+        // Outermost first, e.g. the manifest and then the action
+        let spans: Vec<Value> = ctx
+            .event_scope()
+            .map(|scope| {
+                scope
+                    .from_root()
+                    .map(|span| {
+                        let mut object = span
+                            .extensions()
+                            .get::<FormattedFields<LogFields>>()
+                            .and_then(|fields| serde_json::from_str(&fields.fields).ok())
+                            .unwrap_or_else(Map::new);
+                        if !span.name().is_empty() {
+                            object.insert(String::from("name"), Value::from(span.name()));
+                        }
+
+                        Value::Object(object)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default();
+
+        let line = json!({
+            "timestamp": timestamp,
+            "level": metadata.level().as_str(),
+            "target": metadata.target(),
+            "fields": fields.0,
+            "spans": spans,
+        });
+
+        writeln!(writer, "{line}")
+    }
+}
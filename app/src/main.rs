@@ -1,21 +1,34 @@
-use std::io;
-
 use commands::ComtryaCommand;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use comtrya_lib::contexts::build_contexts;
 use comtrya_lib::contexts::Contexts;
 use comtrya_lib::manifests;
 
-use tracing::{error, Level};
+use std::fs::File;
+use std::process::ExitCode;
+use std::sync::Mutex;
+use tracing::{error, warn, Level};
 
 #[allow(unused_imports)]
-use tracing_subscriber::{fmt::writer::MakeWriterExt, layer::SubscriberExt, FmtSubscriber};
-
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::format::Format,
+    fmt::writer::{BoxMakeWriter, MakeWriterExt},
+    layer::SubscriberExt,
+    FmtSubscriber,
+};
+
+mod cancel;
 mod commands;
 mod config;
+mod logs;
+mod progress;
+
+use logs::{create_log_file, LogFields};
+use progress::Console;
 
-use config::{load_config, Config};
+use config::{load_config, machine_config, Config};
 #[derive(Parser, Debug)]
 #[command(version, about, name="comtrya", long_about = None)]
 struct GlobalArgs {
@@ -30,25 +43,106 @@ struct GlobalArgs {
     #[arg(short, action = clap::ArgAction::Count)]
     verbose: u8,
 
+    /// Output format for the results of a run; logs go to stderr unless text
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    pub output: OutputFormat,
+
+    /// Format of the logs, json prints one object per event for log shippers
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    pub log_format: LogFormat,
+
+    /// Have sudo ask for the password with the program in SUDO_ASKPASS, for unattended runs
+    #[arg(long, global = true)]
+    pub sudo_askpass: bool,
+
+    /// Only use downloads fetched before with `comtrya fetch`, for machines without internet
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// Keep downloads in this directory rather than comtrya's cache, to carry them elsewhere
+    #[arg(long, global = true)]
+    pub bundle: Option<std::path::PathBuf>,
+
+    /// Sets a variable, overriding Comtrya.yaml, e.g. `--var email=me@example.com`
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_variable, global = true)]
+    pub variables: Vec<(String, String)>,
+
+    /// Applies this profile, like `work`, leaving out manifests and actions of other profiles
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+fn parse_variable(variable: &str) -> Result<(String, String), String> {
+    match variable.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(String::from("expected NAME=VALUE")),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    Text,
+    Json,
+    Yaml,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum LogFormat {
+    Text,
+    Json,
+}
+
 #[derive(Debug, Subcommand)]
 enum Commands {
+    /// Create a starter manifest repository
+    Init(commands::Init),
+
     /// Apply manifests
     #[clap(aliases = &["do", "run"])]
     Apply(commands::Apply),
 
+    /// Plan manifests again whenever they change, or apply them with --interactive
+    Watch(commands::Watch),
+
     ///  List manifests status (ALPHA)
     Status(commands::Apply),
 
+    /// Download what the manifests need, to apply them later with --offline
+    Fetch(commands::Fetch),
+
+    /// Show, clean or garbage collect what's downloaded and cached
+    Cache(commands::Cache),
+
+    /// Check manifests for errors without applying them
+    Validate(commands::Validate),
+
+    /// Apply manifests in throwaway containers of other distributions
+    Test(commands::Test),
+
+    /// Print the manifest dependency graph in DOT or Mermaid format
+    Graph(commands::DependencyGraph),
+
+    /// Package a manifest directory and push it to an OCI registry
+    Push(commands::Push),
+
+    /// Pull a manifest bundle from an OCI registry
+    Pull(commands::Pull),
+
+    /// Revert the changes of a previous apply
+    Rollback(commands::Rollback),
+
     /// Print version information
     Version(commands::Version),
 
     /// List available contexts
     Contexts(commands::Contexts),
 
+    /// Print the JSON Schema of manifests, for editor completion and validation
+    Schema(commands::Schema),
+
     /// Auto generate completions
     ///
     /// for examples:
@@ -62,72 +156,148 @@ enum Commands {
 pub struct Runtime {
     pub(crate) args: GlobalArgs,
     pub(crate) config: Config,
+    /// The config as written, for remote hosts to apply their own `machines` to
+    pub(crate) remote_config: Config,
     pub(crate) contexts: Contexts,
 }
 
-pub(crate) fn execute(runtime: Runtime) -> anyhow::Result<()> {
-    match &runtime.args.command {
-        Commands::Apply(apply) => apply.execute(&runtime),
+/// Runs the command, with the code to exit with when it succeeds
+pub(crate) fn execute(runtime: Runtime) -> anyhow::Result<ExitCode> {
+    let result = match &runtime.args.command {
+        Commands::Init(init) => init.execute(&runtime),
+        Commands::Apply(apply) => return apply.run(&runtime),
         Commands::Status(apply) => apply.status(&runtime),
+        Commands::Watch(watch) => watch.execute(&runtime),
+        Commands::Fetch(fetch) => fetch.execute(&runtime),
+        Commands::Cache(cache) => cache.execute(&runtime),
+        Commands::Validate(validate) => validate.execute(&runtime),
+        Commands::Test(test) => test.execute(&runtime),
+        Commands::Graph(graph) => graph.execute(&runtime),
+        Commands::Push(push) => push.execute(&runtime),
+        Commands::Pull(pull) => pull.execute(&runtime),
+        Commands::Rollback(rollback) => rollback.execute(&runtime),
         Commands::Version(version) => version.execute(&runtime),
         Commands::Contexts(contexts) => contexts.execute(&runtime),
+        Commands::Schema(schema) => schema.execute(&runtime),
         Commands::GenCompletions(gen_completions) => gen_completions.execute(&runtime),
-    }
+    };
+
+    result.map(|_| ExitCode::SUCCESS)
 }
 
-fn configure_tracing(args: &GlobalArgs) {
-    let stdout_writer = match args.verbose {
-        0 => io::stdout.with_max_level(tracing::Level::INFO),
-        1 => io::stdout.with_max_level(tracing::Level::DEBUG),
-        _ => io::stdout.with_max_level(tracing::Level::TRACE),
+type ConsoleSubscriber = FmtSubscriber<LogFields, logs::LogFormat, LevelFilter, BoxMakeWriter>;
+
+fn console_subscriber(args: &GlobalArgs) -> ConsoleSubscriber {
+    let max_level = match args.verbose {
+        0 => tracing::Level::INFO,
+        1 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+
+    // Keep stdout clean for machine readable output
+    let stdout_writer = match args.output {
+        OutputFormat::Text => BoxMakeWriter::new(Console::Stdout.with_max_level(max_level)),
+        _ => BoxMakeWriter::new(Console::Stderr.with_max_level(max_level)),
     };
 
-    let builder = FmtSubscriber::builder()
+    let json = args.log_format == LogFormat::Json;
+    let text = Format::default().with_target(false).without_time();
+
+    FmtSubscriber::builder()
         .with_max_level(Level::TRACE)
-        .with_ansi(!args.no_color)
-        .with_target(false)
+        .with_ansi(!args.no_color && !json)
         .with_writer(stdout_writer)
-        .without_time();
+        .fmt_fields(LogFields::new(json))
+        .event_format(logs::LogFormat::new(json, text))
+        .finish()
+}
+
+fn configure_tracing(args: &GlobalArgs, log_file: Option<File>) {
+    // Everything comtrya logs, whatever the console shows
+    let file_layer = log_file.map(|file| {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(
+                Mutex::new(file).with_filter(|metadata| metadata.target().starts_with("comtrya")),
+            )
+    });
+
+    let subscriber = console_subscriber(args).with(file_layer);
 
     #[cfg(target_os = "linux")]
     if let Ok(layer) = tracing_journald::layer() {
-        tracing::subscriber::set_global_default(builder.finish().with(layer))
+        tracing::subscriber::set_global_default(subscriber.with(layer))
             .expect("Unable to set a global subscriber");
         return;
     }
 
-    tracing::subscriber::set_global_default(builder.finish())
-        .expect("Unable to set a global subscriber");
+    tracing::subscriber::set_global_default(subscriber).expect("Unable to set a global subscriber");
 }
 
-fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<ExitCode> {
+    // Started through sudo (or doas, ...) to run privileged commands, see set_elevated_helper
+    if std::env::args().nth(1).as_deref() == Some(comtrya_lib::atoms::command::ELEVATED_HELPER) {
+        return comtrya_lib::atoms::command::serve_elevated().map(|_| ExitCode::SUCCESS);
+    }
+
     let args = GlobalArgs::parse();
-    configure_tracing(&args);
-
-    let config = match load_config(&args) {
-        Ok(config) => config,
-        Err(error) => {
-            error!("{}", error.to_string());
-            panic!();
-        }
+
+    // The config says where to write logs, so loading it only logs to the console
+    let (config, remote_config) =
+        tracing::subscriber::with_default(console_subscriber(&args), || match load_config(&args) {
+            Ok(config) => (machine_config(&config, &args), config),
+            Err(error) => {
+                error!("{}", error.to_string());
+                panic!();
+            }
+        });
+
+    let (log_file, log_error) = match config.logs.as_ref().map(create_log_file).transpose() {
+        Ok(log_file) => (log_file, None),
+        Err(error) => (None, Some(error)),
     };
 
-    if !config.disable_update_check {
+    configure_tracing(&args, log_file);
+
+    if let Some(error) = log_error {
+        warn!("Unable to write logs to a file: {}", error);
+    }
+
+    // Remote manifests are fetched by most commands, not only apply
+    comtrya_lib::atoms::http::set_proxy(config.proxy.clone());
+    comtrya_lib::atoms::http::set_offline(args.offline);
+    comtrya_lib::cache::set_dir(args.bundle.clone());
+    comtrya_lib::atoms::command::set_privilege(config.privilege);
+    comtrya_lib::atoms::command::set_privilege_policy(config.privilege_policy);
+    comtrya_lib::atoms::command::set_sudo_askpass(args.sudo_askpass, config.sudo_password.clone());
+    comtrya_lib::atoms::command::set_elevated_helper(
+        (config.elevated_helper || cfg!(windows))
+            .then(|| std::env::current_exe().ok())
+            .flatten(),
+    );
+
+    // The update notice would corrupt machine readable output
+    if !config.disable_update_check
+        && !args.offline
+        && args.output == OutputFormat::Text
+        && args.log_format == LogFormat::Text
+    {
         check_for_updates(args.no_color);
     }
 
     // Run Context Providers
     let contexts = build_contexts(&config);
 
-    let runtime = Runtime {
+    let mut runtime = Runtime {
         args,
         config,
+        remote_config,
         contexts,
     };
 
-    execute(runtime)?;
+    commands::ask_variables(&mut runtime)?;
 
-    Ok(())
+    execute(runtime)
 }
 
 fn check_for_updates(no_color: bool) {
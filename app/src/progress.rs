@@ -0,0 +1,315 @@
+use colored::Colorize;
+use comtrya_lib::report::{ActionReport, Status};
+use crossterm::{cursor, queue, terminal};
+use std::io::{self, Stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+static PROGRESS: RwLock<Option<Arc<Progress>>> = RwLock::new(None);
+
+/// The live view of `apply --progress`: a bar for every running manifest with the
+/// step it's on, and counts of what changed and failed. Manifests are collapsed
+/// to a single line once done, and only warnings and errors are logged above.
+pub(crate) struct Progress {
+    state: Mutex<State>,
+    stopped: AtomicBool,
+}
+
+#[derive(Default)]
+struct State {
+    no_color: bool,
+    manifests: usize,
+    finished: usize,
+    changed: usize,
+    failed: usize,
+    running: Vec<Bar>,
+    /// Printed above the bars on the next draw
+    lines: Vec<String>,
+    /// Lines of the last draw, cleared before drawing again
+    drawn: u16,
+    tick: usize,
+}
+
+struct Bar {
+    name: String,
+    actions: usize,
+    done: usize,
+    step: Option<String>,
+    started: Instant,
+}
+
+/// Removes the view when dropped, after drawing it a last time
+pub(crate) struct ProgressGuard {
+    progress: Arc<Progress>,
+    renderer: Option<JoinHandle<()>>,
+}
+
+/// Shows the view until the guard is dropped, `manifests` is how many will run
+pub(crate) fn start(manifests: usize, no_color: bool) -> ProgressGuard {
+    let progress = Arc::new(Progress {
+        state: Mutex::new(State {
+            manifests,
+            no_color,
+            ..Default::default()
+        }),
+        stopped: AtomicBool::new(false),
+    });
+
+    *PROGRESS.write().unwrap() = Some(progress.clone());
+
+    let renderer = {
+        let progress = progress.clone();
+
+        std::thread::spawn(move || {
+            while !progress.stopped.load(Ordering::Relaxed) {
+                progress.draw(false);
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        })
+    };
+
+    ProgressGuard {
+        progress,
+        renderer: Some(renderer),
+    }
+}
+
+impl Drop for ProgressGuard {
+    fn drop(&mut self) {
+        self.progress.stopped.store(true, Ordering::Relaxed);
+
+        if let Some(renderer) = self.renderer.take() {
+            let _ = renderer.join();
+        }
+
+        *PROGRESS.write().unwrap() = None;
+        self.progress.draw(true);
+    }
+}
+
+fn with_state(update: impl FnOnce(&mut State)) {
+    if let Some(progress) = PROGRESS.read().unwrap().as_ref() {
+        update(&mut progress.state.lock().unwrap());
+    }
+}
+
+/// A manifest started, with this many actions
+pub(crate) fn manifest_started(name: &str, actions: usize) {
+    with_state(|state| {
+        state.running.push(Bar {
+            name: name.to_string(),
+            actions,
+            done: 0,
+            step: None,
+            started: Instant::now(),
+        })
+    });
+}
+
+/// An action of the manifest is done, counted with what it changed
+pub(crate) fn action_done(name: &str, action: &ActionReport) {
+    with_state(|state| {
+        let Some(bar) = state.running.iter_mut().find(|bar| bar.name == name) else {
+            return;
+        };
+
+        bar.done += 1;
+        bar.step = None;
+
+        match action.status {
+            Status::Applied | Status::Planned => state.changed += 1,
+            Status::Failed => state.failed += 1,
+            _ => (),
+        }
+    });
+}
+
+/// The manifest is running this step
+pub(crate) fn step(name: &str, step: &str) {
+    with_state(|state| {
+        if let Some(bar) = state.running.iter_mut().find(|bar| bar.name == name) {
+            bar.step = Some(step.to_string());
+        }
+    });
+}
+
+/// The manifest is done, its bar is replaced by a line with the outcome
+pub(crate) fn manifest_done(name: &str, status: Status) {
+    with_state(|state| {
+        let elapsed = state
+            .running
+            .iter()
+            .position(|bar| bar.name == name)
+            .map(|position| state.running.remove(position).started.elapsed());
+
+        state.finished += 1;
+
+        let (mark, color) = match status {
+            Status::Failed => ("✘", colored::Color::Red),
+            Status::Unreachable | Status::Skipped => ("-", colored::Color::Yellow),
+            _ => ("✔", colored::Color::Green),
+        };
+
+        let mut line = format!("{mark} {name} {status}");
+        if let Some(elapsed) = elapsed {
+            line.push_str(&format!(" ({:.1}s)", elapsed.as_secs_f64()));
+        }
+
+        let line = if state.no_color {
+            line
+        } else {
+            line.color(color).to_string()
+        };
+        state.lines.push(line);
+    });
+}
+
+impl Progress {
+    /// Draws the bars below any new lines, `last` leaves only the lines and summary
+    fn draw(&self, last: bool) {
+        let mut state = self.state.lock().unwrap();
+        let mut stdout = io::stdout().lock();
+        let width = match terminal::size() {
+            Ok((width, _)) if width > 0 => width as usize,
+            _ => 80,
+        };
+
+        if state.drawn > 0 {
+            let _ = queue!(
+                stdout,
+                cursor::MoveToPreviousLine(state.drawn),
+                terminal::Clear(terminal::ClearType::FromCursorDown)
+            );
+        }
+
+        for line in state.lines.drain(..) {
+            let _ = writeln!(stdout, "{line}");
+        }
+
+        state.tick += 1;
+        let spinner = SPINNER[state.tick % SPINNER.len()];
+        let mut drawn = 0;
+
+        for bar in state.running.iter().filter(|_| !last) {
+            let filled = (bar.done * 20)
+                .checked_div(bar.actions)
+                .unwrap_or(0)
+                .min(20);
+            let line = format!(
+                "{spinner} {} [{}{}] {}/{} {}s {}",
+                bar.name,
+                "#".repeat(filled),
+                "-".repeat(20 - filled),
+                bar.done,
+                bar.actions,
+                bar.started.elapsed().as_secs(),
+                bar.step.as_deref().unwrap_or_default()
+            );
+
+            let _ = writeln!(stdout, "{}", truncate(&line, width));
+            drawn += 1;
+        }
+
+        let summary = format!(
+            "{}/{} manifests, {} changed, {} failed",
+            state.finished, state.manifests, state.changed, state.failed
+        );
+        let summary = if state.no_color {
+            summary
+        } else {
+            summary.bold().to_string()
+        };
+        let _ = writeln!(stdout, "{summary}");
+        drawn += 1;
+
+        state.drawn = if last { 0 } else { drawn };
+        let _ = stdout.flush();
+    }
+}
+
+/// Keeps lines from wrapping, which would break clearing them
+fn truncate(line: &str, width: usize) -> String {
+    let width = width.saturating_sub(1).max(1);
+
+    match line.char_indices().nth(width) {
+        Some((index, _)) => format!("{}…", &line[..index]),
+        None => line.to_string(),
+    }
+}
+
+/// Writes console logs to stdout or stderr, or above the view while it's shown
+#[derive(Clone, Copy)]
+pub(crate) enum Console {
+    Stdout,
+    Stderr,
+}
+
+pub(crate) enum ConsoleWriter {
+    Stdout(Stdout),
+    Stderr(io::Stderr),
+    Progress(Vec<u8>),
+    Discard,
+}
+
+impl<'a> MakeWriter<'a> for Console {
+    type Writer = ConsoleWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        match self {
+            Console::Stdout => ConsoleWriter::Stdout(io::stdout()),
+            Console::Stderr => ConsoleWriter::Stderr(io::stderr()),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        if PROGRESS.read().unwrap().is_none() {
+            return self.make_writer();
+        }
+
+        // The view shows what's going on, so only problems are logged
+        if *meta.level() <= Level::WARN {
+            ConsoleWriter::Progress(vec![])
+        } else {
+            ConsoleWriter::Discard
+        }
+    }
+}
+
+impl Write for ConsoleWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ConsoleWriter::Stdout(stdout) => stdout.write(buf),
+            ConsoleWriter::Stderr(stderr) => stderr.write(buf),
+            ConsoleWriter::Progress(buffer) => buffer.write(buf),
+            ConsoleWriter::Discard => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ConsoleWriter::Stdout(stdout) => stdout.flush(),
+            ConsoleWriter::Stderr(stderr) => stderr.flush(),
+            ConsoleWriter::Progress(_) | ConsoleWriter::Discard => Ok(()),
+        }
+    }
+}
+
+impl Drop for ConsoleWriter {
+    fn drop(&mut self) {
+        if let ConsoleWriter::Progress(buffer) = self {
+            let text = String::from_utf8_lossy(buffer).to_string();
+
+            with_state(|state| {
+                state
+                    .lines
+                    .extend(text.lines().map(|line| line.to_string()))
+            });
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use predicates::prelude::*;
 use tempfile::TempDir;
 use utils::*;
 
@@ -60,3 +61,1281 @@ actions:
 
     assert.success();
 }
+
+#[test]
+fn dry_run_prints_json_plan() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "echo.yaml",
+            r#"
+actions:
+  - action: command.run
+    command: echo
+    args:
+      - hello
+"#,
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path)
+        .run("--no-color -d ./manifests apply --dry-run --output json")
+        .success()
+        .stdout(predicates::str::starts_with("{"))
+        .stdout(predicates::str::contains(r#""name": "echo""#))
+        .stdout(predicates::str::contains(r#""status": "planned""#));
+}
+
+#[test]
+fn failures_are_summarized() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![
+            f(
+                "broken.yaml",
+                r#"
+actions:
+  - action: command.run
+    shell: none
+    script: echo hello
+  - action: command.run
+    command: echo
+"#,
+            ),
+            f(
+                "cosmetic.yaml",
+                r#"
+actions:
+  - action: command.run
+    shell: none
+    script: echo hello
+    ignore_errors: true
+"#,
+            ),
+            f(
+                "dependant.yaml",
+                r#"
+depends:
+  - broken
+actions:
+  - action: command.run
+    command: echo
+"#,
+            ),
+        ],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply --keep-going")
+        .code(1)
+        .stdout(predicates::str::contains("Failed:"))
+        .stdout(predicates::str::contains("broken: Running script"))
+        .stdout(predicates::str::contains(
+            "cosmetic: Running script (ignored)",
+        ))
+        .stdout(predicates::str::contains("Skipped due to failure:"))
+        .stdout(predicates::str::contains(
+            "dependant (dependency 'broken' failed)",
+        ));
+
+    // Dry-runs plan everything regardless
+    cd(path)
+        .run("--no-color -d ./manifests apply --dry-run")
+        .code(1)
+        .stdout(predicates::str::contains("broken: Running script"))
+        .stdout(predicates::str::contains("Skipped due to failure:").not());
+}
+
+#[test]
+fn prompted_variables_come_from_args_environment_or_defaults() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "git.yaml",
+            r#"
+prompts:
+  email:
+    message: Email to commit with
+  role:
+    choices: [work, personal]
+    default: personal
+
+actions:
+  - action: command.run
+    command: touch
+    args: ["{{ variables.email }}-{{ variables.role }}"]
+"#,
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    // Without a terminal, only the defaults can be used
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply")
+        .failure()
+        .stderr(predicates::str::contains("Set it with --var email=<value>"));
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply --var email=me")
+        .success();
+    assert!(path.join("me-personal").exists());
+
+    let output = assert_cmd::Command::cargo_bin("comtrya")
+        .unwrap()
+        .current_dir(&path)
+        .env("COMTRYA_VAR_EMAIL", "you")
+        .args([
+            "--no-color",
+            "-d",
+            "./manifests",
+            "apply",
+            "--var",
+            "role=work",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(path.join("you-work").exists());
+}
+
+#[test]
+fn profiles_select_manifests_and_actions() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![
+            f(
+                "work.yaml",
+                "profiles: [work]
+actions:
+  - action: command.run
+    command: touch
+    args: [work]
+",
+            ),
+            f(
+                "common.yaml",
+                r#"
+actions:
+  - action: command.run
+    command: touch
+    args: [common]
+  - action: command.run
+    command: touch
+    args: [slack]
+    profiles: [work]
+  - action: command.run
+    command: touch
+    args: ["{{ profile.name }}-only"]
+    where: profile.name == "personal"
+"#,
+            ),
+        ],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply")
+        .success();
+    assert!(path.join("common").exists());
+    assert!(!path.join("work").exists());
+    assert!(!path.join("slack").exists());
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply --profile work")
+        .success();
+    assert!(path.join("work").exists());
+    assert!(path.join("slack").exists());
+    assert!(!path.join("personal-only").exists());
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests --profile personal apply")
+        .success();
+    assert!(path.join("personal-only").exists());
+}
+
+#[test]
+fn machines_pick_manifests_and_variables_by_hostname() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    let touch = |file: &str| {
+        format!("actions:\n  - action: command.run\n    command: touch\n    args: [\"{file}\"]\n")
+    };
+    dir(
+        "manifests",
+        vec![
+            f("base.yaml", touch("base-{{ variables.role }}")),
+            f("desktop.yaml", touch("desktop")),
+            f("server.yaml", touch("server")),
+        ],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+    std::fs::write(
+        path.join("Comtrya.yaml"),
+        r#"
+disable_update_check: true
+variables:
+  role: none
+machines:
+  - hostname: ".*"
+    manifests: [base, desktop]
+    variables:
+      role: desktop
+  - hostname: "never-[0-9]{64}"
+    manifests: [server]
+    variables:
+      role: server
+"#,
+    )
+    .unwrap();
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply")
+        .success();
+    assert!(path.join("base-desktop").exists());
+    assert!(path.join("desktop").exists());
+    assert!(!path.join("server").exists());
+
+    // The command line wins over the machine
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply -m server --var role=cli")
+        .success();
+    assert!(path.join("server").exists());
+    assert!(!path.join("base-cli").exists());
+}
+
+#[test]
+fn concurrent_applies_are_locked_out() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "greet.yaml",
+            "actions:\n  - action: command.run\n    command: echo\n    args: [hello]\n",
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+    std::fs::write(
+        path.join("Comtrya.yaml"),
+        "disable_update_check: true\nstate_file: ./state.json\n",
+    )
+    .unwrap();
+
+    // Another run, applying right now
+    let lock = std::fs::File::create(path.join("state.lock")).unwrap();
+    lock.try_lock().unwrap();
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply")
+        .failure()
+        .stderr(predicates::str::contains("Another run holds the lock"));
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply --dry-run")
+        .success();
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply --force-unlock")
+        .success();
+    assert!(path.join("state.json").is_file());
+}
+
+#[cfg(unix)]
+#[test]
+fn cancelled_applies_can_be_resumed() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    let touch = |name: &str| {
+        format!(
+            "  - action: command.run\n    command: touch\n    args: [{}]\n",
+            path.join(name).display()
+        )
+    };
+    dir(
+        "manifests",
+        vec![
+            f(
+                "one.yaml",
+                format!(
+                    "actions:\n{}  - action: command.run\n    command: sh\n    args: [-c, 'touch {} && sleep 2']\n{}",
+                    touch("first"),
+                    path.join("started").display(),
+                    touch("third")
+                ),
+            ),
+            f(
+                "two.yaml",
+                format!("depends: [one]\nactions:\n{}", touch("two")),
+            ),
+        ],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+    std::fs::write(
+        path.join("Comtrya.yaml"),
+        "disable_update_check: true\nstate_file: ./state.json\n",
+    )
+    .unwrap();
+
+    let apply = std::process::Command::new(assert_cmd::cargo::cargo_bin("comtrya"))
+        .current_dir(&path)
+        .args(["--no-color", "-d", "./manifests", "apply"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    while !path.join("started").exists() {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    // Ctrl-C while the second action of `one` runs
+    std::process::Command::new("kill")
+        .args(["-INT", &apply.id().to_string()])
+        .status()
+        .unwrap();
+
+    let output = apply.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("comtrya apply --resume"));
+    assert!(path.join("first").exists());
+    assert!(!path.join("third").exists());
+    assert!(!path.join("two").exists());
+    assert!(path.join("state.checkpoint").exists());
+
+    // What was done isn't done again
+    std::fs::remove_file(path.join("first")).unwrap();
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply --resume")
+        .success();
+    assert!(!path.join("first").exists());
+    assert!(path.join("third").exists());
+    assert!(path.join("two").exists());
+    assert!(!path.join("state.checkpoint").exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn commands_can_prompt_on_the_terminal() {
+    use std::io::Write;
+
+    // Runs comtrya with a terminal, like it is when started from a shell
+    if std::process::Command::new("script")
+        .arg("--version")
+        .output()
+        .is_err()
+    {
+        return;
+    }
+
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "ask.yaml",
+            format!(
+                "actions:\n  - action: command.run\n    command: sh\n    args: [-c, 'read answer < /dev/tty && echo $answer > {}']\n",
+                path.join("answer").display()
+            ),
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+    std::fs::write(path.join("Comtrya.yaml"), "disable_update_check: true\n").unwrap();
+
+    let mut apply = std::process::Command::new("script")
+        .current_dir(&path)
+        .args([
+            "--quiet",
+            "--return",
+            "--command",
+            &format!(
+                "{} --no-color -d ./manifests apply",
+                assert_cmd::cargo::cargo_bin("comtrya").display()
+            ),
+            "/dev/null",
+        ])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Typed into the terminal, kept open until comtrya is done
+    let mut typed = apply.stdin.take().unwrap();
+    typed.write_all(b"yes\n").unwrap();
+
+    // A command that can't read the terminal is stopped, and never finishes
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(60);
+    let status = loop {
+        if let Some(status) = apply.try_wait().unwrap() {
+            break Some(status);
+        }
+        if std::time::Instant::now() >= deadline {
+            let _ = apply.kill();
+            break None;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    };
+    drop(typed);
+
+    assert!(status.is_some_and(|status| status.success()));
+    assert_eq!(
+        "yes\n",
+        std::fs::read_to_string(path.join("answer")).unwrap()
+    );
+}
+
+#[test]
+fn detailed_exitcode_reports_changes() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "greet.yaml",
+            "actions:\n  - action: command.run\n    command: echo\n    args: [hello]\n",
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply --check")
+        .code(2);
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply --detailed-exitcode --tags none")
+        .code(0);
+
+    cd(path).run("--no-color -d ./manifests apply").code(0);
+}
+
+#[test]
+fn actions_can_run_when_others_changed() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "theme.yaml",
+            r#"
+actions:
+  - action: command.run
+    command: echo
+    args: [theme]
+    id: theme
+  - action: command.run
+    command: echo
+    args: [rebuild]
+    when_changed: theme
+  - action: command.run
+    command: echo
+    args: [never]
+    when_changed: other
+    id: other
+"#,
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    let assert = cd(path)
+        .run("--no-color -d ./manifests --output json apply --dry-run")
+        .success();
+    let report: serde_json::Value = serde_json::from_slice(&assert.get_output().stdout).unwrap();
+
+    let statuses: Vec<&str> = report["manifests"][0]["actions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|action| action["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(vec!["planned", "planned", "skipped"], statuses);
+}
+
+#[test]
+fn on_failure_and_always_blocks_run() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![
+            f(
+                "broken.yaml",
+                r#"
+actions:
+  - action: command.run
+    shell: none
+    script: echo hello
+on_failure:
+  - action: command.run
+    command: rescue
+always:
+  - action: command.run
+    command: cleanup
+"#,
+            ),
+            f(
+                "working.yaml",
+                r#"
+actions:
+  - action: command.run
+    command: echo
+on_failure:
+  - action: command.run
+    command: unused
+always:
+  - action: command.run
+    command: finally
+"#,
+            ),
+        ],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path)
+        .run("--no-color -d ./manifests apply --dry-run --keep-going --output json")
+        .code(1)
+        .stdout(predicates::str::contains("Running rescue command"))
+        .stdout(predicates::str::contains("Running cleanup command"))
+        .stdout(predicates::str::contains("Running finally command"))
+        .stdout(predicates::str::contains("Running unused command").not());
+}
+
+#[test]
+fn before_and_after_hooks_only_run_with_changes() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![
+            f(
+                "changed.yaml",
+                r#"
+before:
+  - action: command.run
+    command: stop
+actions:
+  - action: command.run
+    command: echo
+after:
+  - action: command.run
+    command: restart
+"#,
+            ),
+            f(
+                "unchanged.yaml",
+                r#"
+before:
+  - action: command.run
+    command: unused-before
+actions:
+  - action: command.run
+    command: echo
+    where: "false"
+after:
+  - action: command.run
+    command: unused-after
+"#,
+            ),
+        ],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path)
+        .run("--no-color -d ./manifests apply --dry-run --output json")
+        .success()
+        .stdout(predicates::str::contains("Running stop command"))
+        .stdout(predicates::str::contains("Running restart command"))
+        .stdout(predicates::str::contains("unused-before").not())
+        .stdout(predicates::str::contains("unused-after").not());
+}
+
+#[test]
+fn interactive_apply_asks_before_each_step() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "touch.yaml",
+            r#"
+actions:
+  - action: command.run
+    command: touch
+    args: [one]
+  - action: command.run
+    command: touch
+    args: [two]
+  - action: command.run
+    command: touch
+    args: [three]
+"#,
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path.clone())
+        .stdin("?\ny\nn\nq\n")
+        .run("--no-color -d ./manifests apply --interactive")
+        .success()
+        .stderr(predicates::str::contains("Apply this step [y,n,a,q,?]?"))
+        .stderr(predicates::str::contains(
+            "a - apply this step and all remaining steps",
+        ));
+
+    assert!(path.join("one").exists());
+    assert!(!path.join("two").exists());
+    assert!(!path.join("three").exists());
+
+    std::fs::remove_file(path.join("one")).unwrap();
+
+    cd(path.clone())
+        .stdin("n\na\n")
+        .run("--no-color -d ./manifests apply --interactive")
+        .success();
+
+    assert!(!path.join("one").exists());
+    assert!(path.join("two").exists());
+    assert!(path.join("three").exists());
+}
+
+#[test]
+fn watch_plans_again_on_changes() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "one.yaml",
+            "actions:\n  - action: command.run\n    command: touch\n    args: [one]\n",
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("comtrya"))
+        .current_dir(&path)
+        .args([
+            "--no-color",
+            "-d",
+            "./manifests",
+            "watch",
+            "--interval",
+            "100",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        use std::io::BufRead;
+
+        for line in std::io::BufReader::new(stdout)
+            .lines()
+            .map_while(Result::ok)
+        {
+            let _ = sender.send(line);
+        }
+    });
+
+    // Collects the output until it has what's expected, with a deadline that's
+    // generous for slow machines
+    let mut stdout = String::new();
+    let mut wait_for = |expected: &dyn Fn(&str) -> bool| {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(60);
+
+        while !expected(&stdout) {
+            let left = deadline.saturating_duration_since(std::time::Instant::now());
+            match receiver.recv_timeout(left) {
+                Ok(line) => stdout.push_str(&format!("{line}\n")),
+                Err(_) => break,
+            }
+        }
+
+        expected(&stdout)
+    };
+
+    let watching = wait_for(&|stdout| stdout.contains("Watching"));
+
+    std::fs::write(
+        path.join("manifests/two.yaml"),
+        "actions:\n  - action: command.run\n    command: touch\n    args: [two]\n",
+    )
+    .unwrap();
+
+    let planned = wait_for(&|stdout| {
+        stdout.matches("Watching").count() == 2 && stdout.contains(r#"manifest="two""#)
+    });
+
+    let _ = child.kill();
+    let _ = child.wait();
+
+    assert!(watching, "{}", stdout);
+    assert!(planned, "{}", stdout);
+
+    // Changes are only planned
+    assert!(!path.join("one").exists());
+    assert!(!path.join("two").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn applies_on_remote_hosts_over_ssh() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "touch.yaml",
+            "actions:\n  - action: command.run\n    command: touch\n    args: [remote]\n",
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    // Runs the command locally, with the temporary directory as home of the "host"
+    let bin = path.join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    std::fs::write(
+        bin.join("ssh"),
+        "#!/bin/sh\nfor arg; do command=\"$arg\"; done\nexec sh -c \"$command\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(bin.join("ssh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = assert_cmd::Command::cargo_bin("comtrya")
+        .unwrap()
+        .current_dir(&path)
+        .env("HOME", &path)
+        .env("PATH", format!("{}:/usr/bin:/bin", bin.display()))
+        .args([
+            "--no-color",
+            "-d",
+            "./manifests",
+            "apply",
+            "--host",
+            "me@server",
+        ])
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("Copying comtrya to me@server"));
+
+    let remote = path.join(".cache/comtrya/remote");
+    assert!(remote.join("Comtrya.yaml").is_file());
+    assert!(remote.join("manifests/touch.yaml").is_file());
+    assert!(remote.join("remote").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn tests_manifests_in_containers_of_each_image() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "touch.yaml",
+            format!(
+                "actions:\n  - action: command.run\n    command: touch\n    args: [{}]\n",
+                path.join("applied").display()
+            ),
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    // Mounts volumes by linking them into a directory of its own, and fails for
+    // the image named broken
+    let bin = path.join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    std::fs::write(
+        bin.join("docker"),
+        r#"#!/bin/sh
+root="$PWD/container-$$"
+while [ $# -gt 0 ]; do
+  case "$1" in
+    run|--rm) ;;
+    --volume) shift; dst="${1#*:}"; dst="${dst%%:*}"; mkdir -p "$root$(dirname "$dst")"; ln -s "${1%%:*}" "$root$dst" ;;
+    --workdir) shift; workdir="$1" ;;
+    --entrypoint) shift; entrypoint="$1" ;;
+    *) image="$1"; shift; break ;;
+  esac
+  shift
+done
+[ "$image" = broken ] && exit 1
+cd "$root$workdir" && exec "$root$entrypoint" "$@"
+"#,
+    )
+    .unwrap();
+    std::fs::set_permissions(bin.join("docker"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = assert_cmd::Command::cargo_bin("comtrya")
+        .unwrap()
+        .current_dir(&path)
+        .env("PATH", format!("{}:/usr/bin:/bin", bin.display()))
+        .args([
+            "--no-color",
+            "-d",
+            "./manifests",
+            "test",
+            "--engine",
+            "docker",
+            "--image",
+            "ubuntu,broken",
+        ])
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "{stdout}");
+    assert!(stdout.contains("[ubuntu]"), "{stdout}");
+    assert!(stdout.contains("| ubuntu | passed"), "{stdout}");
+    assert!(stdout.contains("| broken | failed"), "{stdout}");
+    assert!(path.join("applied").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn applies_on_inventory_hosts_with_their_variables() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "touch.yaml",
+            "actions:\n  - action: command.run\n    command: touch\n    args: [\"{{ variables.name }}-{{ variables.tier }}\"]\n",
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+    std::fs::write(
+        path.join("inventory.yaml"),
+        r#"
+hosts:
+  web1:
+    variables:
+      name: one
+  web2:
+    address: me@web2
+    variables:
+      name: two
+  db: {}
+groups:
+  web:
+    hosts: [web1, web2]
+    variables:
+      name: web
+      tier: frontend
+"#,
+    )
+    .unwrap();
+
+    // Runs the command locally, with a home directory per host
+    let bin = path.join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    std::fs::write(
+        bin.join("ssh"),
+        "#!/bin/sh\nfor arg; do host=\"$command\"; command=\"$arg\"; done\nexport HOME=\"$HOME/$host\"\nmkdir -p \"$HOME\"\nexec sh -c \"$command\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(bin.join("ssh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = assert_cmd::Command::cargo_bin("comtrya")
+        .unwrap()
+        .current_dir(&path)
+        .env("HOME", &path)
+        .env("PATH", format!("{}:/usr/bin:/bin", bin.display()))
+        .args([
+            "--no-color",
+            "-d",
+            "./manifests",
+            "apply",
+            "--inventory",
+            "inventory.yaml",
+            "--host",
+            "web",
+        ])
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("[web1]"));
+    assert!(stdout.contains("| web2"));
+
+    let remote = |host: &str| path.join(host).join(".cache/comtrya/remote");
+    assert!(remote("web1").join("one-frontend").exists());
+    assert!(remote("me@web2").join("two-frontend").exists());
+    assert!(!path.join("db").exists());
+}
+
+#[test]
+fn apply_writes_reports() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "greet.yaml",
+            "actions:\n  - action: command.run\n    command: echo\n    args: [hello]\n",
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply --report report.json")
+        .success();
+
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path.join("report.json")).unwrap()).unwrap();
+    let step = &report["manifests"][0]["actions"][0]["steps"][0];
+    assert_eq!("applied", step["status"]);
+    assert_eq!("hello", step["output"]);
+    assert!(step["duration_ms"].is_u64());
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply --report report.html")
+        .success();
+
+    let html = std::fs::read_to_string(path.join("report.html")).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<h2>greet"));
+    assert!(html.contains("<pre>hello</pre>"));
+}
+
+#[test]
+fn logs_are_written_to_rotated_files() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "greet.yaml",
+            "actions:\n  - action: command.run\n    command: echo\n    args: [hello]\n",
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+    std::fs::write(
+        path.join("Comtrya.yaml"),
+        "disable_update_check: true\nlogs:\n  directory: ./logs\n  keep: 2\n",
+    )
+    .unwrap();
+
+    for _ in 0..3 {
+        cd(path.clone())
+            .run("--no-color -d ./manifests apply")
+            .success()
+            .stdout(predicates::str::contains("TRACE").not());
+    }
+
+    let logs: Vec<std::path::PathBuf> = std::fs::read_dir(path.join("logs"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(2, logs.len());
+
+    let log = std::fs::read_to_string(&logs[0]).unwrap();
+    assert!(log.contains("TRACE"));
+    assert!(log.contains("Running echo command"));
+    assert!(!log.contains("\u{1b}["));
+}
+
+#[test]
+fn logs_can_be_json() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "greet.yaml",
+            "actions:\n  - action: command.run\n    command: echo\n    args: [hello]\n",
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    let assert = cd(path)
+        .run("--log-format json -v -d ./manifests apply")
+        .success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).to_string();
+
+    let events: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("every line should be a JSON object"))
+        .collect();
+
+    let action = events
+        .iter()
+        .find(|event| event["fields"]["message"] == "Running echo command")
+        .expect("the action should be logged");
+    assert_eq!("INFO", action["level"]);
+    assert_eq!("applied", action["fields"]["status"]);
+    assert_eq!("greet", action["spans"][0]["manifest"]);
+    assert_eq!("command.run", action["spans"][1]["action"]);
+
+    assert!(events
+        .iter()
+        .any(|event| event["fields"]["message"] == "Step applied"
+            && event["fields"]["step"].is_string()));
+}
+
+#[test]
+fn progress_falls_back_to_logs_without_a_terminal() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![f(
+            "greet.yaml",
+            "actions:\n  - action: command.run\n    command: echo\n    args: [hello]\n",
+        )],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests apply --progress")
+        .success()
+        .stdout(predicates::str::contains("Running echo command"))
+        .stdout(predicates::str::contains("manifests,").not());
+
+    cd(path)
+        .run("--no-color -d ./manifests apply --progress --interactive")
+        .failure();
+}
+
+#[test]
+fn manifests_can_run_in_parallel() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    let echo = |word: &str| {
+        format!("actions:\n  - action: command.run\n    command: echo\n    args:\n      - {word}\n")
+    };
+    dir(
+        "manifests",
+        vec![
+            f("one.yaml", echo("one")),
+            f("two.yaml", echo("two")),
+            f(
+                "three.yaml",
+                format!("depends:\n  - one\n  - two\n{}", echo("three")),
+            ),
+        ],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path)
+        .run("--no-color -d ./manifests apply --dry-run --jobs 2 --output json")
+        .success()
+        .stdout(predicates::str::contains(r#""name": "one""#))
+        .stdout(predicates::str::contains(r#""name": "two""#))
+        .stdout(predicates::str::contains(r#""name": "three""#))
+        .stdout(predicates::str::contains(r#""status": "failed""#).not());
+}
+
+#[test]
+fn prints_the_dependency_graph() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![
+            f("base.yaml", "actions: []\n"),
+            f("apps.yaml", "depends:\n  - base\nactions: []\n"),
+            f("a.yaml", "depends:\n  - b\nactions: []\n"),
+            f("b.yaml", "depends:\n  - a\nactions: []\n"),
+        ],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests graph")
+        .success()
+        .stdout(predicates::str::starts_with("digraph manifests {"))
+        .stdout(predicates::str::contains(r#""apps" -> "base";"#))
+        .stdout(predicates::str::contains("[color=red]"))
+        .stdout(predicates::str::contains("Dependency cycle, closed by"));
+
+    cd(path.clone())
+        .run("--no-color -d ./manifests graph --format mermaid")
+        .success()
+        .stdout(predicates::str::starts_with("graph TD"))
+        .stdout(predicates::str::contains(r#"["apps"]"#));
+
+    cd(path)
+        .run("--no-color -d ./manifests apply --dry-run")
+        .failure();
+}
+
+#[test]
+fn validate_reports_problems() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "valid",
+        vec![
+            dir("files", vec![f("vimrc", "set number\n")]),
+            f("base.yaml", "actions: []\n"),
+            f(
+                "vim.yaml",
+                r#"
+depends:
+  - base
+where: os.name == "linux" || true
+actions:
+  - action: file.copy
+    from: vimrc
+    to: /tmp/vimrc
+"#,
+            ),
+        ],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+    dir(
+        "invalid",
+        vec![
+            f("typo.yaml", "actions:\n  - action: command.run\n    comand: echo\n"),
+            f("missing.yaml", "depends:\n  - nothing\nactions: []\n"),
+            f(
+                "broken.yaml",
+                "actions:\n  - action: file.copy\n    from: nowhere\n    to: /tmp/nowhere\n    where: os.name ==\n",
+            ),
+        ],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path.clone())
+        .run("--no-color -d ./valid validate")
+        .success()
+        .stdout(predicates::str::contains("2 manifests are valid"));
+
+    cd(path)
+        .run("--no-color -d ./invalid validate")
+        .failure()
+        .stdout(predicates::str::contains("unknown field `comand`"))
+        .stdout(predicates::str::contains("at line 2 column 3"))
+        .stdout(predicates::str::contains(
+            "depends on 'nothing', which doesn't exist",
+        ))
+        .stdout(predicates::str::contains("invalid where condition"))
+        .stdout(predicates::str::contains("nowhere, which doesn't exist"));
+}
+
+#[test]
+fn prints_the_manifest_schema() {
+    run("schema")
+        .success()
+        .stdout(predicates::str::contains(r#""title": "Manifest""#))
+        .stdout(predicates::str::contains(r#""command.run""#))
+        .stdout(predicates::str::contains(r#""variants""#));
+
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+
+    cd(path.clone())
+        .run("schema --file comtrya.schema.json")
+        .success()
+        .stdout(predicates::str::is_empty());
+
+    let schema = std::fs::read_to_string(path.join("comtrya.schema.json")).unwrap();
+    assert!(schema.contains(r#""file.copy""#));
+}
+
+#[test]
+fn init_creates_valid_manifests() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+
+    cd(path.clone()).run("init dotfiles").success();
+
+    assert!(path.join("dotfiles/Comtrya.yaml").is_file());
+    assert!(path.join("dotfiles/manifests/files").is_dir());
+
+    cd(path.join("dotfiles"))
+        .run("--no-color validate")
+        .success()
+        .stdout(predicates::str::contains("4 manifests are valid"));
+
+    // Existing files are left alone
+    std::fs::write(path.join("dotfiles/manifests/common.yaml"), "actions: []\n").unwrap();
+    cd(path.clone()).run("init dotfiles").success();
+    assert_eq!(
+        "actions: []\n",
+        std::fs::read_to_string(path.join("dotfiles/manifests/common.yaml")).unwrap()
+    );
+}
+
+#[test]
+fn manifests_can_be_selected_by_glob_and_label() {
+    let t = TempDir::new().expect("could not create tempdir");
+    let path = t.into_path();
+    dir(
+        "manifests",
+        vec![
+            f("base.yaml", "actions: []\n"),
+            dir(
+                "apps",
+                vec![
+                    f("git.yaml", "depends:\n  - base\nactions: []\n"),
+                    f("firefox.yaml", "labels:\n  - gui\nactions: []\n"),
+                ],
+            ),
+        ],
+    )
+    .create_in(&path)
+    .expect("should have create test directories");
+
+    cd(path.clone())
+        .run(
+            "--no-color -d ./manifests apply --dry-run -m apps/* --exclude label:gui --output json",
+        )
+        .success()
+        .stdout(predicates::str::contains(r#""name": "apps.git""#))
+        .stdout(predicates::str::contains(r#""name": "base""#))
+        .stdout(predicates::str::contains(r#""name": "apps.firefox""#).not());
+
+    cd(path)
+        .run("--no-color -d ./manifests apply --dry-run -m label:gui --output json")
+        .success()
+        .stdout(predicates::str::contains(r#""name": "apps.firefox""#))
+        .stdout(predicates::str::contains(r#""name": "base""#).not());
+}
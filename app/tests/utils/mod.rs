@@ -12,6 +12,7 @@ use std::path::PathBuf;
 pub(crate) struct Dir {
     cwd: PathBuf,
     env: String,
+    stdin: Option<String>,
 }
 
 impl Dir {
@@ -23,6 +24,10 @@ impl Dir {
         let args = cli.split(' ').collect::<Vec<_>>();
         comtrya.args(args);
 
+        if let Some(stdin) = self.stdin {
+            comtrya.write_stdin(stdin);
+        }
+
         comtrya.assert()
     }
 
@@ -31,6 +36,12 @@ impl Dir {
 
         self
     }
+
+    pub fn stdin<S: Into<String>>(mut self, stdin: S) -> Dir {
+        self.stdin = Some(stdin.into());
+
+        self
+    }
 }
 
 pub(crate) fn run(cli: &'static str) -> Assert {
@@ -45,6 +56,7 @@ pub(crate) fn cd(path: PathBuf) -> Dir {
     Dir {
         cwd: path,
         env: "".into(),
+        stdin: None,
     }
 }
 
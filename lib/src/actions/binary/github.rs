@@ -1,6 +1,6 @@
-use crate::actions::Action;
+use crate::actions::{default_retry_delay, Action, Retry};
 use crate::atoms::file::Chmod;
-use crate::atoms::http::Download;
+use crate::atoms::http::{api_dir, get_json_cached, Download};
 use crate::contexts::Contexts;
 use crate::manifests::Manifest;
 use crate::steps::Step;
@@ -8,7 +8,6 @@ use anyhow::anyhow;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
-use tokio::runtime::Runtime;
 use tracing::debug;
 
 #[derive(Clone, Debug, Default, JsonSchema, PartialEq, Eq, Serialize, Deserialize)]
@@ -17,6 +16,12 @@ pub struct BinaryGitHub {
     pub directory: String,
     pub repository: String,
     pub version: Option<String>,
+
+    #[serde(default)]
+    pub retries: u32,
+
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
 }
 
 struct GitHubAsset {
@@ -24,20 +29,32 @@ struct GitHubAsset {
     pub score: i32,
 }
 
+/// The parts of GitHub's release API used to pick an asset
+#[derive(Deserialize)]
+struct GitHubRelease {
+    assets: Vec<GitHubReleaseAsset>,
+}
+
+#[derive(Deserialize)]
+struct GitHubReleaseAsset {
+    name: String,
+    browser_download_url: String,
+}
+
 impl Action for BinaryGitHub {
+    fn retry(&self) -> Retry {
+        Retry {
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+        }
+    }
+
     fn plan(&self, _: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
         // Don't need to do anything if something already exists at the path
         if std::path::Path::new(format!("{}/{}", self.directory, self.name).as_str()).exists() {
             return Ok(vec![]);
         };
 
-        let async_runtime = match Runtime::new() {
-            Ok(runtime) => runtime,
-            Err(e) => {
-                return Err(anyhow!("Failed to create async runtime: {}", e));
-            }
-        };
-
         let (owner, repo) = self.repository.split_once('/').ok_or_else(|| {
             anyhow!(
                 "Failed to parse repository name: {}",
@@ -45,16 +62,16 @@ impl Action for BinaryGitHub {
             )
         })?;
 
-        let octocrab = async_runtime.block_on(async { octocrab::instance() });
-
-        let repos = octocrab.repos(owner, repo);
-        let releases = repos.releases();
-
-        let result = match &self.version {
-            Some(version) => async_runtime.block_on(releases.get_by_tag(version.as_str())),
-            None => async_runtime.block_on(releases.get_latest()),
+        let release = match &self.version {
+            Some(version) => format!("tags/{version}"),
+            None => String::from("latest"),
         };
 
+        let result: anyhow::Result<GitHubRelease> = get_json_cached(
+            &format!("https://api.github.com/repos/{owner}/{repo}/releases/{release}"),
+            &api_dir(),
+        );
+
         let release = match result {
             Ok(release) => release,
             Err(e) => {
@@ -101,7 +118,7 @@ impl Action for BinaryGitHub {
                 Some(ass) => {
                     if score > ass.score {
                         Some(GitHubAsset {
-                            url: asset.browser_download_url.into(),
+                            url: asset.browser_download_url,
                             score,
                         })
                     } else {
@@ -109,7 +126,7 @@ impl Action for BinaryGitHub {
                     }
                 }
                 None => Some(GitHubAsset {
-                    url: asset.browser_download_url.into(),
+                    url: asset.browser_download_url,
                     score,
                 }),
             }
@@ -130,6 +147,7 @@ impl Action for BinaryGitHub {
                 atom: Box::new(Download {
                     url: asset.url,
                     to: PathBuf::from(format!("{}/{}", self.directory, self.name)),
+                    ..Default::default()
                 }),
                 initializers: vec![],
                 finalizers: vec![],
@@ -138,6 +156,7 @@ impl Action for BinaryGitHub {
                 atom: Box::new(Chmod {
                     path: PathBuf::from(format!("{}/{}", self.directory, self.name)),
                     mode: 0o755,
+                    privileged: false,
                 }),
                 initializers: vec![],
                 finalizers: vec![],
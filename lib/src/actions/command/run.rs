@@ -1,21 +1,141 @@
 use crate::contexts::Contexts;
+use crate::steps::initializers::FlowControl::{Ensure, SkipIf};
+use crate::steps::initializers::{CommandSucceeds, FileExists};
 use crate::steps::Step;
-use crate::{actions::Action, manifests::Manifest};
+use crate::{
+    actions::{default_retry_delay, Action, Retry},
+    manifests::Manifest,
+};
+use anyhow::anyhow;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RunCommand {
+    #[serde(default)]
     pub command: String,
 
     #[serde(default)]
     pub args: Vec<String>,
 
+    /// Run the command, or `script`, through this shell instead of executing it directly
+    #[serde(default)]
+    pub shell: Option<Shell>,
+
+    /// Multi-line script run through `shell`, instead of `command` and `args`
+    #[serde(default)]
+    pub script: Option<String>,
+
     #[serde(default = "get_false", alias = "sudo")]
     pub privileged: bool,
 
+    /// Run the command as this user, using `sudo -u` (or `runas` on Windows)
+    #[serde(default)]
+    pub user: Option<String>,
+
+    /// Store stdout, stderr and exit_code under this context for later actions
+    #[serde(default)]
+    pub register: Option<String>,
+
+    /// Text written to the command's stdin
+    #[serde(default)]
+    pub stdin: Option<String>,
+
+    /// File whose contents are written to the command's stdin, relative paths are resolved from `dir`
+    #[serde(default)]
+    pub stdin_file: Option<String>,
+
+    /// Seconds after which the command is killed and the action fails
+    #[serde(default)]
+    pub timeout: Option<u64>,
+
+    /// Attempts after the first failure before giving up
+    #[serde(default)]
+    pub retries: u32,
+
+    /// Seconds before the first retry, doubled after every failure
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
+
     #[serde(default = "get_cwd")]
     pub dir: String,
+
+    /// Environment variables passed to the command
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Don't inherit comtrya's environment, only `env` is passed to the command
+    #[serde(default)]
+    pub env_clear: bool,
+
+    /// Skip the command when this path exists, relative paths are resolved from `dir`
+    #[serde(default)]
+    pub creates: Option<String>,
+
+    /// Skip the command when this shell command succeeds
+    #[serde(default)]
+    pub unless: Option<String>,
+
+    /// Only run the command when this shell command succeeds
+    #[serde(default)]
+    pub onlyif: Option<String>,
+}
+
+#[derive(JsonSchema, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    Sh,
+    Bash,
+    Zsh,
+    Powershell,
+    Cmd,
+    /// Execute the command directly, without a shell
+    None,
+}
+
+impl Shell {
+    fn arguments(&self, script: String) -> (String, Vec<String>) {
+        let (shell, flags): (&str, &[&str]) = match self {
+            Shell::Sh | Shell::None => ("sh", &["-c"]),
+            Shell::Bash => ("bash", &["-c"]),
+            Shell::Zsh => ("zsh", &["-c"]),
+            Shell::Powershell => ("powershell", &["-NoProfile", "-NonInteractive", "-Command"]),
+            Shell::Cmd => ("cmd", &["/C"]),
+        };
+
+        (
+            String::from(shell),
+            flags
+                .iter()
+                .map(|flag| flag.to_string())
+                .chain(std::iter::once(script))
+                .collect(),
+        )
+    }
+
+    /// Quotes an argument so the shell passes it on as it is
+    fn quote(&self, arg: &str) -> String {
+        match self {
+            Shell::Sh | Shell::Bash | Shell::Zsh | Shell::None => {
+                format!("'{}'", arg.replace('\'', r"'\''"))
+            }
+            Shell::Powershell => format!("'{}'", arg.replace('\'', "''")),
+            Shell::Cmd => format!("\"{}\"", arg.replace('"', "\"\"")),
+        }
+    }
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        if cfg!(target_family = "windows") {
+            Shell::Cmd
+        } else {
+            Shell::Sh
+        }
+    }
 }
 
 fn get_false() -> bool {
@@ -28,24 +148,235 @@ fn get_cwd() -> String {
         .expect("Failed to get current directory")
 }
 
+impl RunCommand {
+    /// The program and arguments to execute, wrapping the command in a shell when asked to
+    fn invocation(&self) -> anyhow::Result<(String, Vec<String>)> {
+        match (&self.script, self.shell) {
+            (Some(_), Some(Shell::None)) => Err(anyhow!("A script can't be run without a shell")),
+            (Some(_), _) if !self.command.is_empty() => {
+                Err(anyhow!("Only one of command and script can be given"))
+            }
+            (Some(script), shell) => Ok(shell.unwrap_or_default().arguments(script.clone())),
+            (None, _) if self.command.is_empty() => {
+                Err(anyhow!("Either command or script is required"))
+            }
+            (None, None | Some(Shell::None)) => Ok((self.command.clone(), self.args.clone())),
+            // The command is shell code, its arguments are passed on as they are
+            (None, Some(shell)) => Ok(shell.arguments(
+                std::iter::once(self.command.clone())
+                    .chain(self.args.iter().map(|arg| shell.quote(arg)))
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            )),
+        }
+    }
+}
+
 impl Action for RunCommand {
     fn summarize(&self) -> String {
-        format!("Running {} command", self.command)
+        match self.script {
+            Some(_) => String::from("Running script"),
+            None => format!("Running {} command", self.command),
+        }
+    }
+
+    fn register(&self) -> Option<&str> {
+        self.register.as_deref()
+    }
+
+    fn retry(&self) -> Retry {
+        Retry {
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+        }
     }
 
     fn plan(&self, _: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
         use crate::atoms::command::Exec;
 
+        let (command, arguments) = self.invocation()?;
+
+        let stdin = match (&self.stdin, &self.stdin_file) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow!("Only one of stdin and stdin_file can be given"))
+            }
+            (Some(stdin), None) => Some(stdin.clone()),
+            (None, Some(file)) => {
+                let path = PathBuf::from(&self.dir).join(file);
+                Some(std::fs::read_to_string(&path).map_err(|err| {
+                    anyhow!("Failed to read stdin from {}: {}", path.display(), err)
+                })?)
+            }
+            (None, None) => None,
+        };
+        let mut initializers = vec![];
+
+        if let Some(creates) = &self.creates {
+            initializers.push(SkipIf(Box::new(FileExists(
+                PathBuf::from(&self.dir).join(creates),
+            ))));
+        }
+
+        if let Some(unless) = &self.unless {
+            initializers.push(SkipIf(Box::new(CommandSucceeds {
+                command: unless.clone(),
+                dir: Some(self.dir.clone()),
+            })));
+        }
+
+        if let Some(onlyif) = &self.onlyif {
+            initializers.push(Ensure(Box::new(CommandSucceeds {
+                command: onlyif.clone(),
+                dir: Some(self.dir.clone()),
+            })));
+        }
+
         Ok(vec![Step {
             atom: Box::new(Exec {
-                command: self.command.clone(),
-                arguments: self.args.clone(),
+                command,
+                arguments,
                 privileged: self.privileged,
+                user: self.user.clone(),
                 working_dir: Some(self.dir.clone()),
+                environment: self.env.clone().into_iter().collect(),
+                env_clear: self.env_clear,
+                timeout: self.timeout.map(Duration::from_secs),
+                stdin,
                 ..Default::default()
             }),
-            initializers: vec![],
+            initializers,
             finalizers: vec![],
         }])
     }
 }
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::{RunCommand, Shell};
+    use crate::actions::Actions;
+    use crate::contexts::Contexts;
+    use crate::manifests::Manifest;
+    use pretty_assertions::assert_eq;
+
+    fn runs(yaml: &str) -> bool {
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+        let action = actions.pop().unwrap();
+
+        action
+            .inner_ref()
+            .plan(&Manifest::default(), &Contexts::default())
+            .unwrap()
+            .iter()
+            .all(|step| step.do_initializers_allow_us_to_run())
+    }
+
+    #[test]
+    fn it_can_be_guarded() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("exists"), "").unwrap();
+        let dir = dir.path().display();
+
+        assert_eq!(
+            false,
+            runs(&format!(
+                "- action: command.run\n  command: touch\n  dir: {dir}\n  creates: exists"
+            ))
+        );
+        assert_eq!(
+            true,
+            runs(&format!(
+                "- action: command.run\n  command: touch\n  dir: {dir}\n  creates: missing"
+            ))
+        );
+        assert_eq!(
+            false,
+            runs("- action: command.run\n  command: echo\n  unless: \"true\"")
+        );
+        assert_eq!(
+            true,
+            runs("- action: command.run\n  command: echo\n  unless: \"false\"")
+        );
+        assert_eq!(
+            false,
+            runs("- action: command.run\n  command: echo\n  onlyif: \"false\"")
+        );
+        assert_eq!(
+            true,
+            runs("- action: command.run\n  command: echo\n  onlyif: \"true\"")
+        );
+    }
+
+    #[test]
+    fn it_can_run_scripts() {
+        let yaml = r#"
+- action: command.run
+  shell: bash
+  script: |
+    for word in one two; do
+      echo $word
+    done | wc -l
+"#;
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+        let mut steps = actions
+            .pop()
+            .unwrap()
+            .inner_ref()
+            .plan(&Manifest::default(), &Contexts::default())
+            .unwrap();
+
+        let atom = &mut steps[0].atom;
+        atom.execute().unwrap();
+        assert_eq!("2", atom.output_string().trim());
+    }
+
+    #[test]
+    fn it_wraps_commands_in_a_shell() {
+        let command = RunCommand {
+            command: String::from("echo *.txt |"),
+            args: vec![String::from("it's $HOME; rm")],
+            shell: Some(Shell::Zsh),
+            ..Default::default()
+        };
+        assert_eq!(
+            (
+                String::from("zsh"),
+                vec![
+                    String::from("-c"),
+                    String::from(r"echo *.txt | 'it'\''s $HOME; rm'")
+                ]
+            ),
+            command.invocation().unwrap()
+        );
+
+        let command = RunCommand {
+            command: String::from("Write-Output"),
+            args: vec![String::from("it's")],
+            shell: Some(Shell::Powershell),
+            ..Default::default()
+        };
+        assert_eq!(
+            "Write-Output 'it''s'",
+            command.invocation().unwrap().1.last().unwrap()
+        );
+
+        let command = RunCommand {
+            command: String::from("echo"),
+            shell: Some(Shell::None),
+            ..Default::default()
+        };
+        assert_eq!(
+            (String::from("echo"), vec![]),
+            command.invocation().unwrap()
+        );
+
+        let command = RunCommand {
+            script: Some(String::from("echo hi")),
+            shell: Some(Shell::None),
+            ..Default::default()
+        };
+        assert_eq!(true, command.invocation().is_err());
+
+        assert_eq!(true, RunCommand::default().invocation().is_err());
+    }
+}
@@ -1,18 +1,135 @@
-use super::DirectoryAction;
+use super::{DirectoryAction, Exclude};
 use crate::actions::Action;
 use crate::contexts::Contexts;
 use crate::steps::Step;
 use crate::{atoms::command::Exec, manifests::Manifest};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
 
 #[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DirectoryCopy {
     pub from: String,
     pub to: String,
+
+    /// Globs of files and directories that aren't copied, like `.git` or `*.swp`,
+    /// matched against their path within `from` and their name
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// Removes what's in `to` but not in `from`, except excluded paths
+    #[serde(default)]
+    pub purge: bool,
 }
 
-impl DirectoryCopy {}
+impl DirectoryCopy {
+    /// Whether the directory is copied file by file, rather than with `cp` or
+    /// `Xcopy`, which can't leave files out
+    fn mirrors(&self) -> bool {
+        self.purge || !self.exclude.is_empty()
+    }
+
+    /// Steps that make `to` a copy of `from`, leaving out excluded paths
+    fn mirror(&self, manifest: &Manifest) -> anyhow::Result<Vec<Step>> {
+        use crate::atoms::directory::{Create as DirCreate, Remove as DirRemove};
+        use crate::atoms::file::{Copy, Create, Remove};
+
+        let from = self.resolve(manifest, &self.from);
+        let to = PathBuf::from(&self.to);
+
+        let exclude = Exclude::new(&self.exclude)?;
+        let excluded = |root: &Path, entry: &walkdir::DirEntry| exclude.matches(root, entry);
+
+        let mut steps = vec![Step {
+            atom: Box::new(DirCreate { path: to.clone() }),
+            initializers: vec![],
+            finalizers: vec![],
+        }];
+        let mut copied = HashSet::new();
+
+        for entry in WalkDir::new(&from)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|entry| !excluded(&from, entry))
+        {
+            let entry = entry?;
+            let relative = entry.path().strip_prefix(&from)?.to_path_buf();
+            let path = to.join(&relative);
+
+            if entry.path().is_dir() {
+                steps.push(Step {
+                    atom: Box::new(DirCreate { path }),
+                    initializers: vec![],
+                    finalizers: vec![],
+                });
+            } else {
+                steps.push(Step {
+                    atom: Box::new(Create { path: path.clone() }),
+                    initializers: vec![],
+                    finalizers: vec![],
+                });
+
+                #[cfg(unix)]
+                steps.push(Step {
+                    atom: Box::new(crate::atoms::file::Chmod {
+                        path: path.clone(),
+                        mode: entry.metadata()?.permissions().mode() & 0o777,
+                        privileged: false,
+                    }),
+                    initializers: vec![],
+                    finalizers: vec![],
+                });
+
+                // Compared and copied as streams, so big files aren't read into memory
+                steps.push(Step {
+                    atom: Box::new(Copy {
+                        from: entry.path().to_path_buf(),
+                        to: path,
+                    }),
+                    initializers: vec![],
+                    finalizers: vec![],
+                });
+            }
+
+            copied.insert(relative);
+        }
+
+        if self.purge && to.is_dir() {
+            let mut extra = vec![];
+            for entry in WalkDir::new(&to)
+                .min_depth(1)
+                .into_iter()
+                .filter_entry(|entry| !excluded(&to, entry))
+            {
+                let entry = entry?;
+
+                if !copied.contains(entry.path().strip_prefix(&to)?) {
+                    extra.push(entry);
+                }
+            }
+
+            // Contents come before their directory, which is only empty once they're removed
+            for entry in extra.into_iter().rev() {
+                let target = entry.path().to_path_buf();
+                steps.push(Step {
+                    atom: match entry.file_type().is_dir() {
+                        true => Box::new(DirRemove { target }),
+                        false => Box::new(Remove { target }),
+                    },
+                    initializers: vec![],
+                    finalizers: vec![],
+                });
+            }
+        }
+
+        Ok(steps)
+    }
+}
 
 impl DirectoryAction for DirectoryCopy {}
 
@@ -22,7 +139,19 @@ impl Action for DirectoryCopy {
         format!("Copying {} to {}", self.from, self.to)
     }
 
+    fn files(&self, manifest: &Manifest) -> Vec<PathBuf> {
+        manifest
+            .root_dir
+            .iter()
+            .map(|root_dir| root_dir.join("files").join(&self.from))
+            .collect()
+    }
+
     fn plan(&self, manifest: &Manifest, _context: &Contexts) -> anyhow::Result<Vec<Step>> {
+        if self.mirrors() {
+            return self.mirror(manifest);
+        }
+
         let from: String = self.resolve(manifest, &self.from).display().to_string();
 
         Ok(vec![Step {
@@ -43,7 +172,19 @@ impl Action for DirectoryCopy {
         format!("Copying {} to {}", self.from, self.to)
     }
 
+    fn files(&self, manifest: &Manifest) -> Vec<PathBuf> {
+        manifest
+            .root_dir
+            .iter()
+            .map(|root_dir| root_dir.join("files").join(&self.from))
+            .collect()
+    }
+
     fn plan(&self, manifest: &Manifest, _context: &Contexts) -> anyhow::Result<Vec<Step>> {
+        if self.mirrors() {
+            return self.mirror(manifest);
+        }
+
         let mut from: String = self.resolve(manifest, &self.from).display().to_string();
 
         if self.to.ends_with("/") {
@@ -75,8 +216,10 @@ impl Action for DirectoryCopy {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::actions::Actions;
     use crate::manifests::Manifest;
+    use pretty_assertions::assert_eq;
     use std::path::PathBuf;
 
     fn get_manifest_dir() -> PathBuf {
@@ -104,4 +247,48 @@ mod tests {
             }
         };
     }
+
+    #[test]
+    fn it_mirrors_without_excluded_files() {
+        let root = tempfile::tempdir().unwrap();
+        let from = root.path().join("files").join("mydir");
+        for file in ["a", "b.swp", ".git/config", "sub/c"] {
+            std::fs::create_dir_all(from.join(file).parent().unwrap()).unwrap();
+            std::fs::write(from.join(file), file).unwrap();
+        }
+
+        let to = tempfile::tempdir().unwrap();
+        for file in ["a", "stale", ".git/HEAD", "old/d"] {
+            std::fs::create_dir_all(to.path().join(file).parent().unwrap()).unwrap();
+            std::fs::write(to.path().join(file), "old").unwrap();
+        }
+
+        let action = DirectoryCopy {
+            from: String::from("mydir"),
+            to: to.path().display().to_string(),
+            exclude: vec![String::from(".git"), String::from("*.swp")],
+            purge: true,
+        };
+        let manifest = Manifest {
+            root_dir: Some(root.path().to_path_buf()),
+            ..Default::default()
+        };
+
+        for mut step in action.plan(&manifest, &Default::default()).unwrap() {
+            if step.atom.plan().unwrap().should_run {
+                step.atom.execute().unwrap();
+            }
+        }
+
+        assert_eq!("a", std::fs::read_to_string(to.path().join("a")).unwrap());
+        assert_eq!(
+            "sub/c",
+            std::fs::read_to_string(to.path().join("sub/c")).unwrap()
+        );
+        assert_eq!(false, to.path().join("b.swp").exists());
+        assert_eq!(false, to.path().join("stale").exists());
+        assert_eq!(false, to.path().join("old").exists());
+        assert_eq!(true, to.path().join(".git/HEAD").exists());
+        assert_eq!(false, to.path().join(".git/config").exists());
+    }
 }
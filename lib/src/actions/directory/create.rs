@@ -1,14 +1,38 @@
-use crate::atoms::directory::Create as DirectoryCreateAtom;
+use crate::atoms::command::Exec;
+use crate::atoms::directory::{can_create, Create as DirectoryCreateAtom};
+use crate::atoms::file::{needs_privilege, owned_by_others, Chmod, Chown};
 use crate::manifests::Manifest;
+use crate::steps::initializers::{FileExists, FlowControl};
 use crate::steps::Step;
 use crate::{actions::Action, contexts::Contexts};
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
 use std::path::PathBuf;
 
 #[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DirectoryCreate {
     pub path: String,
+
+    /// Octal permissions, like `"0755"`
+    #[serde(default, deserialize_with = "from_octal")]
+    pub mode: Option<u32>,
+
+    pub owner: Option<String>,
+
+    pub group: Option<String>,
+
+    /// Changes the owner and group of everything in the directory too
+    #[serde(default)]
+    pub recursive: bool,
+}
+
+fn from_octal<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|mode| u32::from_str_radix(&mode, 8).map_err(D::Error::custom))
+        .transpose()
 }
 
 impl Action for DirectoryCreate {
@@ -17,21 +41,72 @@ impl Action for DirectoryCreate {
     }
 
     fn plan(&self, _: &Manifest, _context: &Contexts) -> anyhow::Result<Vec<Step>> {
-        Ok(vec![Step {
-            atom: Box::new(DirectoryCreateAtom {
-                path: PathBuf::from(&self.path),
-            }),
-            initializers: vec![],
-            finalizers: vec![],
-        }])
+        let path = PathBuf::from(&self.path);
+
+        // Parents we can't write to, like /opt, need sudo, and leave the directory to root
+        let elevated = !path.exists() && !can_create(&path);
+        let ours = !elevated && !owned_by_others(&path);
+
+        let mut steps = vec![match elevated {
+            true => Step {
+                atom: Box::new(Exec {
+                    command: String::from("mkdir"),
+                    arguments: vec![String::from("-p"), self.path.clone()],
+                    privileged: true,
+                    ..Default::default()
+                }),
+                initializers: vec![FlowControl::SkipIf(Box::new(FileExists(path.clone())))],
+                finalizers: vec![],
+            },
+            false => Step {
+                atom: Box::new(DirectoryCreateAtom { path: path.clone() }),
+                initializers: vec![],
+                finalizers: vec![],
+            },
+        }];
+
+        if self.owner.is_some() || self.group.is_some() {
+            steps.push(Step {
+                atom: Box::new(Chown {
+                    path: path.clone(),
+                    owner: self.owner.clone(),
+                    group: self.group.clone(),
+                    privileged: !ours
+                        || needs_privilege(self.owner.as_deref(), self.group.as_deref()),
+                    recursive: self.recursive,
+                }),
+                initializers: vec![],
+                finalizers: vec![],
+            });
+        }
+
+        if let Some(mode) = self.mode {
+            // Once given away, only root can change the permissions
+            let ours = match self.owner.as_deref() {
+                Some(owner) => !needs_privilege(Some(owner), None),
+                None => ours,
+            };
+
+            steps.push(Step {
+                atom: Box::new(Chmod {
+                    path,
+                    mode,
+                    privileged: !ours,
+                }),
+                initializers: vec![],
+                finalizers: vec![],
+            });
+        }
+
+        Ok(steps)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::actions::Actions;
-    use crate::manifests::Manifest;
-    use std::path::PathBuf;
+    use pretty_assertions::assert_eq;
 
     fn get_manifest_dir() -> PathBuf {
         std::env::current_dir()
@@ -50,10 +125,65 @@ mod tests {
         match manifest.actions.pop() {
             Some(Actions::DirectoryCreate(action)) => {
                 assert_eq!("/some-directory", action.action.path);
+                assert_eq!(None, action.action.mode);
             }
             _ => {
                 panic!("DirectoryCopy didn't deserialize to the correct type");
             }
         };
     }
+
+    #[test]
+    fn it_can_set_permissions() {
+        let yaml = r#"
+- action: directory.create
+  path: /opt/tools
+  mode: "0750"
+  owner: tools
+  group: staff
+  recursive: true
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::DirectoryCreate(action)) => {
+                assert_eq!(Some(0o750), action.action.mode);
+                assert_eq!(Some(String::from("tools")), action.action.owner);
+                assert_eq!(Some(String::from("staff")), action.action.group);
+                assert_eq!(true, action.action.recursive);
+            }
+            _ => {
+                panic!("DirectoryCreate didn't deserialize to the correct type");
+            }
+        };
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_sets_the_mode_of_new_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let action = DirectoryCreate {
+            path: tmpdir.path().join("a/b").display().to_string(),
+            mode: Some(0o700),
+            ..Default::default()
+        };
+
+        for mut step in action
+            .plan(&Default::default(), &Default::default())
+            .unwrap()
+        {
+            if step.do_initializers_allow_us_to_run() && step.atom.plan().unwrap().should_run {
+                step.atom.execute().unwrap();
+            }
+        }
+
+        let mode = std::fs::metadata(tmpdir.path().join("a/b"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(0o700, mode & 0o777);
+    }
 }
@@ -0,0 +1,237 @@
+use super::{DirectoryAction, Exclude};
+use crate::atoms::directory::Create as DirCreate;
+use crate::atoms::file::Link;
+use crate::manifests::Manifest;
+use crate::steps::Step;
+use crate::{actions::Action, contexts::Contexts};
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Links every file of a directory into another one, keeping the structure, like
+/// GNU stow: directories are created, while files become links to the source.
+#[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryLink {
+    pub source: String,
+    pub target: String,
+
+    /// Globs of files and directories that aren't linked, like `.git` or `README.md`
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// Links point to the source relative to their own directory
+    #[serde(default)]
+    pub relative: bool,
+
+    /// Files in the way are moved into the source, replacing what's there, and linked
+    #[serde(default)]
+    pub adopt: bool,
+}
+
+impl DirectoryAction for DirectoryLink {}
+
+/// Whether something other than a link is at `path`
+fn occupied(path: &Path) -> bool {
+    path.symlink_metadata()
+        .map(|metadata| !metadata.file_type().is_symlink())
+        .unwrap_or(false)
+}
+
+impl Action for DirectoryLink {
+    fn summarize(&self) -> String {
+        format!("Linking directory {} into {}", self.source, self.target)
+    }
+
+    fn files(&self, manifest: &Manifest) -> Vec<PathBuf> {
+        manifest
+            .root_dir
+            .iter()
+            .map(|root_dir| root_dir.join("files").join(&self.source))
+            .collect()
+    }
+
+    fn plan(&self, manifest: &Manifest, _context: &Contexts) -> anyhow::Result<Vec<Step>> {
+        let from = self.resolve(manifest, &self.source);
+        let to = PathBuf::from(&self.target);
+        let exclude = Exclude::new(&self.exclude)?;
+
+        let mut steps = vec![Step {
+            atom: Box::new(DirCreate { path: to.clone() }),
+            initializers: vec![],
+            finalizers: vec![],
+        }];
+        let mut conflicts = vec![];
+
+        for entry in WalkDir::new(&from)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| !exclude.matches(&from, entry))
+        {
+            let entry = entry?;
+            let target = to.join(entry.path().strip_prefix(&from)?);
+
+            if entry.file_type().is_dir() {
+                // A file can't be adopted where a directory goes
+                if occupied(&target) && !target.is_dir() {
+                    conflicts.push(target);
+                    continue;
+                }
+
+                steps.push(Step {
+                    atom: Box::new(DirCreate { path: target }),
+                    initializers: vec![],
+                    finalizers: vec![],
+                });
+                continue;
+            }
+
+            if occupied(&target) && (!self.adopt || target.is_dir()) {
+                conflicts.push(target);
+                continue;
+            }
+
+            steps.push(Step {
+                atom: Box::new(Link {
+                    source: entry.path().to_path_buf(),
+                    target,
+                    relative: self.relative,
+                    adopt: self.adopt,
+                    ..Default::default()
+                }),
+                initializers: vec![],
+                finalizers: vec![],
+            });
+        }
+
+        // Nothing is linked unless everything can be
+        if !conflicts.is_empty() {
+            return Err(anyhow!(
+                "{} in the way of links, move them or use adopt: {}",
+                match conflicts.len() {
+                    1 => String::from("1 file is"),
+                    n => format!("{n} files are"),
+                },
+                conflicts
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        Ok(steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
+
+    fn manifest(files: &[&str]) -> (tempfile::TempDir, Manifest) {
+        let root = tempfile::tempdir().unwrap();
+        for file in files {
+            let path = root.path().join("files").join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file).unwrap();
+        }
+
+        let manifest = Manifest {
+            root_dir: Some(root.path().to_path_buf()),
+            ..Default::default()
+        };
+
+        (root, manifest)
+    }
+
+    fn apply(steps: Vec<Step>) {
+        for mut step in steps {
+            if step.do_initializers_allow_us_to_run() && step.atom.plan().unwrap().should_run {
+                step.atom.execute().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let yaml = r#"
+- action: directory.link
+  source: nvim
+  target: /home/me/.config/nvim
+  exclude:
+    - .git
+  adopt: true
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::DirectoryLink(action)) => {
+                assert_eq!("nvim", action.action.source);
+                assert_eq!(vec![String::from(".git")], action.action.exclude);
+                assert_eq!(true, action.action.adopt);
+                assert_eq!(false, action.action.relative);
+            }
+            _ => {
+                panic!("DirectoryLink didn't deserialize to the correct type");
+            }
+        };
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_links_every_file() {
+        let (root, manifest) =
+            manifest(&["dots/init.lua", "dots/lua/plugins.lua", "dots/.git/HEAD"]);
+        let target = tempfile::tempdir().unwrap();
+
+        let action = DirectoryLink {
+            source: String::from("dots"),
+            target: target.path().display().to_string(),
+            exclude: vec![String::from(".git")],
+            ..Default::default()
+        };
+
+        apply(action.plan(&manifest, &Default::default()).unwrap());
+
+        assert_eq!(
+            root.path().join("files/dots/lua/plugins.lua"),
+            std::fs::read_link(target.path().join("lua/plugins.lua")).unwrap()
+        );
+        assert_eq!(true, target.path().join("init.lua").is_symlink());
+        assert_eq!(false, target.path().join("lua").is_symlink());
+        assert_eq!(false, target.path().join(".git").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_reports_conflicts_or_adopts_them() {
+        let (root, manifest) = manifest(&["dots/init.lua"]);
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(target.path().join("init.lua"), "mine").unwrap();
+
+        let mut action = DirectoryLink {
+            source: String::from("dots"),
+            target: target.path().display().to_string(),
+            ..Default::default()
+        };
+
+        let Err(err) = action.plan(&manifest, &Default::default()) else {
+            panic!("The file in the way wasn't reported");
+        };
+        assert_eq!(true, err.to_string().contains("1 file is in the way"));
+
+        action.adopt = true;
+        apply(action.plan(&manifest, &Default::default()).unwrap());
+
+        assert_eq!(true, target.path().join("init.lua").is_symlink());
+        assert_eq!(
+            "mine",
+            std::fs::read_to_string(root.path().join("files/dots/init.lua")).unwrap()
+        );
+    }
+}
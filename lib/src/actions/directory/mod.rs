@@ -1,12 +1,15 @@
 use crate::{actions::Action, manifests::Manifest};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use normpath::PathExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 mod copy;
 mod create;
+mod link;
 mod remove;
 pub use copy::DirectoryCopy;
 pub use create::DirectoryCreate;
+pub use link::DirectoryLink;
 pub use remove::DirectoryRemove;
 
 pub trait DirectoryAction: Action {
@@ -19,3 +22,24 @@ pub trait DirectoryAction: Action {
             .expect("Failed to resolve path")
     }
 }
+
+/// Globs of paths left out of a directory, like `.git` or `*.swp`, matched against
+/// the path within the directory and the name
+struct Exclude(GlobSet);
+
+impl Exclude {
+    fn new(patterns: &[String]) -> anyhow::Result<Self> {
+        let mut exclude = GlobSetBuilder::new();
+        for pattern in patterns {
+            exclude.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+        }
+
+        Ok(Exclude(exclude.build()?))
+    }
+
+    fn matches(&self, root: &Path, entry: &walkdir::DirEntry) -> bool {
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+
+        self.0.is_match(relative) || self.0.is_match(entry.file_name())
+    }
+}
@@ -1,5 +1,5 @@
 use super::FileAction;
-use super::{default_chmod, from_octal};
+use super::{default_chmod, expand, from_octal, glob_root, is_glob};
 use crate::atoms::file::Decrypt;
 use crate::manifests::Manifest;
 use crate::steps::Step;
@@ -9,7 +9,7 @@ use anyhow::anyhow;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::error::Error as StdError;
-use std::{path::PathBuf, u32};
+use std::path::PathBuf;
 use tera::Tera;
 
 #[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
@@ -20,9 +20,19 @@ pub struct FileCopy {
     #[serde(alias = "target")]
     pub to: String,
 
-    #[serde(default = "default_chmod", deserialize_with = "from_octal")]
+    #[serde(
+        default = "default_chmod",
+        deserialize_with = "from_octal",
+        alias = "mode"
+    )]
     pub chmod: u32,
 
+    /// Owner of the copy, which is changed through sudo when it isn't us
+    pub owner: Option<String>,
+
+    /// Group of the copy, which is changed through sudo when we aren't in it
+    pub group: Option<String>,
+
     #[serde(default = "default_template")]
     pub template: bool,
 
@@ -33,8 +43,6 @@ fn default_template() -> bool {
     false
 }
 
-impl FileCopy {}
-
 impl FileAction for FileCopy {}
 
 impl Action for FileCopy {
@@ -42,12 +50,71 @@ impl Action for FileCopy {
         format!("Copy file from {} to {}", self.from, self.to)
     }
 
+    fn files(&self, manifest: &Manifest) -> Vec<PathBuf> {
+        manifest
+            .root_dir
+            .iter()
+            .flat_map(|root_dir| {
+                expand(&root_dir.join("files").join(&self.from)).unwrap_or_default()
+            })
+            .collect()
+    }
+
     fn plan(
         &self,
         manifest: &Manifest,
         context: &crate::contexts::Contexts,
     ) -> anyhow::Result<Vec<Step>> {
-        let contents = match self.load(manifest, &self.from) {
+        if !is_glob(&self.from) {
+            let mut path = PathBuf::from(&self.to);
+
+            if path.is_dir() {
+                if let Some(file_name) = PathBuf::from(self.from.clone()).file_name() {
+                    path = path.join(file_name);
+                }
+            }
+
+            return self.copy(manifest, context, &self.from, path);
+        }
+
+        // Every match is copied into `to`, keeping its path below the directory
+        // the glob starts in, so matches in different directories don't collide
+        let files_dir = manifest
+            .root_dir
+            .as_ref()
+            .ok_or_else(|| anyhow!("Failed because manifest has no root_dir"))?
+            .join("files");
+
+        let pattern = files_dir.join(&self.from);
+        let root = glob_root(&pattern);
+
+        let sources = expand(&pattern)?;
+        if sources.is_empty() {
+            return Err(anyhow!("No files match {}", self.from));
+        }
+
+        let mut steps = vec![];
+        for source in sources {
+            let from = source.strip_prefix(&files_dir)?;
+            let path = PathBuf::from(&self.to).join(source.strip_prefix(&root)?);
+
+            steps.extend(self.copy(manifest, context, &from.to_string_lossy(), path)?);
+        }
+
+        Ok(steps)
+    }
+}
+
+impl FileCopy {
+    /// The steps that copy `from`, in the manifest's files, to `path`
+    fn copy(
+        &self,
+        manifest: &Manifest,
+        context: &crate::contexts::Contexts,
+        from: &str,
+        path: PathBuf,
+    ) -> anyhow::Result<Vec<Step>> {
+        let contents = match self.load(manifest, from) {
             Ok(contents) => {
                 if self.template {
                     let mut tera = Tera::default();
@@ -87,15 +154,7 @@ impl Action for FileCopy {
         };
 
         use crate::atoms::directory::Create as DirCreate;
-        use crate::atoms::file::{Chmod, Create, SetContents};
-
-        let mut path = PathBuf::from(&self.to);
-
-        if path.is_dir() {
-            if let Some(file_name) = PathBuf::from(self.from.clone()).file_name() {
-                path = path.join(file_name);
-            }
-        }
+        use crate::atoms::file::{needs_privilege, Chmod, Chown, Create, SetContents};
 
         let parent = path.clone();
         let mut steps = vec![
@@ -120,12 +179,26 @@ impl Action for FileCopy {
                 atom: Box::new(Chmod {
                     path: path.clone(),
                     mode: self.chmod,
+                    privileged: false,
                 }),
                 initializers: vec![],
                 finalizers: vec![],
             },
         ];
 
+        // The owner changes last, the contents can't be written once it's not us
+        let chown = (self.owner.is_some() || self.group.is_some()).then(|| Step {
+            atom: Box::new(Chown {
+                path: path.clone(),
+                owner: self.owner.clone(),
+                group: self.group.clone(),
+                privileged: needs_privilege(self.owner.as_deref(), self.group.as_deref()),
+                recursive: false,
+            }),
+            initializers: vec![],
+            finalizers: vec![],
+        });
+
         if let Some(passphrase) = self.passphrase.to_owned() {
             steps.push(Step {
                 atom: Box::new(Decrypt {
@@ -136,23 +209,25 @@ impl Action for FileCopy {
                 initializers: vec![],
                 finalizers: vec![],
             });
-
-            Ok(steps)
         } else {
             steps.push(Step {
                 atom: Box::new(SetContents { path, contents }),
                 initializers: vec![],
                 finalizers: vec![],
             });
-
-            Ok(steps)
         }
+
+        steps.extend(chown);
+
+        Ok(steps)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::actions::Actions;
+    use crate::contexts::Contexts;
+    use crate::manifests::Manifest;
 
     #[test]
     fn it_can_be_deserialized() {
@@ -170,10 +245,66 @@ mod tests {
                 assert_eq!("a", action.action.from);
                 assert_eq!("b", action.action.to);
                 assert_eq!(0o777, action.action.chmod);
+                assert_eq!(None, action.action.owner);
+            }
+            _ => {
+                panic!("FileCopy didn't deserialize to the correct type");
+            }
+        };
+    }
+
+    #[test]
+    fn it_can_set_the_owner() {
+        let yaml = r#"
+- action: file.copy
+  from: a
+  to: b
+  mode: "0600"
+  owner: root
+  group: wheel
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::FileCopy(action)) => {
+                assert_eq!(0o600, action.action.chmod);
+                assert_eq!(Some(String::from("root")), action.action.owner);
+                assert_eq!(Some(String::from("wheel")), action.action.group);
             }
             _ => {
                 panic!("FileCopy didn't deserialize to the correct type");
             }
         };
     }
+
+    #[test]
+    fn it_keeps_the_directories_of_glob_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["files/configs/a/x.conf", "files/configs/b/x.conf"] {
+            let file = dir.path().join(file);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, "").unwrap();
+        }
+
+        let manifest = Manifest {
+            root_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let action: Actions =
+            serde_yml::from_str("action: file.copy\nfrom: configs/**/*.conf\nto: /etc/app")
+                .unwrap();
+
+        let steps: Vec<String> = action
+            .inner_ref()
+            .plan(&manifest, &Contexts::default())
+            .unwrap()
+            .iter()
+            .map(|step| step.atom.to_string())
+            .collect();
+
+        for path in ["/etc/app/a/x.conf", "/etc/app/b/x.conf"] {
+            assert!(steps.iter().any(|step| step.contains(path)), "{:?}", steps);
+        }
+    }
 }
@@ -1,14 +1,20 @@
 use super::FileAction;
 use super::{default_chmod, from_octal};
 use crate::manifests::Manifest;
+use crate::secrets::{BasicAuth, Secret};
 use crate::steps::Step;
-use crate::{actions::Action, contexts::Contexts};
+use crate::{
+    actions::{default_retry_delay, Action, Retry},
+    contexts::Contexts,
+};
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
-use std::{path::PathBuf, u32};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
+// Deserialized by hand, to reject conflicting authorizations
 #[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename = "file.download")]
+#[serde(remote = "Self", rename = "file.download")]
 pub struct FileDownload {
     pub from: String,
     pub to: String,
@@ -18,12 +24,52 @@ pub struct FileDownload {
 
     #[serde(default = "default_template")]
     pub template: bool,
+
+    #[serde(default)]
+    pub retries: u32,
+
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
+
+    /// Sent with the request, e.g. API keys
+    #[serde(default)]
+    pub headers: BTreeMap<String, Secret>,
+
+    #[serde(default)]
+    pub basic_auth: Option<BasicAuth>,
+
+    /// Sent as `Authorization: Bearer <token>`
+    #[serde(default)]
+    pub bearer_token: Option<Secret>,
 }
 
 fn default_template() -> bool {
     false
 }
 
+impl<'de> Deserialize<'de> for FileDownload {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let download = FileDownload::deserialize(deserializer)?;
+
+        // Both would be sent as an Authorization header
+        if download.basic_auth.is_some() && download.bearer_token.is_some() {
+            return Err(D::Error::custom(
+                "basic_auth and bearer_token can't be used together",
+            ));
+        }
+
+        Ok(download)
+    }
+}
+
+impl Serialize for FileDownload {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FileDownload::serialize(self, serializer)
+    }
+}
+
 impl FileDownload {}
 
 impl FileAction for FileDownload {}
@@ -33,6 +79,13 @@ impl Action for FileDownload {
         format!("Downloading file {} to {}", self.from, self.to)
     }
 
+    fn retry(&self) -> Retry {
+        Retry {
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+        }
+    }
+
     fn plan(&self, _manifest: &Manifest, _context: &Contexts) -> anyhow::Result<Vec<Step>> {
         use crate::atoms::directory::Create as DirCreate;
         use crate::atoms::file::Chmod;
@@ -61,6 +114,10 @@ impl Action for FileDownload {
                 atom: Box::new(Download {
                     url: self.from.clone(),
                     to: path.clone(),
+                    headers: self.headers.clone(),
+                    basic_auth: self.basic_auth.clone(),
+                    bearer_token: self.bearer_token.clone(),
+                    ..Default::default()
                 }),
                 initializers: vec![],
                 finalizers: vec![],
@@ -69,6 +126,7 @@ impl Action for FileDownload {
                 atom: Box::new(Chmod {
                     path,
                     mode: self.chmod,
+                    privileged: false,
                 }),
                 initializers: vec![],
                 finalizers: vec![],
@@ -80,6 +138,8 @@ impl Action for FileDownload {
 #[cfg(test)]
 mod tests {
     use crate::actions::Actions;
+    use crate::secrets::Secret;
+    use pretty_assertions::assert_eq;
 
     #[test]
     fn it_can_be_deserialized() {
@@ -87,6 +147,14 @@ mod tests {
 - action: file.download
   from: a
   to: b
+  retries: 3
+  headers:
+    X-Api-Key:
+      env: API_KEY
+  basic_auth:
+    username: ci
+    password:
+      file: /run/secrets/nexus
 "#;
 
         let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
@@ -95,10 +163,49 @@ mod tests {
             Some(Actions::FileDownload(action)) => {
                 assert_eq!("a", action.action.from);
                 assert_eq!("b", action.action.to);
+                assert_eq!(3, action.action.retries);
+                assert_eq!(5, action.action.retry_delay);
+                assert_eq!(
+                    Some(&Secret::Env {
+                        env: String::from("API_KEY")
+                    }),
+                    action.action.headers.get("X-Api-Key")
+                );
+                assert_eq!(
+                    Secret::File {
+                        file: String::from("/run/secrets/nexus")
+                    },
+                    action.action.basic_auth.unwrap().password
+                );
+                assert_eq!(None, action.action.bearer_token);
             }
             _ => {
                 panic!("FileDownload didn't deserialize to the correct type");
             }
         };
     }
+
+    #[test]
+    fn it_rejects_two_authorizations() {
+        let yaml = r#"
+- action: file.download
+  from: a
+  to: b
+  basic_auth:
+    username: ci
+    password: secret
+  bearer_token: token
+"#;
+
+        assert_eq!(true, serde_yml::from_str::<Vec<Actions>>(yaml).is_err());
+
+        let yaml = "- action: file.download\n  from: a\n  to: b\n  bearer_token: token\n";
+        match serde_yml::from_str::<Vec<Actions>>(yaml).unwrap().pop() {
+            Some(Actions::FileDownload(action)) => assert_eq!(
+                Some(Secret::Value(String::from("token"))),
+                action.action.bearer_token
+            ),
+            _ => panic!("FileDownload didn't deserialize to the correct type"),
+        }
+    }
 }
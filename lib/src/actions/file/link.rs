@@ -21,6 +21,18 @@ pub struct FileLink {
 
     #[serde(default = "walk_dir_default")]
     pub walk_dir: bool,
+
+    /// Links point to the source relative to their own directory
+    #[serde(default)]
+    pub relative: bool,
+
+    /// Hard links instead of symlinks
+    #[serde(default)]
+    pub hard: bool,
+
+    /// Replaces files that are in the way, keeping them as `name.bak`
+    #[serde(default)]
+    pub force: bool,
 }
 
 fn walk_dir_default() -> bool {
@@ -53,7 +65,7 @@ impl FileLink {
         }
     }
 
-    pub fn plan_no_walk(from: PathBuf, to: PathBuf) -> Vec<Step> {
+    pub fn plan_no_walk(&self, from: PathBuf, to: PathBuf) -> Vec<Step> {
         use crate::atoms::directory::Create as DirCreate;
         use crate::atoms::file::Link;
 
@@ -71,6 +83,10 @@ impl FileLink {
                         atom: Box::new(Link {
                             source: from.to_owned(),
                             target: to,
+                            relative: self.relative,
+                            hard: self.hard,
+                            force: self.force,
+                            adopt: false,
                         }),
                         initializers: vec![Ensure(Box::new(FileExists(from)))],
                         finalizers: vec![],
@@ -81,7 +97,7 @@ impl FileLink {
         }
     }
 
-    pub fn plan_walk(from: PathBuf, to: PathBuf) -> Vec<Step> {
+    pub fn plan_walk(&self, from: PathBuf, to: PathBuf) -> Vec<Step> {
         use crate::atoms::directory::Create as DirCreate;
         use crate::atoms::file::Link;
 
@@ -101,6 +117,10 @@ impl FileLink {
                             atom: Box::new(Link {
                                 source: p.clone(),
                                 target: to.join(file_name),
+                                relative: self.relative,
+                                hard: self.hard,
+                                force: self.force,
+                                adopt: false,
                             }),
                             initializers: vec![Ensure(Box::new(FileExists(p.clone())))],
                             finalizers: vec![],
@@ -125,6 +145,18 @@ impl Action for FileLink {
         )
     }
 
+    fn files(&self, manifest: &Manifest) -> Vec<PathBuf> {
+        let Some(source) = self.source.as_ref().or(self.from.as_ref()) else {
+            return vec![];
+        };
+
+        manifest
+            .root_dir
+            .iter()
+            .map(|root_dir| root_dir.join("files").join(source))
+            .collect()
+    }
+
     fn plan(&self, manifest: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
         let from: PathBuf = self.resolve(manifest, self.source().as_str())?;
 
@@ -132,12 +164,12 @@ impl Action for FileLink {
 
         // Can't walk a file
         if from.is_file() {
-            return Ok(FileLink::plan_no_walk(from, to));
+            return Ok(self.plan_no_walk(from, to));
         }
 
         match self.walk_dir {
-            false => Ok(FileLink::plan_no_walk(from, to)),
-            true => Ok(FileLink::plan_walk(from, to)),
+            false => Ok(self.plan_no_walk(from, to)),
+            true => Ok(self.plan_walk(from, to)),
         }
     }
 }
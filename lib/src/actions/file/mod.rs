@@ -6,9 +6,10 @@ pub mod remove;
 use crate::actions::Action;
 use crate::manifests::Manifest;
 use anyhow::{anyhow, Result};
+use globset::GlobBuilder;
 use normpath::PathExt;
 use serde::{de::Error, Deserialize, Deserializer};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub trait FileAction: Action {
     fn resolve(&self, manifest: &Manifest, path: &str) -> anyhow::Result<PathBuf> {
@@ -64,3 +65,98 @@ where
 fn default_chmod() -> u32 {
     0o644
 }
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '{'])
+}
+
+/// The directory before the first wildcard, which is the only one that needs
+/// to be searched. Matches are named relative to it.
+fn glob_root(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+        .collect()
+}
+
+/// The files matching `pattern`, sorted, or `pattern` itself when it isn't a glob.
+/// `*` doesn't cross directories, `**` does.
+fn expand(pattern: &Path) -> Result<Vec<PathBuf>> {
+    if !is_glob(&pattern.to_string_lossy()) {
+        return Ok(vec![pattern.to_path_buf()]);
+    }
+
+    let root = glob_root(pattern);
+
+    let glob = GlobBuilder::new(&pattern.to_string_lossy())
+        .literal_separator(true)
+        .build()?
+        .compile_matcher();
+
+    let relative = root.as_os_str().is_empty();
+    let mut paths: Vec<PathBuf> =
+        walkdir::WalkDir::new(if relative { Path::new(".") } else { &root })
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| match relative {
+                true => entry
+                    .path()
+                    .strip_prefix(".")
+                    .unwrap_or(entry.path())
+                    .to_path_buf(),
+                false => entry.into_path(),
+            })
+            .filter(|path| glob.is_match(path))
+            .collect();
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    #[test]
+    fn it_expands_globs() {
+        let tmpdir = tempdir().unwrap();
+        std::fs::create_dir_all(tmpdir.path().join("configs/nested")).unwrap();
+        for file in [
+            "configs/a.toml",
+            "configs/b.toml",
+            "configs/c.yaml",
+            "configs/nested/d.toml",
+        ] {
+            std::fs::write(tmpdir.path().join(file), "").unwrap();
+        }
+
+        assert_eq!(
+            vec![
+                tmpdir.path().join("configs/a.toml"),
+                tmpdir.path().join("configs/b.toml")
+            ],
+            expand(&tmpdir.path().join("configs/*.toml")).unwrap()
+        );
+        assert_eq!(
+            4,
+            expand(&tmpdir.path().join("configs/**/*")).unwrap().len()
+        );
+        assert_eq!(
+            vec![tmpdir.path().join("configs/missing")],
+            expand(&tmpdir.path().join("configs/missing")).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_finds_the_root_of_globs() {
+        assert_eq!(
+            PathBuf::from("/files/configs"),
+            glob_root(Path::new("/files/configs/**/*.toml"))
+        );
+        assert_eq!(PathBuf::new(), glob_root(Path::new("*.toml")));
+    }
+}
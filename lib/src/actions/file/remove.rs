@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{actions::Action, steps::Step};
 
-use super::FileAction;
+use super::{expand, FileAction};
 
 #[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileRemove {
@@ -28,13 +28,15 @@ impl Action for FileRemove {
     ) -> anyhow::Result<Vec<crate::steps::Step>> {
         use crate::atoms::file::Remove as RemoveFile;
 
-        let path = PathBuf::from(&self.target);
-
-        let steps = vec![Step {
-            atom: Box::new(RemoveFile { target: path }),
-            initializers: vec![],
-            finalizers: vec![],
-        }];
+        // A glob removes every file it matches, and nothing when none do
+        let steps = expand(&PathBuf::from(&self.target))?
+            .into_iter()
+            .map(|target| Step {
+                atom: Box::new(RemoveFile { target }),
+                initializers: vec![],
+                finalizers: vec![],
+            })
+            .collect();
 
         Ok(steps)
     }
@@ -42,7 +44,9 @@ impl Action for FileRemove {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
 
     #[test]
     fn it_can_be_deserialized() {
@@ -62,4 +66,22 @@ mod tests {
             }
         };
     }
+
+    #[test]
+    fn it_removes_globs() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        for file in ["a.bak", "b.bak", "c.conf"] {
+            std::fs::write(tmpdir.path().join(file), "").unwrap();
+        }
+
+        let action = FileRemove {
+            target: tmpdir.path().join("*.bak").display().to_string(),
+        };
+
+        let steps = action
+            .plan(&Default::default(), &Default::default())
+            .unwrap();
+
+        assert_eq!(2, steps.len());
+    }
 }
@@ -0,0 +1,2 @@
+mod overrides;
+pub use overrides::FlatpakOverride;
@@ -0,0 +1,257 @@
+use crate::actions::Action;
+use crate::atoms::command::Exec;
+use crate::contexts::Contexts;
+use crate::manifests::Manifest;
+use crate::steps::Step;
+use crate::utilities::list_output;
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use which::which;
+
+/// Grants a flatpak application access outside of its sandbox, with `flatpak override`
+#[derive(JsonSchema, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlatpakOverride {
+    /// Application ID, like `org.mozilla.firefox`
+    pub app: String,
+
+    /// Like `~/.themes:ro` or `xdg-config/gtk-3.0`
+    #[serde(default)]
+    pub filesystems: Vec<String>,
+
+    /// Like `wayland` or `ssh-auth`
+    #[serde(default)]
+    pub sockets: Vec<String>,
+
+    /// Like `dri` or `all`
+    #[serde(default)]
+    pub devices: Vec<String>,
+
+    /// Names on the session bus it can talk to
+    #[serde(default)]
+    pub talk_names: Vec<String>,
+
+    /// Environment variables set in the sandbox, like `GTK_THEME`
+    #[serde(default)]
+    pub environment: BTreeMap<String, String>,
+
+    /// Overrides for the current user, or for everyone, which needs privileges
+    #[serde(default = "yes")]
+    pub user: bool,
+}
+
+impl Default for FlatpakOverride {
+    fn default() -> Self {
+        FlatpakOverride {
+            app: String::new(),
+            filesystems: vec![],
+            sockets: vec![],
+            devices: vec![],
+            talk_names: vec![],
+            environment: BTreeMap::new(),
+            user: true,
+        }
+    }
+}
+
+fn yes() -> bool {
+    true
+}
+
+/// The overrides `flatpak override --show` prints, in the keyfile format
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Overrides {
+    filesystems: Vec<String>,
+    sockets: Vec<String>,
+    devices: Vec<String>,
+    talk_names: Vec<String>,
+    environment: BTreeMap<String, String>,
+}
+
+impl Overrides {
+    fn parse(keyfile: &str) -> Overrides {
+        let mut overrides = Overrides::default();
+        let mut group = "";
+
+        let list = |value: &str| {
+            value
+                .split(';')
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<String>>()
+        };
+
+        for line in keyfile.lines().map(str::trim) {
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                group = name;
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            match (group, key) {
+                ("Context", "filesystems") => overrides.filesystems = list(value),
+                ("Context", "sockets") => overrides.sockets = list(value),
+                ("Context", "devices") => overrides.devices = list(value),
+                ("Environment", _) => {
+                    overrides
+                        .environment
+                        .insert(key.to_string(), value.to_string());
+                }
+                ("Session Bus Policy", _) if value == "talk" => {
+                    overrides.talk_names.push(key.to_string())
+                }
+                _ => (),
+            }
+        }
+
+        overrides
+    }
+}
+
+impl FlatpakOverride {
+    /// The options of `flatpak override` for what isn't overridden yet
+    fn missing(&self, current: &Overrides) -> Vec<String> {
+        let mut options = vec![];
+
+        let mut missing = |option: &str, wanted: &[String], current: &[String]| {
+            options.extend(
+                wanted
+                    .iter()
+                    .filter(|value| !current.contains(value))
+                    .map(|value| format!("--{option}={value}")),
+            );
+        };
+
+        missing("filesystem", &self.filesystems, &current.filesystems);
+        missing("socket", &self.sockets, &current.sockets);
+        missing("device", &self.devices, &current.devices);
+        missing("talk-name", &self.talk_names, &current.talk_names);
+
+        options.extend(
+            self.environment
+                .iter()
+                .filter(|(name, value)| current.environment.get(*name) != Some(value))
+                .map(|(name, value)| format!("--env={name}={value}")),
+        );
+
+        options
+    }
+
+    fn scope(&self) -> &'static str {
+        match self.user {
+            true => "--user",
+            false => "--system",
+        }
+    }
+}
+
+impl Action for FlatpakOverride {
+    fn summarize(&self) -> String {
+        format!("Overriding flatpak permissions of {}", self.app)
+    }
+
+    fn plan(&self, _: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
+        if which("flatpak").is_err() {
+            return Err(anyhow!("flatpak isn't installed to override {}", self.app));
+        }
+
+        let current = Overrides::parse(&list_output(
+            "flatpak",
+            &["override", self.scope(), "--show", &self.app],
+        ));
+
+        let options = self.missing(&current);
+        if options.is_empty() {
+            return Ok(vec![]);
+        }
+
+        Ok(vec![Step {
+            atom: Box::new(Exec {
+                command: String::from("flatpak"),
+                arguments: [
+                    vec![String::from("override"), String::from(self.scope())],
+                    options,
+                    vec![self.app.clone()],
+                ]
+                .concat(),
+                privileged: !self.user,
+                ..Default::default()
+            }),
+            initializers: vec![],
+            finalizers: vec![],
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let yaml = r#"
+- action: flatpak.override
+  app: org.mozilla.firefox
+  filesystems: ["~/.themes:ro"]
+  environment:
+    GTK_THEME: Adwaita-dark
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::FlatpakOverride(action)) => {
+                assert_eq!("org.mozilla.firefox", action.action.app);
+                assert_eq!(
+                    vec![String::from("~/.themes:ro")],
+                    action.action.filesystems
+                );
+                assert_eq!(true, action.action.user);
+            }
+            _ => {
+                panic!("FlatpakOverride didn't deserialize to the correct type");
+            }
+        };
+    }
+
+    #[test]
+    fn it_only_overrides_whats_missing() {
+        let current = Overrides::parse(
+            "[Context]\nfilesystems=~/.themes:ro;xdg-config/gtk-3.0;\nsockets=wayland;\n\n[Environment]\nGTK_THEME=Adwaita\n\n[Session Bus Policy]\norg.freedesktop.Flatpak=talk\n",
+        );
+
+        assert_eq!(
+            vec![
+                String::from("~/.themes:ro"),
+                String::from("xdg-config/gtk-3.0")
+            ],
+            current.filesystems
+        );
+
+        let action = FlatpakOverride {
+            app: String::from("org.mozilla.firefox"),
+            filesystems: vec![String::from("~/.themes:ro"), String::from("~/.icons:ro")],
+            sockets: vec![String::from("wayland")],
+            talk_names: vec![String::from("org.freedesktop.Flatpak")],
+            environment: BTreeMap::from([(
+                String::from("GTK_THEME"),
+                String::from("Adwaita-dark"),
+            )]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            vec![
+                String::from("--filesystem=~/.icons:ro"),
+                String::from("--env=GTK_THEME=Adwaita-dark")
+            ],
+            action.missing(&current)
+        );
+    }
+}
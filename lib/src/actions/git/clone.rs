@@ -0,0 +1,158 @@
+use crate::actions::{default_retry_delay, Action, Retry};
+use crate::atoms::git::{Clone, Sync};
+use crate::contexts::Contexts;
+use crate::manifests::Manifest;
+use crate::steps::Step;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GitClone {
+    /// A URL, or `owner/repo` on GitHub
+    pub repository: String,
+
+    pub directory: String,
+
+    /// A branch, tag or commit, instead of the default branch
+    #[serde(alias = "ref", alias = "branch", alias = "tag", alias = "commit")]
+    pub reference: Option<String>,
+
+    /// Only clones this many commits of history, `1` for the latest
+    pub depth: Option<u32>,
+
+    /// Initializes submodules, recursively
+    #[serde(default)]
+    pub submodules: bool,
+
+    /// Only checks out these directories, for monorepos
+    #[serde(default)]
+    pub sparse: Vec<String>,
+
+    /// Brings an existing clone up to date with the reference
+    #[serde(default)]
+    pub sync: bool,
+
+    #[serde(default)]
+    pub retries: u32,
+
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
+}
+
+impl GitClone {
+    fn url(&self) -> String {
+        let shorthand = !self.repository.contains(':')
+            && !self.repository.starts_with(['/', '.', '~'])
+            && self.repository.matches('/').count() == 1;
+
+        match shorthand {
+            true => format!("https://github.com/{}.git", self.repository),
+            false => self.repository.clone(),
+        }
+    }
+}
+
+impl Action for GitClone {
+    fn summarize(&self) -> String {
+        format!("Cloning {} to {}", self.repository, self.directory)
+    }
+
+    fn retry(&self) -> Retry {
+        Retry {
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+        }
+    }
+
+    fn plan(&self, _: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
+        let directory = PathBuf::from(&self.directory);
+
+        let mut steps = vec![Step {
+            atom: Box::new(Clone {
+                repository: self.url(),
+                directory: directory.clone(),
+                reference: self.reference.clone(),
+                depth: self.depth,
+                submodules: self.submodules,
+                sparse: self.sparse.clone(),
+            }),
+            initializers: vec![],
+            finalizers: vec![],
+        }];
+
+        // Fresh clones are already at the reference, so this only runs for old ones
+        if self.sync {
+            steps.push(Step {
+                atom: Box::new(Sync {
+                    directory,
+                    reference: self.reference.clone(),
+                    depth: self.depth,
+                    submodules: self.submodules,
+                    sparse: self.sparse.clone(),
+                }),
+                initializers: vec![],
+                finalizers: vec![],
+            });
+        }
+
+        Ok(steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let yaml = r#"
+- action: git.clone
+  repository: comtrya/comtrya
+  directory: /src/comtrya
+  tag: v0.9.0
+  depth: 1
+  submodules: true
+  sparse:
+    - nvim
+  sync: true
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::GitClone(action)) => {
+                assert_eq!(Some(String::from("v0.9.0")), action.action.reference);
+                assert_eq!(Some(1), action.action.depth);
+                assert_eq!(true, action.action.submodules);
+                assert_eq!(vec![String::from("nvim")], action.action.sparse);
+                assert_eq!(true, action.action.sync);
+                assert_eq!(
+                    "https://github.com/comtrya/comtrya.git",
+                    action.action.url()
+                );
+            }
+            _ => {
+                panic!("GitClone didn't deserialize to the correct type");
+            }
+        };
+    }
+
+    #[test]
+    fn it_keeps_urls() {
+        for repository in [
+            "git@github.com:comtrya/comtrya.git",
+            "https://gitlab.com/comtrya/comtrya",
+            "/srv/git/dotfiles",
+        ] {
+            let action = GitClone {
+                repository: repository.to_string(),
+                ..Default::default()
+            };
+
+            assert_eq!(repository, action.url());
+        }
+    }
+}
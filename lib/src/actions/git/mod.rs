@@ -0,0 +1,3 @@
+mod clone;
+
+pub use clone::GitClone;
@@ -0,0 +1,226 @@
+use crate::contexts::{to_tera, Contexts};
+use crate::manifests::Manifest;
+use anyhow::anyhow;
+use regex::{Captures, Regex};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+/// References to registered outputs, like `{{ name.stdout }}`, `{{name.exit_code}}`
+/// or `{{ name.stdout | trim }}`, with the name, the field and the filters
+pub(crate) fn references() -> &'static Regex {
+    static REFERENCES: OnceLock<Regex> = OnceLock::new();
+
+    REFERENCES.get_or_init(|| {
+        Regex::new(r"\{\{-?\s*([A-Za-z_][A-Za-z0-9_]*)\.(stdout|stderr|exit_code)\s*(?:\|([^}]*?))?\s*-?\}\}")
+            .expect("reference pattern is a valid regex")
+    })
+}
+
+/// Fills in the references to registered outputs left in the string fields of
+/// an action when it's planned, like `{{ node_version.stdout }}`, with the
+/// outputs registered earlier in the run. Manifests are rendered when they're
+/// loaded, before anything has run, so nothing else is rendered again.
+/// Outputs whose action hasn't run yet, like in dry-runs, are left as they
+/// are, and references to outputs that nothing registers are an error.
+pub(crate) fn interpolate<'a, T>(
+    action: &'a T,
+    manifest: &Manifest,
+    contexts: &Contexts,
+) -> anyhow::Result<Cow<'a, T>>
+where
+    T: Clone + Serialize + DeserializeOwned,
+{
+    let mut fields = serde_json::to_value(action)?;
+
+    if !render(&mut fields, manifest, contexts)? {
+        return Ok(Cow::Borrowed(action));
+    }
+
+    Ok(Cow::Owned(serde_json::from_value(fields)?))
+}
+
+/// The output a reference refers to, or the reference itself when its action
+/// hasn't run yet
+fn output(
+    reference: &Captures,
+    manifest: &Manifest,
+    contexts: &Contexts,
+) -> anyhow::Result<String> {
+    let (name, field) = (&reference[1], &reference[2]);
+
+    let Some(value) = contexts.get(name).and_then(|values| values.get(field)) else {
+        return match manifest
+            .registered
+            .iter()
+            .any(|registered| registered == name)
+        {
+            true => Ok(reference[0].to_string()),
+            false => Err(anyhow!(
+                "No output is registered as `{}`, for `{}`",
+                name,
+                &reference[0]
+            )),
+        };
+    };
+
+    match reference.get(3).map(|filters| filters.as_str().trim()) {
+        Some(filters) if !filters.is_empty() => Ok(tera::Tera::one_off(
+            &format!("{{{{ {name}.{field} | {filters} }}}}"),
+            &to_tera(contexts),
+            false,
+        )?),
+        _ => Ok(value.to_string()),
+    }
+}
+
+/// Whether any string changed
+fn render(value: &mut Value, manifest: &Manifest, contexts: &Contexts) -> anyhow::Result<bool> {
+    match value {
+        Value::String(string) if string.contains("{{") => {
+            let mut error = None;
+            let rendered = references().replace_all(string, |reference: &Captures| {
+                output(reference, manifest, contexts).unwrap_or_else(|err| {
+                    error.get_or_insert(err);
+                    reference[0].to_string()
+                })
+            });
+
+            if let Some(error) = error {
+                return Err(error);
+            }
+
+            let rendered = rendered.into_owned();
+            let changed = rendered != *string;
+            *string = rendered;
+            Ok(changed)
+        }
+        Value::Array(values) => {
+            let mut changed = false;
+            for value in values {
+                changed |= render(value, manifest, contexts)?;
+            }
+            Ok(changed)
+        }
+        Value::Object(values) => {
+            let mut changed = false;
+            for value in values.values_mut() {
+                changed |= render(value, manifest, contexts)?;
+            }
+            Ok(changed)
+        }
+        _ => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::command::run::RunCommand;
+    use pretty_assertions::assert_eq;
+
+    fn contexts() -> Contexts {
+        let mut contexts = Contexts::default();
+        contexts.insert(
+            String::from("node_version"),
+            [
+                (String::from("stdout"), " v20.1.0 ".into()),
+                (String::from("exit_code"), 0.into()),
+            ]
+            .into(),
+        );
+        contexts.insert(
+            String::from("user"),
+            [(String::from("username"), "root".into())].into(),
+        );
+        contexts
+    }
+
+    fn echo(args: &[&str]) -> RunCommand {
+        RunCommand {
+            command: String::from("echo"),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_renders_registered_contexts() {
+        let action = echo(&[
+            "{{ node_version.stdout }}",
+            "--format={{.Names}}",
+            "{{ user.username }}",
+        ]);
+
+        // Only registered outputs are filled in, anything else, like what
+        // `{% raw %}` kept, was rendered with the manifest already
+        assert_eq!(
+            vec![" v20.1.0 ", "--format={{.Names}}", "{{ user.username }}"],
+            interpolate(&action, &Manifest::default(), &contexts())
+                .unwrap()
+                .args
+        );
+    }
+
+    #[test]
+    fn it_renders_every_spelling() {
+        let action = echo(&[
+            "{{node_version.stdout}}",
+            "{{  node_version.stdout   }}",
+            "{{ node_version.exit_code }}",
+            "{{ node_version.stdout | trim }}",
+            "{{node_version.stdout|trim|upper}}",
+        ]);
+
+        assert_eq!(
+            vec![" v20.1.0 ", " v20.1.0 ", "0", "v20.1.0", "V20.1.0"],
+            interpolate(&action, &Manifest::default(), &contexts())
+                .unwrap()
+                .args
+        );
+    }
+
+    #[test]
+    fn it_rejects_outputs_that_arent_registered() {
+        let action = echo(&["{{ nodeversion.stdout }}"]);
+
+        assert_eq!(
+            "No output is registered as `nodeversion`, for `{{ nodeversion.stdout }}`",
+            interpolate(&action, &Manifest::default(), &contexts())
+                .unwrap_err()
+                .to_string()
+        );
+
+        // Contexts that aren't outputs don't have them either
+        assert!(interpolate(
+            &echo(&["{{ user.stdout }}"]),
+            &Manifest::default(),
+            &contexts()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_keeps_outputs_that_werent_registered_yet() {
+        let manifest = Manifest {
+            registered: vec![String::from("go_version")],
+            ..Default::default()
+        };
+        let action = echo(&["{{ go_version.stdout | trim }}"]);
+
+        // Like in dry-runs, where the command registering them doesn't run
+        assert_eq!(
+            vec!["{{ go_version.stdout | trim }}"],
+            interpolate(&action, &manifest, &contexts()).unwrap().args
+        );
+    }
+
+    #[test]
+    fn it_borrows_actions_without_templates() {
+        assert!(matches!(
+            interpolate(&echo(&[]), &Manifest::default(), &Contexts::default()),
+            Ok(Cow::Borrowed(_))
+        ));
+    }
+}
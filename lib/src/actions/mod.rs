@@ -2,29 +2,58 @@ mod binary;
 mod command;
 mod directory;
 mod file;
+mod flatpak;
+mod git;
 mod group;
+pub(crate) mod interpolate;
 mod macos;
+mod network;
 mod package;
+mod plugin;
+mod plugins;
+mod printer;
+mod retry;
+mod runtime;
+mod rust;
+mod script;
+mod shell;
+mod system;
 mod user;
 
-use crate::contexts::Contexts;
+use crate::contexts::{profile::in_profile, Contexts};
 use crate::manifests::Manifest;
 use crate::steps::Step;
 use anyhow::anyhow;
 use binary::BinaryGitHub;
 use command::run::RunCommand;
-use directory::{DirectoryCopy, DirectoryCreate, DirectoryRemove};
+use directory::{DirectoryCopy, DirectoryCreate, DirectoryLink, DirectoryRemove};
 use file::copy::FileCopy;
 use file::download::FileDownload;
 use file::link::FileLink;
 use file::remove::FileRemove;
+use flatpak::FlatpakOverride;
+use git::GitClone;
 use group::add::GroupAdd;
+use interpolate::interpolate;
 use macos::MacOSDefault;
+use network::NetworkConnection;
+pub use package::installed_packages;
 use package::{PackageInstall, PackageRepository};
-use rhai::Engine;
+use plugin::PluginAction;
+pub use plugin::{set_plugin_dirs, PLUGIN_PREFIX};
+use plugins::{NeovimPlugins, TmuxPlugins};
+use printer::PrinterAdd;
+use retry::default_retry_delay;
+pub use retry::Retry;
+use runtime::RuntimeInstall;
+use rust::RustToolchain;
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use script::RhaiScript;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use shell::ShellFramework;
 use std::fmt::Display;
+use std::path::PathBuf;
+use system::SystemDns;
 use tracing::{error, warn};
 use user::add::UserAdd;
 
@@ -41,6 +70,34 @@ pub struct ConditionalVariantAction<T> {
 
     #[serde(default)]
     pub variants: Vec<Variant<T>>,
+
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Only runs when one of these profiles is active
+    #[serde(default)]
+    pub profiles: Vec<String>,
+
+    #[serde(default)]
+    pub ignore_errors: bool,
+
+    /// Names the action, for the `when_changed` of later actions
+    #[serde(default)]
+    pub id: Option<String>,
+
+    /// Only runs when the action with this id changed something earlier in the run
+    #[serde(default)]
+    pub when_changed: Option<String>,
+
+    /// Ids of the actions that finish before this one starts, in manifests
+    /// that aren't `ordered`
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+
+    /// Overrides `privilege_policy` for this action: `false` skips its
+    /// privileged steps, `true` runs them, asking for the password when needed
+    #[serde(default)]
+    pub allow_sudo: Option<bool>,
 }
 
 #[derive(JsonSchema, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -54,14 +111,72 @@ pub struct Variant<T> {
 
 impl<T> Action for ConditionalVariantAction<T>
 where
-    T: Action,
+    T: Action + Clone + Serialize + DeserializeOwned,
 {
     fn summarize(&self) -> String {
         self.action.summarize()
     }
 
+    fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    fn register(&self) -> Option<&str> {
+        self.action.register()
+    }
+
+    fn ignore_errors(&self) -> bool {
+        self.ignore_errors
+    }
+
+    fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    fn when_changed(&self) -> Option<&str> {
+        self.when_changed.as_deref()
+    }
+
+    fn depends_on(&self) -> &[String] {
+        &self.depends_on
+    }
+
+    fn allow_sudo(&self) -> Option<bool> {
+        self.allow_sudo
+    }
+
+    fn retry(&self) -> Retry {
+        self.action.retry()
+    }
+
+    fn conditions(&self) -> Vec<&str> {
+        let mut conditions: Vec<&str> = self.condition.as_deref().into_iter().collect();
+
+        conditions.extend(
+            self.variants
+                .iter()
+                .filter_map(|variant| variant.condition.as_deref()),
+        );
+
+        conditions
+    }
+
+    fn files(&self, manifest: &Manifest) -> Vec<PathBuf> {
+        let mut files = self.action.files(manifest);
+
+        for variant in self.variants.iter() {
+            files.extend(variant.action.files(manifest));
+        }
+
+        files
+    }
+
     fn plan(&self, manifest: &Manifest, context: &Contexts) -> Result<Vec<Step>, anyhow::Error> {
-        let engine = Engine::new();
+        if !in_profile(&self.profiles, context) {
+            return Ok(vec![]);
+        }
+
+        let engine = crate::rhai_functions::engine();
         let mut scope = crate::contexts::to_rhai(context);
 
         let variant = self.variants.iter().find(|variant| {
@@ -81,26 +196,27 @@ where
         });
 
         if let Some(variant) = variant {
-            return variant.action.plan(manifest, context);
+            return interpolate(&variant.action, manifest, context)?.plan(manifest, context);
         }
 
         if self.condition.is_none() {
-            return self.action.plan(manifest, context);
+            return interpolate(&self.action, manifest, context)?.plan(manifest, context);
         }
 
         // .unwrap() is safe here because we checked for None above
         let condition = self.condition.as_ref().unwrap();
 
         match engine.eval_with_scope::<bool>(&mut scope, condition.as_str()) {
-            Ok(true) => self.action.plan(manifest, context),
+            Ok(true) => interpolate(&self.action, manifest, context)?.plan(manifest, context),
             Ok(false) => Ok(vec![]),
             Err(error) => Err(anyhow!("Failed execution condition for action: {}", error)),
         }
     }
 }
 
+// Deserialized by hand, to fall back to plugins for unknown actions
 #[derive(JsonSchema, Clone, Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields, tag = "action")]
+#[serde(remote = "Self", deny_unknown_fields, tag = "action")]
 pub enum Actions {
     #[serde(rename = "command.run", alias = "cmd.run")]
     CommandRun(ConditionalVariantAction<RunCommand>),
@@ -111,6 +227,9 @@ pub enum Actions {
     #[serde(rename = "directory.create", alias = "dir.create")]
     DirectoryCreate(ConditionalVariantAction<DirectoryCreate>),
 
+    #[serde(rename = "directory.link", alias = "dir.link")]
+    DirectoryLink(ConditionalVariantAction<DirectoryLink>),
+
     #[serde(rename = "file.copy")]
     FileCopy(ConditionalVariantAction<FileCopy>),
 
@@ -134,6 +253,9 @@ pub enum Actions {
     )]
     BinaryGitHub(ConditionalVariantAction<BinaryGitHub>),
 
+    #[serde(rename = "git.clone")]
+    GitClone(ConditionalVariantAction<GitClone>),
+
     #[serde(rename = "group.add")]
     GroupAdd(ConditionalVariantAction<GroupAdd>),
 
@@ -146,20 +268,122 @@ pub enum Actions {
     #[serde(rename = "package.repository", alias = "package.repo")]
     PackageRepository(ConditionalVariantAction<PackageRepository>),
 
+    #[serde(rename = "runtime.install")]
+    RuntimeInstall(ConditionalVariantAction<RuntimeInstall>),
+
+    #[serde(rename = "rust.toolchain")]
+    RustToolchain(ConditionalVariantAction<RustToolchain>),
+
+    #[serde(rename = "script.rhai")]
+    RhaiScript(ConditionalVariantAction<RhaiScript>),
+
+    #[serde(rename = "shell.framework")]
+    ShellFramework(ConditionalVariantAction<ShellFramework>),
+
+    #[serde(rename = "flatpak.override")]
+    FlatpakOverride(ConditionalVariantAction<FlatpakOverride>),
+
+    #[serde(rename = "network.connection")]
+    NetworkConnection(ConditionalVariantAction<NetworkConnection>),
+
+    #[serde(rename = "printer.add")]
+    PrinterAdd(ConditionalVariantAction<PrinterAdd>),
+
+    #[serde(rename = "system.dns")]
+    SystemDns(ConditionalVariantAction<SystemDns>),
+
+    #[serde(rename = "tmux.plugins")]
+    TmuxPlugins(ConditionalVariantAction<TmuxPlugins>),
+
+    #[serde(rename = "neovim.plugins")]
+    NeovimPlugins(ConditionalVariantAction<NeovimPlugins>),
+
     #[serde(rename = "user.add")]
     UserAdd(ConditionalVariantAction<UserAdd>),
 
     #[serde(rename = "user.group")]
     UserAddGroup(ConditionalVariantAction<UserAddGroup>),
+
+    #[serde(skip)]
+    Plugin(PluginAction),
+}
+
+impl<'de> Deserialize<'de> for Actions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        #[derive(Deserialize)]
+        struct Name {
+            action: String,
+        }
+
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let Name { action } = Name::deserialize(&value).map_err(D::Error::custom)?;
+
+        if is_built_in(&action) {
+            Actions::deserialize(value).map_err(D::Error::custom)
+        } else {
+            PluginAction::deserialize(value)
+                .map(Actions::Plugin)
+                .map_err(D::Error::custom)
+        }
+    }
+}
+
+/// Whether the action is one of the built-in ones, including their aliases
+fn is_built_in(action: &str) -> bool {
+    /// Only tells an unknown action name apart from the other errors
+    #[derive(Debug)]
+    struct NameCheck {
+        unknown: bool,
+    }
+
+    impl Display for NameCheck {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "unknown action: {}", self.unknown)
+        }
+    }
+
+    impl std::error::Error for NameCheck {}
+
+    impl serde::de::Error for NameCheck {
+        fn custom<T: Display>(_: T) -> Self {
+            NameCheck { unknown: false }
+        }
+
+        fn unknown_variant(_: &str, _: &'static [&'static str]) -> Self {
+            NameCheck { unknown: true }
+        }
+    }
+
+    // Built-in actions fail on their missing fields instead
+    let fields =
+        serde::de::value::MapDeserializer::<_, NameCheck>::new(std::iter::once(("action", action)));
+
+    !matches!(
+        Actions::deserialize(fields),
+        Err(NameCheck { unknown: true })
+    )
+}
+
+impl Serialize for Actions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Actions::Plugin(action) => action.serialize(serializer),
+            action => Actions::serialize(action, serializer),
+        }
+    }
 }
 
 impl Actions {
     pub fn inner_ref(&self) -> &dyn Action {
         match self {
             Actions::BinaryGitHub(a) => a,
+            Actions::GitClone(a) => a,
             Actions::CommandRun(a) => a,
             Actions::DirectoryCopy(a) => a,
             Actions::DirectoryCreate(a) => a,
+            Actions::DirectoryLink(a) => a,
             Actions::FileCopy(a) => a,
             Actions::FileDownload(a) => a,
             Actions::FileLink(a) => a,
@@ -167,12 +391,32 @@ impl Actions {
             Actions::MacOSDefault(a) => a,
             Actions::PackageInstall(a) => a,
             Actions::PackageRepository(a) => a,
+            Actions::RuntimeInstall(a) => a,
+            Actions::RustToolchain(a) => a,
+            Actions::RhaiScript(a) => a,
+            Actions::ShellFramework(a) => a,
+            Actions::FlatpakOverride(a) => a,
+            Actions::NetworkConnection(a) => a,
+            Actions::PrinterAdd(a) => a,
+            Actions::SystemDns(a) => a,
+            Actions::TmuxPlugins(a) => a,
+            Actions::NeovimPlugins(a) => a,
             Actions::UserAdd(a) => a,
             Actions::UserAddGroup(a) => a,
             Actions::FileRemove(a) => a,
             Actions::DirectoryRemove(a) => a,
+            Actions::Plugin(a) => a,
         }
     }
+
+    /// Whether the action runs the system's package manager, which only one
+    /// action can do at a time
+    pub fn uses_package_manager(&self) -> bool {
+        matches!(
+            self,
+            Actions::PackageInstall(_) | Actions::PackageRepository(_)
+        )
+    }
 }
 
 impl Display for Actions {
@@ -181,18 +425,31 @@ impl Display for Actions {
             Actions::CommandRun(_) => "command.run",
             Actions::DirectoryCopy(_) => "directory.copy",
             Actions::DirectoryCreate(_) => "directory.create",
+            Actions::DirectoryLink(_) => "directory.link",
             Actions::FileCopy(_) => "file.copy",
             Actions::FileDownload(_) => "file.download",
             Actions::FileLink(_) => "file.link",
             Actions::FileRemove(_) => "file.remove",
             Actions::DirectoryRemove(_) => "directory.remove",
             Actions::BinaryGitHub(_) => "github.binary",
+            Actions::GitClone(_) => "git.clone",
             Actions::GroupAdd(_) => "group.add",
             Actions::MacOSDefault(_) => "macos.default",
             Actions::PackageInstall(_) => "package.install",
             Actions::PackageRepository(_) => "package.repository",
+            Actions::RuntimeInstall(_) => "runtime.install",
+            Actions::RustToolchain(_) => "rust.toolchain",
+            Actions::RhaiScript(_) => "script.rhai",
+            Actions::ShellFramework(_) => "shell.framework",
+            Actions::FlatpakOverride(_) => "flatpak.override",
+            Actions::NetworkConnection(_) => "network.connection",
+            Actions::PrinterAdd(_) => "printer.add",
+            Actions::SystemDns(_) => "system.dns",
+            Actions::TmuxPlugins(_) => "tmux.plugins",
+            Actions::NeovimPlugins(_) => "neovim.plugins",
             Actions::UserAdd(_) => "user.add",
             Actions::UserAddGroup(_) => "user.group",
+            Actions::Plugin(a) => a.action.as_str(),
         };
 
         write!(f, "{}", name)
@@ -224,6 +481,57 @@ pub trait Action {
         warn!("need to define action summarize");
         "not found action summarize".to_string()
     }
+
+    /// Tags attached to this action, used by `--tags` and `--skip-tags`
+    fn tags(&self) -> &[String] {
+        &[]
+    }
+
+    /// Context name the outcome of this action is stored under, for later actions
+    fn register(&self) -> Option<&str> {
+        None
+    }
+
+    /// A failure of this action is reported, but doesn't fail the manifest
+    fn ignore_errors(&self) -> bool {
+        false
+    }
+
+    /// Name later actions refer to this action by in `when_changed`
+    fn id(&self) -> Option<&str> {
+        None
+    }
+
+    /// Id of an earlier action that must have changed something for this one to run
+    fn when_changed(&self) -> Option<&str> {
+        None
+    }
+
+    /// Ids of actions that must finish before this one, when they don't run in order
+    fn depends_on(&self) -> &[String] {
+        &[]
+    }
+
+    /// Whether privileged steps of this action may run, instead of `privilege_policy`
+    fn allow_sudo(&self) -> Option<bool> {
+        None
+    }
+
+    /// How often planning and executing this action is attempted before it fails
+    fn retry(&self) -> Retry {
+        Retry::default()
+    }
+
+    /// The `where` expressions of this action, including those of its variants
+    fn conditions(&self) -> Vec<&str> {
+        vec![]
+    }
+
+    /// Files from the manifest directory this action needs
+    fn files(&self, _manifest: &Manifest) -> Vec<PathBuf> {
+        vec![]
+    }
+
     fn plan(&self, manifest: &Manifest, context: &Contexts) -> anyhow::Result<Vec<Step>>;
 }
 
@@ -263,7 +571,9 @@ actions:
                     .unwrap()
                     .into_os_string()
                     .into_string()
-                    .unwrap()
+                    .unwrap(),
+                retry_delay: 5,
+                ..Default::default()
             }
         );
 
@@ -271,4 +581,64 @@ actions:
         assert_eq!(variant.condition, Some(String::from("Debian")));
         assert_eq!(variant.action.command, "halt");
     }
+
+    #[test]
+    fn lists_conditions_and_files() {
+        let content = r#"
+actions:
+- action: file.copy
+  from: vimrc
+  to: ~/.vimrc
+  where: os.name == "linux"
+  variants:
+    - where: os.name == "macos"
+      from: vimrc.macos
+      to: ~/.vimrc
+"#;
+        let mut m: Manifest = serde_yml::from_str(content).unwrap();
+        m.root_dir = Some(std::path::PathBuf::from("/dotfiles"));
+
+        let action = m.actions[0].inner_ref();
+
+        assert_eq!(
+            vec![r#"os.name == "linux""#, r#"os.name == "macos""#],
+            action.conditions()
+        );
+        assert_eq!(
+            vec![
+                std::path::PathBuf::from("/dotfiles/files/vimrc"),
+                std::path::PathBuf::from("/dotfiles/files/vimrc.macos")
+            ],
+            action.files(&m)
+        );
+    }
+
+    #[test]
+    fn can_filter_actions_by_tags() {
+        let content = r#"
+tags:
+  - dotfiles
+actions:
+- action: command.run
+  command: echo
+  tags:
+    - shell
+- action: command.run
+  command: echo
+  tags:
+    - editor
+"#;
+        let m: Manifest = serde_yml::from_str(content).unwrap();
+
+        let shell = m.actions[0].inner_ref();
+        let editor = m.actions[1].inner_ref();
+        let tags = |t: &[&str]| t.iter().map(|t| t.to_string()).collect::<Vec<String>>();
+
+        assert!(m.is_action_selected(shell, &[], &[]));
+        assert!(m.is_action_selected(shell, &tags(&["shell"]), &[]));
+        assert!(!m.is_action_selected(editor, &tags(&["shell"]), &[]));
+        assert!(m.is_action_selected(editor, &tags(&["dotfiles"]), &[]));
+        assert!(!m.is_action_selected(shell, &[], &tags(&["shell"])));
+        assert!(!m.is_action_selected(editor, &tags(&["editor"]), &tags(&["dotfiles"])));
+    }
 }
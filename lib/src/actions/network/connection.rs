@@ -0,0 +1,383 @@
+use crate::actions::Action;
+use crate::atoms::command::Exec;
+use crate::atoms::SideEffect;
+use crate::contexts::Contexts;
+use crate::manifests::Manifest;
+use crate::secrets::Secret;
+use crate::steps::Step;
+use crate::utilities::list_output;
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use which::which;
+
+const CONNECTIONS: &str = "/etc/NetworkManager/system-connections";
+const PSK: &str = "802-11-wireless-security.psk";
+
+/// A NetworkManager connection profile, for wifi when there's an SSID, and
+/// ethernet otherwise
+#[derive(JsonSchema, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkConnection {
+    /// Name of the profile
+    pub name: String,
+
+    pub ssid: Option<String>,
+
+    /// The WPA passphrase, open networks don't have one
+    pub psk: Option<Secret>,
+
+    /// Only uses this device, any that fits when not set
+    pub interface: Option<String>,
+
+    /// A static address like `192.168.1.10/24`, DHCP when not set
+    pub address: Option<String>,
+
+    pub gateway: Option<String>,
+
+    #[serde(default)]
+    pub dns: Vec<String>,
+
+    #[serde(default = "yes")]
+    pub autoconnect: bool,
+}
+
+impl Default for NetworkConnection {
+    fn default() -> Self {
+        NetworkConnection {
+            name: String::new(),
+            ssid: None,
+            psk: None,
+            interface: None,
+            address: None,
+            gateway: None,
+            dns: vec![],
+            autoconnect: true,
+        }
+    }
+}
+
+fn yes() -> bool {
+    true
+}
+
+impl NetworkConnection {
+    /// The settings as `nmcli -g` shows them, to compare with the existing profile
+    fn settings(&self, psk: Option<&str>) -> Vec<(&'static str, String)> {
+        let mut settings = vec![
+            (
+                "connection.type",
+                String::from(match self.ssid {
+                    Some(_) => "802-11-wireless",
+                    None => "802-3-ethernet",
+                }),
+            ),
+            (
+                "connection.interface-name",
+                self.interface.clone().unwrap_or_default(),
+            ),
+            (
+                "connection.autoconnect",
+                String::from(match self.autoconnect {
+                    true => "yes",
+                    false => "no",
+                }),
+            ),
+        ];
+
+        if let Some(ssid) = &self.ssid {
+            settings.extend([
+                ("802-11-wireless.ssid", ssid.clone()),
+                (
+                    "802-11-wireless-security.key-mgmt",
+                    String::from(if psk.is_some() { "wpa-psk" } else { "" }),
+                ),
+                (PSK, psk.unwrap_or_default().to_string()),
+            ]);
+        }
+
+        settings.extend([
+            (
+                "ipv4.method",
+                String::from(match self.address {
+                    Some(_) => "manual",
+                    None => "auto",
+                }),
+            ),
+            ("ipv4.addresses", self.address.clone().unwrap_or_default()),
+            ("ipv4.gateway", self.gateway.clone().unwrap_or_default()),
+            ("ipv4.dns", self.dns.join(",")),
+        ]);
+
+        settings
+    }
+
+    /// The profile in NetworkManager's keyfile format, keeping the UUID of the
+    /// profile it replaces
+    fn keyfile(&self, psk: Option<&str>, uuid: Option<&str>) -> String {
+        let mut keyfile = format!("[connection]\nid={}\n", self.name);
+
+        if let Some(uuid) = uuid {
+            keyfile.push_str(&format!("uuid={uuid}\n"));
+        }
+
+        keyfile.push_str(match self.ssid {
+            Some(_) => "type=wifi\n",
+            None => "type=ethernet\n",
+        });
+        if let Some(interface) = &self.interface {
+            keyfile.push_str(&format!("interface-name={interface}\n"));
+        }
+        keyfile.push_str(&format!("autoconnect={}\n", self.autoconnect));
+
+        match &self.ssid {
+            Some(ssid) => {
+                keyfile.push_str(&format!("\n[wifi]\nmode=infrastructure\nssid={ssid}\n"));
+                if let Some(psk) = psk {
+                    keyfile.push_str(&format!("\n[wifi-security]\nkey-mgmt=wpa-psk\npsk={psk}\n"));
+                }
+            }
+            None => keyfile.push_str("\n[ethernet]\n"),
+        }
+
+        keyfile.push_str("\n[ipv4]\n");
+        match &self.address {
+            Some(address) => {
+                keyfile.push_str("method=manual\n");
+                match &self.gateway {
+                    Some(gateway) => keyfile.push_str(&format!("address1={address},{gateway}\n")),
+                    None => keyfile.push_str(&format!("address1={address}\n")),
+                }
+            }
+            None => keyfile.push_str("method=auto\n"),
+        }
+        if !self.dns.is_empty() {
+            keyfile.push_str(&format!("dns={};\n", self.dns.join(";")));
+        }
+
+        keyfile.push_str("\n[ipv6]\nmethod=auto\n");
+
+        keyfile
+    }
+}
+
+/// Whether what `nmcli -g` showed, one setting per line, is what's wanted
+fn matches(shown: &str, settings: &[(&str, String)]) -> bool {
+    let shown = shown.lines().collect::<Vec<&str>>();
+
+    shown.len() == settings.len()
+        && shown.iter().zip(settings).all(|(shown, (field, wanted))| {
+            // NetworkManager only shows the secrets of system profiles to root,
+            // so a passphrase that isn't shown can't be compared
+            (*field == PSK && shown.is_empty())
+                // Lists are separated by `,` or `, ` depending on the version
+                || shown.replace(", ", ",") == *wanted
+        })
+}
+
+fn nmcli(arguments: Vec<String>) -> Step {
+    Step {
+        atom: Box::new(Exec {
+            command: String::from("nmcli"),
+            arguments,
+            privileged: true,
+            ..Default::default()
+        }),
+        initializers: vec![],
+        finalizers: vec![],
+    }
+}
+
+impl Action for NetworkConnection {
+    fn summarize(&self) -> String {
+        format!("Configuring network connection {}", self.name)
+    }
+
+    fn plan(&self, _: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
+        if which("nmcli").is_err() {
+            return Err(anyhow!(
+                "NetworkManager isn't installed to configure {}",
+                self.name
+            ));
+        }
+
+        let psk = self.psk.as_ref().map(Secret::resolve).transpose()?;
+        let settings = self.settings(psk.as_deref());
+
+        let fields = settings
+            .iter()
+            .map(|(field, _)| *field)
+            .collect::<Vec<&str>>()
+            .join(",");
+
+        // Secrets are only shown with `-s`, to those allowed to see them, the
+        // others are compared without the passphrase
+        let shown = list_output(
+            "nmcli",
+            &["-s", "-g", &fields, "connection", "show", &self.name],
+        );
+
+        if matches(&shown, &settings) {
+            return Ok(vec![]);
+        }
+
+        let shown = |field: &str| {
+            let value = list_output("nmcli", &["-g", field, "connection", "show", &self.name]);
+            Some(value.trim().to_string()).filter(|value| !value.is_empty())
+        };
+
+        // An existing profile is overwritten in place and loaded again, rather than
+        // deleted first, which would take the connection down in the meantime
+        let uuid = shown("connection.uuid");
+        let path = match shown("GENERAL.FILENAME") {
+            Some(path) if path.ends_with(".nmconnection") => path,
+            Some(path) => {
+                return Err(anyhow!(
+                    "The {} profile is stored in {}, which isn't a keyfile, move it with `nmcli connection migrate` first",
+                    self.name,
+                    path
+                ))
+            }
+            None => format!(
+                "{}/{}.nmconnection",
+                CONNECTIONS,
+                self.name.replace('/', "_")
+            ),
+        };
+
+        // Through stdin, so the passphrase isn't in the plan or the process list.
+        // Moved into place, so a failed write leaves the profile as it was.
+        let write = Step {
+            atom: Box::new(Exec {
+                command: String::from("sh"),
+                arguments: vec![
+                    String::from("-c"),
+                    String::from("umask 077 && cat > \"$1.partial\" && mv \"$1.partial\" \"$1\""),
+                    String::from("sh"),
+                    path.clone(),
+                ],
+                stdin: Some(self.keyfile(psk.as_deref(), uuid.as_deref())),
+                privileged: true,
+                side_effects: vec![SideEffect::Write {
+                    path: PathBuf::from(&path),
+                }],
+                ..Default::default()
+            }),
+            initializers: vec![],
+            finalizers: vec![],
+        };
+
+        Ok(vec![
+            write,
+            nmcli(vec![String::from("connection"), String::from("load"), path]),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
+
+    fn home() -> NetworkConnection {
+        NetworkConnection {
+            name: String::from("Home"),
+            ssid: Some(String::from("home")),
+            psk: Some(Secret::Env {
+                env: String::from("HOME_PSK"),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let yaml = r#"
+- action: network.connection
+  name: Home
+  ssid: home
+  psk:
+    env: HOME_PSK
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::NetworkConnection(action)) => {
+                assert_eq!(home(), action.action);
+            }
+            _ => {
+                panic!("NetworkConnection didn't deserialize to the correct type");
+            }
+        };
+    }
+
+    #[test]
+    fn it_writes_keyfiles() {
+        assert_eq!(
+            "[connection]\nid=Home\ntype=wifi\nautoconnect=true\n\n[wifi]\nmode=infrastructure\nssid=home\n\n[wifi-security]\nkey-mgmt=wpa-psk\npsk=hunter22\n\n[ipv4]\nmethod=auto\n\n[ipv6]\nmethod=auto\n",
+            home().keyfile(Some("hunter22"), None)
+        );
+
+        let office = NetworkConnection {
+            name: String::from("Office"),
+            interface: Some(String::from("eth0")),
+            address: Some(String::from("192.168.1.10/24")),
+            gateway: Some(String::from("192.168.1.1")),
+            dns: vec![String::from("1.1.1.1"), String::from("9.9.9.9")],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            "[connection]\nid=Office\ntype=ethernet\ninterface-name=eth0\nautoconnect=true\n\n[ethernet]\n\n[ipv4]\nmethod=manual\naddress1=192.168.1.10/24,192.168.1.1\ndns=1.1.1.1;9.9.9.9;\n\n[ipv6]\nmethod=auto\n",
+            office.keyfile(None, None)
+        );
+
+        // Replacing a profile keeps its UUID, so NetworkManager updates it in place
+        assert_eq!(
+            true,
+            office
+                .keyfile(None, Some("7d8f6b3e-0c2a-4c55-9a43-2a1e5d9a4c11"))
+                .starts_with(
+                    "[connection]\nid=Office\nuuid=7d8f6b3e-0c2a-4c55-9a43-2a1e5d9a4c11\ntype=ethernet\n"
+                )
+        );
+    }
+
+    #[test]
+    fn it_compares_existing_profiles() {
+        let settings = home().settings(Some("hunter22"));
+
+        assert_eq!(
+            true,
+            matches(
+                "802-11-wireless\n\nyes\nhome\nwpa-psk\nhunter22\nauto\n\n\n\n",
+                &settings
+            )
+        );
+        assert_eq!(
+            false,
+            matches(
+                "802-11-wireless\n\nyes\nhome\nwpa-psk\nchanged\nauto\n\n\n\n",
+                &settings
+            )
+        );
+        // The profile doesn't exist
+        assert_eq!(false, matches("", &settings));
+
+        // The passphrase is hidden from users other than root
+        assert_eq!(
+            true,
+            matches(
+                "802-11-wireless\n\nyes\nhome\nwpa-psk\n\nauto\n\n\n\n",
+                &settings
+            )
+        );
+        // But the network still has to be secured with one
+        assert_eq!(
+            false,
+            matches("802-11-wireless\n\nyes\nhome\n\n\nauto\n\n\n\n", &settings)
+        );
+    }
+}
@@ -1,9 +1,10 @@
-use super::providers::PackageProviders;
+use super::providers::{ForgetInstalled, PackageProviders};
 use super::Package;
 use super::PackageVariant;
-use crate::actions::Action;
+use crate::actions::{Action, Retry};
 use crate::contexts::Contexts;
 use crate::manifests::Manifest;
+use crate::steps::finalizers::FlowControl;
 use crate::steps::Step;
 use anyhow::anyhow;
 use std::ops::Deref;
@@ -14,7 +15,14 @@ pub type PackageInstall = Package;
 
 impl Action for PackageInstall {
     fn summarize(&self) -> String {
-        format!("Installing packages")
+        "Installing packages".to_string()
+    }
+
+    fn retry(&self) -> Retry {
+        Retry {
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+        }
     }
 
     fn plan(&self, _manifest: &Manifest, _context: &Contexts) -> anyhow::Result<Vec<Step>> {
@@ -58,7 +66,17 @@ impl Action for PackageInstall {
             atoms.append(&mut provider.bootstrap());
         }
 
-        atoms.append(&mut provider.install(&variant)?);
+        let mut install = provider.install(&variant)?;
+
+        // Later actions see what this one installed, once it ran
+        if let Some(step) = install.last_mut() {
+            step.finalizers
+                .push(FlowControl::Always(Box::new(ForgetInstalled(
+                    provider.name().to_string(),
+                ))));
+        }
+
+        atoms.append(&mut install);
 
         span.exit();
 
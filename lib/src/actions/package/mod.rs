@@ -2,6 +2,8 @@ mod install;
 mod providers;
 mod repository;
 
+use crate::actions::default_retry_delay;
+use anyhow::anyhow;
 pub(crate) use install::PackageInstall;
 use providers::PackageProviders;
 pub(crate) use repository::PackageRepository;
@@ -35,6 +37,12 @@ pub struct Package {
 
     #[serde(default)]
     file: bool,
+
+    #[serde(default)]
+    retries: u32,
+
+    #[serde(default = "default_retry_delay")]
+    retry_delay: u64,
 }
 
 #[derive(JsonSchema, Clone, Debug, Default, Serialize, Deserialize)]
@@ -54,6 +62,19 @@ pub struct PackageVariant {
     file: bool,
 }
 
+/// Packages explicitly installed with the package provider of the running OS
+pub fn installed_packages() -> anyhow::Result<Vec<String>> {
+    let provider = PackageProviders::detect()
+        .ok_or_else(|| anyhow!("No package provider is known for this OS"))?
+        .get_provider();
+
+    if !provider.available() {
+        return Err(anyhow!("{} isn't available", provider.name()));
+    }
+
+    provider.installed()
+}
+
 impl PackageVariant {
     fn packages(&self) -> Vec<String> {
         self.name
@@ -77,7 +98,7 @@ impl From<&Package> for PackageVariant {
                 list: package.list.clone(),
                 provider: package.provider.clone(),
                 extra_args: package.extra_args.clone(),
-                file: package.file.clone(),
+                file: package.file,
             };
         };
 
@@ -91,7 +112,7 @@ impl From<&Package> for PackageVariant {
             list: package.list.clone(),
             provider: variant.provider.clone(),
             extra_args: variant.extra_args.clone(),
-            file: package.file.clone(),
+            file: package.file,
         };
 
         if variant.name.is_some() {
@@ -1,5 +1,6 @@
-use super::PackageProvider;
-use crate::actions::package::{repository::PackageRepository, PackageVariant};
+use super::{list_packages, not_installed, PackageProvider};
+use crate::actions::package::repository::{PackageRepository, RepositoryKey};
+use crate::actions::package::PackageVariant;
 use crate::atoms::command::Exec;
 use crate::steps::Step;
 use serde::{Deserialize, Serialize};
@@ -67,22 +68,31 @@ impl PackageProvider for Aptitude {
             // .unwrap() is safe here because we checked for key.is_some() above
             let key = repository.clone().key.unwrap();
 
-            let key_name = key.name.unwrap_or_else(|| digest(&*key.url));
+            let key_name = key.name.clone().unwrap_or_else(|| digest(&*key.url));
             let key_path = format!("/usr/share/keyrings/{}.asc", key_name);
 
             signed_by = format!("signed-by={}", key_path);
 
-            steps.push(Step {
-                atom: Box::new(Exec {
-                    command: String::from("curl"),
-                    arguments: vec![String::from("-o"), key_path, key.url],
-                    environment: self.env(),
-                    privileged: true,
-                    ..Default::default()
-                }),
-                initializers: vec![],
-                finalizers: vec![],
-            });
+            let (download, downloaded) = key.download();
+
+            steps.extend([
+                download,
+                Step {
+                    atom: Box::new(Exec {
+                        command: String::from("install"),
+                        arguments: vec![
+                            String::from("-m"),
+                            String::from("0644"),
+                            downloaded.display().to_string(),
+                            key_path,
+                        ],
+                        privileged: true,
+                        ..Default::default()
+                    }),
+                    initializers: vec![],
+                    finalizers: vec![RepositoryKey::cleanup(&downloaded)],
+                },
+            ]);
         }
 
         //sudo apt-add-repository "deb [arch=$(dpkg --print-architecture) signed-by=/usr/share/keyrings/<myrepository>-archive-keyring.gpg] https://repository.example.com/debian/ $(lsb_release -cs) stable main "
@@ -122,32 +132,50 @@ impl PackageProvider for Aptitude {
     }
 
     fn query(&self, package: &PackageVariant) -> anyhow::Result<Vec<String>> {
-        Ok(package.packages())
+        Ok(not_installed(self.name(), package, || {
+            // Removed packages keep their config, and are listed until purged
+            Ok(list_packages(
+                "dpkg-query",
+                &["--show", "--showformat", "${db:Status-Abbrev}${Package}\n"],
+            )?
+            .iter()
+            .filter_map(|line| line.strip_prefix("ii "))
+            .map(|package| package.trim().to_string())
+            .collect())
+        }))
     }
 
     fn install(&self, package: &PackageVariant) -> anyhow::Result<Vec<Step>> {
+        let need_installed = self.query(package)?;
+        if need_installed.is_empty() {
+            return Ok(vec![]);
+        }
+
         Ok(vec![Step {
             atom: Box::new(Exec {
                 command: String::from("apt"),
                 arguments: vec![String::from("install"), String::from("--yes")]
                     .into_iter()
                     .chain(package.extra_args.clone())
-                    .chain(package.packages())
+                    .chain(need_installed.clone())
                     .collect(),
                 environment: self.env(),
                 privileged: true,
+                side_effects: self.installs(need_installed),
                 ..Default::default()
             }),
             initializers: vec![],
             finalizers: vec![],
         }])
     }
+
+    fn installed(&self) -> anyhow::Result<Vec<String>> {
+        list_packages("apt-mark", &["showmanual"])
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::actions::package::repository::RepositoryKey;
-
     use super::*;
 
     // These tests are really weak at the moment, but that's because I'm not
@@ -177,7 +205,7 @@ mod test {
             ..Default::default()
         });
 
-        assert_eq!(steps.unwrap().len(), 3);
+        assert_eq!(steps.unwrap().len(), 4);
     }
 
     #[test]
@@ -193,7 +221,7 @@ mod test {
             ..Default::default()
         });
 
-        assert_eq!(steps.unwrap().len(), 3);
+        assert_eq!(steps.unwrap().len(), 4);
     }
 
     #[test]
@@ -209,16 +237,13 @@ mod test {
             ..Default::default()
         });
 
-        let steps = match steps {
-            Ok(s) => s,
-            Err(_) => vec![],
-        };
+        let steps = steps.unwrap_or_default();
 
-        if let Some(step) = steps.first() {
+        if let Some(step) = steps.get(1) {
             let exec = step.atom.to_string();
             assert!(exec.contains(" /usr/share/keyrings/"));
         } else {
-            assert!(false);
+            panic!("expected the key to be installed");
         }
     }
 }
@@ -1,4 +1,4 @@
-use super::PackageProvider;
+use super::{list_packages, not_installed, PackageProvider};
 use crate::actions::package::repository::PackageRepository;
 use crate::steps::finalizers::FlowControl::StopIf;
 use crate::steps::finalizers::OutputContains;
@@ -56,9 +56,9 @@ impl PackageProvider for BsdPkg {
     }
 
     fn query(&self, package: &PackageVariant) -> anyhow::Result<Vec<String>> {
-        // Install all packages for now, don't get smart about which
-        // already are
-        Ok(package.packages())
+        Ok(not_installed(self.name(), package, || {
+            list_packages("pkg", &["query", "%n"])
+        }))
     }
 
     fn install(&self, package: &PackageVariant) -> anyhow::Result<Vec<Step>> {
@@ -72,6 +72,7 @@ impl PackageProvider for BsdPkg {
                         .chain(package.packages())
                         .collect(),
                     privileged: true,
+                    side_effects: self.installs(package.packages()),
                     ..Default::default()
                 }),
                 initializers: vec![],
@@ -79,6 +80,11 @@ impl PackageProvider for BsdPkg {
             }]);
         }
 
+        let need_installed = self.query(package)?;
+        if need_installed.is_empty() {
+            return Ok(vec![]);
+        }
+
         Ok(vec![
             Step {
                 atom: Box::new(Exec {
@@ -86,9 +92,10 @@ impl PackageProvider for BsdPkg {
                     arguments: vec![String::from("install"), String::from("-y")]
                         .into_iter()
                         .chain(package.extra_args.clone())
-                        .chain(package.packages())
+                        .chain(need_installed.clone())
                         .collect(),
                     privileged: true,
+                    side_effects: self.installs(need_installed.clone()),
                     ..Default::default()
                 }),
                 initializers: vec![],
@@ -100,7 +107,7 @@ impl PackageProvider for BsdPkg {
                     arguments: vec![String::from("install"), String::from("-y")]
                         .into_iter()
                         .chain(package.extra_args.clone())
-                        .chain(package.packages())
+                        .chain(need_installed)
                         .collect(),
                     privileged: true,
                     ..Default::default()
@@ -110,4 +117,8 @@ impl PackageProvider for BsdPkg {
             },
         ])
     }
+
+    fn installed(&self) -> anyhow::Result<Vec<String>> {
+        list_packages("pkg", &["query", "-e", "%a = 0", "%n"])
+    }
 }
@@ -1,6 +1,7 @@
-use super::PackageProvider;
+use super::{list_packages, not_installed, rpm_packages, PackageProvider};
 
-use crate::actions::package::{repository::PackageRepository, PackageVariant};
+use crate::actions::package::repository::{PackageRepository, RepositoryKey};
+use crate::actions::package::PackageVariant;
 use crate::atoms::command::Exec;
 use crate::steps::Step;
 use serde::{Deserialize, Serialize};
@@ -52,17 +53,21 @@ impl PackageProvider for Dnf {
         if repository.key.is_some() {
             // .unwrap() is safe here because we checked for key presence above
             let key = repository.clone().key.unwrap();
-
-            steps.extend(vec![Step {
-                atom: Box::new(Exec {
-                    command: String::from("rpm"),
-                    arguments: vec![String::from("--import"), key.url],
-                    privileged: true,
-                    ..Default::default()
-                }),
-                initializers: vec![],
-                finalizers: vec![],
-            }]);
+            let (download, key_path) = key.download();
+
+            steps.extend(vec![
+                download,
+                Step {
+                    atom: Box::new(Exec {
+                        command: String::from("rpm"),
+                        arguments: vec![String::from("--import"), key_path.display().to_string()],
+                        privileged: true,
+                        ..Default::default()
+                    }),
+                    initializers: vec![],
+                    finalizers: vec![RepositoryKey::cleanup(&key_path)],
+                },
+            ]);
         }
 
         steps.extend(vec![
@@ -101,10 +106,15 @@ impl PackageProvider for Dnf {
     }
 
     fn query(&self, package: &PackageVariant) -> anyhow::Result<Vec<String>> {
-        Ok(package.packages())
+        Ok(not_installed(self.name(), package, rpm_packages))
     }
 
     fn install(&self, package: &PackageVariant) -> anyhow::Result<Vec<Step>> {
+        let packages = self.query(package)?;
+        if packages.is_empty() {
+            return Ok(vec![]);
+        }
+
         Ok(vec![Step {
             atom: Box::new(Exec {
                 command: String::from("dnf"),
@@ -115,20 +125,28 @@ impl PackageProvider for Dnf {
                 ]
                 .into_iter()
                 .chain(package.extra_args.clone())
-                .chain(self.query(package)?)
+                .chain(packages.clone())
                 .collect(),
                 privileged: true,
+                side_effects: self.installs(packages),
                 ..Default::default()
             }),
             initializers: vec![],
             finalizers: vec![],
         }])
     }
+
+    fn installed(&self) -> anyhow::Result<Vec<String>> {
+        list_packages(
+            "dnf",
+            &["repoquery", "--userinstalled", "--queryformat", "%{name}\n"],
+        )
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::actions::package::{providers::PackageProviders, repository::RepositoryKey};
+    use crate::actions::package::providers::PackageProviders;
 
     use super::*;
 
@@ -156,6 +174,6 @@ mod test {
             provider: PackageProviders::Dnf,
         });
 
-        assert_eq!(steps.unwrap().len(), 3);
+        assert_eq!(steps.unwrap().len(), 4);
     }
 }
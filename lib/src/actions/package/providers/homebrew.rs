@@ -1,10 +1,8 @@
-use super::PackageProvider;
+use super::{list_packages, not_installed, PackageProvider};
 use crate::actions::package::repository::PackageRepository;
 use crate::steps::Step;
 use crate::{actions::package::PackageVariant, atoms::command::Exec};
 use serde::{Deserialize, Serialize};
-use std::{path::Path, process::Command};
-use tracing::{debug, trace};
 use which::which;
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -50,28 +48,10 @@ impl PackageProvider for Homebrew {
     }
 
     fn query(&self, package: &PackageVariant) -> anyhow::Result<Vec<String>> {
-        let prefix = String::from_utf8(Command::new("brew").arg("--prefix").output()?.stdout)?
-            .replace(['\n', '\r'], "");
-
-        let cellar = Path::new(&prefix).join("Cellar");
-        let caskroom = Path::new(&prefix).join("Caskroom");
-
-        Ok(package
-            .packages()
-            .into_iter()
-            .filter(|p| {
-                if cellar.join(p).is_dir() {
-                    trace!("{}: found in Cellar", p);
-                    false
-                } else if caskroom.join(p).is_dir() {
-                    trace!("{}: found in Caskroom", p);
-                    false
-                } else {
-                    debug!("{}: doesn't appear to be installed", p);
-                    true
-                }
-            })
-            .collect())
+        // Formulae and casks, without asking brew for each
+        Ok(not_installed(self.name(), package, || {
+            list_packages("brew", &["list", "-1"])
+        }))
     }
 
     fn install(&self, package: &PackageVariant) -> anyhow::Result<Vec<Step>> {
@@ -87,13 +67,25 @@ impl PackageProvider for Homebrew {
                 arguments: [
                     vec![String::from("install")],
                     package.extra_args.clone(),
-                    need_installed,
+                    need_installed.clone(),
                 ]
                 .concat(),
+                side_effects: self.installs(need_installed.clone()),
+                undo: Some(
+                    [
+                        vec![String::from("brew"), String::from("uninstall")],
+                        need_installed,
+                    ]
+                    .concat(),
+                ),
                 ..Default::default()
             }),
             initializers: vec![],
             finalizers: vec![],
         }])
     }
+
+    fn installed(&self) -> anyhow::Result<Vec<String>> {
+        list_packages("brew", &["leaves", "--installed-on-request"])
+    }
 }
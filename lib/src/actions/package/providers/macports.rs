@@ -61,6 +61,7 @@ impl PackageProvider for Macports {
                     .chain(package.packages())
                     .collect(),
                 privileged: true,
+                side_effects: self.installs(package.packages()),
                 ..Default::default()
             }),
             initializers: vec![],
@@ -1,5 +1,6 @@
 mod aptitude;
 use self::aptitude::Aptitude;
+use crate::atoms::{Atom, Outcome, SideEffect};
 use crate::steps::Step;
 mod bsdpkg;
 use self::bsdpkg::BsdPkg;
@@ -20,8 +21,17 @@ use self::xbps::Xbps;
 mod zypper;
 use self::zypper::Zypper;
 use super::{repository::PackageRepository, PackageVariant};
+use anyhow::anyhow;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::process::Command;
+use std::sync::Mutex;
+use tracing::{debug, trace};
+
+/// Every package installed with each provider, listed once per run rather than
+/// for every action
+static INSTALLED: Mutex<BTreeMap<String, HashSet<String>>> = Mutex::new(BTreeMap::new());
 
 #[derive(JsonSchema, Clone, Debug, Serialize, Deserialize)]
 pub enum PackageProviders {
@@ -73,17 +83,18 @@ impl PackageProviders {
     }
 }
 
-impl Default for PackageProviders {
-    fn default() -> Self {
+impl PackageProviders {
+    /// The package provider of the running OS, if comtrya knows it
+    pub fn detect() -> Option<Self> {
         let info = os_info::get();
 
-        match info.os_type() {
+        let provider = match info.os_type() {
             // Arch Variants
-            os_info::Type::Arch=> PackageProviders::Yay,
-            os_info::Type::Manjaro=> PackageProviders::Yay,
+            os_info::Type::Arch => PackageProviders::Yay,
+            os_info::Type::Manjaro => PackageProviders::Yay,
             // BSD operating systems
-            os_info::Type::DragonFly=> PackageProviders::BsdPkg,
-            os_info::Type::FreeBSD=> PackageProviders::BsdPkg,
+            os_info::Type::DragonFly => PackageProviders::BsdPkg,
+            os_info::Type::FreeBSD => PackageProviders::BsdPkg,
             os_info::Type::NetBSD => PackageProviders::Pkgin,
             // Debian / Ubuntu Variants
             os_info::Type::Debian => PackageProviders::Aptitude,
@@ -105,8 +116,16 @@ impl Default for PackageProviders {
             os_info::Type::Macos => PackageProviders::Homebrew,
             os_info::Type::Windows => PackageProviders::Winget,
 
-            _ => panic!("Sorry, but we don't have a default provider for {} OS. Please be explicit when requesting a package installation with `provider: XYZ`.", info.os_type()),
-        }
+            _ => return None,
+        };
+
+        Some(provider)
+    }
+}
+
+impl Default for PackageProviders {
+    fn default() -> Self {
+        PackageProviders::detect().unwrap_or_else(|| panic!("Sorry, but we don't have a default provider for {} OS. Please be explicit when requesting a package installation with `provider: XYZ`.", os_info::get().os_type()))
     }
 }
 
@@ -118,4 +137,152 @@ pub trait PackageProvider {
     fn add_repository(&self, package: &PackageRepository) -> anyhow::Result<Vec<Step>>;
     fn query(&self, package: &PackageVariant) -> anyhow::Result<Vec<String>>;
     fn install(&self, package: &PackageVariant) -> anyhow::Result<Vec<Step>>;
+
+    /// Reported as what the command installing `packages` changes
+    fn installs(&self, packages: Vec<String>) -> Vec<SideEffect> {
+        vec![SideEffect::Install {
+            provider: self.name().to_string(),
+            packages,
+        }]
+    }
+
+    /// Packages that were explicitly installed, leaving out their dependencies
+    fn installed(&self) -> anyhow::Result<Vec<String>> {
+        Err(anyhow!(
+            "Listing installed packages isn't supported for {}",
+            self.name()
+        ))
+    }
+}
+
+/// Runs a command that lists one package per line
+fn list_packages(command: &str, args: &[&str]) -> anyhow::Result<Vec<String>> {
+    let output = Command::new(command).args(args).output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed to list installed packages: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8(output.stdout)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Every package in the rpm database, for dnf and zypper
+fn rpm_packages() -> anyhow::Result<Vec<String>> {
+    list_packages("rpm", &["--query", "--all", "--queryformat", "%{NAME}\n"])
+}
+
+/// The packages that aren't installed yet. Installed ones are listed with `list`
+/// the first time a provider is asked, and all packages are returned when that fails.
+fn not_installed(
+    provider: &str,
+    package: &PackageVariant,
+    list: impl FnOnce() -> anyhow::Result<Vec<String>>,
+) -> Vec<String> {
+    // Local files are paths, not package names
+    if package.file {
+        return package.packages();
+    }
+
+    let mut cache = INSTALLED.lock().unwrap();
+
+    if !cache.contains_key(provider) {
+        match list() {
+            Ok(installed) => {
+                debug!("{} packages installed with {}", installed.len(), provider);
+                cache.insert(provider.to_string(), installed.into_iter().collect());
+            }
+            Err(err) => {
+                debug!("Installing every package, {}", err);
+                return package.packages();
+            }
+        }
+    }
+
+    let installed = &cache[provider];
+
+    package
+        .packages()
+        .into_iter()
+        .filter(|p| {
+            if installed.contains(p) {
+                trace!("{}: already installed", p);
+                false
+            } else {
+                debug!("{}: doesn't appear to be installed", p);
+                true
+            }
+        })
+        .collect()
+}
+
+/// Lists the provider's packages again when next asked
+pub(crate) fn forget_installed(provider: &str) {
+    INSTALLED.lock().unwrap().remove(provider);
+}
+
+/// Runs after a step installing packages, whether it succeeded or not, so that
+/// later actions see what it installed
+pub(crate) struct ForgetInstalled(pub String);
+
+impl Atom for ForgetInstalled {
+    fn plan(&self) -> anyhow::Result<Outcome> {
+        Ok(Outcome {
+            side_effects: vec![],
+            should_run: true,
+        })
+    }
+
+    fn execute(&mut self) -> anyhow::Result<()> {
+        forget_installed(&self.0);
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for ForgetInstalled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Forget the packages installed with {}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_lists_installed_packages_once() {
+        let package = PackageVariant {
+            name: None,
+            list: vec![String::from("curl"), String::from("git")],
+            extra_args: vec![],
+            provider: PackageProviders::Aptitude,
+            file: false,
+        };
+
+        let listed = std::cell::Cell::new(0);
+        let list = || {
+            listed.set(listed.get() + 1);
+            Ok(vec![String::from("curl")])
+        };
+
+        assert_eq!(vec!["git"], not_installed("test", &package, list));
+        assert_eq!(vec!["git"], not_installed("test", &package, || Ok(vec![])));
+        assert_eq!(1, listed.get());
+
+        // Once the step installing them ran
+        ForgetInstalled(String::from("test")).execute().unwrap();
+        assert_eq!(
+            vec!["curl", "git"],
+            not_installed("test", &package, || Ok(vec![]))
+        );
+    }
 }
@@ -73,6 +73,7 @@ impl PackageProvider for Pkgin {
                         .chain(package.packages())
                         .collect(),
                     privileged: true,
+                    side_effects: self.installs(package.packages()),
                     ..Default::default()
                 }),
                 initializers: vec![],
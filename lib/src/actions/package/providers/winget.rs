@@ -61,6 +61,7 @@ impl PackageProvider for Winget {
                         vec![p.clone()],
                     ]
                     .concat(),
+                    side_effects: self.installs(vec![p.clone()]),
                     ..Default::default()
                 }),
                 initializers: vec![],
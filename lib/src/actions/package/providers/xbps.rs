@@ -1,12 +1,10 @@
-use super::PackageProvider;
+use super::{list_packages, not_installed, PackageProvider};
 use crate::actions::package::repository::PackageRepository;
 use crate::actions::package::PackageVariant;
 use crate::atoms::command::Exec;
 use crate::steps::Step;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::process::Command;
-use tracing::{debug, trace, warn};
+use tracing::warn;
 use which::which;
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -52,38 +50,15 @@ impl PackageProvider for Xbps {
     }
 
     fn query(&self, package: &PackageVariant) -> anyhow::Result<Vec<String>> {
-        let requested_already_installed: HashSet<String> = String::from_utf8(
-            Command::new("xbps-query")
-                .args(
-                    vec![String::from("-s")]
-                        .into_iter()
-                        .chain(package.packages().into_iter()),
-                )
-                .output()?
-                .stdout,
-        )?
-        .split('\n')
-        .map(String::from)
-        .collect();
-
-        debug!(
-            "all requested installed packages: {:?}",
-            requested_already_installed
-        );
-
-        Ok(package
-            .packages()
-            .into_iter()
-            .filter(|p| {
-                if requested_already_installed.contains(p) {
-                    trace!("{}: already installed", p);
-                    false
-                } else {
-                    debug!("{}: doesn't appear to be installed", p);
-                    true
-                }
-            })
-            .collect())
+        // Lines like `ii curl-8.5.0_1  Client that groks URLs`
+        Ok(not_installed(self.name(), package, || {
+            Ok(list_packages("xbps-query", &["--list-pkgs"])?
+                .iter()
+                .filter_map(|line| line.split_whitespace().nth(1))
+                .filter_map(|version| version.rsplit_once('-'))
+                .map(|(name, _)| name.to_string())
+                .collect())
+        }))
     }
 
     fn install(&self, package: &PackageVariant) -> anyhow::Result<Vec<Step>> {
@@ -102,10 +77,18 @@ impl PackageProvider for Xbps {
                         String::from("--update"),
                     ],
                     package.extra_args.clone(),
-                    need_installed,
+                    need_installed.clone(),
                 ]
                 .concat(),
                 privileged: true,
+                side_effects: self.installs(need_installed.clone()),
+                undo: Some(
+                    [
+                        vec![String::from("xbps-remove"), String::from("--yes")],
+                        need_installed,
+                    ]
+                    .concat(),
+                ),
                 ..Default::default()
             }),
             initializers: vec![],
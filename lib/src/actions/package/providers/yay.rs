@@ -1,13 +1,10 @@
-use super::PackageProvider;
+use super::{list_packages, not_installed, PackageProvider};
 use crate::actions::package::repository::PackageRepository;
 use crate::actions::package::PackageVariant;
 use crate::atoms::command::Exec;
-use crate::steps::Step;
+use crate::steps::{finalizers::FlowControl, Step};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::process::Command;
 use tracing::warn;
-use tracing::{debug, trace};
 use which::which;
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -66,7 +63,12 @@ impl PackageProvider for Yay {
                     ..Default::default()
                 }),
                 initializers: vec![],
-                finalizers: vec![],
+                // Otherwise the clone fails when bootstrapping again after a failed build
+                finalizers: vec![FlowControl::Always(Box::new(Exec {
+                    command: String::from("rm"),
+                    arguments: vec![String::from("-rf"), String::from("/tmp/yay")],
+                    ..Default::default()
+                }))],
             },
         ]
     }
@@ -80,38 +82,9 @@ impl PackageProvider for Yay {
     }
 
     fn query(&self, package: &PackageVariant) -> anyhow::Result<Vec<String>> {
-        let requested_already_installed: HashSet<String> = String::from_utf8(
-            Command::new("yay")
-                .args(
-                    vec![String::from("-Q"), String::from("-q")]
-                        .into_iter()
-                        .chain(package.packages().into_iter()),
-                )
-                .output()?
-                .stdout,
-        )?
-        .split('\n')
-        .map(String::from)
-        .collect();
-
-        debug!(
-            "all requested installed packages: {:?}",
-            requested_already_installed
-        );
-
-        Ok(package
-            .packages()
-            .into_iter()
-            .filter(|p| {
-                if requested_already_installed.contains(p) {
-                    trace!("{}: already installed", p);
-                    false
-                } else {
-                    debug!("{}: doesn't appear to be installed", p);
-                    true
-                }
-            })
-            .collect())
+        Ok(not_installed(self.name(), package, || {
+            list_packages("pacman", &["-Qq"])
+        }))
     }
 
     fn install(&self, package: &PackageVariant) -> anyhow::Result<Vec<Step>> {
@@ -131,13 +104,29 @@ impl PackageProvider for Yay {
                         String::from("--nodiffmenu"),
                     ],
                     package.extra_args.clone(),
-                    need_installed,
+                    need_installed.clone(),
                 ]
                 .concat(),
+                side_effects: self.installs(need_installed.clone()),
+                undo: Some(
+                    [
+                        vec![
+                            String::from("yay"),
+                            String::from("-R"),
+                            String::from("--noconfirm"),
+                        ],
+                        need_installed,
+                    ]
+                    .concat(),
+                ),
                 ..Default::default()
             }),
             initializers: vec![],
             finalizers: vec![],
         }])
     }
+
+    fn installed(&self) -> anyhow::Result<Vec<String>> {
+        list_packages("pacman", &["-Qqe"])
+    }
 }
@@ -1,4 +1,4 @@
-use super::PackageProvider;
+use super::{not_installed, rpm_packages, PackageProvider};
 use crate::actions::package::{repository::PackageRepository, PackageVariant};
 use crate::atoms::command::Exec;
 use crate::steps::Step;
@@ -37,19 +37,25 @@ impl PackageProvider for Zypper {
     }
 
     fn query(&self, package: &PackageVariant) -> anyhow::Result<Vec<String>> {
-        Ok(package.packages())
+        Ok(not_installed(self.name(), package, rpm_packages))
     }
 
     fn install(&self, package: &PackageVariant) -> anyhow::Result<Vec<Step>> {
+        let need_installed = self.query(package)?;
+        if need_installed.is_empty() {
+            return Ok(vec![]);
+        }
+
         Ok(vec![Step {
             atom: Box::new(Exec {
                 command: String::from("zypper"),
                 arguments: vec![String::from("install"), String::from("-y")]
                     .into_iter()
                     .chain(package.extra_args.clone())
-                    .chain(package.packages())
+                    .chain(need_installed.clone())
                     .collect(),
                 privileged: true,
+                side_effects: self.installs(need_installed),
                 ..Default::default()
             }),
             initializers: vec![],
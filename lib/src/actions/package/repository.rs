@@ -1,12 +1,17 @@
 use super::providers::PackageProviders;
 use crate::actions::Action;
+use crate::atoms::file::Remove;
+use crate::atoms::http::Download;
 use crate::contexts::Contexts;
 use crate::manifests::Manifest;
+use crate::steps::finalizers::FlowControl;
 use crate::steps::Step;
 use anyhow::anyhow;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha256::digest;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use tracing::span;
 
 #[derive(JsonSchema, Clone, Debug, Default, Serialize, Deserialize)]
@@ -28,6 +33,33 @@ pub struct RepositoryKey {
     pub fingerprint: Option<String>,
 }
 
+impl RepositoryKey {
+    /// Downloads the key to a temporary file, through the download cache so
+    /// `comtrya fetch` can prepare offline runs, returning where it's downloaded to
+    pub(crate) fn download(&self) -> (Step, PathBuf) {
+        let path = std::env::temp_dir().join(format!("comtrya-key-{}", digest(&*self.url)));
+
+        let step = Step {
+            atom: Box::new(Download {
+                url: self.url.clone(),
+                to: path.clone(),
+                ..Default::default()
+            }),
+            initializers: vec![],
+            finalizers: vec![],
+        };
+
+        (step, path)
+    }
+
+    /// Removes the downloaded key once the step using it ran, whatever happened
+    pub(crate) fn cleanup(path: &Path) -> FlowControl {
+        FlowControl::Always(Box::new(Remove {
+            target: path.to_path_buf(),
+        }))
+    }
+}
+
 impl Action for PackageRepository {
     fn summarize(&self) -> String {
         format!("Adding repository {}", self.name)
@@ -0,0 +1,307 @@
+use super::{default_retry_delay, Action, Retry};
+use crate::atoms::command::Exec;
+use crate::atoms::plugin::PluginStep;
+use crate::atoms::Atom;
+use crate::contexts::{profile::in_profile, Contexts};
+use crate::manifests::Manifest;
+use crate::steps::Step;
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// Plugins are executables named after the action they provide
+pub const PLUGIN_PREFIX: &str = "comtrya-action-";
+
+static PLUGIN_DIRS: RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+
+/// Directories searched for plugins before `PATH`
+pub fn set_plugin_dirs(dirs: Vec<PathBuf>) {
+    *PLUGIN_DIRS.write().unwrap() = dirs;
+}
+
+fn find_plugin(name: &str) -> Option<PathBuf> {
+    let file_name = format!("{PLUGIN_PREFIX}{name}{}", std::env::consts::EXE_SUFFIX);
+
+    let path = std::env::var_os("PATH").unwrap_or_default();
+
+    PLUGIN_DIRS
+        .read()
+        .unwrap()
+        .iter()
+        .cloned()
+        .chain(std::env::split_paths(&path))
+        .map(|dir| dir.join(&file_name))
+        .find(|plugin| plugin.is_file())
+}
+
+/// An action that isn't built in, which is handed to the `comtrya-action-<name>`
+/// plugin. Options other than the ones every action has are passed on as is.
+#[derive(JsonSchema, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PluginAction {
+    pub action: String,
+
+    #[serde(rename = "where")]
+    pub condition: Option<String>,
+
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    #[serde(default)]
+    pub profiles: Vec<String>,
+
+    #[serde(default)]
+    pub ignore_errors: bool,
+
+    #[serde(default)]
+    pub id: Option<String>,
+
+    #[serde(default)]
+    pub when_changed: Option<String>,
+
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+
+    #[serde(default)]
+    pub allow_sudo: Option<bool>,
+
+    #[serde(default)]
+    pub retries: u32,
+
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
+
+    #[serde(flatten)]
+    pub options: BTreeMap<String, Value>,
+}
+
+impl PluginAction {
+    fn request(&self, manifest: &Manifest, contexts: &Contexts) -> Value {
+        json!({
+            "action": self.action,
+            "options": self.options,
+            "manifest": {
+                "name": manifest.name,
+                "root_dir": manifest.root_dir,
+            },
+            "contexts": contexts,
+        })
+    }
+
+    /// Asks the plugin which steps are needed, an empty list means there's
+    /// nothing to do
+    fn plan_steps(&self, plugin: &Path, request: &Value) -> anyhow::Result<Vec<Value>> {
+        let mut exec = Exec {
+            command: plugin.display().to_string(),
+            arguments: vec![String::from("plan")],
+            stdin: Some(request.to_string()),
+            ..Default::default()
+        };
+
+        if let Err(err) = exec.execute() {
+            return Err(anyhow!(
+                "Plugin '{}' failed to plan: {} {}",
+                self.action,
+                err,
+                exec.error_message().trim()
+            ));
+        }
+
+        #[derive(Deserialize)]
+        struct Plan {
+            steps: Vec<Value>,
+        }
+
+        let plan: Plan = serde_json::from_str(&exec.output_string())
+            .map_err(|err| anyhow!("Plugin '{}' returned an invalid plan: {}", self.action, err))?;
+
+        Ok(plan.steps)
+    }
+}
+
+impl Action for PluginAction {
+    fn summarize(&self) -> String {
+        format!("Running {} plugin", self.action)
+    }
+
+    fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    fn ignore_errors(&self) -> bool {
+        self.ignore_errors
+    }
+
+    fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    fn when_changed(&self) -> Option<&str> {
+        self.when_changed.as_deref()
+    }
+
+    fn depends_on(&self) -> &[String] {
+        &self.depends_on
+    }
+
+    fn allow_sudo(&self) -> Option<bool> {
+        self.allow_sudo
+    }
+
+    fn retry(&self) -> Retry {
+        Retry {
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+        }
+    }
+
+    fn conditions(&self) -> Vec<&str> {
+        self.condition.as_deref().into_iter().collect()
+    }
+
+    fn plan(&self, manifest: &Manifest, context: &Contexts) -> anyhow::Result<Vec<Step>> {
+        if !in_profile(&self.profiles, context) {
+            return Ok(vec![]);
+        }
+
+        if let Some(condition) = self.condition.as_ref() {
+            let mut scope = crate::contexts::to_rhai(context);
+
+            match crate::rhai_functions::engine().eval_with_scope::<bool>(&mut scope, condition) {
+                Ok(true) => (),
+                Ok(false) => return Ok(vec![]),
+                Err(error) => {
+                    return Err(anyhow!("Failed execution condition for action: {}", error))
+                }
+            }
+        }
+
+        let plugin = find_plugin(&self.action).ok_or_else(|| {
+            anyhow!(
+                "Unknown action '{}', and no {}{} plugin was found",
+                self.action,
+                PLUGIN_PREFIX,
+                self.action
+            )
+        })?;
+
+        let request = self.request(manifest, context);
+
+        Ok(self
+            .plan_steps(&plugin, &request)?
+            .into_iter()
+            .map(|step| {
+                let mut request = request.clone();
+                request["step"] = step;
+
+                Step {
+                    atom: Box::new(PluginStep::new(&self.action, &plugin, request)),
+                    initializers: vec![],
+                    finalizers: vec![],
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+    use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let yaml = r#"
+- action: docker.network
+  name: backend
+  where: os.name == "linux"
+  tags: [docker]
+  depends_on: [docker]
+  retries: 2
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::Plugin(action)) => {
+                assert_eq!("docker.network", action.action);
+                assert_eq!(
+                    Some(Value::from("backend")),
+                    action.options.get("name").cloned()
+                );
+                assert_eq!(vec![String::from("docker")], action.tags);
+                assert_eq!(vec![String::from("docker")], action.depends_on);
+                assert_eq!(2, action.retry().retries);
+                assert_eq!(None, action.options.get("retries"));
+            }
+            _ => panic!("Plugin action didn't deserialize to the correct type"),
+        };
+
+        // Typos in built-in actions are still errors
+        assert_eq!(
+            true,
+            serde_yml::from_str::<Vec<Actions>>("- action: command.run\n  comand: echo").is_err()
+        );
+        assert_eq!(
+            true,
+            serde_yml::from_str::<Vec<Actions>>("- action: cmd.run\n  comand: echo").is_err()
+        );
+        // As are unknown values deeper in built-in actions
+        assert_eq!(
+            true,
+            serde_yml::from_str::<Vec<Actions>>(
+                "- action: package.install\n  name: git\n  provider: nope"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn it_runs_plugins() {
+        let dir = tempfile::tempdir().unwrap();
+        let plugin = dir.path().join(format!("{PLUGIN_PREFIX}test.greet"));
+        let output = dir.path().join("greeting");
+
+        std::fs::write(
+            &plugin,
+            format!(
+                r#"#!/bin/sh
+request=$(cat)
+case "$1" in
+  plan) echo '{{"steps": [{{"description": "Greet the world"}}, {{"description": "Greet root", "privileged": true}}]}}' ;;
+  execute) echo "$request" > "{}" ;;
+esac
+"#,
+                output.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&plugin, std::fs::Permissions::from_mode(0o755)).unwrap();
+        set_plugin_dirs(vec![dir.path().to_path_buf()]);
+
+        let action: Actions = serde_yml::from_str("action: test.greet\nwho: world").unwrap();
+        let mut steps = action
+            .inner_ref()
+            .plan(&Manifest::default(), &Contexts::default())
+            .unwrap();
+
+        assert_eq!(2, steps.len());
+        assert_eq!("Greet the world", steps[0].atom.to_string());
+        // Privileged steps go through sudo, or are skipped, like those of built-in actions
+        assert_eq!(false, steps[0].atom.privileged());
+        assert_eq!(true, steps[1].atom.privileged());
+
+        steps[0].atom.execute().unwrap();
+
+        let request: Value =
+            serde_json::from_str(&std::fs::read_to_string(output).unwrap()).unwrap();
+        assert_eq!("world", request["options"]["who"]);
+        assert_eq!("Greet the world", request["step"]["description"]);
+    }
+}
@@ -0,0 +1,107 @@
+mod neovim;
+mod tmux;
+pub use neovim::NeovimPlugins;
+pub use tmux::TmuxPlugins;
+
+use crate::atoms::command::Exec;
+use crate::atoms::file::SetContents;
+use crate::atoms::git::Clone;
+use crate::steps::Step;
+use std::path::{Path, PathBuf};
+
+/// Where the checksums of the files watched by the last sync are kept
+fn stamps_dir() -> PathBuf {
+    dirs_next::data_local_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("comtrya")
+        .join("plugins")
+}
+
+/// One checksum for all the watched files, where missing files count too
+fn checksum(watch: &[PathBuf]) -> String {
+    let files = watch
+        .iter()
+        .map(|path| {
+            format!(
+                "{} {}\n",
+                sha256::try_digest(path.as_path()).unwrap_or_default(),
+                path.display()
+            )
+        })
+        .collect::<String>();
+
+    sha256::digest(files)
+}
+
+fn clone(repository: &str, reference: Option<&str>, directory: PathBuf) -> Step {
+    Step {
+        atom: Box::new(Clone {
+            repository: repository.to_string(),
+            directory,
+            reference: reference.map(str::to_string),
+            depth: Some(1),
+            submodules: false,
+            sparse: vec![],
+        }),
+        initializers: vec![],
+        finalizers: vec![],
+    }
+}
+
+/// Runs `sync` when the plugin manager is cloned, or when the watched files
+/// changed since the last sync, then records their checksum in `stamp`
+fn sync(cloned: bool, watch: &[PathBuf], stamp: &Path, sync: Exec) -> Vec<Step> {
+    let checksum = checksum(watch);
+    let synced = std::fs::read_to_string(stamp).unwrap_or_default();
+
+    if !cloned && synced.trim() == checksum {
+        return vec![];
+    }
+
+    vec![
+        Step {
+            atom: Box::new(sync),
+            initializers: vec![],
+            finalizers: vec![],
+        },
+        Step {
+            atom: Box::new(SetContents {
+                path: stamp.to_path_buf(),
+                contents: checksum.into_bytes(),
+            }),
+            initializers: vec![],
+            finalizers: vec![],
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_only_syncs_when_the_watched_files_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("tmux.conf");
+        let stamp = dir.path().join("tmux");
+        std::fs::write(&config, "set -g @plugin 'tmux-plugins/tmux-sensible'").unwrap();
+
+        let watch = vec![config.clone()];
+        let exec = || Exec {
+            command: String::from("true"),
+            ..Default::default()
+        };
+
+        assert_eq!(2, sync(false, &watch, &stamp, exec()).len());
+
+        std::fs::write(&stamp, checksum(&watch)).unwrap();
+        assert_eq!(0, sync(false, &watch, &stamp, exec()).len());
+
+        // A fresh clone of the plugin manager has no plugins yet
+        assert_eq!(2, sync(true, &watch, &stamp, exec()).len());
+
+        std::fs::write(&config, "set -g @plugin 'tmux-plugins/tmux-yank'").unwrap();
+        assert_eq!(2, sync(false, &watch, &stamp, exec()).len());
+    }
+}
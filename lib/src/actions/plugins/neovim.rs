@@ -0,0 +1,145 @@
+use super::{clone, stamps_dir, sync};
+use crate::actions::Action;
+use crate::atoms::command::Exec;
+use crate::contexts::Contexts;
+use crate::manifests::Manifest;
+use crate::steps::Step;
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const LAZY: &str = "https://github.com/folke/lazy.nvim.git";
+
+/// Clones lazy.nvim where its bootstrap snippet looks for it, and installs the
+/// plugins of the neovim config with it
+#[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NeovimPlugins {
+    /// Where lazy.nvim is cloned to, `~/.local/share/nvim/lazy/lazy.nvim` by default
+    pub directory: Option<String>,
+
+    /// Restores the plugins of `lazy-lock.json`, or installs them without it,
+    /// when lazy.nvim was cloned or the watched files changed
+    #[serde(default)]
+    pub sync: bool,
+
+    /// `lazy-lock.json` and `init.lua` of `~/.config/nvim` by default
+    #[serde(default)]
+    pub watch: Vec<String>,
+}
+
+fn xdg(var: &str, home: &Path, default: &str) -> PathBuf {
+    std::env::var(var)
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(default))
+}
+
+impl NeovimPlugins {
+    fn directory(&self, data: &Path) -> PathBuf {
+        self.directory
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| data.join("nvim").join("lazy").join("lazy.nvim"))
+    }
+
+    fn watch(&self, config: &Path) -> Vec<PathBuf> {
+        match self.watch.is_empty() {
+            true => vec![
+                config.join("nvim").join("lazy-lock.json"),
+                config.join("nvim").join("init.lua"),
+            ],
+            false => self.watch.iter().map(PathBuf::from).collect(),
+        }
+    }
+}
+
+/// `restore` leaves the lockfile as it is, where `sync` would update it and
+/// sync again on the next run
+fn command(lockfile: bool) -> Exec {
+    let lazy = match lockfile {
+        true => "+Lazy! restore",
+        false => "+Lazy! install",
+    };
+
+    Exec {
+        command: String::from("nvim"),
+        arguments: vec![
+            String::from("--headless"),
+            String::from(lazy),
+            String::from("+qa"),
+        ],
+        ..Default::default()
+    }
+}
+
+impl Action for NeovimPlugins {
+    fn summarize(&self) -> String {
+        String::from("Installing neovim plugins")
+    }
+
+    fn plan(&self, _: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
+        let home = dirs_next::home_dir()
+            .ok_or_else(|| anyhow!("There's no home directory to install lazy.nvim to"))?;
+        let config = xdg("XDG_CONFIG_HOME", &home, ".config");
+        let data = xdg("XDG_DATA_HOME", &home, ".local/share");
+
+        let directory = self.directory(&data);
+        let cloned = !directory.join(".git").exists();
+
+        let mut steps = vec![clone(LAZY, Some("stable"), directory)];
+
+        if self.sync {
+            let lockfile = config.join("nvim").join("lazy-lock.json").exists();
+
+            steps.extend(sync(
+                cloned,
+                &self.watch(&config),
+                &stamps_dir().join("neovim"),
+                command(lockfile),
+            ));
+        }
+
+        Ok(steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let yaml = r#"
+- action: neovim.plugins
+  sync: true
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::NeovimPlugins(action)) => {
+                assert_eq!(true, action.action.sync);
+                assert_eq!(
+                    PathBuf::from("/home/test/.local/share/nvim/lazy/lazy.nvim"),
+                    action
+                        .action
+                        .directory(&PathBuf::from("/home/test/.local/share"))
+                );
+                assert_eq!(
+                    vec![
+                        PathBuf::from("/home/test/.config/nvim/lazy-lock.json"),
+                        PathBuf::from("/home/test/.config/nvim/init.lua")
+                    ],
+                    action.action.watch(&PathBuf::from("/home/test/.config"))
+                );
+            }
+            _ => {
+                panic!("NeovimPlugins didn't deserialize to the correct type");
+            }
+        };
+    }
+}
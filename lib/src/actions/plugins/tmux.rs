@@ -0,0 +1,120 @@
+use super::{clone, stamps_dir, sync};
+use crate::actions::Action;
+use crate::atoms::command::Exec;
+use crate::contexts::Contexts;
+use crate::manifests::Manifest;
+use crate::steps::Step;
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const TPM: &str = "https://github.com/tmux-plugins/tpm.git";
+
+/// Clones the tmux plugin manager, and installs the plugins of tmux.conf with it
+#[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TmuxPlugins {
+    /// Where tpm is cloned to, `~/.tmux/plugins/tpm` by default
+    pub directory: Option<String>,
+
+    /// Installs the plugins when tpm was cloned, or the watched files changed
+    #[serde(default)]
+    pub sync: bool,
+
+    /// `~/.tmux.conf` or `~/.config/tmux/tmux.conf` by default, whichever exists
+    #[serde(default)]
+    pub watch: Vec<String>,
+}
+
+impl TmuxPlugins {
+    fn directory(&self, home: &std::path::Path) -> PathBuf {
+        self.directory
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home.join(".tmux").join("plugins").join("tpm"))
+    }
+
+    fn watch(&self, home: &std::path::Path) -> Vec<PathBuf> {
+        if !self.watch.is_empty() {
+            return self.watch.iter().map(PathBuf::from).collect();
+        }
+
+        let config = home.join(".config").join("tmux").join("tmux.conf");
+        match config.exists() {
+            true => vec![config],
+            false => vec![home.join(".tmux.conf")],
+        }
+    }
+}
+
+impl Action for TmuxPlugins {
+    fn summarize(&self) -> String {
+        String::from("Installing tmux plugins")
+    }
+
+    fn plan(&self, _: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
+        let home = dirs_next::home_dir()
+            .ok_or_else(|| anyhow!("There's no home directory to install tpm to"))?;
+        let directory = self.directory(&home);
+        let cloned = !directory.join(".git").exists();
+
+        let mut steps = vec![clone(TPM, None, directory.clone())];
+
+        if self.sync {
+            // Reads the plugins from tmux.conf, and doesn't need tmux to be running
+            let install = Exec {
+                command: directory
+                    .join("bin")
+                    .join("install_plugins")
+                    .display()
+                    .to_string(),
+                ..Default::default()
+            };
+
+            steps.extend(sync(
+                cloned,
+                &self.watch(&home),
+                &stamps_dir().join("tmux"),
+                install,
+            ));
+        }
+
+        Ok(steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let yaml = r#"
+- action: tmux.plugins
+  sync: true
+  watch:
+    - /home/test/.tmux.conf
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::TmuxPlugins(action)) => {
+                assert_eq!(true, action.action.sync);
+                assert_eq!(
+                    PathBuf::from("/home/test/.tmux/plugins/tpm"),
+                    action.action.directory(&PathBuf::from("/home/test"))
+                );
+                assert_eq!(
+                    vec![PathBuf::from("/home/test/.tmux.conf")],
+                    action.action.watch(&PathBuf::from("/home/test"))
+                );
+            }
+            _ => {
+                panic!("TmuxPlugins didn't deserialize to the correct type");
+            }
+        };
+    }
+}
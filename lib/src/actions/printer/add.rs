@@ -0,0 +1,177 @@
+use crate::actions::Action;
+use crate::atoms::command::Exec;
+use crate::contexts::Contexts;
+use crate::manifests::Manifest;
+use crate::steps::Step;
+use crate::utilities::list_output;
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use which::which;
+
+/// Adds a network printer to CUPS with lpadmin
+#[derive(JsonSchema, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrinterAdd {
+    /// Name of the queue, without spaces
+    pub name: String,
+
+    /// Like `ipp://printer.local/ipp/print`
+    pub uri: String,
+
+    /// The driver, `everywhere` for printers that speak IPP Everywhere
+    #[serde(default = "everywhere")]
+    pub model: String,
+
+    pub description: Option<String>,
+
+    pub location: Option<String>,
+
+    /// Makes it the printer used when none is chosen
+    #[serde(default)]
+    pub default: bool,
+}
+
+impl Default for PrinterAdd {
+    fn default() -> Self {
+        PrinterAdd {
+            name: String::new(),
+            uri: String::new(),
+            model: everywhere(),
+            description: None,
+            location: None,
+            default: false,
+        }
+    }
+}
+
+fn everywhere() -> String {
+    String::from("everywhere")
+}
+
+/// The URI of a printer in `lpstat -v`, like `device for office: ipp://...`
+fn device(lpstat: &str, name: &str) -> Option<String> {
+    lpstat.lines().find_map(|line| {
+        line.strip_prefix("device for ")
+            .and_then(|line| line.split_once(": "))
+            .filter(|(printer, _)| *printer == name)
+            .map(|(_, uri)| uri.trim().to_string())
+    })
+}
+
+/// The printer in `lpstat -d`, like `system default destination: office`
+fn default_printer(lpstat: &str) -> Option<String> {
+    lpstat
+        .lines()
+        .find_map(|line| line.strip_prefix("system default destination: "))
+        .map(|name| name.trim().to_string())
+}
+
+fn lpadmin(arguments: Vec<String>) -> Step {
+    Step {
+        atom: Box::new(Exec {
+            command: String::from("lpadmin"),
+            arguments,
+            privileged: true,
+            ..Default::default()
+        }),
+        initializers: vec![],
+        finalizers: vec![],
+    }
+}
+
+impl Action for PrinterAdd {
+    fn summarize(&self) -> String {
+        format!("Adding printer {}", self.name)
+    }
+
+    fn plan(&self, _: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
+        if which("lpadmin").is_err() {
+            return Err(anyhow!("CUPS isn't installed to add {}", self.name));
+        }
+
+        let mut steps = vec![];
+
+        if device(&list_output("lpstat", &["-v"]), &self.name).as_deref() != Some(&self.uri) {
+            let mut arguments = vec![
+                String::from("-p"),
+                self.name.clone(),
+                // Enables it and accepts jobs
+                String::from("-E"),
+                String::from("-v"),
+                self.uri.clone(),
+                String::from("-m"),
+                self.model.clone(),
+            ];
+            if let Some(description) = &self.description {
+                arguments.extend([String::from("-D"), description.clone()]);
+            }
+            if let Some(location) = &self.location {
+                arguments.extend([String::from("-L"), location.clone()]);
+            }
+
+            steps.push(lpadmin(arguments));
+        }
+
+        if self.default
+            && default_printer(&list_output("lpstat", &["-d"])).as_deref() != Some(&self.name)
+        {
+            steps.push(lpadmin(vec![String::from("-d"), self.name.clone()]));
+        }
+
+        Ok(steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let yaml = r#"
+- action: printer.add
+  name: office
+  uri: ipp://printer.local/ipp/print
+  default: true
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::PrinterAdd(action)) => {
+                assert_eq!(
+                    PrinterAdd {
+                        name: String::from("office"),
+                        uri: String::from("ipp://printer.local/ipp/print"),
+                        default: true,
+                        ..Default::default()
+                    },
+                    action.action
+                );
+            }
+            _ => {
+                panic!("PrinterAdd didn't deserialize to the correct type");
+            }
+        };
+    }
+
+    #[test]
+    fn it_reads_lpstat() {
+        let devices =
+            "device for home: usb://HP/DeskJet\ndevice for office: ipp://printer.local/ipp/print\n";
+
+        assert_eq!(
+            Some(String::from("ipp://printer.local/ipp/print")),
+            device(devices, "office")
+        );
+        assert_eq!(None, device(devices, "lab"));
+
+        assert_eq!(
+            Some(String::from("home")),
+            default_printer("system default destination: home\n")
+        );
+        assert_eq!(None, default_printer("no system default destination\n"));
+    }
+}
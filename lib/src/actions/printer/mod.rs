@@ -0,0 +1,2 @@
+mod add;
+pub use add::PrinterAdd;
@@ -0,0 +1,104 @@
+use std::time::Duration;
+use tracing::warn;
+
+/// How often a failing action is attempted again before giving up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retry {
+    /// Number of extra attempts after the first failure
+    pub retries: u32,
+
+    /// Seconds to wait before the first retry, doubled after every further failure
+    pub retry_delay: u64,
+}
+
+pub(crate) fn default_retry_delay() -> u64 {
+    5
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            retries: 0,
+            retry_delay: default_retry_delay(),
+        }
+    }
+}
+
+impl Retry {
+    /// The wait before the given retry, starting at 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        Duration::from_secs(self.retry_delay.saturating_mul(1 << (attempt - 1).min(16)))
+    }
+
+    /// Calls `f` until it succeeds or the retries are used up, returning the last error
+    pub fn run<T>(&self, mut f: impl FnMut() -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let mut attempt = 0;
+
+        loop {
+            match f() {
+                Ok(value) => return Ok(value),
+                Err(err) if attempt < self.retries => {
+                    attempt += 1;
+                    let delay = self.delay(attempt);
+
+                    warn!(
+                        "Attempt {} of {} failed, retrying in {}s: {}",
+                        attempt,
+                        self.retries + 1,
+                        delay.as_secs(),
+                        err
+                    );
+
+                    std::thread::sleep(delay);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_backs_off() {
+        let retry = Retry {
+            retries: 3,
+            retry_delay: 2,
+        };
+
+        assert_eq!(Duration::from_secs(2), retry.delay(1));
+        assert_eq!(Duration::from_secs(4), retry.delay(2));
+        assert_eq!(Duration::from_secs(8), retry.delay(3));
+    }
+
+    #[test]
+    fn it_retries_until_success() {
+        let retry = Retry {
+            retries: 2,
+            retry_delay: 0,
+        };
+
+        let mut attempts = 0;
+        let result = retry.run(|| {
+            attempts += 1;
+            if attempts < 3 {
+                anyhow::bail!("flaky")
+            }
+            Ok(attempts)
+        });
+
+        assert_eq!(3, result.unwrap());
+
+        let mut attempts = 0;
+        let result: anyhow::Result<()> = retry.run(|| {
+            attempts += 1;
+            anyhow::bail!("broken")
+        });
+
+        assert_eq!(true, result.is_err());
+        assert_eq!(3, attempts);
+    }
+}
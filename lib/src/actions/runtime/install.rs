@@ -0,0 +1,305 @@
+use crate::actions::Action;
+use crate::atoms::command::Exec;
+use crate::atoms::SideEffect;
+use crate::contexts::Contexts;
+use crate::manifests::Manifest;
+use crate::steps::Step;
+use crate::utilities::list_output as list;
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use which::which;
+
+/// Installs a language runtime, like node or python, with mise or asdf
+#[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuntimeInstall {
+    /// Name of the tool, or the asdf plugin, like `node` or `python`
+    pub tool: String,
+
+    /// An exact version, a prefix like `22`, or `latest`. Installed versions
+    /// starting with the prefix count, `latest` is only installed once.
+    #[serde(default = "latest")]
+    pub version: String,
+
+    /// Makes it the version used outside of projects, in `~/.config/mise/config.toml`
+    /// or `~/.tool-versions`
+    #[serde(default)]
+    pub global: bool,
+
+    /// The first of mise and asdf that's installed when not set
+    #[serde(default)]
+    pub manager: Option<RuntimeManager>,
+}
+
+#[derive(JsonSchema, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuntimeManager {
+    Mise,
+    Asdf,
+}
+
+fn latest() -> String {
+    String::from("latest")
+}
+
+impl RuntimeManager {
+    fn detect() -> Option<RuntimeManager> {
+        [RuntimeManager::Mise, RuntimeManager::Asdf]
+            .into_iter()
+            .find(|manager| which(manager.command()).is_ok())
+    }
+
+    fn command(&self) -> &'static str {
+        match self {
+            RuntimeManager::Mise => "mise",
+            RuntimeManager::Asdf => "asdf",
+        }
+    }
+}
+
+/// Whether an installed version is what was asked for
+fn satisfies(installed: &str, version: &str) -> bool {
+    version == "latest" || installed == version || installed.starts_with(&format!("{version}."))
+}
+
+/// The versions in `mise ls --json <tool>`, both resolved and requested ones
+fn mise_versions(json: &str, tool: &str) -> Vec<String> {
+    let json: serde_json::Value = serde_json::from_str(json).unwrap_or_default();
+
+    // An array for one tool, an object keyed by tool for all of them
+    let entries = match json.get(tool) {
+        Some(entries) => entries.clone(),
+        None => json,
+    };
+
+    entries
+        .as_array()
+        .map(|entries| {
+            entries
+                .iter()
+                .flat_map(|entry| [&entry["version"], &entry["requested_version"]])
+                .filter_map(|version| version.as_str())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The versions in `asdf list <tool>`, where the current one is starred
+fn asdf_versions(list: &str) -> Vec<String> {
+    list.lines()
+        .map(|line| line.trim().trim_start_matches('*').trim())
+        .filter(|version| !version.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// The version of `tool` in a `.tool-versions` file
+fn tool_version(tool_versions: &str, tool: &str) -> Option<String> {
+    tool_versions.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        (words.next() == Some(tool)).then(|| words.next().map(String::from))?
+    })
+}
+
+impl RuntimeInstall {
+    fn exec(&self, manager: RuntimeManager, arguments: &[&str]) -> Step {
+        Step {
+            atom: Box::new(Exec {
+                command: String::from(manager.command()),
+                arguments: arguments
+                    .iter()
+                    .map(|argument| argument.to_string())
+                    .collect(),
+                ..Default::default()
+            }),
+            initializers: vec![],
+            finalizers: vec![],
+        }
+    }
+
+    /// Reports the runtime as installed, rather than the command
+    fn install(&self, manager: RuntimeManager, arguments: &[&str]) -> Step {
+        Step {
+            atom: Box::new(Exec {
+                command: String::from(manager.command()),
+                arguments: arguments
+                    .iter()
+                    .map(|argument| argument.to_string())
+                    .collect(),
+                side_effects: vec![SideEffect::Install {
+                    provider: String::from(manager.command()),
+                    packages: vec![format!("{}@{}", self.tool, self.version)],
+                }],
+                ..Default::default()
+            }),
+            initializers: vec![],
+            finalizers: vec![],
+        }
+    }
+
+    fn plan_mise(&self) -> Vec<Step> {
+        let spec = format!("{}@{}", self.tool, self.version);
+        let mut steps = vec![];
+
+        let installed = mise_versions(
+            &list("mise", &["ls", "--installed", "--json", &self.tool]),
+            &self.tool,
+        );
+        if !installed
+            .iter()
+            .any(|installed| satisfies(installed, &self.version))
+        {
+            steps.push(self.install(RuntimeManager::Mise, &["install", &spec]));
+        }
+
+        if self.global {
+            let global = mise_versions(
+                &list("mise", &["ls", "--global", "--json", &self.tool]),
+                &self.tool,
+            );
+            if !global.iter().any(|global| satisfies(global, &self.version)) {
+                steps.push(self.exec(RuntimeManager::Mise, &["use", "--global", &spec]));
+            }
+        }
+
+        steps
+    }
+
+    fn plan_asdf(&self) -> Vec<Step> {
+        let mut steps = vec![];
+
+        let plugins = asdf_versions(&list("asdf", &["plugin", "list"]));
+        if !plugins.contains(&self.tool) {
+            steps.push(self.exec(RuntimeManager::Asdf, &["plugin", "add", &self.tool]));
+        }
+
+        let installed = asdf_versions(&list("asdf", &["list", &self.tool]));
+        if !installed
+            .iter()
+            .any(|installed| satisfies(installed, &self.version))
+        {
+            steps.push(self.install(
+                RuntimeManager::Asdf,
+                &["install", &self.tool, &self.version],
+            ));
+        }
+
+        if self.global {
+            let tool_versions = dirs_next::home_dir()
+                .and_then(|home| std::fs::read_to_string(home.join(".tool-versions")).ok())
+                .unwrap_or_default();
+
+            if tool_version(&tool_versions, &self.tool).as_deref() != Some(self.version.as_str()) {
+                // asdf 0.16 replaced `global` with `set --home`
+                let version = list("asdf", &["--version"]);
+                let numbers = crate::rhai_functions::version_numbers(
+                    version.trim().trim_start_matches("asdf version "),
+                );
+
+                let arguments = match numbers.as_slice() {
+                    [0, minor, ..] if *minor < 16 => vec!["global"],
+                    _ => vec!["set", "--home"],
+                };
+
+                steps.push(self.exec(
+                    RuntimeManager::Asdf,
+                    &[arguments, vec![&self.tool, &self.version]].concat(),
+                ));
+            }
+        }
+
+        steps
+    }
+}
+
+impl Action for RuntimeInstall {
+    fn summarize(&self) -> String {
+        format!("Installing {} {}", self.tool, self.version)
+    }
+
+    fn plan(&self, _: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
+        let manager = self
+            .manager
+            .or_else(RuntimeManager::detect)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Neither mise nor asdf is installed to install {}",
+                    self.tool
+                )
+            })?;
+
+        Ok(match manager {
+            RuntimeManager::Mise => self.plan_mise(),
+            RuntimeManager::Asdf => self.plan_asdf(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let yaml = r#"
+- action: runtime.install
+  tool: node
+  version: "22"
+  global: true
+  manager: mise
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::RuntimeInstall(action)) => {
+                assert_eq!(
+                    RuntimeInstall {
+                        tool: String::from("node"),
+                        version: String::from("22"),
+                        global: true,
+                        manager: Some(RuntimeManager::Mise),
+                    },
+                    action.action
+                );
+            }
+            _ => {
+                panic!("RuntimeInstall didn't deserialize to the correct type");
+            }
+        };
+    }
+
+    #[test]
+    fn it_reads_installed_versions() {
+        let mise = r#"[{"version": "22.11.0", "requested_version": "22", "installed": true}]"#;
+        assert_eq!(vec!["22.11.0", "22"], mise_versions(mise, "node"));
+        assert_eq!(
+            vec!["3.12.7"],
+            mise_versions(r#"{"python": [{"version": "3.12.7"}]}"#, "python")
+        );
+        assert_eq!(Vec::<String>::new(), mise_versions("", "node"));
+
+        assert_eq!(
+            vec!["20.18.0", "22.11.0"],
+            asdf_versions("  20.18.0\n *22.11.0\n")
+        );
+
+        assert_eq!(
+            Some(String::from("3.12.7")),
+            tool_version("nodejs 22.11.0\npython 3.12.7 system\n", "python")
+        );
+        assert_eq!(None, tool_version("nodejs 22.11.0\n", "python"));
+    }
+
+    #[test]
+    fn it_matches_version_prefixes() {
+        assert_eq!(true, satisfies("22.11.0", "22"));
+        assert_eq!(true, satisfies("22.11.0", "22.11.0"));
+        assert_eq!(true, satisfies("22.11.0", "latest"));
+        assert_eq!(false, satisfies("2.11.0", "22"));
+        assert_eq!(false, satisfies("220.1.0", "22"));
+    }
+}
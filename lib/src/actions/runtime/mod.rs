@@ -0,0 +1,2 @@
+mod install;
+pub use install::RuntimeInstall;
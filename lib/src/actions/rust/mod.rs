@@ -0,0 +1,2 @@
+mod toolchain;
+pub use toolchain::RustToolchain;
@@ -0,0 +1,281 @@
+use crate::actions::{default_retry_delay, Action, Retry};
+use crate::atoms::command::Exec;
+use crate::atoms::SideEffect;
+use crate::contexts::Contexts;
+use crate::manifests::Manifest;
+use crate::steps::Step;
+use crate::utilities::list_output;
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use which::which;
+
+/// Installs a Rust toolchain with rustup, with components and targets
+#[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RustToolchain {
+    /// Like `stable`, `nightly` or `1.82.0`
+    #[serde(default = "stable")]
+    pub toolchain: String,
+
+    /// Like `clippy`, `rustfmt` or `rust-analyzer`
+    #[serde(default)]
+    pub components: Vec<String>,
+
+    /// Targets to cross-compile to, like `wasm32-unknown-unknown`
+    #[serde(default)]
+    pub targets: Vec<String>,
+
+    /// Makes it the toolchain used outside of projects that pin one
+    #[serde(default)]
+    pub default: bool,
+
+    #[serde(default)]
+    pub retries: u32,
+
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
+}
+
+fn stable() -> String {
+    String::from("stable")
+}
+
+/// Whether a name rustup lists, like `stable-x86_64-unknown-linux-gnu (default)`
+/// or `clippy-x86_64-unknown-linux-gnu`, is `name`, which leaves out the host
+fn lists(listed: &str, name: &str, host: &str) -> bool {
+    let listed = listed.split_whitespace().next().unwrap_or_default();
+
+    listed == name || (!host.is_empty() && listed == format!("{name}-{host}"))
+}
+
+/// The names of `wanted` that aren't in rustup's list
+fn missing(list: &str, wanted: &[String], host: &str) -> Vec<String> {
+    wanted
+        .iter()
+        .filter(|name| !list.lines().any(|listed| lists(listed, name, host)))
+        .cloned()
+        .collect()
+}
+
+/// The triple rustup installs toolchains for, empty when it doesn't say
+fn host(show: &str) -> String {
+    show.lines()
+        .find_map(|line| line.strip_prefix("Default host:"))
+        .map(|host| host.trim().to_string())
+        .unwrap_or_default()
+}
+
+impl RustToolchain {
+    fn rustup(&self, arguments: Vec<String>, side_effects: Vec<SideEffect>) -> Step {
+        Step {
+            atom: Box::new(Exec {
+                command: String::from("rustup"),
+                arguments,
+                side_effects,
+                ..Default::default()
+            }),
+            initializers: vec![],
+            finalizers: vec![],
+        }
+    }
+
+    fn installs(&self, names: Vec<String>) -> Vec<SideEffect> {
+        vec![SideEffect::Install {
+            provider: String::from("rustup"),
+            packages: names,
+        }]
+    }
+}
+
+impl Action for RustToolchain {
+    fn summarize(&self) -> String {
+        format!("Installing Rust {} toolchain", self.toolchain)
+    }
+
+    fn retry(&self) -> Retry {
+        Retry {
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+        }
+    }
+
+    fn plan(&self, _: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
+        if which("rustup").is_err() {
+            return Err(anyhow!(
+                "rustup isn't installed to install the {} toolchain",
+                self.toolchain
+            ));
+        }
+
+        let toolchain = self.toolchain.clone();
+        let mut steps = vec![];
+
+        let host = host(&list_output("rustup", &["show"]));
+
+        let installed = missing(
+            &list_output("rustup", &["toolchain", "list"]),
+            std::slice::from_ref(&toolchain),
+            &host,
+        )
+        .is_empty();
+
+        if installed {
+            let components = missing(
+                &list_output(
+                    "rustup",
+                    &[
+                        "component",
+                        "list",
+                        "--installed",
+                        "--toolchain",
+                        &toolchain,
+                    ],
+                ),
+                &self.components,
+                &host,
+            );
+            if !components.is_empty() {
+                steps.push(
+                    self.rustup(
+                        [
+                            vec![String::from("component"), String::from("add")],
+                            vec![String::from("--toolchain"), toolchain.clone()],
+                            components.clone(),
+                        ]
+                        .concat(),
+                        self.installs(components),
+                    ),
+                );
+            }
+
+            let targets = missing(
+                &list_output(
+                    "rustup",
+                    &["target", "list", "--installed", "--toolchain", &toolchain],
+                ),
+                &self.targets,
+                &host,
+            );
+            if !targets.is_empty() {
+                steps.push(
+                    self.rustup(
+                        [
+                            vec![String::from("target"), String::from("add")],
+                            vec![String::from("--toolchain"), toolchain.clone()],
+                            targets.clone(),
+                        ]
+                        .concat(),
+                        self.installs(targets),
+                    ),
+                );
+            }
+        } else {
+            let mut arguments = vec![
+                String::from("toolchain"),
+                String::from("install"),
+                toolchain.clone(),
+            ];
+            if !self.components.is_empty() {
+                arguments.extend([String::from("--component"), self.components.join(",")]);
+            }
+            if !self.targets.is_empty() {
+                arguments.extend([String::from("--target"), self.targets.join(",")]);
+            }
+
+            steps.push(
+                self.rustup(
+                    arguments,
+                    self.installs(
+                        [
+                            vec![toolchain.clone()],
+                            self.components.clone(),
+                            self.targets.clone(),
+                        ]
+                        .concat(),
+                    ),
+                ),
+            );
+        }
+
+        if self.default && !lists(&list_output("rustup", &["default"]), &toolchain, &host) {
+            steps.push(self.rustup(vec![String::from("default"), toolchain], vec![]));
+        }
+
+        Ok(steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let yaml = r#"
+- action: rust.toolchain
+  toolchain: nightly
+  components: [clippy, rust-analyzer]
+  targets: [wasm32-unknown-unknown]
+  default: true
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::RustToolchain(action)) => {
+                assert_eq!("nightly", action.action.toolchain);
+                assert_eq!(
+                    vec![String::from("clippy"), String::from("rust-analyzer")],
+                    action.action.components
+                );
+                assert_eq!(
+                    vec![String::from("wasm32-unknown-unknown")],
+                    action.action.targets
+                );
+                assert_eq!(true, action.action.default);
+            }
+            _ => {
+                panic!("RustToolchain didn't deserialize to the correct type");
+            }
+        };
+    }
+
+    #[test]
+    fn it_finds_whats_missing() {
+        let host = host("Default host: x86_64-unknown-linux-gnu\nrustup home:  /root/.rustup\n");
+        assert_eq!("x86_64-unknown-linux-gnu", host);
+
+        let toolchains =
+            "stable-x86_64-unknown-linux-gnu (default)\n1.82.0-x86_64-unknown-linux-gnu\n";
+        assert_eq!(
+            vec![String::from("nightly")],
+            missing(
+                toolchains,
+                &[
+                    String::from("stable"),
+                    String::from("1.82.0-x86_64-unknown-linux-gnu"),
+                    String::from("nightly")
+                ],
+                &host
+            )
+        );
+
+        let components =
+            "cargo-x86_64-unknown-linux-gnu\nclippy-x86_64-unknown-linux-gnu\nrust-analyzer-x86_64-unknown-linux-gnu\nrust-src\n";
+        assert_eq!(
+            vec![String::from("rust"), String::from("rustfmt")],
+            missing(
+                components,
+                &[
+                    String::from("clippy"),
+                    String::from("rust-src"),
+                    String::from("rust"),
+                    String::from("rustfmt")
+                ],
+                &host
+            )
+        );
+    }
+}
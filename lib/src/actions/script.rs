@@ -0,0 +1,97 @@
+use crate::atoms::script::Rhai;
+use crate::manifests::Manifest;
+use crate::steps::Step;
+use crate::{actions::Action, contexts::Contexts};
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RhaiScript {
+    /// Inline Rhai script
+    #[serde(default)]
+    pub script: Option<String>,
+
+    /// Rhai script file, relative to the manifest
+    #[serde(default)]
+    pub file: Option<String>,
+
+    /// Store what the script printed under this context for later actions
+    #[serde(default)]
+    pub register: Option<String>,
+}
+
+impl Action for RhaiScript {
+    fn summarize(&self) -> String {
+        match &self.file {
+            Some(file) => format!("Running Rhai script {}", file),
+            None => String::from("Running Rhai script"),
+        }
+    }
+
+    fn register(&self) -> Option<&str> {
+        self.register.as_deref()
+    }
+
+    fn files(&self, manifest: &Manifest) -> Vec<PathBuf> {
+        let dir = manifest
+            .root_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        self.file.iter().map(|file| dir.join(file)).collect()
+    }
+
+    fn plan(&self, manifest: &Manifest, context: &Contexts) -> anyhow::Result<Vec<Step>> {
+        let dir = manifest
+            .root_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        let script = match (&self.script, &self.file) {
+            (Some(_), Some(_)) => return Err(anyhow!("Only one of script and file can be given")),
+            (Some(script), None) => script.clone(),
+            (None, Some(file)) => std::fs::read_to_string(dir.join(file))
+                .map_err(|err| anyhow!("Failed to read script {}: {}", file, err))?,
+            (None, None) => return Err(anyhow!("Either script or file is required")),
+        };
+
+        Ok(vec![Step {
+            atom: Box::new(Rhai::new(script, context.clone(), dir)),
+            initializers: vec![],
+            finalizers: vec![],
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::actions::Actions;
+    use crate::manifests::Manifest;
+    use std::path::PathBuf;
+
+    fn get_manifest_dir() -> PathBuf {
+        std::env::current_dir()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join("examples")
+            .join("script")
+    }
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let example_yaml = std::fs::File::open(get_manifest_dir().join("rhai.yaml")).unwrap();
+        let mut manifest: Manifest = serde_yml::from_reader(example_yaml).unwrap();
+
+        match manifest.actions.pop() {
+            Some(Actions::RhaiScript(action)) => {
+                assert_eq!(Some(String::from("scripts/facts.rhai")), action.action.file);
+            }
+            _ => {
+                panic!("RhaiScript didn't deserialize to the correct type");
+            }
+        };
+    }
+}
@@ -0,0 +1,347 @@
+use crate::actions::{default_retry_delay, Action, Retry};
+use crate::atoms::command::Exec;
+use crate::atoms::git::Clone;
+use crate::atoms::SideEffect;
+use crate::contexts::Contexts;
+use crate::manifests::Manifest;
+use crate::steps::Step;
+use crate::utilities::list_output;
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use which::which;
+
+const OH_MY_ZSH: &str = "https://github.com/ohmyzsh/ohmyzsh.git";
+const ZINIT: &str = "https://github.com/zdharma-continuum/zinit.git";
+const FISHER: &str =
+    "https://raw.githubusercontent.com/jorgebucaran/fisher/main/functions/fisher.fish";
+
+/// Installs a shell framework, and plugins for it, without running its install script
+#[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShellFramework {
+    pub framework: Framework,
+
+    /// `owner/repo` on GitHub. Plugins bundled with oh-my-zsh are only a name,
+    /// and aren't installed.
+    #[serde(default)]
+    pub plugins: Vec<String>,
+
+    #[serde(default)]
+    pub retries: u32,
+
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
+}
+
+#[derive(JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Framework {
+    #[default]
+    OhMyZsh,
+    Zinit,
+    Fisher,
+}
+
+impl std::fmt::Display for Framework {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Framework::OhMyZsh => write!(f, "oh-my-zsh"),
+            Framework::Zinit => write!(f, "zinit"),
+            Framework::Fisher => write!(f, "fisher"),
+        }
+    }
+}
+
+/// Where the frameworks live, from the variables they read themselves
+struct Directories {
+    home: PathBuf,
+    var: fn(&str) -> Option<String>,
+}
+
+impl Directories {
+    fn var(&self, name: &str) -> Option<PathBuf> {
+        (self.var)(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    }
+
+    fn oh_my_zsh(&self) -> PathBuf {
+        self.var("ZSH")
+            .unwrap_or_else(|| self.home.join(".oh-my-zsh"))
+    }
+
+    fn oh_my_zsh_custom(&self) -> PathBuf {
+        self.var("ZSH_CUSTOM")
+            .unwrap_or_else(|| self.oh_my_zsh().join("custom"))
+    }
+
+    fn zinit(&self) -> PathBuf {
+        self.var("XDG_DATA_HOME")
+            .unwrap_or_else(|| self.home.join(".local").join("share"))
+            .join("zinit")
+    }
+
+    fn fish_functions(&self) -> PathBuf {
+        self.var("XDG_CONFIG_HOME")
+            .unwrap_or_else(|| self.home.join(".config"))
+            .join("fish")
+            .join("functions")
+    }
+}
+
+/// The repository of an `owner/repo` plugin
+fn repository(framework: Framework, plugin: &str) -> anyhow::Result<(&str, &str)> {
+    plugin
+        .split_once('/')
+        .filter(|(owner, repo)| !owner.is_empty() && !repo.is_empty() && !repo.contains('/'))
+        .ok_or_else(|| anyhow!("The {framework} plugin {plugin} isn't `owner/repo` on GitHub"))
+}
+
+fn clone(repository: String, directory: PathBuf) -> Step {
+    Step {
+        atom: Box::new(Clone {
+            repository,
+            directory,
+            reference: None,
+            depth: Some(1),
+            submodules: false,
+            sparse: vec![],
+        }),
+        initializers: vec![],
+        finalizers: vec![],
+    }
+}
+
+fn fish(script: String, side_effects: Vec<SideEffect>) -> Step {
+    Step {
+        atom: Box::new(Exec {
+            command: String::from("fish"),
+            arguments: vec![String::from("-c"), script],
+            side_effects,
+            ..Default::default()
+        }),
+        initializers: vec![],
+        finalizers: vec![],
+    }
+}
+
+/// The plugins `fisher list` doesn't have, which lists them in lowercase
+fn missing(list: &str, plugins: &[String]) -> Vec<String> {
+    plugins
+        .iter()
+        .filter(|plugin| {
+            !list
+                .lines()
+                .any(|listed| listed.trim().eq_ignore_ascii_case(plugin))
+        })
+        .cloned()
+        .collect()
+}
+
+impl ShellFramework {
+    fn plan_in(&self, directories: &Directories) -> anyhow::Result<Vec<Step>> {
+        let mut steps = vec![];
+
+        match self.framework {
+            Framework::OhMyZsh => {
+                steps.push(clone(String::from(OH_MY_ZSH), directories.oh_my_zsh()));
+
+                let custom = directories.oh_my_zsh_custom().join("plugins");
+                for plugin in self.plugins.iter().filter(|plugin| plugin.contains('/')) {
+                    let (_, repo) = repository(self.framework, plugin)?;
+                    steps.push(clone(
+                        format!("https://github.com/{plugin}.git"),
+                        custom.join(repo),
+                    ));
+                }
+            }
+
+            Framework::Zinit => {
+                let zinit = directories.zinit();
+                steps.push(clone(String::from(ZINIT), zinit.join("zinit.git")));
+
+                // Where `zinit light owner/repo` looks for them before cloning
+                for plugin in &self.plugins {
+                    let (owner, repo) = repository(self.framework, plugin)?;
+                    steps.push(clone(
+                        format!("https://github.com/{plugin}.git"),
+                        zinit.join("plugins").join(format!("{owner}---{repo}")),
+                    ));
+                }
+            }
+
+            Framework::Fisher => {
+                if which("fish").is_err() {
+                    return Err(anyhow!("fish isn't installed to install fisher"));
+                }
+
+                for plugin in &self.plugins {
+                    repository(self.framework, plugin)?;
+                }
+
+                let installed = is_installed(&directories.fish_functions());
+
+                if !installed {
+                    steps.push(fish(
+                        format!("curl -sL {FISHER} | source && fisher install jorgebucaran/fisher"),
+                        vec![SideEffect::Install {
+                            provider: String::from("fish"),
+                            packages: vec![String::from("jorgebucaran/fisher")],
+                        }],
+                    ));
+                }
+
+                let plugins = match installed {
+                    true => missing(&list_output("fish", &["-c", "fisher list"]), &self.plugins),
+                    false => self.plugins.clone(),
+                };
+
+                if !plugins.is_empty() {
+                    steps.push(fish(
+                        format!("fisher install {}", plugins.join(" ")),
+                        vec![SideEffect::Install {
+                            provider: String::from("fisher"),
+                            packages: plugins,
+                        }],
+                    ));
+                }
+            }
+        }
+
+        Ok(steps)
+    }
+}
+
+fn is_installed(fish_functions: &Path) -> bool {
+    fish_functions.join("fisher.fish").exists()
+}
+
+impl Action for ShellFramework {
+    fn summarize(&self) -> String {
+        format!("Installing {}", self.framework)
+    }
+
+    fn retry(&self) -> Retry {
+        Retry {
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+        }
+    }
+
+    fn plan(&self, _: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
+        let home = dirs_next::home_dir()
+            .ok_or_else(|| anyhow!("There's no home directory to install {}", self.framework))?;
+
+        self.plan_in(&Directories {
+            home,
+            var: |name| std::env::var(name).ok(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
+
+    fn directories() -> Directories {
+        Directories {
+            home: PathBuf::from("/home/test"),
+            var: |_| None,
+        }
+    }
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let yaml = r#"
+- action: shell.framework
+  framework: oh-my-zsh
+  plugins:
+    - git
+    - zsh-users/zsh-autosuggestions
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::ShellFramework(action)) => {
+                assert_eq!(Framework::OhMyZsh, action.action.framework);
+                assert_eq!(
+                    vec![
+                        String::from("git"),
+                        String::from("zsh-users/zsh-autosuggestions")
+                    ],
+                    action.action.plugins
+                );
+            }
+            _ => {
+                panic!("ShellFramework didn't deserialize to the correct type");
+            }
+        };
+    }
+
+    #[test]
+    fn it_clones_oh_my_zsh_and_its_plugins() {
+        let action = ShellFramework {
+            framework: Framework::OhMyZsh,
+            plugins: vec![
+                String::from("git"),
+                String::from("zsh-users/zsh-autosuggestions"),
+            ],
+            retries: 0,
+            retry_delay: 0,
+        };
+
+        let steps = action.plan_in(&directories()).unwrap();
+
+        assert_eq!(2, steps.len());
+        assert_eq!(
+            "The repository https://github.com/ohmyzsh/ohmyzsh.git needs to be cloned to /home/test/.oh-my-zsh",
+            steps[0].atom.to_string()
+        );
+        assert_eq!(
+            "The repository https://github.com/zsh-users/zsh-autosuggestions.git needs to be cloned to /home/test/.oh-my-zsh/custom/plugins/zsh-autosuggestions",
+            steps[1].atom.to_string()
+        );
+    }
+
+    #[test]
+    fn it_clones_zinit_plugins_where_zinit_looks() {
+        let action = ShellFramework {
+            framework: Framework::Zinit,
+            plugins: vec![String::from("zsh-users/zsh-syntax-highlighting")],
+            retries: 0,
+            retry_delay: 0,
+        };
+
+        let steps = action.plan_in(&directories()).unwrap();
+
+        assert_eq!(2, steps.len());
+        assert_eq!(
+            "The repository https://github.com/zsh-users/zsh-syntax-highlighting.git needs to be cloned to /home/test/.local/share/zinit/plugins/zsh-users---zsh-syntax-highlighting",
+            steps[1].atom.to_string()
+        );
+
+        let action = ShellFramework {
+            plugins: vec![String::from("git")],
+            ..action
+        };
+        assert!(action.plan_in(&directories()).is_err());
+    }
+
+    #[test]
+    fn it_finds_missing_fisher_plugins() {
+        assert_eq!(
+            vec![String::from("PatrickF1/fzf.fish")],
+            missing(
+                "jorgebucaran/fisher\njorgebucaran/nvm.fish\n",
+                &[
+                    String::from("jorgebucaran/nvm.fish"),
+                    String::from("PatrickF1/fzf.fish")
+                ]
+            )
+        );
+    }
+}
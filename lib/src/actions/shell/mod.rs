@@ -0,0 +1,2 @@
+mod framework;
+pub use framework::ShellFramework;
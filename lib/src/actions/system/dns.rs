@@ -0,0 +1,336 @@
+use crate::actions::Action;
+use crate::atoms::command::Exec;
+use crate::atoms::SideEffect;
+use crate::contexts::Contexts;
+use crate::manifests::Manifest;
+use crate::steps::Step;
+use crate::utilities::list_output;
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const RESOLVED_DROP_IN: &str = "/etc/systemd/resolved.conf.d/comtrya.conf";
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Sets the nameservers and search domains of the system resolver
+#[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SystemDns {
+    pub nameservers: Vec<String>,
+
+    /// Domains tried for names without a dot
+    #[serde(default)]
+    pub search: Vec<String>,
+
+    /// Only systemd-resolved supports it
+    #[serde(default)]
+    pub dns_over_tls: Option<DnsOverTls>,
+
+    /// The network service on macOS, like `Wi-Fi` or `Ethernet`
+    #[serde(default = "wifi")]
+    pub service: String,
+
+    /// Detected when not set: networksetup on macOS, systemd-resolved when it's
+    /// running, resolv.conf otherwise
+    #[serde(default)]
+    pub resolver: Option<Resolver>,
+}
+
+#[derive(JsonSchema, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsOverTls {
+    Yes,
+    Opportunistic,
+    No,
+}
+
+#[derive(JsonSchema, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Resolver {
+    SystemdResolved,
+    ResolvConf,
+    Networksetup,
+}
+
+fn wifi() -> String {
+    String::from("Wi-Fi")
+}
+
+impl Resolver {
+    fn detect() -> Resolver {
+        if cfg!(target_os = "macos") {
+            Resolver::Networksetup
+        } else if Path::new("/run/systemd/resolve").is_dir() {
+            Resolver::SystemdResolved
+        } else {
+            Resolver::ResolvConf
+        }
+    }
+}
+
+/// Writes a file owned by root, through `tee`
+fn write(path: &str, contents: String) -> Step {
+    Step {
+        atom: Box::new(Exec {
+            command: String::from("tee"),
+            arguments: vec![path.to_string()],
+            stdin: Some(contents),
+            privileged: true,
+            side_effects: vec![SideEffect::Write {
+                path: PathBuf::from(path),
+            }],
+            ..Default::default()
+        }),
+        initializers: vec![],
+        finalizers: vec![],
+    }
+}
+
+fn run(command: &str, arguments: Vec<String>) -> Step {
+    Step {
+        atom: Box::new(Exec {
+            command: command.to_string(),
+            arguments,
+            privileged: true,
+            ..Default::default()
+        }),
+        initializers: vec![],
+        finalizers: vec![],
+    }
+}
+
+/// What `networksetup -getdnsservers` or `-getsearchdomains` lists, which is a
+/// sentence when there's nothing
+fn networksetup_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.contains(' '))
+        .map(str::to_string)
+        .collect()
+}
+
+/// resolv.conf is usually a link to a file that resolvconf, NetworkManager,
+/// netconfig or systemd-resolved generate, which would be overwritten
+fn unmanaged(path: &Path) -> anyhow::Result<()> {
+    match std::fs::read_link(path) {
+        Ok(target) => Err(anyhow!(
+            "{} links to {}, which another resolver manages. Set `resolver` to systemd-resolved when it's that one, or configure the one managing it instead",
+            path.display(),
+            target.display()
+        )),
+        Err(_) => Ok(()),
+    }
+}
+
+impl SystemDns {
+    fn resolved_conf(&self) -> String {
+        let mut conf = format!("[Resolve]\nDNS={}\n", self.nameservers.join(" "));
+
+        if !self.search.is_empty() {
+            conf.push_str(&format!("Domains={}\n", self.search.join(" ")));
+        }
+
+        if let Some(dns_over_tls) = self.dns_over_tls {
+            let dns_over_tls = match dns_over_tls {
+                DnsOverTls::Yes => "yes",
+                DnsOverTls::Opportunistic => "opportunistic",
+                DnsOverTls::No => "no",
+            };
+            conf.push_str(&format!("DNSOverTLS={dns_over_tls}\n"));
+        }
+
+        conf
+    }
+
+    fn resolv_conf(&self) -> String {
+        let mut conf = String::from("# Managed by comtrya\n");
+
+        for nameserver in &self.nameservers {
+            conf.push_str(&format!("nameserver {nameserver}\n"));
+        }
+
+        if !self.search.is_empty() {
+            conf.push_str(&format!("search {}\n", self.search.join(" ")));
+        }
+
+        conf
+    }
+
+    fn plan_networksetup(&self) -> Vec<Step> {
+        let mut steps = vec![];
+
+        // `Empty` clears them
+        let or_empty = |values: &[String]| match values.is_empty() {
+            true => vec![String::from("Empty")],
+            false => values.to_vec(),
+        };
+
+        let nameservers = networksetup_list(&list_output(
+            "networksetup",
+            &["-getdnsservers", &self.service],
+        ));
+        if nameservers != self.nameservers {
+            steps.push(run(
+                "networksetup",
+                [
+                    vec![String::from("-setdnsservers"), self.service.clone()],
+                    or_empty(&self.nameservers),
+                ]
+                .concat(),
+            ));
+        }
+
+        let search = networksetup_list(&list_output(
+            "networksetup",
+            &["-getsearchdomains", &self.service],
+        ));
+        if search != self.search {
+            steps.push(run(
+                "networksetup",
+                [
+                    vec![String::from("-setsearchdomains"), self.service.clone()],
+                    or_empty(&self.search),
+                ]
+                .concat(),
+            ));
+        }
+
+        steps
+    }
+}
+
+impl Action for SystemDns {
+    fn summarize(&self) -> String {
+        format!("Setting DNS servers to {}", self.nameservers.join(", "))
+    }
+
+    fn plan(&self, _: &Manifest, _: &Contexts) -> anyhow::Result<Vec<Step>> {
+        let resolver = self.resolver.unwrap_or_else(Resolver::detect);
+
+        if self.dns_over_tls.is_some() && resolver != Resolver::SystemdResolved {
+            return Err(anyhow!("DNS-over-TLS needs systemd-resolved"));
+        }
+
+        let current = |path: &str| std::fs::read_to_string(path).unwrap_or_default();
+
+        Ok(match resolver {
+            Resolver::Networksetup => self.plan_networksetup(),
+
+            Resolver::SystemdResolved => {
+                let conf = self.resolved_conf();
+                match current(RESOLVED_DROP_IN) == conf {
+                    true => vec![],
+                    false => vec![
+                        run(
+                            "mkdir",
+                            vec![
+                                String::from("-p"),
+                                String::from("/etc/systemd/resolved.conf.d"),
+                            ],
+                        ),
+                        write(RESOLVED_DROP_IN, conf),
+                        run(
+                            "systemctl",
+                            vec![String::from("restart"), String::from("systemd-resolved")],
+                        ),
+                    ],
+                }
+            }
+
+            Resolver::ResolvConf => {
+                unmanaged(Path::new(RESOLV_CONF))?;
+
+                let conf = self.resolv_conf();
+                match current(RESOLV_CONF) == conf {
+                    true => vec![],
+                    false => vec![write(RESOLV_CONF, conf)],
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Actions;
+    use pretty_assertions::assert_eq;
+
+    fn dns() -> SystemDns {
+        SystemDns {
+            nameservers: vec![String::from("1.1.1.1"), String::from("9.9.9.9")],
+            search: vec![String::from("example.com")],
+            dns_over_tls: Some(DnsOverTls::Opportunistic),
+            service: wifi(),
+            resolver: None,
+        }
+    }
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let yaml = r#"
+- action: system.dns
+  nameservers: [1.1.1.1, 9.9.9.9]
+  search: [example.com]
+  dns_over_tls: opportunistic
+"#;
+
+        let mut actions: Vec<Actions> = serde_yml::from_str(yaml).unwrap();
+
+        match actions.pop() {
+            Some(Actions::SystemDns(action)) => {
+                assert_eq!(dns(), action.action);
+            }
+            _ => {
+                panic!("SystemDns didn't deserialize to the correct type");
+            }
+        };
+    }
+
+    #[test]
+    fn it_renders_the_resolver_config() {
+        assert_eq!(
+            "[Resolve]\nDNS=1.1.1.1 9.9.9.9\nDomains=example.com\nDNSOverTLS=opportunistic\n",
+            dns().resolved_conf()
+        );
+
+        assert_eq!(
+            "# Managed by comtrya\nnameserver 1.1.1.1\nnameserver 9.9.9.9\nsearch example.com\n",
+            dns().resolv_conf()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_refuses_resolv_conf_managed_by_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let resolv_conf = dir.path().join("resolv.conf");
+
+        std::fs::write(&resolv_conf, "nameserver 1.1.1.1\n").unwrap();
+        assert_eq!(true, unmanaged(&resolv_conf).is_ok());
+
+        // Like the one NetworkManager generates
+        std::fs::remove_file(&resolv_conf).unwrap();
+        std::os::unix::fs::symlink("/run/NetworkManager/resolv.conf", &resolv_conf).unwrap();
+        assert_eq!(
+            format!(
+                "{} links to /run/NetworkManager/resolv.conf, which another resolver manages. Set `resolver` to systemd-resolved when it's that one, or configure the one managing it instead",
+                resolv_conf.display()
+            ),
+            unmanaged(&resolv_conf).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn it_reads_networksetup() {
+        assert_eq!(
+            vec![String::from("1.1.1.1"), String::from("9.9.9.9")],
+            networksetup_list("1.1.1.1\n9.9.9.9\n")
+        );
+        assert_eq!(
+            Vec::<String>::new(),
+            networksetup_list("There aren't any DNS Servers set on Wi-Fi.\n")
+        );
+    }
+}
@@ -0,0 +1,2 @@
+mod dns;
+pub use dns::SystemDns;
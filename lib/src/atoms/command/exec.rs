@@ -1,8 +1,18 @@
-use crate::atoms::Outcome;
+use crate::atoms::{Outcome, SideEffect};
 
 use super::super::Atom;
+use super::helper;
+use super::privilege::{askpass_arguments, askpass_environment, is_elevated, privilege, Privilege};
+use crate::rollback::Undo;
 use crate::utilities;
 use anyhow::anyhow;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use tracing::debug;
 
 #[derive(Default)]
@@ -11,7 +21,19 @@ pub struct Exec {
     pub arguments: Vec<String>,
     pub working_dir: Option<String>,
     pub environment: Vec<(String, String)>,
+    /// Start from an empty environment, only `environment` is passed on
+    pub env_clear: bool,
     pub privileged: bool,
+    /// Run the command as this user, rather than root or the current user
+    pub user: Option<String>,
+    /// Command line that reverses this command, recorded for rollback
+    pub undo: Option<Vec<String>>,
+    /// Kill the command when it runs longer than this
+    pub timeout: Option<Duration>,
+    /// Written to the command's stdin, which is otherwise empty
+    pub stdin: Option<String>,
+    /// What the command changes, reported next to running it, like the packages it installs
+    pub side_effects: Vec<SideEffect>,
     pub(crate) status: ExecStatus,
 }
 
@@ -22,6 +44,146 @@ pub(crate) struct ExecStatus {
     stderr: String,
 }
 
+/// Process groups of the running commands, in fixed slots as the signal
+/// handler that interrupts them can't take a lock
+#[cfg(unix)]
+static GROUPS: [AtomicI32; 64] = [const { AtomicI32::new(0) }; 64];
+
+/// Keeps the process group of a running command in [`GROUPS`] until dropped
+#[cfg(unix)]
+struct Running(Option<usize>);
+
+#[cfg(unix)]
+impl Running {
+    fn new(pid: u32) -> Self {
+        Running(GROUPS.iter().position(|slot| {
+            slot.compare_exchange(0, pid as i32, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        }))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Running {
+    fn drop(&mut self) {
+        if let Some(slot) = self.0 {
+            GROUPS[slot].store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Held while a command has the terminal, so only one at a time is given it
+#[cfg(unix)]
+static TERMINAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// The terminal, given to a command that stopped to read it, as shells do, until
+/// dropped. Ctrl-C reaches the command in the meantime.
+#[cfg(unix)]
+struct Foreground {
+    terminal: libc::c_int,
+    _held: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(unix)]
+impl Foreground {
+    /// None while another command has it, or when comtrya doesn't either, like
+    /// when it was started in the background
+    fn give(group: u32) -> Option<Self> {
+        let held = TERMINAL.try_lock().ok()?;
+
+        let terminal = unsafe { libc::open(c"/dev/tty".as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+        if terminal < 0 {
+            return None;
+        }
+
+        let given = unsafe {
+            libc::tcgetpgrp(terminal) == libc::getpgrp()
+                && libc::tcsetpgrp(terminal, group as libc::pid_t) == 0
+        };
+        if !given {
+            unsafe { libc::close(terminal) };
+            return None;
+        }
+
+        Some(Foreground {
+            terminal,
+            _held: held,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Foreground {
+    fn drop(&mut self) {
+        // Out of the foreground, taking the terminal back raises SIGTTOU, unless
+        // it's blocked
+        unsafe {
+            let mut ttou = std::mem::zeroed::<libc::sigset_t>();
+            let mut previous = std::mem::zeroed::<libc::sigset_t>();
+            libc::sigemptyset(&mut ttou);
+            libc::sigaddset(&mut ttou, libc::SIGTTOU);
+
+            libc::pthread_sigmask(libc::SIG_BLOCK, &ttou, &mut previous);
+            libc::tcsetpgrp(self.terminal, libc::getpgrp());
+            libc::pthread_sigmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+
+            libc::close(self.terminal);
+        }
+    }
+}
+
+/// What a command is doing, as `waitpid` tells
+#[cfg(unix)]
+enum Waited {
+    Running,
+    /// Stopped to read from, or configure, the terminal it isn't in the foreground of
+    WantsTerminal,
+    Exited(std::process::ExitStatus),
+}
+
+/// Like `Child::try_wait`, but also tells when the command stopped for the terminal
+#[cfg(unix)]
+fn try_wait(child: &std::process::Child) -> std::io::Result<Waited> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+
+    match unsafe {
+        libc::waitpid(
+            child.id() as libc::pid_t,
+            &mut status,
+            libc::WNOHANG | libc::WUNTRACED,
+        )
+    } {
+        -1 => Err(std::io::Error::last_os_error()),
+        0 => Ok(Waited::Running),
+        _ if libc::WIFSTOPPED(status) => {
+            Ok(match libc::WSTOPSIG(status) {
+                libc::SIGTTIN | libc::SIGTTOU => Waited::WantsTerminal,
+                // Stopped by someone else, who'll continue it
+                _ => Waited::Running,
+            })
+        }
+        _ => Ok(Waited::Exited(std::process::ExitStatus::from_raw(status))),
+    }
+}
+
+/// Sends SIGINT to the running commands. They run in their own process group,
+/// so that the first Ctrl-C lets them finish, and only get it once this is
+/// called. Safe to call from a signal handler.
+pub fn interrupt_running() {
+    #[cfg(unix)]
+    for slot in GROUPS.iter() {
+        let group = slot.load(Ordering::SeqCst);
+
+        if group > 0 {
+            unsafe {
+                libc::killpg(group, libc::SIGINT);
+            }
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub fn new_run_command(command: String) -> Exec {
     Exec {
@@ -31,47 +193,237 @@ pub fn new_run_command(command: String) -> Exec {
 }
 
 impl Exec {
-    fn elevate_if_required(&self) -> (String, Vec<String>) {
+    fn elevate_with(&self, privilege: Privilege) -> (String, Vec<String>) {
+        let username = whoami::username();
+
+        // Running as ourselves doesn't need any elevation
+        let run_as = self.user.as_deref().filter(|user| username.ne(user));
+
         // Depending on the priviledged flag and who who the current user is
-        // we can determine if we need to prepend sudo to the command
-        match (self.privileged, whoami::username().as_str()) {
+        // we can determine if we need to prepend sudo (or doas, ...) to the command
+        match (self.privileged, run_as, is_elevated()) {
+            // Requested another user, which always goes through the privilege
+            // program (or runas on Windows)
+            (_, Some(user), _) if cfg!(target_family = "windows") => (
+                String::from("runas"),
+                vec![
+                    format!("/user:{}", user),
+                    [vec![self.command.clone()], self.arguments.clone()]
+                        .concat()
+                        .join(" "),
+                ],
+            ),
+
+            (_, Some(user), _) => (
+                String::from(privilege.command()),
+                [
+                    askpass_arguments(privilege),
+                    privilege.user_arguments(user),
+                    self.elevated_environment(),
+                    vec![self.command.clone()],
+                    self.arguments.clone(),
+                ]
+                .concat(),
+            ),
+
             // Hasn't requested priviledged, so never try to elevate
-            (false, _) => (self.command.clone(), self.arguments.clone()),
+            (false, None, _) => (self.command.clone(), self.arguments.clone()),
 
-            // Requested priviledged, but is already root
-            (true, "root") => (self.command.clone(), self.arguments.clone()),
+            // Requested priviledged, but is already root (or elevated on Windows)
+            (true, None, true) => (self.command.clone(), self.arguments.clone()),
+
+            // Requested priviledged on Windows, so UAC asks for consent
+            (true, None, false) if privilege == Privilege::Uac => (
+                String::from(privilege.command()),
+                Privilege::uac_arguments(&self.command, &self.arguments),
+            ),
 
             // Requested priviledged, but is not root
-            (true, _) => (
-                String::from("sudo"),
-                [vec![self.command.clone()], self.arguments.clone()].concat(),
+            (true, None, false) => (
+                String::from(privilege.command()),
+                [
+                    askpass_arguments(privilege),
+                    self.elevated_environment(),
+                    vec![self.command.clone()],
+                    self.arguments.clone(),
+                ]
+                .concat(),
             ),
         }
     }
 
-    fn elevate(&mut self) -> anyhow::Result<()> {
+    /// Like `Command::output`, but feeds stdin and kills the process once the timeout has passed
+    fn output(&self, process: &mut Command) -> anyhow::Result<Output> {
+        // Out of the terminal's foreground process group, so Ctrl-C cancels the
+        // run without killing the command. A command that prompts, like sudo once
+        // its timestamp ran out, or run0 and pkexec every time, is given the
+        // terminal when it stops to read it.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(process, 0);
+
+        let mut child = process
+            .stdin(match self.stdin {
+                Some(_) => Stdio::piped(),
+                None => Stdio::null(),
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // The pipe is closed once written, so the command sees the end of its input
+        if let (Some(input), Some(mut pipe)) = (self.stdin.clone(), child.stdin.take()) {
+            std::thread::spawn(move || pipe.write_all(input.as_bytes()));
+        }
+
+        // Read concurrently, so a chatty process can't block on a full pipe
+        fn read_all<R: Read + Send + 'static>(reader: Option<R>) -> JoinHandle<Vec<u8>> {
+            std::thread::spawn(move || {
+                let mut buffer = vec![];
+                if let Some(mut reader) = reader {
+                    let _ = reader.read_to_end(&mut buffer);
+                }
+                buffer
+            })
+        }
+
+        let stdout = read_all(child.stdout.take());
+        let stderr = read_all(child.stderr.take());
+
+        #[cfg(unix)]
+        let _running = Running::new(child.id());
+        let started = Instant::now();
+
+        #[cfg(unix)]
+        let mut foreground = None;
+        #[cfg(unix)]
+        let mut wants_terminal = false;
+
+        let status = loop {
+            #[cfg(unix)]
+            {
+                if wants_terminal {
+                    if foreground.is_none() {
+                        foreground = Foreground::give(child.id());
+                    }
+
+                    if foreground.is_some() {
+                        wants_terminal = false;
+                        unsafe {
+                            libc::killpg(child.id() as i32, libc::SIGCONT);
+                        }
+                    }
+                }
+
+                match try_wait(&child)? {
+                    Waited::Exited(status) => break status,
+                    Waited::WantsTerminal => wants_terminal = true,
+                    Waited::Running => {}
+                }
+            }
+            #[cfg(not(unix))]
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+
+            if let Some(timeout) = self.timeout.filter(|timeout| started.elapsed() >= *timeout) {
+                // The whole group, as the child may be sudo, or a shell, whose
+                // own children would keep running
+                #[cfg(unix)]
+                unsafe {
+                    libc::killpg(child.id() as i32, libc::SIGKILL);
+                }
+                #[cfg(not(unix))]
+                child.kill()?;
+
+                child.wait()?;
+
+                return Err(anyhow!(
+                    "Command timed out after {} seconds",
+                    timeout.as_secs()
+                ));
+            }
+
+            std::thread::sleep(Duration::from_millis(50));
+        };
+
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+
+    /// sudo, doas, run0 and pkexec reset the environment, so it's passed on through `env`
+    fn elevated_environment(&self) -> Vec<String> {
+        if !self.env_clear && self.environment.is_empty() {
+            return vec![];
+        }
+
+        [
+            vec![String::from("env")],
+            if self.env_clear {
+                vec![String::from("-i")]
+            } else {
+                vec![]
+            },
+            self.environment
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect(),
+        ]
+        .concat()
+    }
+
+    /// Commands are looked up in the PATH passed to them first, if any
+    fn binary_path(&self, command: &str) -> anyhow::Result<String> {
+        let path = self
+            .environment
+            .iter()
+            .find(|(key, _)| key == "PATH")
+            .and_then(|(_, path)| {
+                which::which_in(command, Some(path), std::env::current_dir().ok()?).ok()
+            });
+
+        match path {
+            Some(path) => Ok(path.to_string_lossy().to_string()),
+            None => utilities::get_binary_path(command)
+                .map_err(|_| anyhow!("Command `{}` not found in path", command)),
+        }
+    }
+
+    fn elevate(&mut self, privilege: Privilege) -> anyhow::Result<()> {
+        let Some(arguments) = privilege.validate_arguments() else {
+            return Ok(());
+        };
+
         tracing::info!(
-            "Sudo required for privilege elevation to run `{} {}`. Validating sudo ...",
+            "{} required for privilege elevation to run `{} {}`. Validating {} ...",
+            privilege.command(),
             &self.command,
-            &self.arguments.join(" ")
+            &self.arguments.join(" "),
+            privilege.command()
         );
 
-        match std::process::Command::new("sudo")
+        match std::process::Command::new(privilege.command())
             .stdin(std::process::Stdio::inherit())
             .stdout(std::process::Stdio::inherit())
             .stderr(std::process::Stdio::inherit())
-            .arg("--validate")
+            .args(askpass_arguments(privilege))
+            .args(arguments)
+            .envs(askpass_environment(privilege)?)
             .output()
         {
             Ok(std::process::Output { status, .. }) if status.success() => Ok(()),
 
             Ok(std::process::Output { stderr, .. }) => Err(anyhow!(
-                "Command requires sudo, but couldn't elevate privileges: {}",
+                "Command requires {}, but couldn't elevate privileges: {}",
+                privilege.command(),
                 String::from_utf8(stderr)?
             )),
 
             Err(err) => Err(anyhow!(
-                "Command requires sudo, but couldn't elevate privileges: {}",
+                "Command requires {}, but couldn't elevate privileges: {}",
+                privilege.command(),
                 err
             )),
         }
@@ -92,12 +444,18 @@ impl std::fmt::Display for Exec {
 
 impl Atom for Exec {
     fn plan(&self) -> anyhow::Result<Outcome> {
+        let (command, arguments) = self.elevate_with(privilege());
+
         Ok(Outcome {
-            // Commands may have side-effects, but none that can be "known"
-            // without some sandboxed operations to detect filesystem and network
-            // affects.
-            // Maybe we'll look into this one day?
-            side_effects: vec![],
+            // What else the command changes can't be known without running it
+            // in a sandbox, unless the action declared it
+            side_effects: [
+                vec![SideEffect::Command {
+                    command: [vec![command], arguments].concat().join(" "),
+                }],
+                self.side_effects.clone(),
+            ]
+            .concat(),
             // Commands should always run, we have no cache-key based
             // determinism atm the moment.
             should_run: true,
@@ -105,33 +463,54 @@ impl Atom for Exec {
     }
 
     fn execute(&mut self) -> anyhow::Result<()> {
-        let (command, arguments) = self.elevate_if_required();
-
-        let command = utilities::get_binary_path(&command)
-            .or_else(|_| Err(anyhow!("Command `{}` not found in path", command)))?;
-
-        // If we require root, we need to use sudo with inherited IO
-        // to ensure the user can respond if prompted for a password
-        if command.eq("sudo") {
-            match self.elevate() {
-                Ok(_) => (),
-                Err(err) => {
-                    return Err(anyhow!(err));
+        let privilege = privilege();
+        let (command, arguments) = self.elevate_with(privilege);
+        let elevated = command.eq(privilege.command()) && command.ne(&self.command);
+
+        if elevated {
+            // Running as root goes through the elevated helper, when there's one
+            if self.user.is_none() {
+                if let Some(reply) = helper::run(self, privilege)? {
+                    self.status.code = reply.code;
+                    self.status.stdout = reply.stdout;
+                    self.status.stderr = reply.stderr;
+
+                    return match reply.error {
+                        Some(error) => Err(anyhow!(error)),
+                        None => Ok(()),
+                    };
                 }
             }
+
+            // If we require root, we need to use sudo (or doas, ...) with inherited IO
+            // to ensure the user can respond if prompted for a password
+            self.elevate(privilege)?;
+        }
+
+        let command = self.binary_path(&command)?;
+
+        let mut process = std::process::Command::new(&command);
+
+        if self.env_clear {
+            process.env_clear();
+        }
+
+        if elevated {
+            process.envs(askpass_environment(privilege)?);
         }
 
-        match std::process::Command::new(&command)
+        process
             .envs(self.environment.clone())
             .args(&arguments)
-            .current_dir(&self.working_dir.clone().unwrap_or_else(|| {
+            .current_dir(self.working_dir.clone().unwrap_or_else(|| {
                 std::env::current_dir()
                     .map(|current_dir| current_dir.display().to_string())
                     .expect("Failed to get current directory")
-            }))
-            .output()
-        {
+            }));
+
+        match self.output(&mut process) {
             Ok(output) if output.status.success() => {
+                self.status.code = output.status.code().unwrap_or(0);
                 self.status.stdout = String::from_utf8(output.stdout)?;
                 self.status.stderr = String::from_utf8(output.stderr)?;
 
@@ -141,6 +520,7 @@ impl Atom for Exec {
             }
 
             Ok(output) => {
+                self.status.code = output.status.code().unwrap_or(1);
                 self.status.stdout = String::from_utf8(output.stdout)?;
                 self.status.stderr = String::from_utf8(output.stderr)?;
 
@@ -150,7 +530,7 @@ impl Atom for Exec {
 
                 Err(anyhow!(
                     "Command failed with exit code: {}",
-                    output.status.code().unwrap_or(1)
+                    self.status.code
                 ))
             }
 
@@ -158,6 +538,18 @@ impl Atom for Exec {
         }
     }
 
+    fn prepare_undo(&self, _: &Path) -> anyhow::Result<Option<Undo>> {
+        Ok(self
+            .undo
+            .as_ref()
+            .and_then(|undo| undo.split_first())
+            .map(|(command, args)| Undo::Command {
+                command: command.clone(),
+                args: args.to_vec(),
+                privileged: self.privileged,
+            }))
+    }
+
     fn output_string(&self) -> String {
         self.status.stdout.clone()
     }
@@ -165,6 +557,22 @@ impl Atom for Exec {
     fn error_message(&self) -> String {
         self.status.stderr.clone()
     }
+
+    fn status_code(&self) -> i32 {
+        self.status.code
+    }
+
+    fn set_default_timeout(&mut self, timeout: Duration) {
+        self.timeout.get_or_insert(timeout);
+    }
+
+    fn privileged(&self) -> bool {
+        self.privileged
+            || self
+                .user
+                .as_deref()
+                .is_some_and(|user| user != whoami::username())
+    }
 }
 
 #[cfg(test)]
@@ -197,7 +605,7 @@ mod tests {
     fn elevate() {
         let mut command_run = new_run_command(String::from("echo"));
         command_run.arguments = vec![String::from("Hello, world!")];
-        let (command, args) = command_run.elevate_if_required();
+        let (command, args) = command_run.elevate_with(Privilege::Sudo);
 
         assert_eq!(String::from("echo"), command);
         assert_eq!(vec![String::from("Hello, world!")], args);
@@ -205,7 +613,7 @@ mod tests {
         let mut command_run = new_run_command(String::from("echo"));
         command_run.arguments = vec![String::from("Hello, world!")];
         command_run.privileged = true;
-        let (command, args) = command_run.elevate_if_required();
+        let (command, args) = command_run.elevate_with(Privilege::Sudo);
 
         assert_eq!(String::from("sudo"), command);
         assert_eq!(
@@ -214,6 +622,142 @@ mod tests {
         );
     }
 
+    #[test]
+    fn elevate_with_environment() {
+        let mut command_run = new_run_command(String::from("echo"));
+        command_run.privileged = true;
+        command_run.env_clear = true;
+        command_run.environment = vec![(String::from("FOO"), String::from("bar"))];
+
+        if whoami::username() == "root" {
+            return;
+        }
+
+        let (command, args) = command_run.elevate_with(Privilege::Sudo);
+
+        assert_eq!(String::from("sudo"), command);
+        assert_eq!(vec!["env", "-i", "FOO=bar", "echo"], args);
+
+        let (command, args) = command_run.elevate_with(Privilege::Doas);
+
+        assert_eq!(String::from("doas"), command);
+        assert_eq!(vec!["env", "-i", "FOO=bar", "echo"], args);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_passes_environment() {
+        let mut command_run = new_run_command(String::from("sh"));
+        command_run.arguments = vec![String::from("-c"), String::from("echo $FOO")];
+        command_run.environment = vec![
+            (String::from("FOO"), String::from("bar")),
+            (String::from("PATH"), String::from("/usr/bin:/bin")),
+        ];
+        command_run.env_clear = true;
+
+        assert_eq!(true, command_run.execute().is_ok());
+        assert_eq!("bar\n", command_run.output_string());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn elevate_as_user() {
+        let mut command_run = new_run_command(String::from("echo"));
+        command_run.user = Some(String::from("not-the-current-user"));
+        let (command, args) = command_run.elevate_with(Privilege::Sudo);
+
+        assert_eq!(String::from("sudo"), command);
+        assert_eq!(vec!["-u", "not-the-current-user", "-H", "echo"], args);
+
+        let (command, args) = command_run.elevate_with(Privilege::Run0);
+
+        assert_eq!(String::from("run0"), command);
+        assert_eq!(vec!["--user=not-the-current-user", "echo"], args);
+
+        command_run.user = Some(whoami::username());
+        let (command, args) = command_run.elevate_with(Privilege::Sudo);
+
+        assert_eq!(String::from("echo"), command);
+        assert_eq!(0, args.len());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_times_out() {
+        let mut command_run = new_run_command(String::from("sleep"));
+        command_run.arguments = vec![String::from("5")];
+        command_run.timeout = Some(Duration::from_millis(200));
+
+        let started = Instant::now();
+        let result = command_run.execute();
+
+        assert_eq!(true, result.is_err());
+        assert_eq!(true, started.elapsed() < Duration::from_secs(5));
+
+        // Along with what the command started
+        let dir = tempfile::tempdir().unwrap();
+        let pid = dir.path().join("pid");
+        let mut command_run = new_run_command(String::from("sh"));
+        command_run.arguments = vec![
+            String::from("-c"),
+            format!("sleep 30 & echo $! > {}; wait", pid.display()),
+        ];
+        command_run.timeout = Some(Duration::from_secs(1));
+        assert_eq!(true, command_run.execute().is_err());
+
+        #[cfg(target_os = "linux")]
+        {
+            let pid = std::fs::read_to_string(&pid).unwrap();
+            let stat = format!("/proc/{}/stat", pid.trim());
+
+            // Gone, or a zombie nobody reaped yet, once the signal got there
+            let killed =
+                || std::fs::read_to_string(&stat).map_or(true, |stat| stat.contains(") Z "));
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !killed() && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            assert_eq!(true, killed());
+        }
+
+        let mut command_run = new_run_command(String::from("echo"));
+        command_run.arguments = vec![String::from("in time")];
+        command_run.set_default_timeout(Duration::from_secs(5));
+
+        assert_eq!(true, command_run.execute().is_ok());
+        assert_eq!("in time\n", command_run.output_string());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_writes_stdin() {
+        let mut command_run = new_run_command(String::from("cat"));
+        command_run.stdin = Some(String::from("secret token"));
+
+        assert_eq!(true, command_run.execute().is_ok());
+        assert_eq!("secret token", command_run.output_string());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn it_runs_commands_in_their_own_process_group() {
+        // The 5th field of stat is the process group
+        let mut command_run = new_run_command(String::from("sh"));
+        command_run.arguments = vec![
+            String::from("-c"),
+            String::from("echo $$ $(cut -d' ' -f5 /proc/$$/stat)"),
+        ];
+
+        assert_eq!(true, command_run.execute().is_ok());
+
+        let output = command_run.output_string();
+        let (pid, group) = output.trim().split_once(' ').unwrap();
+
+        // Leading a group of its own, Ctrl-C in the terminal doesn't reach it
+        assert_eq!(pid, group);
+        assert_ne!(unsafe { libc::getpgrp() }.to_string(), group);
+    }
+
     #[test]
     fn error_propagation() {
         let mut command_run = new_run_command(String::from("non-existant-command"));
@@ -0,0 +1,332 @@
+use super::privilege::{askpass_arguments, askpass_environment, Privilege};
+use super::Exec;
+use crate::atoms::Atom;
+use anyhow::anyhow;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// The argument the helper program is started with, see `serve_elevated`
+pub const ELEVATED_HELPER: &str = "elevated-helper";
+
+const READY: &str = "ready";
+
+static PROGRAM: RwLock<Option<PathBuf>> = RwLock::new(None);
+static HELPER: Mutex<Option<Helper>> = Mutex::new(None);
+
+/// Privileged commands are sent to one elevated process, started with sudo (or
+/// doas, ...) at the first of them, so the password is only asked for once and
+/// the sudo timestamp can't expire in the middle of a long run.
+struct Helper {
+    child: Child,
+    requests: Box<dyn Write + Send>,
+    replies: Box<dyn BufRead + Send>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Request {
+    command: String,
+    arguments: Vec<String>,
+    working_dir: Option<String>,
+    environment: Vec<(String, String)>,
+    env_clear: bool,
+    timeout: Option<Duration>,
+    stdin: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(super) struct Reply {
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
+    /// Why the command failed, if it did
+    pub error: Option<String>,
+}
+
+/// Sends privileged commands to this program, started with `ELEVATED_HELPER`,
+/// rather than elevating each one. Usually the running comtrya binary. Commands
+/// are elevated one by one without it, or when it can't be started.
+pub fn set_elevated_helper(program: Option<PathBuf>) {
+    *PROGRAM.write().unwrap() = program;
+}
+
+/// Runs the privileged commands sent on stdin, until it's closed. Started through
+/// UAC, which can't pass pipes on, it connects back to the address it's given.
+pub fn serve_elevated() -> anyhow::Result<()> {
+    let mut arguments = std::env::args().skip(2);
+
+    match (arguments.next(), arguments.next()) {
+        (Some(address), Some(token)) => {
+            let mut stream = TcpStream::connect(address)?;
+            writeln!(stream, "{}", token)?;
+
+            serve(BufReader::new(stream.try_clone()?), stream)
+        }
+        _ => serve(std::io::stdin().lock(), std::io::stdout().lock()),
+    }
+}
+
+fn serve(requests: impl BufRead, mut replies: impl Write) -> anyhow::Result<()> {
+    writeln!(replies, "{}", READY)?;
+    replies.flush()?;
+
+    for request in requests.lines() {
+        let request: Request = serde_json::from_str(&request?)?;
+
+        // Already elevated, so the command runs as is
+        let mut exec = Exec {
+            command: request.command,
+            arguments: request.arguments,
+            working_dir: request.working_dir,
+            environment: request.environment,
+            env_clear: request.env_clear,
+            timeout: request.timeout,
+            stdin: request.stdin,
+            ..Default::default()
+        };
+
+        let result = exec.execute();
+
+        let reply = Reply {
+            code: exec.status_code(),
+            stdout: exec.output_string(),
+            stderr: exec.error_message(),
+            error: result.err().map(|err| err.to_string()),
+        };
+
+        writeln!(replies, "{}", serde_json::to_string(&reply)?)?;
+        replies.flush()?;
+    }
+
+    Ok(())
+}
+
+impl Helper {
+    fn start(program: &Path, privilege: Privilege) -> anyhow::Result<Helper> {
+        info!(
+            "Starting an elevated helper with {}, for every privileged command",
+            privilege.command()
+        );
+
+        let mut helper = match privilege {
+            Privilege::Uac => Helper::start_uac(program)?,
+            _ => Helper::start_piped(program, privilege)?,
+        };
+
+        let mut ready = String::new();
+        let _ = helper.replies.read_line(&mut ready);
+
+        if ready.trim_end() != READY {
+            let _ = helper.child.kill();
+            let _ = helper.child.wait();
+
+            return Err(anyhow!(
+                "Command requires {}, but couldn't elevate privileges",
+                privilege.command()
+            ));
+        }
+
+        Ok(helper)
+    }
+
+    fn start_piped(program: &Path, privilege: Privilege) -> anyhow::Result<Helper> {
+        // The password prompt goes to the terminal, not these pipes
+        let mut child = Command::new(privilege.command())
+            .args(askpass_arguments(privilege))
+            .envs(askpass_environment(privilege)?)
+            .arg(program)
+            .arg(ELEVATED_HELPER)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|err| anyhow!("Unable to run {}: {}", privilege.command(), err))?;
+
+        let requests = child.stdin.take().ok_or(anyhow!("No stdin"))?;
+        let replies = BufReader::new(child.stdout.take().ok_or(anyhow!("No stdout"))?);
+
+        Ok(Helper {
+            child,
+            requests: Box::new(requests),
+            replies: Box::new(replies),
+        })
+    }
+
+    /// The helper connects back over the loopback, proving it's the one we
+    /// started with a token. PowerShell waits for it, or exits when UAC is declined.
+    fn start_uac(program: &Path) -> anyhow::Result<Helper> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        listener.set_nonblocking(true)?;
+
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+
+        let mut child = Command::new(Privilege::Uac.command())
+            .args(Privilege::uac_arguments(
+                &program.display().to_string(),
+                &[
+                    String::from(ELEVATED_HELPER),
+                    listener.local_addr()?.to_string(),
+                    token.clone(),
+                ],
+            ))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|err| anyhow!("Unable to run {}: {}", Privilege::Uac.command(), err))?;
+
+        let stream = loop {
+            match listener.accept() {
+                Ok((stream, _)) => break stream,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    if child.try_wait()?.is_some() {
+                        return Err(anyhow!("Elevation through UAC was declined"));
+                    }
+
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        };
+
+        stream.set_nonblocking(false)?;
+
+        let mut replies = BufReader::new(stream.try_clone()?);
+        let mut proof = String::new();
+        replies.read_line(&mut proof)?;
+
+        if proof.trim_end() != token {
+            let _ = child.kill();
+            return Err(anyhow!("Unexpected connection from the elevated helper"));
+        }
+
+        Ok(Helper {
+            child,
+            requests: Box::new(stream),
+            replies: Box::new(replies),
+        })
+    }
+
+    fn send(&mut self, request: &Request) -> anyhow::Result<Reply> {
+        writeln!(self.requests, "{}", serde_json::to_string(request)?)?;
+        self.requests.flush()?;
+
+        let mut reply = String::new();
+        if self.replies.read_line(&mut reply)? == 0 {
+            let _ = self.child.wait();
+            return Err(anyhow!("The elevated helper exited"));
+        }
+
+        Ok(serde_json::from_str(&reply)?)
+    }
+}
+
+/// Runs the command through the elevated helper, or returns `None` when there's
+/// no helper program to start
+pub(super) fn run(exec: &Exec, privilege: Privilege) -> anyhow::Result<Option<Reply>> {
+    let Some(program) = PROGRAM.read().unwrap().clone() else {
+        return Ok(None);
+    };
+
+    // Commands from parallel manifests wait for their turn
+    let mut helper = HELPER.lock().unwrap();
+
+    if helper.is_none() {
+        match Helper::start(&program, privilege) {
+            Ok(started) => *helper = Some(started),
+            Err(err) => {
+                // Elevated one by one instead, for the rest of the run
+                warn!("{}, elevating commands one by one", err);
+                set_elevated_helper(None);
+                return Ok(None);
+            }
+        }
+    }
+
+    let request = Request {
+        command: exec.command.clone(),
+        arguments: exec.arguments.clone(),
+        working_dir: Some(match &exec.working_dir {
+            Some(working_dir) => working_dir.clone(),
+            None => std::env::current_dir()?.display().to_string(),
+        }),
+        environment: exec.environment.clone(),
+        env_clear: exec.env_clear,
+        timeout: exec.timeout,
+        stdin: exec.stdin.clone(),
+    };
+
+    let Some(running) = helper.as_mut() else {
+        return Ok(None);
+    };
+
+    match running.send(&request) {
+        Ok(reply) => Ok(Some(reply)),
+        Err(err) => {
+            // Started again for the next command
+            *helper = None;
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[cfg(unix)]
+    #[test]
+    fn it_serves_commands() {
+        let requests = [
+            Request {
+                command: String::from("sh"),
+                arguments: vec![String::from("-c"), String::from("echo $FOO")],
+                working_dir: None,
+                environment: vec![(String::from("FOO"), String::from("bar"))],
+                env_clear: false,
+                timeout: None,
+                stdin: None,
+            },
+            Request {
+                command: String::from("false"),
+                arguments: vec![],
+                working_dir: None,
+                environment: vec![],
+                env_clear: false,
+                timeout: None,
+                stdin: None,
+            },
+        ]
+        .iter()
+        .map(|request| serde_json::to_string(request).unwrap() + "\n")
+        .collect::<String>();
+
+        let mut replies = vec![];
+        serve(requests.as_bytes(), &mut replies).unwrap();
+
+        let replies = String::from_utf8(replies).unwrap();
+        let mut lines = replies.lines();
+
+        assert_eq!(Some(READY), lines.next());
+
+        let reply: Reply = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert_eq!("bar\n", reply.stdout);
+        assert_eq!(None, reply.error);
+
+        let reply: Reply = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert_eq!(1, reply.code);
+        assert_eq!(true, reply.error.is_some());
+    }
+}
@@ -1,6 +1,16 @@
 use super::Atom;
 
 mod exec;
-pub use exec::Exec;
+pub use exec::{interrupt_running, Exec};
+
+mod helper;
+pub use helper::{serve_elevated, set_elevated_helper, ELEVATED_HELPER};
+
+mod privilege;
+pub(crate) use privilege::{can_elevate_without_password, is_elevated, privilege};
+pub use privilege::{
+    may_elevate, set_privilege, set_privilege_policy, set_sudo_askpass, Privilege, PrivilegePolicy,
+    PRIVILEGES_NOT_ALLOWED,
+};
 
 pub trait CommandAtom: Atom {}
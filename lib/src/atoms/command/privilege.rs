@@ -0,0 +1,353 @@
+use crate::secrets::Secret;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{OnceLock, RwLock};
+use tracing::debug;
+
+/// The program privileged commands are run through
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Privilege {
+    Sudo,
+    /// OpenBSD's doas, also common on Alpine
+    Doas,
+    /// systemd's run0, from v256
+    Run0,
+    /// polkit's pkexec
+    Pkexec,
+    /// Windows' UAC prompt, through PowerShell's `Start-Process -Verb RunAs`
+    Uac,
+}
+
+/// Whether privileged steps run, or are skipped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrivilegePolicy {
+    /// Only when they don't need a password, like with passwordless sudo
+    Auto,
+    /// Always, asking for the password when needed
+    #[default]
+    Prompt,
+    /// Never, unless comtrya runs as root already
+    Never,
+}
+
+/// Why privileged steps are skipped, in reports
+pub const PRIVILEGES_NOT_ALLOWED: &str = "needs privileges, which aren't allowed";
+
+static PRIVILEGE: RwLock<Option<Privilege>> = RwLock::new(None);
+static POLICY: RwLock<PrivilegePolicy> = RwLock::new(PrivilegePolicy::Prompt);
+static PASSWORDLESS: OnceLock<bool> = OnceLock::new();
+static DETECTED: OnceLock<Privilege> = OnceLock::new();
+static ELEVATED: OnceLock<bool> = OnceLock::new();
+static ASKPASS: AtomicBool = AtomicBool::new(false);
+static PASSWORD: RwLock<Option<Secret>> = RwLock::new(None);
+static ASKPASS_SCRIPT: OnceLock<PathBuf> = OnceLock::new();
+
+/// The variable the askpass script answers sudo with
+const PASSWORD_VARIABLE: &str = "COMTRYA_SUDO_PASSWORD";
+
+/// Runs privileged commands through this program, instead of the first one installed
+pub fn set_privilege(privilege: Option<Privilege>) {
+    *PRIVILEGE.write().unwrap() = privilege;
+}
+
+/// Decides which privileged steps run, the others are skipped
+pub fn set_privilege_policy(policy: PrivilegePolicy) {
+    *POLICY.write().unwrap() = policy;
+}
+
+/// Whether privileged steps may run, under the policy, or the `allow_sudo` of
+/// their action: `true` runs them like `prompt`, `false` skips them like `never`
+pub fn may_elevate(allow_sudo: Option<bool>) -> bool {
+    let policy = match allow_sudo {
+        Some(true) => PrivilegePolicy::Prompt,
+        Some(false) => PrivilegePolicy::Never,
+        None => *POLICY.read().unwrap(),
+    };
+
+    match policy {
+        PrivilegePolicy::Prompt => true,
+        PrivilegePolicy::Never => is_elevated(),
+        PrivilegePolicy::Auto => *PASSWORDLESS.get_or_init(can_elevate_without_password),
+    }
+}
+
+/// The configured program, or the first of sudo, doas, run0 and pkexec that's
+/// installed. Always UAC on Windows.
+pub(crate) fn privilege() -> Privilege {
+    if let Some(privilege) = *PRIVILEGE.read().unwrap() {
+        return privilege;
+    }
+
+    if cfg!(target_family = "windows") {
+        return Privilege::Uac;
+    }
+
+    *DETECTED.get_or_init(|| {
+        let detected = [
+            Privilege::Sudo,
+            Privilege::Doas,
+            Privilege::Run0,
+            Privilege::Pkexec,
+        ]
+        .into_iter()
+        .find(|privilege| which::which(privilege.command()).is_ok())
+        .unwrap_or(Privilege::Sudo);
+
+        debug!("Running privileged commands with {}", detected.command());
+
+        detected
+    })
+}
+
+/// Has sudo ask for the password through a program rather than the terminal, for
+/// unattended runs: the one in `SUDO_ASKPASS`, or one answering with `password`
+pub fn set_sudo_askpass(askpass: bool, password: Option<Secret>) {
+    ASKPASS.store(askpass, Ordering::Relaxed);
+    *PASSWORD.write().unwrap() = password;
+}
+
+/// Arguments for sudo to ask through askpass, when configured
+pub(crate) fn askpass_arguments(privilege: Privilege) -> Vec<String> {
+    let askpass = ASKPASS.load(Ordering::Relaxed) || PASSWORD.read().unwrap().is_some();
+
+    match (privilege, askpass) {
+        (Privilege::Sudo, true) => vec![String::from("-A")],
+        _ => vec![],
+    }
+}
+
+/// Variables for sudo to answer with the configured password. The password is
+/// only in sudo's environment, which it doesn't pass on to commands.
+pub(crate) fn askpass_environment(privilege: Privilege) -> anyhow::Result<Vec<(String, String)>> {
+    let Some(password) = PASSWORD.read().unwrap().clone() else {
+        return Ok(vec![]);
+    };
+
+    if privilege != Privilege::Sudo {
+        return Ok(vec![]);
+    }
+
+    Ok(vec![
+        (
+            String::from("SUDO_ASKPASS"),
+            askpass_script()?.display().to_string(),
+        ),
+        (String::from(PASSWORD_VARIABLE), password.resolve()?),
+    ])
+}
+
+/// A script printing the password from the environment, kept in our cache
+/// directory rather than a shared one like /tmp
+fn askpass_script() -> anyhow::Result<PathBuf> {
+    if let Some(path) = ASKPASS_SCRIPT.get() {
+        return Ok(path.clone());
+    }
+
+    let dir = dirs_next::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("comtrya");
+    let path = write_askpass_script(&dir)?;
+
+    Ok(ASKPASS_SCRIPT.get_or_init(|| path).clone())
+}
+
+fn write_askpass_script(dir: &Path) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+
+    let path = dir.join("askpass");
+    std::fs::write(
+        &path,
+        format!("#!/bin/sh\nprintf '%s\\n' \"${}\"\n", PASSWORD_VARIABLE),
+    )?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o700))?;
+    }
+
+    Ok(path)
+}
+
+/// Whether we already have full privileges: running as root, or from an
+/// elevated prompt on Windows
+pub(crate) fn is_elevated() -> bool {
+    *ELEVATED.get_or_init(|| {
+        if cfg!(target_family = "windows") {
+            // Only administrators with an elevated token can list sessions
+            return std::process::Command::new("net")
+                .arg("session")
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status()
+                .map(|status| status.success())
+                .unwrap_or(false);
+        }
+
+        whoami::username() == "root"
+    })
+}
+
+/// Whether privileged commands run without asking for a password: when we're
+/// elevated already, or sudo and doas are allowed to with `-n`. run0 and pkexec
+/// always ask through polkit, so they count as asking.
+pub(crate) fn can_elevate_without_password() -> bool {
+    if is_elevated() {
+        return true;
+    }
+
+    match privilege() {
+        Privilege::Sudo | Privilege::Doas => std::process::Command::new(privilege().command())
+            .args(["-n", "true"])
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false),
+        Privilege::Run0 | Privilege::Pkexec | Privilege::Uac => false,
+    }
+}
+
+/// Quotes for a single-quoted PowerShell string
+fn powershell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Quotes an argument for a Windows command line, when it needs to be
+fn windows_quote(argument: &str) -> String {
+    if !argument.is_empty() && !argument.contains([' ', '\t', '"']) {
+        return argument.to_string();
+    }
+
+    format!("\"{}\"", argument.replace('"', "\\\""))
+}
+
+impl Privilege {
+    /// The name used in `Comtrya.yaml`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Privilege::Sudo => "sudo",
+            Privilege::Doas => "doas",
+            Privilege::Run0 => "run0",
+            Privilege::Pkexec => "pkexec",
+            Privilege::Uac => "uac",
+        }
+    }
+
+    pub fn command(&self) -> &'static str {
+        match self {
+            Privilege::Sudo => "sudo",
+            Privilege::Doas => "doas",
+            Privilege::Run0 => "run0",
+            Privilege::Pkexec => "pkexec",
+            Privilege::Uac => "powershell",
+        }
+    }
+
+    /// Arguments to run the command as another user. sudo also sets `HOME` to theirs.
+    pub(crate) fn user_arguments(&self, user: &str) -> Vec<String> {
+        match self {
+            Privilege::Sudo => vec![String::from("-u"), user.to_string(), String::from("-H")],
+            Privilege::Doas => vec![String::from("-u"), user.to_string()],
+            Privilege::Run0 => vec![format!("--user={}", user)],
+            Privilege::Pkexec => vec![String::from("--user"), user.to_string()],
+            // Other users go through runas instead
+            Privilege::Uac => vec![],
+        }
+    }
+
+    /// Arguments to run the command elevated through UAC, which waits for it and
+    /// exits with its code. Its output can't be captured, and its environment
+    /// isn't passed on, so commands are sent to the elevated helper when possible.
+    pub(crate) fn uac_arguments(command: &str, arguments: &[String]) -> Vec<String> {
+        let mut script = format!(
+            "$process = Start-Process -Verb RunAs -Wait -PassThru -WindowStyle Hidden -FilePath {}",
+            powershell_quote(command)
+        );
+
+        if !arguments.is_empty() {
+            let arguments = arguments
+                .iter()
+                .map(|argument| windows_quote(argument))
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            script.push_str(&format!(" -ArgumentList {}", powershell_quote(&arguments)));
+        }
+
+        script.push_str("; exit $process.ExitCode");
+
+        vec![String::from("-NoProfile"), String::from("-Command"), script]
+    }
+
+    /// A command that asks for the password once, so it isn't asked for in the
+    /// middle of a command's output. run0 and pkexec ask through polkit every
+    /// time instead.
+    pub(crate) fn validate_arguments(&self) -> Option<Vec<&'static str>> {
+        match self {
+            Privilege::Sudo => Some(vec!["--validate"]),
+            // With `persist` in doas.conf, later commands don't ask again
+            Privilege::Doas => Some(vec!["true"]),
+            Privilege::Run0 | Privilege::Pkexec | Privilege::Uac => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_can_be_deserialized() {
+        let privilege: Privilege = serde_yml::from_str("run0").unwrap();
+
+        assert_eq!(Privilege::Run0, privilege);
+        assert_eq!("run0", privilege.name());
+        assert_eq!(true, serde_yml::from_str::<Privilege>("su").is_err());
+    }
+
+    #[test]
+    fn it_follows_the_allow_sudo_of_actions() {
+        assert_eq!(true, may_elevate(Some(true)));
+        assert_eq!(is_elevated(), may_elevate(Some(false)));
+    }
+
+    #[test]
+    fn it_runs_as_other_users() {
+        assert_eq!(vec!["-u", "me"], Privilege::Doas.user_arguments("me"));
+        assert_eq!(vec!["--user=me"], Privilege::Run0.user_arguments("me"));
+        assert_eq!(vec!["--user", "me"], Privilege::Pkexec.user_arguments("me"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_answers_sudo_with_the_password() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let output = std::process::Command::new(write_askpass_script(dir.path()).unwrap())
+            .arg("[sudo] password for me: ")
+            .env(PASSWORD_VARIABLE, "hunter2")
+            .output()
+            .unwrap();
+
+        assert_eq!("hunter2\n", String::from_utf8(output.stdout).unwrap());
+    }
+
+    #[test]
+    fn it_elevates_through_uac() {
+        let arguments = Privilege::uac_arguments(
+            "choco",
+            &[String::from("install"), String::from("it's mine")],
+        );
+
+        assert_eq!(
+            "$process = Start-Process -Verb RunAs -Wait -PassThru -WindowStyle Hidden -FilePath 'choco' -ArgumentList 'install \"it''s mine\"'; exit $process.ExitCode",
+            arguments[2]
+        );
+    }
+}
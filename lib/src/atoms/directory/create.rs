@@ -1,7 +1,7 @@
-use crate::atoms::Outcome;
+use crate::atoms::{Outcome, SideEffect};
 
 use super::super::Atom;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub struct Create {
     pub path: PathBuf,
@@ -17,10 +17,48 @@ impl std::fmt::Display for Create {
     }
 }
 
+/// Whether we can create `path`, which needs its closest existing parent to be
+/// writable by us
+#[cfg(unix)]
+pub fn can_create(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let uid = uzers::get_current_uid();
+    if uid == 0 {
+        return true;
+    }
+
+    let Some(metadata) = path
+        .ancestors()
+        .find_map(|ancestor| ancestor.metadata().ok())
+    else {
+        return true;
+    };
+
+    let in_group = metadata.gid() == uzers::get_current_gid()
+        || uzers::group_access_list()
+            .unwrap_or_default()
+            .iter()
+            .any(|group| group.gid() == metadata.gid());
+
+    match (metadata.uid() == uid, in_group) {
+        (true, _) => metadata.mode() & 0o200 != 0,
+        (false, true) => metadata.mode() & 0o020 != 0,
+        (false, false) => metadata.mode() & 0o002 != 0,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn can_create(_path: &Path) -> bool {
+    true
+}
+
 impl Atom for Create {
     fn plan(&self) -> anyhow::Result<Outcome> {
         Ok(Outcome {
-            side_effects: vec![],
+            side_effects: vec![SideEffect::CreateDir {
+                path: self.path.clone(),
+            }],
             should_run: !self.path.exists(),
         })
     }
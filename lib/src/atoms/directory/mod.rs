@@ -1,4 +1,4 @@
 mod create;
 mod remove;
-pub use create::Create;
+pub use create::{can_create, Create};
 pub use remove::Remove;
@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use tracing::error;
 
-use crate::atoms::{Atom, Outcome};
+use crate::atoms::{Atom, Outcome, SideEffect};
 
 pub struct Remove {
     pub target: PathBuf,
@@ -48,7 +48,9 @@ impl Atom for Remove {
         }
 
         Ok(Outcome {
-            side_effects: vec![],
+            side_effects: vec![SideEffect::Remove {
+                path: self.target.clone(),
+            }],
             should_run: self.target.exists(),
         })
     }
@@ -7,6 +7,8 @@ use std::path::PathBuf;
 pub struct Chmod {
     pub path: PathBuf,
     pub mode: u32,
+    /// Runs `chmod` through sudo, for paths we don't own
+    pub privileged: bool,
 }
 
 impl FileAtom for Chmod {
@@ -27,7 +29,10 @@ impl std::fmt::Display for Chmod {
 }
 
 #[cfg(unix)]
-use {std::os::unix::prelude::PermissionsExt, tracing::error};
+use {
+    crate::atoms::command::Exec, crate::atoms::SideEffect, std::os::unix::prelude::PermissionsExt,
+    tracing::error,
+};
 
 #[cfg(unix)]
 impl Atom for Chmod {
@@ -36,7 +41,10 @@ impl Atom for Chmod {
         // another atom is going to provide it.
         if !self.path.exists() {
             return Ok(Outcome {
-                side_effects: vec![],
+                side_effects: vec![SideEffect::Chmod {
+                    path: self.path.clone(),
+                    mode: self.mode,
+                }],
                 should_run: true,
             });
         }
@@ -57,17 +65,28 @@ impl Atom for Chmod {
             }
         };
 
-        // We expect permissions to come through as if the user was using chmod themselves.
-        // This means we support 644/755 decimal syntax. We need to add 0o100000 to support
-        // the part of chmod they don't often type.
+        // We expect permissions to come through as if the user was using chmod themselves,
+        // so only the permission bits are compared, not the type of file.
         Ok(Outcome {
-            side_effects: vec![],
-            should_run: std::fs::Permissions::from_mode(0o100000 + self.mode).mode()
-                != metadata.permissions().mode(),
+            side_effects: vec![SideEffect::Chmod {
+                path: self.path.clone(),
+                mode: self.mode,
+            }],
+            should_run: self.mode != metadata.permissions().mode() & 0o7777,
         })
     }
 
     fn execute(&mut self) -> anyhow::Result<()> {
+        if self.privileged {
+            return Exec {
+                command: String::from("chmod"),
+                arguments: vec![format!("{:o}", self.mode), self.path.display().to_string()],
+                privileged: true,
+                ..Default::default()
+            }
+            .execute();
+        }
+
         std::fs::set_permissions(
             self.path.as_path(),
             std::fs::Permissions::from_mode(self.mode),
@@ -128,6 +147,7 @@ mod tests {
         let file_chmod = Chmod {
             path: temp_dir.path().join("644"),
             mode: 0o644,
+            privileged: false,
         };
 
         assert_eq!(false, file_chmod.plan().unwrap().should_run);
@@ -135,6 +155,7 @@ mod tests {
         let file_chmod = Chmod {
             path: temp_dir.path().join("644"),
             mode: 0o640,
+            privileged: false,
         };
 
         assert_eq!(true, file_chmod.plan().unwrap().should_run);
@@ -170,6 +191,7 @@ mod tests {
         let file_chmod = Chmod {
             path: temp_dir.path().join("644"),
             mode: 0o644,
+            privileged: false,
         };
 
         assert_eq!(false, file_chmod.plan().unwrap().should_run);
@@ -177,6 +199,7 @@ mod tests {
         let mut file_chmod = Chmod {
             path: temp_dir.path().join("644"),
             mode: 0o640,
+            privileged: false,
         };
 
         assert_eq!(true, file_chmod.plan().unwrap().should_run);
@@ -2,15 +2,21 @@ use crate::atoms::Outcome;
 
 use super::super::Atom;
 use super::FileAtom;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[cfg(unix)]
-use tracing::error;
+use {crate::atoms::command::Exec, crate::atoms::SideEffect, anyhow::anyhow, tracing::error};
 
 pub struct Chown {
     pub path: PathBuf,
-    pub owner: String,
-    pub group: String,
+    /// Left as is when not set
+    pub owner: Option<String>,
+    /// Left as is when not set
+    pub group: Option<String>,
+    /// Runs `chown` through sudo, see `needs_privilege`
+    pub privileged: bool,
+    /// Also changes everything within a directory
+    pub recursive: bool,
 }
 
 impl FileAtom for Chown {
@@ -19,18 +25,91 @@ impl FileAtom for Chown {
     }
 }
 
+impl Chown {
+    /// The path, and what's in it when recursive, but not what links within point to
+    fn paths(&self) -> Vec<PathBuf> {
+        if !self.recursive {
+            return vec![self.path.clone()];
+        }
+
+        walkdir::WalkDir::new(&self.path)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.depth() == 0 || !entry.path_is_symlink())
+            .map(|entry| entry.into_path())
+            .collect()
+    }
+
+    /// `owner:group` as `chown` takes it
+    fn spec(&self) -> String {
+        match (self.owner.as_deref(), self.group.as_deref()) {
+            (Some(owner), Some(group)) => format!("{owner}:{group}"),
+            (Some(owner), None) => owner.to_string(),
+            (None, Some(group)) => format!(":{group}"),
+            (None, None) => String::new(),
+        }
+    }
+}
+
 impl std::fmt::Display for Chown {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "The owner and group on {} need to be set to {}:{}",
+            "The owner and group on {} need to be set to {}",
             self.path.display(),
-            self.owner,
-            self.group,
+            self.spec(),
         )
     }
 }
 
+/// Only root can give files away, while anyone can give their own files to a
+/// group they're in
+#[cfg(unix)]
+pub fn needs_privilege(owner: Option<&str>, group: Option<&str>) -> bool {
+    if uzers::get_current_uid() == 0 {
+        return false;
+    }
+
+    let foreign_owner = owner.is_some_and(|owner| {
+        uzers::get_user_by_name(owner).map(|user| user.uid()) != Some(uzers::get_current_uid())
+    });
+
+    let foreign_group = group.is_some_and(|group| {
+        let Some(group) = uzers::get_group_by_name(group) else {
+            return true;
+        };
+
+        group.gid() != uzers::get_current_gid()
+            && !uzers::group_access_list()
+                .unwrap_or_default()
+                .iter()
+                .any(|member| member.gid() == group.gid())
+    });
+
+    foreign_owner || foreign_group
+}
+
+#[cfg(not(unix))]
+pub fn needs_privilege(_owner: Option<&str>, _group: Option<&str>) -> bool {
+    false
+}
+
+/// Whether `path` exists and belongs to another user, so only root can change it
+#[cfg(unix)]
+pub fn owned_by_others(path: &Path) -> bool {
+    let uid = uzers::get_current_uid();
+
+    uid != 0
+        && std::fs::metadata(path)
+            .map(|metadata| metadata.uid() != uid)
+            .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+pub fn owned_by_others(_path: &Path) -> bool {
+    false
+}
+
 #[cfg(unix)]
 use std::os::unix::prelude::MetadataExt;
 
@@ -41,37 +120,41 @@ impl Atom for Chown {
         // another atom is going to provide it.
         if !self.path.exists() {
             return Ok(Outcome {
-                side_effects: vec![],
+                side_effects: vec![SideEffect::Chown {
+                    path: self.path.clone(),
+                    owner: self.owner.clone(),
+                    group: self.group.clone(),
+                }],
                 should_run: true,
             });
         }
 
-        let metadata = match std::fs::metadata(&self.path) {
-            Ok(m) => m,
-            Err(err) => {
-                error!(
-                    "Couldn't get metadata for {}, rejecting atom: {}",
-                    &self.path.display(),
-                    err.to_string()
-                );
+        let mut metadata = vec![];
+        for path in self.paths() {
+            match std::fs::metadata(&path) {
+                Ok(m) => metadata.push(m),
+                Err(err) => {
+                    error!(
+                        "Couldn't get metadata for {}, rejecting atom: {}",
+                        &path.display(),
+                        err.to_string()
+                    );
 
-                return Ok(Outcome {
-                    side_effects: vec![],
-                    should_run: false,
-                });
+                    return Ok(Outcome {
+                        side_effects: vec![],
+                        should_run: false,
+                    });
+                }
             }
-        };
+        }
 
-        if let (Some(current_owner), Some(current_group)) = (
-            uzers::get_user_by_uid(metadata.uid()),
-            uzers::get_group_by_gid(metadata.gid()),
-        ) {
-            let requested_owner = match uzers::get_user_by_name(self.owner.as_str()) {
+        if let Some(owner) = self.owner.as_deref() {
+            let requested_owner = match uzers::get_user_by_name(owner) {
                 Some(owner) => owner,
                 None => {
                     error!(
                         "Skipping chown as requested owner, {}, does not exist",
-                        self.owner,
+                        owner,
                     );
                     return Ok(Outcome {
                         side_effects: vec![],
@@ -80,12 +163,25 @@ impl Atom for Chown {
                 }
             };
 
-            let requested_group = match uzers::get_group_by_name(self.group.as_str()) {
+            if metadata.iter().any(|m| m.uid() != requested_owner.uid()) {
+                return Ok(Outcome {
+                    side_effects: vec![SideEffect::Chown {
+                        path: self.path.clone(),
+                        owner: self.owner.clone(),
+                        group: self.group.clone(),
+                    }],
+                    should_run: true,
+                });
+            }
+        }
+
+        if let Some(group) = self.group.as_deref() {
+            let requested_group = match uzers::get_group_by_name(group) {
                 Some(group) => group,
                 None => {
                     error!(
                         "Skipping chown as requested group, {}, does not exist",
-                        self.group,
+                        group,
                     );
 
                     return Ok(Outcome {
@@ -95,16 +191,13 @@ impl Atom for Chown {
                 }
             };
 
-            if current_owner.uid() != requested_owner.uid() {
-                return Ok(Outcome {
-                    side_effects: vec![],
-                    should_run: true,
-                });
-            }
-
-            if current_group.gid() != requested_group.gid() {
+            if metadata.iter().any(|m| m.gid() != requested_group.gid()) {
                 return Ok(Outcome {
-                    side_effects: vec![],
+                    side_effects: vec![SideEffect::Chown {
+                        path: self.path.clone(),
+                        owner: self.owner.clone(),
+                        group: self.group.clone(),
+                    }],
                     should_run: true,
                 });
             }
@@ -117,6 +210,43 @@ impl Atom for Chown {
     }
 
     fn execute(&mut self) -> anyhow::Result<()> {
+        if self.privileged {
+            let mut arguments = vec![self.spec(), self.path.display().to_string()];
+            if self.recursive {
+                arguments.insert(0, String::from("-R"));
+            }
+
+            return Exec {
+                command: String::from("chown"),
+                arguments,
+                privileged: true,
+                ..Default::default()
+            }
+            .execute();
+        }
+
+        let uid = match self.owner.as_deref() {
+            Some(owner) => Some(
+                uzers::get_user_by_name(owner)
+                    .ok_or_else(|| anyhow!("User {} does not exist", owner))?
+                    .uid(),
+            ),
+            None => None,
+        };
+
+        let gid = match self.group.as_deref() {
+            Some(group) => Some(
+                uzers::get_group_by_name(group)
+                    .ok_or_else(|| anyhow!("Group {} does not exist", group))?
+                    .gid(),
+            ),
+            None => None,
+        };
+
+        for path in self.paths() {
+            std::os::unix::fs::chown(path, uid, gid)?;
+        }
+
         Ok(())
     }
 }
@@ -167,34 +297,57 @@ mod tests {
 
         let file_chown = Chown {
             path: temp_file.path().to_path_buf(),
-            owner: user.clone(),
-            group: group.clone(),
+            owner: Some(user.clone()),
+            group: Some(group.clone()),
+            privileged: false,
+            recursive: false,
         };
 
         assert_eq!(false, file_chown.plan().unwrap().should_run);
 
         let file_chown = Chown {
             path: temp_file.path().to_path_buf(),
-            owner: user,
-            group: String::from("daemon"),
+            owner: Some(user),
+            group: Some(String::from("daemon")),
+            privileged: false,
+            recursive: false,
         };
 
         assert_eq!(true, file_chown.plan().unwrap().should_run);
 
         let file_chown = Chown {
             path: temp_file.path().to_path_buf(),
-            owner: String::from("root"),
-            group,
+            owner: Some(String::from("root")),
+            group: Some(group),
+            privileged: false,
+            recursive: false,
         };
 
         assert_eq!(true, file_chown.plan().unwrap().should_run);
 
         let file_chown = Chown {
             path: temp_file.path().to_path_buf(),
-            owner: String::from("root"),
-            group: String::from("daemon"),
+            owner: Some(String::from("root")),
+            group: Some(String::from("daemon")),
+            privileged: false,
+            recursive: false,
         };
 
         assert_eq!(true, file_chown.plan().unwrap().should_run);
     }
+
+    #[test]
+    fn it_only_elevates_for_others() {
+        let user = uzers::get_current_username()
+            .unwrap_or_else(|| std::ffi::OsString::from("root"))
+            .into_string()
+            .unwrap();
+
+        assert_eq!(false, needs_privilege(None, None));
+        assert_eq!(false, needs_privilege(Some(&user), None));
+        assert_eq!(
+            uzers::get_current_uid() != 0,
+            needs_privilege(Some("comtrya-nobody"), None)
+        );
+    }
 }
@@ -1,8 +1,10 @@
-use crate::atoms::Outcome;
+use crate::atoms::{Outcome, SideEffect};
+use crate::rollback::{snapshot, Undo};
 
 use super::super::Atom;
-use super::FileAtom;
-use std::path::PathBuf;
+use super::{has_contents, write_atomic, FileAtom};
+use crate::utilities::diff::unified_diff;
+use std::path::{Path, PathBuf};
 use tracing::error;
 
 pub struct SetContents {
@@ -32,17 +34,20 @@ impl Atom for SetContents {
         // another atom is going to provide it.
         if !self.path.exists() {
             return Ok(Outcome {
-                side_effects: vec![],
+                side_effects: vec![SideEffect::Write {
+                    path: self.path.clone(),
+                }],
                 should_run: true,
             });
         }
 
-        let contents = match std::fs::read(&self.path) {
-            Ok(contents) => contents,
+        let unchanged = match has_contents(&self.path, &self.contents) {
+            Ok(unchanged) => unchanged,
             Err(error) => {
                 error!(
                     "Failed to read contents of {} for diff because {:?}. Skipping",
-                    error, self.path
+                    self.path.display(),
+                    error
                 );
 
                 return Ok(Outcome {
@@ -53,16 +58,42 @@ impl Atom for SetContents {
         };
 
         Ok(Outcome {
-            side_effects: vec![],
-            should_run: !contents.eq(&self.contents),
+            side_effects: vec![SideEffect::Write {
+                path: self.path.clone(),
+            }],
+            should_run: !unchanged,
         })
     }
 
     fn execute(&mut self) -> anyhow::Result<()> {
-        std::fs::write(&self.path, &self.contents)?;
+        write_atomic(&self.path, &self.contents)?;
 
         Ok(())
     }
+
+    fn prepare_undo(&self, backup_dir: &Path) -> anyhow::Result<Option<Undo>> {
+        Ok(Some(snapshot(&self.path, backup_dir)?))
+    }
+
+    fn managed_files(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+
+    fn diff(&self) -> Option<String> {
+        let current = std::fs::read(&self.path).unwrap_or_default();
+
+        match (
+            std::str::from_utf8(&current),
+            std::str::from_utf8(&self.contents),
+        ) {
+            (Ok(current), Ok(contents)) => Some(unified_diff(
+                &self.path.display().to_string(),
+                current,
+                contents,
+            )),
+            _ => Some(format!("Binary file {} differs\n", self.path.display())),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -96,4 +127,36 @@ mod tests {
         assert_eq!(true, file_contents.execute().is_ok());
         assert_eq!(false, file_contents.plan().unwrap().should_run);
     }
+
+    #[test]
+    fn it_compares_contents_of_the_same_size() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "abc").unwrap();
+
+        assert_eq!(true, has_contents(file.path(), b"abc").unwrap());
+        assert_eq!(false, has_contents(file.path(), b"abd").unwrap());
+        assert_eq!(false, has_contents(file.path(), b"abcd").unwrap());
+    }
+
+    #[test]
+    fn it_can_diff() {
+        let file = match tempfile::NamedTempFile::new() {
+            std::result::Result::Ok(file) => file,
+            std::result::Result::Err(_) => {
+                assert_eq!(false, true);
+                return;
+            }
+        };
+
+        std::fs::write(file.path(), "token = abc\n").unwrap();
+
+        let file_contents = SetContents {
+            path: file.path().to_path_buf(),
+            contents: String::from("token = xyz\nname = comtrya\n").into_bytes(),
+        };
+
+        let diff = file_contents.diff().unwrap();
+        assert_eq!(true, diff.contains("+name = comtrya"));
+        assert_eq!(false, diff.contains("xyz"));
+    }
 }
@@ -1,9 +1,10 @@
-use crate::atoms::Outcome;
+use crate::atoms::{Outcome, SideEffect};
+use crate::rollback::{snapshot, Undo};
 
 use super::super::Atom;
-use super::FileAtom;
+use super::{copy_atomic, FileAtom};
 use file_diff::diff;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tracing::error;
 
 pub struct Copy {
@@ -30,6 +31,15 @@ impl std::fmt::Display for Copy {
 
 impl Atom for Copy {
     fn plan(&self) -> anyhow::Result<Outcome> {
+        if !self.to.exists() {
+            return Ok(Outcome {
+                side_effects: vec![SideEffect::Write {
+                    path: self.to.clone(),
+                }],
+                should_run: true,
+            });
+        }
+
         if !self.to.is_file() {
             error!("Cannot plan: target isn't a file: {}", self.to.display());
 
@@ -39,20 +49,30 @@ impl Atom for Copy {
             });
         }
 
-        return Ok(Outcome {
-            side_effects: vec![],
+        Ok(Outcome {
+            side_effects: vec![SideEffect::Write {
+                path: self.to.clone(),
+            }],
             should_run: !diff(
                 &self.from.display().to_string(),
                 &self.to.display().to_string(),
             ),
-        });
+        })
     }
 
     fn execute(&mut self) -> anyhow::Result<()> {
-        std::fs::copy(&self.from, &self.to)?;
+        copy_atomic(&self.from, &self.to)?;
 
         Ok(())
     }
+
+    fn prepare_undo(&self, backup_dir: &Path) -> anyhow::Result<Option<Undo>> {
+        Ok(Some(snapshot(&self.to, backup_dir)?))
+    }
+
+    fn managed_files(&self) -> Vec<PathBuf> {
+        vec![self.to.clone()]
+    }
 }
 
 #[cfg(test)]
@@ -140,6 +160,13 @@ mod tests {
         assert_eq!(true, file_copy.plan().unwrap().should_run);
         assert_eq!(true, file_copy.execute().is_ok());
         assert_eq!(false, file_copy.plan().unwrap().should_run);
+
+        // Into a file that doesn't exist yet
+        let dir = tempfile::tempdir().unwrap();
+        file_copy.to = dir.path().join("new");
+        assert_eq!(true, file_copy.plan().unwrap().should_run);
+        assert_eq!(true, file_copy.execute().is_ok());
+        assert_eq!(false, file_copy.plan().unwrap().should_run);
     }
 
     #[test]
@@ -1,8 +1,9 @@
-use crate::atoms::Outcome;
+use crate::atoms::{Outcome, SideEffect};
+use crate::rollback::{snapshot, Undo};
 
 use super::super::Atom;
 use super::FileAtom;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub struct Create {
     pub path: PathBuf,
@@ -23,7 +24,9 @@ impl std::fmt::Display for Create {
 impl Atom for Create {
     fn plan(&self) -> anyhow::Result<Outcome> {
         Ok(Outcome {
-            side_effects: vec![],
+            side_effects: vec![SideEffect::Write {
+                path: self.path.clone(),
+            }],
             should_run: !self.path.exists(),
         })
     }
@@ -33,6 +36,10 @@ impl Atom for Create {
 
         Ok(())
     }
+
+    fn prepare_undo(&self, backup_dir: &Path) -> anyhow::Result<Option<Undo>> {
+        Ok(Some(snapshot(&self.path, backup_dir)?))
+    }
 }
 
 #[cfg(test)]
@@ -1,11 +1,12 @@
-use crate::atoms::Outcome;
+use crate::atoms::{Outcome, SideEffect};
+use crate::rollback::{snapshot, Undo};
 
 use super::super::Atom;
-use super::FileAtom;
+use super::{has_contents, write_atomic, FileAtom};
 use age::armor::ArmoredReader;
 use age::secrecy::Secret;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tracing::error;
 
 pub struct Decrypt {
@@ -36,16 +37,21 @@ impl Atom for Decrypt {
         // another atom is going to provide it.
         if !self.path.exists() {
             return Ok(Outcome {
-                side_effects: vec![],
+                side_effects: vec![SideEffect::Write {
+                    path: self.path.clone(),
+                }],
                 should_run: true,
             });
         }
 
-        // Decrypting file with provided passphrase makes plan work
+        // Decrypting file with provided passphrase makes plan work, and only
+        // writes the file when its decrypted contents changed
         match decrypt(&self.passphrase, &self.encrypted_content) {
-            Ok(_) => Ok(Outcome {
-                side_effects: vec![],
-                should_run: true,
+            Ok(decrypted) => Ok(Outcome {
+                side_effects: vec![SideEffect::Write {
+                    path: self.path.clone(),
+                }],
+                should_run: !has_contents(&self.path, &decrypted).unwrap_or(false),
             }),
             Err(err) => {
                 error!(
@@ -65,10 +71,26 @@ impl Atom for Decrypt {
     fn execute(&mut self) -> anyhow::Result<()> {
         let decrypted_content = decrypt(&self.passphrase, &self.encrypted_content)?;
 
-        std::fs::write(&self.path, decrypted_content)?;
+        write_atomic(&self.path, &decrypted_content)?;
 
         Ok(())
     }
+
+    fn prepare_undo(&self, backup_dir: &Path) -> anyhow::Result<Option<Undo>> {
+        Ok(Some(snapshot(&self.path, backup_dir)?))
+    }
+
+    fn managed_files(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+
+    fn diff(&self) -> Option<String> {
+        // Never show decrypted secrets
+        Some(format!(
+            "Encrypted contents of {} would be updated (redacted)\n",
+            self.path.display()
+        ))
+    }
 }
 
 fn decrypt(passphrase: &str, encrypted_content: &[u8]) -> anyhow::Result<Vec<u8>> {
@@ -149,6 +171,9 @@ mod tests {
         assert_eq!(true, decrypt.plan().unwrap().should_run);
         assert_eq!(true, decrypt.execute().is_ok());
 
+        // Once decrypted, there's nothing left to do
+        assert_eq!(false, decrypt.plan().unwrap().should_run);
+
         Ok(())
     }
 
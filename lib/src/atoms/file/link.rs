@@ -1,13 +1,22 @@
-use crate::atoms::Outcome;
+use crate::atoms::{Outcome, SideEffect};
 
 use super::super::Atom;
-use super::FileAtom;
-use std::path::PathBuf;
-use tracing::{error, warn};
+use super::{copy_atomic, FileAtom};
+use std::path::{Component, Path, PathBuf};
+use tracing::{error, info, warn};
 
+#[derive(Default)]
 pub struct Link {
     pub source: PathBuf,
     pub target: PathBuf,
+    /// Points to the source relative to the target's directory
+    pub relative: bool,
+    /// A hard link rather than a symlink, for files on the same filesystem
+    pub hard: bool,
+    /// Replaces a file or directory at the target, which is moved aside first
+    pub force: bool,
+    /// Moves a file at the target over the source, so it's kept and then linked
+    pub adopt: bool,
 }
 
 impl FileAtom for Link {
@@ -27,6 +36,75 @@ impl std::fmt::Display for Link {
     }
 }
 
+impl Link {
+    /// What the symlink should point to
+    fn link_path(&self) -> PathBuf {
+        let source = if cfg!(target_os = "windows") {
+            const PREFIX: &str = r"\\?\";
+            PathBuf::from(&self.source.display().to_string().replace(PREFIX, ""))
+        } else {
+            self.source.to_owned()
+        };
+
+        match (self.relative, self.target.parent()) {
+            (true, Some(parent)) => relative_path(&source, parent),
+            _ => source,
+        }
+    }
+
+    /// Where a file that's in the way is moved to, `name.bak` or the first free `name.bak.N`
+    fn backup_path(&self) -> PathBuf {
+        let mut name = self.target.file_name().unwrap_or_default().to_os_string();
+        name.push(".bak");
+
+        let mut backup = self.target.with_file_name(&name);
+        let mut n = 1;
+        while backup.symlink_metadata().is_ok() {
+            backup = self
+                .target
+                .with_file_name(format!("{}.{}", name.to_string_lossy(), n));
+            n += 1;
+        }
+
+        backup
+    }
+
+    /// Whether the target is already a hard link to the source
+    #[cfg(unix)]
+    fn is_hard_linked(&self) -> bool {
+        use std::os::unix::fs::MetadataExt;
+
+        match (self.source.metadata(), self.target.symlink_metadata()) {
+            (Ok(source), Ok(target)) => {
+                source.dev() == target.dev() && source.ino() == target.ino()
+            }
+            _ => false,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn is_hard_linked(&self) -> bool {
+        false
+    }
+}
+
+/// `path` as seen from `base`, both absolute, like `../dotfiles/vimrc`
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path: Vec<_> = path.components().collect();
+    let base: Vec<_> = base.components().collect();
+    let common = path
+        .iter()
+        .zip(base.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    base[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .chain(path[common..].iter().copied())
+        .collect()
+}
+
 impl Atom for Link {
     fn plan(&self) -> anyhow::Result<Outcome> {
         // First, ensure source exists and can be linked to
@@ -42,62 +120,161 @@ impl Atom for Link {
             });
         }
 
-        // Target file doesn't exist, we can run safely
-        if !self.target.exists() {
+        // Target file doesn't exist, not even as a broken link, we can run safely
+        let Ok(metadata) = self.target.symlink_metadata() else {
             return Ok(Outcome {
-                side_effects: vec![],
+                side_effects: vec![SideEffect::Link {
+                    path: self.target.clone(),
+                    target: self.link_path(),
+                }],
                 should_run: true,
             });
+        };
+
+        if self.hard && self.is_hard_linked() {
+            return Ok(Outcome {
+                side_effects: vec![],
+                should_run: false,
+            });
         }
+
         // Target file exists, lets check if it's a symlink which can be safely updated
         // or return a false and emit some logging that we can't create the link
         // without purging a file
-        let link = match std::fs::read_link(&self.target) {
-            Ok(link) => link,
-            Err(err) => {
+        if !metadata.file_type().is_symlink() {
+            if self.adopt {
+                return Ok(Outcome {
+                    side_effects: vec![SideEffect::Link {
+                        path: self.target.clone(),
+                        target: self.link_path(),
+                    }],
+                    should_run: true,
+                });
+            }
+
+            if !self.force {
                 warn!(
-                    "Cannot plan: target already exists and isn't a link: {}",
+                    "Cannot plan: target already exists and isn't a link, use force to replace it: {}",
                     self.target.display()
                 );
-                error!("Cannot plan: {}", err);
-
-                return Ok(Outcome {
-                    side_effects: vec![],
-                    should_run: false,
-                });
             }
-        };
 
-        let source = if cfg!(target_os = "windows") {
-            const PREFIX: &str = r"\\?\";
-            PathBuf::from(&self.source.display().to_string().replace(PREFIX, ""))
-        } else {
-            self.source.to_owned()
-        };
+            return Ok(Outcome {
+                side_effects: vec![SideEffect::Link {
+                    path: self.target.clone(),
+                    target: self.link_path(),
+                }],
+                should_run: self.force,
+            });
+        }
 
         // If this file doesn't link to what we expect, lets make it so
+        let link = std::fs::read_link(&self.target)?;
+
         Ok(Outcome {
-            side_effects: vec![],
-            should_run: !link.eq(&source),
+            side_effects: vec![SideEffect::Link {
+                path: self.target.clone(),
+                target: self.link_path(),
+            }],
+            should_run: self.hard || !link.eq(&self.link_path()),
         })
     }
 
-    #[cfg(unix)]
     fn execute(&mut self) -> anyhow::Result<()> {
-        std::os::unix::fs::symlink(&self.source, &self.target)?;
+        if let Ok(metadata) = self.target.symlink_metadata() {
+            if metadata.file_type().is_symlink() {
+                // Directory links on Windows are removed like directories
+                if std::fs::remove_file(&self.target).is_err() {
+                    std::fs::remove_dir(&self.target)?;
+                }
+            } else if self.adopt {
+                info!(
+                    "Adopting {} into {}",
+                    self.target.display(),
+                    self.source.display()
+                );
+                if std::fs::rename(&self.target, &self.source).is_err() {
+                    // Across filesystems, it's copied instead
+                    copy_atomic(&self.target, &self.source)?;
+                    std::fs::remove_file(&self.target)?;
+                }
+            } else if self.force {
+                let backup = self.backup_path();
+                info!(
+                    "Moving {} to {} to link it",
+                    self.target.display(),
+                    backup.display()
+                );
+                std::fs::rename(&self.target, backup)?;
+            }
+        }
+
+        if self.hard {
+            std::fs::hard_link(&self.source, &self.target)?;
+            return Ok(());
+        }
+
+        self.symlink()
+    }
+
+    fn diff(&self) -> Option<String> {
+        let current = match std::fs::read_link(&self.target) {
+            Ok(link) => link.display().to_string(),
+            Err(_) => String::from("(none)"),
+        };
+
+        Some(format!(
+            "--- {target} -> {current}\n+++ {target} -> {source}\n",
+            target = self.target.display(),
+            source = self.link_path().display(),
+        ))
+    }
+}
+
+impl Link {
+    #[cfg(unix)]
+    fn symlink(&self) -> anyhow::Result<()> {
+        std::os::unix::fs::symlink(self.link_path(), &self.target)?;
 
         Ok(())
     }
 
     #[cfg(windows)]
-    fn execute(&mut self) -> anyhow::Result<()> {
-        if self.target.is_dir() {
-            std::os::windows::fs::symlink_dir(&self.source, &self.target)?;
-        } else {
-            std::os::windows::fs::symlink_file(&self.source, &self.target)?;
+    fn symlink(&self) -> anyhow::Result<()> {
+        // Without Developer Mode or admin rights symlinks aren't allowed,
+        // but directories can still be linked with a junction
+        const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+
+        if !self.source.is_dir() {
+            std::os::windows::fs::symlink_file(self.link_path(), &self.target)?;
+            return Ok(());
         }
 
-        Ok(())
+        match std::os::windows::fs::symlink_dir(self.link_path(), &self.target) {
+            Err(err) if err.raw_os_error() == Some(ERROR_PRIVILEGE_NOT_HELD) => {
+                warn!(
+                    "Not allowed to create symlinks, linking {} with a junction",
+                    self.target.display()
+                );
+
+                // Junctions only take absolute paths
+                let output = std::process::Command::new("cmd")
+                    .args(["/C", "mklink", "/J"])
+                    .arg(&self.target)
+                    .arg(&self.source)
+                    .output()?;
+
+                if !output.status.success() {
+                    return Err(anyhow::anyhow!(
+                        "mklink /J failed: {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+
+                Ok(())
+            }
+            result => Ok(result?),
+        }
     }
 }
 
@@ -127,9 +304,81 @@ mod tests {
         let mut atom = Link {
             target: from_dir.path().join("symlink"),
             source: to_file.path().to_path_buf(),
+            ..Default::default()
         };
         assert_eq!(true, atom.plan().unwrap().should_run);
         assert_eq!(true, atom.execute().is_ok());
         assert_eq!(false, atom.plan().unwrap().should_run);
     }
+
+    #[test]
+    fn it_computes_relative_paths() {
+        assert_eq!(
+            PathBuf::from("../dotfiles/files/vimrc"),
+            relative_path(
+                Path::new("/home/me/dotfiles/files/vimrc"),
+                Path::new("/home/me/.config")
+            )
+        );
+        assert_eq!(
+            PathBuf::from("vimrc"),
+            relative_path(Path::new("/home/me/vimrc"), Path::new("/home/me"))
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_can_link_relatively_and_replace_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("files")).unwrap();
+        std::fs::write(dir.path().join("files/vimrc"), "set nocompatible").unwrap();
+        std::fs::write(dir.path().join(".vimrc"), "mine").unwrap();
+
+        let mut atom = Link {
+            source: dir.path().join("files/vimrc"),
+            target: dir.path().join(".vimrc"),
+            relative: true,
+            ..Default::default()
+        };
+        assert_eq!(false, atom.plan().unwrap().should_run);
+
+        atom.force = true;
+        assert_eq!(true, atom.plan().unwrap().should_run);
+        atom.execute().unwrap();
+
+        assert_eq!(false, atom.plan().unwrap().should_run);
+        assert_eq!(
+            PathBuf::from("files/vimrc"),
+            std::fs::read_link(dir.path().join(".vimrc")).unwrap()
+        );
+        assert_eq!(
+            "mine",
+            std::fs::read_to_string(dir.path().join(".vimrc.bak")).unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_can_hard_link() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("source"), "contents").unwrap();
+
+        let mut atom = Link {
+            source: dir.path().join("source"),
+            target: dir.path().join("target"),
+            hard: true,
+            ..Default::default()
+        };
+        assert_eq!(true, atom.plan().unwrap().should_run);
+        atom.execute().unwrap();
+        assert_eq!(false, atom.plan().unwrap().should_run);
+        assert_eq!(
+            false,
+            dir.path()
+                .join("target")
+                .symlink_metadata()
+                .unwrap()
+                .is_symlink()
+        );
+    }
 }
@@ -10,7 +10,7 @@ mod unarchive;
 
 use super::Atom;
 pub use chmod::Chmod;
-pub use chown::Chown;
+pub use chown::{needs_privilege, owned_by_others, Chown};
 pub use contents::SetContents;
 pub use copy::Copy;
 pub use create::Create;
@@ -23,3 +23,125 @@ pub trait FileAtom: Atom {
     // Don't think this is needed? Validate soon
     fn get_path(&self) -> &std::path::PathBuf;
 }
+
+/// Whether the file at `path` holds exactly `contents`, by size and then SHA-256,
+/// so files that differ in size aren't read at all
+pub(crate) fn has_contents(path: &std::path::Path, contents: &[u8]) -> anyhow::Result<bool> {
+    if std::fs::metadata(path)?.len() != contents.len() as u64 {
+        return Ok(false);
+    }
+
+    Ok(sha256::try_digest(path)? == sha256::digest(contents))
+}
+
+/// Writes `contents` to a `.part` file next to `path` and renames it into place,
+/// so an interrupted run leaves either the old file or the new one, never half of it.
+/// An existing file keeps its permissions.
+pub(crate) fn write_atomic(path: &std::path::Path, contents: &[u8]) -> anyhow::Result<()> {
+    replace_atomic(path, None, |partial| {
+        let mut file = std::fs::File::create(partial)?;
+        std::io::Write::write_all(&mut file, contents)?;
+        file.sync_all()
+    })
+}
+
+/// Copies `from` over `to` the way [`write_atomic`] writes, with the permissions of `from`
+pub(crate) fn copy_atomic(from: &std::path::Path, to: &std::path::Path) -> anyhow::Result<()> {
+    replace_atomic(
+        to,
+        Some(std::fs::metadata(from)?.permissions()),
+        |partial| {
+            std::fs::copy(from, partial)?;
+            std::fs::File::open(partial)?.sync_all()
+        },
+    )
+}
+
+fn replace_atomic(
+    path: &std::path::Path,
+    permissions: Option<std::fs::Permissions>,
+    write: impl FnOnce(&std::path::Path) -> std::io::Result<()>,
+) -> anyhow::Result<()> {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".part");
+    let partial = path.with_file_name(file_name);
+
+    let permissions = permissions.or_else(|| {
+        std::fs::metadata(path)
+            .ok()
+            .map(|metadata| metadata.permissions())
+    });
+
+    let result = write(&partial)
+        .and_then(|()| match permissions {
+            Some(permissions) => std::fs::set_permissions(&partial, permissions),
+            None => Ok(()),
+        })
+        .and_then(|()| std::fs::rename(&partial, path));
+
+    if let Err(err) = result {
+        let _ = std::fs::remove_file(&partial);
+        return Err(anyhow::anyhow!(
+            "Failed to write {}: {}",
+            path.display(),
+            err
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_writes_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".zshrc");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_keeps_permissions_when_replacing() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+
+        std::fs::write(&to, "old").unwrap();
+        std::fs::set_permissions(&to, std::fs::Permissions::from_mode(0o600)).unwrap();
+        write_atomic(&to, b"new").unwrap();
+        assert_eq!(
+            std::fs::metadata(&to).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        std::fs::write(&from, "binary").unwrap();
+        std::fs::set_permissions(&from, std::fs::Permissions::from_mode(0o755)).unwrap();
+        copy_atomic(&from, &to).unwrap();
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "binary");
+        assert_eq!(
+            std::fs::metadata(&to).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+    }
+
+    #[test]
+    fn it_leaves_no_partial_file_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        let to = dir.path().join("to");
+
+        assert!(copy_atomic(&missing, &to).is_err());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
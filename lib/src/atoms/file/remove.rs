@@ -1,8 +1,9 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use tracing::error;
 
-use crate::atoms::{Atom, Outcome};
+use crate::atoms::{Atom, Outcome, SideEffect};
+use crate::rollback::{snapshot, Undo};
 
 use super::FileAtom;
 
@@ -66,7 +67,9 @@ impl Atom for Remove {
         };
 
         Ok(Outcome {
-            side_effects: vec![],
+            side_effects: vec![SideEffect::Remove {
+                path: self.target.clone(),
+            }],
             should_run: true,
         })
     }
@@ -75,6 +78,10 @@ impl Atom for Remove {
         std::fs::remove_file(&self.target)?;
         Ok(())
     }
+
+    fn prepare_undo(&self, backup_dir: &Path) -> anyhow::Result<Option<Undo>> {
+        Ok(Some(snapshot(&self.target, backup_dir)?))
+    }
 }
 
 #[cfg(test)]
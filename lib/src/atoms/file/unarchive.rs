@@ -3,7 +3,7 @@ use std::{fs::File, path::PathBuf};
 use flate2::read::GzDecoder;
 use tar::Archive;
 
-use crate::atoms::{Atom, Outcome};
+use crate::atoms::{Atom, Outcome, SideEffect};
 
 use super::FileAtom;
 
@@ -25,7 +25,9 @@ impl Atom for Unarchive {
         if self.dest.exists() {
             if self.force {
                 return Ok(Outcome {
-                    side_effects: vec![],
+                    side_effects: vec![SideEffect::Write {
+                        path: self.dest.clone(),
+                    }],
                     should_run: self.origin.exists(),
                 });
             }
@@ -36,7 +38,9 @@ impl Atom for Unarchive {
         }
 
         Ok(Outcome {
-            side_effects: vec![],
+            side_effects: vec![SideEffect::Write {
+                path: self.dest.clone(),
+            }],
             should_run: self.origin.exists(),
         })
     }
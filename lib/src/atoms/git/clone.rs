@@ -0,0 +1,204 @@
+use super::{fetch_commit, git, is_commit, resolve, set_sparse_checkout, update_submodules};
+use crate::atoms::{Atom, Outcome, SideEffect};
+use std::path::PathBuf;
+use tracing::info;
+
+/// Clones a repository into `directory`, unless it's already a clone
+pub struct Clone {
+    pub repository: String,
+    pub directory: PathBuf,
+    /// A branch, tag or commit to check out, instead of the default branch
+    pub reference: Option<String>,
+    /// Only fetches this many commits of history
+    pub depth: Option<u32>,
+    pub submodules: bool,
+    /// Only checks out these directories, and doesn't download other files
+    pub sparse: Vec<String>,
+}
+
+impl std::fmt::Display for Clone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The repository {} needs to be cloned to {}",
+            self.repository,
+            self.directory.display()
+        )
+    }
+}
+
+impl Atom for Clone {
+    fn plan(&self) -> anyhow::Result<Outcome> {
+        Ok(Outcome {
+            side_effects: vec![SideEffect::Command {
+                command: format!("git clone {} {}", self.repository, self.directory.display()),
+            }],
+            should_run: !self.directory.join(".git").exists(),
+        })
+    }
+
+    fn execute(&mut self) -> anyhow::Result<()> {
+        let parent = match self.directory.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => std::env::current_dir()?,
+        };
+        std::fs::create_dir_all(&parent)?;
+
+        info!(
+            "Cloning {} to {}",
+            self.repository,
+            self.directory.display()
+        );
+
+        let depth = self.depth.map(|depth| depth.to_string());
+        let directory = self.directory.display().to_string();
+        let commit = self.reference.as_deref().filter(|r| is_commit(r));
+
+        let mut args = vec!["clone", "--quiet"];
+        if let Some(depth) = &depth {
+            args.extend(["--depth", depth]);
+        }
+
+        // Blobs are only downloaded for the files that are checked out
+        if !self.sparse.is_empty() {
+            args.extend(["--filter=blob:none", "--sparse"]);
+        }
+
+        // Commits can't be cloned directly, so they're checked out afterwards
+        match (&self.reference, commit) {
+            (_, Some(_)) => args.push("--no-checkout"),
+            (Some(reference), None) => args.extend(["--branch", reference]),
+            (None, None) => {}
+        }
+
+        args.extend([self.repository.as_str(), directory.as_str()]);
+        git(&parent, &args)?;
+
+        if !self.sparse.is_empty() {
+            set_sparse_checkout(&self.directory, &self.sparse)?;
+        }
+
+        if let Some(commit) = commit {
+            // Shallow clones don't have it yet
+            if resolve(&self.directory, commit).is_none() {
+                fetch_commit(&self.directory, commit, self.depth)?;
+            }
+
+            git(
+                &self.directory,
+                &["checkout", "--quiet", "--detach", commit],
+            )?;
+        }
+
+        if self.submodules {
+            update_submodules(&self.directory, self.depth)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{commit, repository, version};
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_clones_tags_and_commits() {
+        let remote = repository(&["v1", "v2"]);
+        let url = format!("file://{}", remote.path().display());
+        let v1 = git(remote.path(), &["rev-parse", "v1"]).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut clone = Clone {
+            repository: url.clone(),
+            directory: dir.path().join("tagged"),
+            reference: Some(String::from("v1")),
+            depth: Some(1),
+            submodules: false,
+            sparse: vec![],
+        };
+
+        assert_eq!(true, clone.plan().unwrap().should_run);
+        clone.execute().unwrap();
+        assert_eq!(false, clone.plan().unwrap().should_run);
+        assert_eq!("v1", version(&clone.directory));
+
+        let mut clone = Clone {
+            repository: url,
+            directory: dir.path().join("pinned"),
+            reference: Some(v1[..10].to_string()),
+            depth: None,
+            submodules: false,
+            sparse: vec![],
+        };
+
+        clone.execute().unwrap();
+        assert_eq!("v1", version(&clone.directory));
+    }
+
+    #[test]
+    fn it_clones_sparsely() {
+        let remote = repository(&["v1"]);
+        for dir in ["nvim", "zsh"] {
+            std::fs::create_dir(remote.path().join(dir)).unwrap();
+            std::fs::write(remote.path().join(dir).join("config"), dir).unwrap();
+        }
+        commit(remote.path(), "v2");
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut clone = Clone {
+            repository: format!("file://{}", remote.path().display()),
+            directory: dir.path().join("clone"),
+            reference: None,
+            depth: None,
+            submodules: false,
+            sparse: vec![String::from("nvim")],
+        };
+
+        clone.execute().unwrap();
+        assert_eq!("v2", version(&clone.directory));
+        assert_eq!(true, clone.directory.join("nvim/config").exists());
+        assert_eq!(false, clone.directory.join("zsh").exists());
+    }
+
+    #[test]
+    fn it_clones_submodules() {
+        let library = repository(&["v1"]);
+        let remote = repository(&["v1"]);
+
+        git(
+            remote.path(),
+            &[
+                "-c",
+                "protocol.file.allow=always",
+                "submodule",
+                "add",
+                "--quiet",
+                &format!("file://{}", library.path().display()),
+                "library",
+            ],
+        )
+        .unwrap();
+        commit(remote.path(), "v2");
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut clone = Clone {
+            repository: format!("file://{}", remote.path().display()),
+            directory: dir.path().join("clone"),
+            reference: None,
+            depth: None,
+            submodules: true,
+            sparse: vec![],
+        };
+
+        // git only follows file:// submodules when allowed
+        std::env::set_var("GIT_CONFIG_COUNT", "1");
+        std::env::set_var("GIT_CONFIG_KEY_0", "protocol.file.allow");
+        std::env::set_var("GIT_CONFIG_VALUE_0", "always");
+
+        clone.execute().unwrap();
+        assert_eq!("v1", version(&clone.directory.join("library")));
+    }
+}
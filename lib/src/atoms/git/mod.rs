@@ -0,0 +1,144 @@
+mod clone;
+mod sync;
+pub use clone::Clone;
+pub use sync::Sync;
+
+use crate::atoms::http::proxy_env;
+use std::path::Path;
+use std::process::Command;
+use tracing::debug;
+
+/// Runs git in `dir`, returning its trimmed output
+pub(crate) fn git(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    debug!("git {}", args.join(" "));
+
+    let output = Command::new("git")
+        .args(args)
+        .envs(proxy_env())
+        .current_dir(dir)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// A full or abbreviated commit never moves, unlike branches and tags
+pub(crate) fn is_commit(reference: &str) -> bool {
+    reference.len() >= 7 && reference.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The commit a reference is at in the clone, without fetching anything
+pub(crate) fn resolve(dir: &Path, reference: &str) -> Option<String> {
+    git(
+        dir,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{}^{{commit}}", reference),
+        ],
+    )
+    .ok()
+}
+
+/// Fetches a commit the clone doesn't have yet. Servers only hand out commits by
+/// their full SHA, so abbreviated ones have to be in the clone already.
+pub(crate) fn fetch_commit(dir: &Path, commit: &str, depth: Option<u32>) -> anyhow::Result<()> {
+    if commit.len() != 40 {
+        return Err(anyhow::anyhow!(
+            "{} isn't in the clone, and only full commit SHAs can be fetched",
+            commit
+        ));
+    }
+
+    let depth = depth.map(|depth| depth.to_string());
+
+    let mut args = vec!["fetch", "--quiet"];
+    if let Some(depth) = &depth {
+        args.extend(["--depth", depth]);
+    }
+    args.extend(["origin", commit]);
+    git(dir, &args)?;
+
+    Ok(())
+}
+
+/// Checks out the submodules, recursively, at the commits the repository records
+fn update_submodules(dir: &Path, depth: Option<u32>) -> anyhow::Result<()> {
+    let depth = depth.map(|depth| depth.to_string());
+
+    let mut args = vec!["submodule", "update", "--quiet", "--init", "--recursive"];
+    if let Some(depth) = &depth {
+        args.extend(["--depth", depth]);
+    }
+
+    git(dir, &args)?;
+
+    Ok(())
+}
+
+/// Limits the working tree to these directories, and the files at the top
+fn set_sparse_checkout(dir: &Path, paths: &[String]) -> anyhow::Result<()> {
+    let mut args = vec!["sparse-checkout", "set", "--cone"];
+    args.extend(paths.iter().map(String::as_str));
+
+    git(dir, &args)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A repository with a commit and tag for each of `tags`, touching `version`
+    pub fn repository(tags: &[&str]) -> tempfile::TempDir {
+        let remote = tempfile::tempdir().unwrap();
+        git(remote.path(), &["init", "--quiet", "--initial-branch=main"]).unwrap();
+
+        for tag in tags {
+            commit(remote.path(), tag);
+            git(remote.path(), &["tag", tag]).unwrap();
+        }
+
+        remote
+    }
+
+    pub fn commit(dir: &Path, version: &str) {
+        std::fs::write(dir.join("version"), version).unwrap();
+        git(dir, &["add", "."]).unwrap();
+        git(
+            dir,
+            &[
+                "-c",
+                "user.name=comtrya",
+                "-c",
+                "user.email=comtrya@example.com",
+                "commit",
+                "--quiet",
+                "-m",
+                version,
+            ],
+        )
+        .unwrap();
+    }
+
+    pub fn version(dir: &Path) -> String {
+        std::fs::read_to_string(PathBuf::from(dir).join("version")).unwrap()
+    }
+
+    #[test]
+    fn it_knows_commits() {
+        assert!(is_commit("0d1bb6a"));
+        assert!(!is_commit("v1.0.0"));
+        assert!(!is_commit("main"));
+    }
+}
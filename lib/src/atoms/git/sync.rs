@@ -0,0 +1,361 @@
+use super::{fetch_commit, git, is_commit, resolve, set_sparse_checkout, update_submodules};
+use crate::atoms::{Atom, Outcome, SideEffect};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// Brings an existing clone up to date: fast-forwards a branch, or checks out a
+/// tag or commit. Without a reference, the checked out branch is fast-forwarded.
+pub struct Sync {
+    pub directory: PathBuf,
+    pub reference: Option<String>,
+    /// Keeps fetches shallow, for clones made with a depth
+    pub depth: Option<u32>,
+    pub submodules: bool,
+    /// Directories to limit the working tree to, when not empty
+    pub sparse: Vec<String>,
+}
+
+impl std::fmt::Display for Sync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The clone at {} needs to be synced{}",
+            self.directory.display(),
+            self.reference
+                .as_ref()
+                .map(|reference| format!(" to {}", reference))
+                .unwrap_or_default()
+        )
+    }
+}
+
+impl Sync {
+    fn git(&self, args: &[&str]) -> anyhow::Result<String> {
+        git(&self.directory, args)
+    }
+
+    /// The checked out branch, if any
+    fn branch(&self) -> Option<String> {
+        self.git(&["symbolic-ref", "--quiet", "--short", "HEAD"])
+            .ok()
+    }
+
+    /// The reference to sync to, or None when a detached clone has nothing to follow
+    fn reference(&self) -> Option<String> {
+        self.reference.clone().or_else(|| self.branch())
+    }
+
+    /// The commit the reference was at when it was last fetched, and whether
+    /// it's a branch, without touching the network
+    fn last_fetched(&self, reference: &str) -> Option<(String, bool)> {
+        if let Some(commit) = resolve(
+            &self.directory,
+            &format!("refs/remotes/origin/{}", reference),
+        ) {
+            return Some((commit, true));
+        }
+
+        resolve(&self.directory, &format!("refs/tags/{}", reference))
+            .or_else(|| {
+                is_commit(reference)
+                    .then(|| resolve(&self.directory, reference))
+                    .flatten()
+            })
+            .map(|commit| (commit, false))
+    }
+
+    /// Fetches the reference, returning the commit it's at and whether it's a
+    /// branch. The remote-tracking branch or tag is updated, for later plans.
+    fn fetch(&self, reference: &str) -> anyhow::Result<(String, bool)> {
+        // Commits never move, so there's nothing to fetch once the clone has them
+        if is_commit(reference) {
+            if resolve(&self.directory, reference).is_none() {
+                fetch_commit(&self.directory, reference, self.depth)?;
+            }
+
+            let commit = resolve(&self.directory, reference)
+                .ok_or_else(|| anyhow::anyhow!("{} isn't a commit", reference))?;
+
+            return Ok((commit, false));
+        }
+
+        let depth = self.depth.map(|depth| depth.to_string());
+
+        let mut args = vec!["fetch", "--quiet"];
+        if let Some(depth) = &depth {
+            args.extend(["--depth", depth]);
+        }
+        args.extend(["origin", reference]);
+        self.git(&args)?;
+
+        // Like `<commit>\t\tbranch 'main' of <url>`
+        let fetched = std::fs::read_to_string(self.directory.join(self.git(&[
+            "rev-parse",
+            "--git-path",
+            "FETCH_HEAD",
+        ])?))?;
+        let branch = fetched.contains(&format!("\tbranch '{}'", reference));
+        let tracking = match branch {
+            true => format!("refs/remotes/origin/{}", reference),
+            false => format!("refs/tags/{}", reference),
+        };
+        self.git(&["update-ref", &tracking, "FETCH_HEAD"])?;
+
+        Ok((self.git(&["rev-parse", "FETCH_HEAD^{commit}"])?, branch))
+    }
+
+    /// Whether the directories checked out differ from the ones listed
+    fn sparse_changed(&self) -> bool {
+        if self.sparse.is_empty() {
+            return false;
+        }
+
+        // Fails for clones that aren't sparse yet
+        let listed = self.git(&["sparse-checkout", "list"]).unwrap_or_default();
+        let mut listed: Vec<&str> = listed.lines().collect();
+        let mut sparse: Vec<&str> = self
+            .sparse
+            .iter()
+            .map(|path| path.trim_matches('/'))
+            .collect();
+
+        listed.sort_unstable();
+        sparse.sort_unstable();
+
+        listed != sparse
+    }
+
+    /// Moves the checked out branch to the commit, refusing to drop local commits.
+    /// Shallow histories can't show the commit descends from ours, so they're reset.
+    fn fast_forward(&self, commit: &str) -> anyhow::Result<()> {
+        match self.depth {
+            Some(_) => self.git(&["reset", "--quiet", "--keep", commit])?,
+            None => self.git(&["merge", "--quiet", "--ff-only", commit])?,
+        };
+
+        Ok(())
+    }
+}
+
+fn is_clone(directory: &Path) -> bool {
+    directory.join(".git").exists()
+}
+
+impl Atom for Sync {
+    fn plan(&self) -> anyhow::Result<Outcome> {
+        if !is_clone(&self.directory) {
+            return Ok(Outcome {
+                side_effects: vec![],
+                should_run: false,
+            });
+        }
+
+        let sparse_changed = self.sparse_changed();
+        let mut side_effects = vec![];
+
+        if sparse_changed {
+            side_effects.push(SideEffect::Command {
+                command: format!(
+                    "git -C {} sparse-checkout set --cone {}",
+                    self.directory.display(),
+                    self.sparse.join(" ")
+                ),
+            });
+        }
+
+        let Some(reference) = self.reference() else {
+            return Ok(Outcome {
+                side_effects,
+                should_run: sparse_changed,
+            });
+        };
+
+        let head = self.git(&["rev-parse", "HEAD"])?;
+
+        // Planning stays offline, so the clone is compared with the reference as
+        // it was last fetched. References that weren't fetched yet need syncing.
+        let (commit, checked_out) = match self.last_fetched(&reference) {
+            // A branch can be at the commit without being checked out
+            Some((commit, true)) => (Some(commit), self.branch() == Some(reference.clone())),
+            Some((commit, false)) => (Some(commit), true),
+            None => (None, true),
+        };
+
+        debug!(
+            "{} is at {}, {} was at {:?} when it was last fetched",
+            self.directory.display(),
+            head,
+            reference,
+            commit
+        );
+
+        side_effects.push(SideEffect::Command {
+            command: format!("git -C {} checkout {}", self.directory.display(), reference),
+        });
+
+        Ok(Outcome {
+            side_effects,
+            should_run: commit != Some(head) || !checked_out || sparse_changed,
+        })
+    }
+
+    fn execute(&mut self) -> anyhow::Result<()> {
+        if self.sparse_changed() {
+            info!(
+                "Checking out {} in {}",
+                self.sparse.join(", "),
+                self.directory.display()
+            );
+            set_sparse_checkout(&self.directory, &self.sparse)?;
+        }
+
+        let Some(reference) = self.reference() else {
+            return Ok(());
+        };
+
+        info!("Syncing {} to {}", self.directory.display(), reference);
+
+        let (commit, branch) = self.fetch(&reference)?;
+
+        if !branch {
+            self.git(&["checkout", "--quiet", "--detach", &commit])?;
+        } else if self.branch().as_ref() == Some(&reference) {
+            self.fast_forward(&commit)?;
+        } else if self
+            .git(&[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("refs/heads/{}", reference),
+            ])
+            .is_ok()
+        {
+            self.git(&["checkout", "--quiet", &reference])?;
+            self.fast_forward(&commit)?;
+        } else {
+            self.git(&["checkout", "--quiet", "-b", &reference, &commit])?;
+        }
+
+        if self.submodules {
+            update_submodules(&self.directory, self.depth)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{commit, repository, version};
+    use super::super::Clone;
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn clone(remote: &Path, dir: &Path, depth: Option<u32>) -> PathBuf {
+        let mut clone = Clone {
+            repository: format!("file://{}", remote.display()),
+            directory: dir.join("clone"),
+            reference: None,
+            depth,
+            submodules: false,
+            sparse: vec![],
+        };
+        clone.execute().unwrap();
+
+        clone.directory
+    }
+
+    #[test]
+    fn it_fast_forwards_branches() {
+        let remote = repository(&["v1"]);
+        let dir = tempfile::tempdir().unwrap();
+        let directory = clone(remote.path(), dir.path(), None);
+
+        let mut sync = Sync {
+            directory: directory.clone(),
+            reference: None,
+            depth: None,
+            submodules: false,
+            sparse: vec![],
+        };
+        assert_eq!(false, sync.plan().unwrap().should_run);
+
+        // Planning doesn't fetch, so it only knows about new commits once
+        // something did
+        commit(remote.path(), "v2");
+        assert_eq!(false, sync.plan().unwrap().should_run);
+        git(&directory, &["fetch", "--quiet"]).unwrap();
+        assert_eq!(true, sync.plan().unwrap().should_run);
+        sync.execute().unwrap();
+        assert_eq!("v2", version(&directory));
+        assert_eq!(false, sync.plan().unwrap().should_run);
+
+        // Local commits aren't thrown away
+        commit(&directory, "local");
+        commit(remote.path(), "v3");
+        assert_eq!(true, sync.execute().is_err());
+    }
+
+    #[test]
+    fn it_checks_out_tags_and_commits() {
+        let remote = repository(&["v1", "v2"]);
+        let dir = tempfile::tempdir().unwrap();
+        let directory = clone(remote.path(), dir.path(), Some(1));
+        assert_eq!("v2", version(&directory));
+
+        let mut sync = Sync {
+            directory: directory.clone(),
+            reference: Some(String::from("v1")),
+            depth: Some(1),
+            submodules: false,
+            sparse: vec![],
+        };
+        assert_eq!(true, sync.plan().unwrap().should_run);
+        sync.execute().unwrap();
+        assert_eq!("v1", version(&directory));
+        assert_eq!(false, sync.plan().unwrap().should_run);
+
+        sync.reference = Some(git(remote.path(), &["rev-parse", "v2"]).unwrap());
+        sync.execute().unwrap();
+        assert_eq!("v2", version(&directory));
+
+        // Abbreviated commits have to be in the clone, as only full ones can be fetched
+        sync.reference = Some(git(remote.path(), &["rev-parse", "--short", "v1"]).unwrap());
+        sync.execute().unwrap();
+        assert_eq!("v1", version(&directory));
+        sync.reference = Some(String::from("0000000"));
+        assert_eq!(true, sync.execute().is_err());
+
+        sync.reference = Some(String::from("main"));
+        assert_eq!(true, sync.plan().unwrap().should_run);
+        sync.execute().unwrap();
+        assert_eq!(Some(String::from("main")), sync.branch());
+        assert_eq!(false, sync.plan().unwrap().should_run);
+    }
+
+    #[test]
+    fn it_changes_the_sparse_checkout() {
+        let remote = repository(&["v1"]);
+        for dir in ["nvim", "zsh"] {
+            std::fs::create_dir(remote.path().join(dir)).unwrap();
+            std::fs::write(remote.path().join(dir).join("config"), dir).unwrap();
+        }
+        commit(remote.path(), "v2");
+
+        let dir = tempfile::tempdir().unwrap();
+        let directory = clone(remote.path(), dir.path(), None);
+
+        let mut sync = Sync {
+            directory: directory.clone(),
+            reference: None,
+            depth: None,
+            submodules: false,
+            sparse: vec![String::from("zsh/")],
+        };
+        assert_eq!(true, sync.plan().unwrap().should_run);
+        sync.execute().unwrap();
+        assert_eq!(false, directory.join("nvim").exists());
+        assert_eq!(true, directory.join("zsh/config").exists());
+        assert_eq!(false, sync.plan().unwrap().should_run);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::atoms::file::write_atomic;
+use reqwest::header::{HeaderMap, ETAG, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Downloads are kept in a content-addressed cache: the body of every completed
+/// download is stored once under its SHA-256, and each URL remembers which body
+/// it last returned along with the validators to revalidate it. Unfinished
+/// downloads are kept too, so they can be resumed with a `Range` request.
+pub(crate) struct Cache {
+    dir: PathBuf,
+    key: String,
+}
+
+/// What the server said identifies a version of a URL's body
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct Entry {
+    #[serde(flatten)]
+    pub validators: Validators,
+    pub sha256: String,
+}
+
+impl Validators {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+
+        Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    /// The value for `If-Range`, which only accepts strong validators
+    pub fn if_range(&self) -> Option<&str> {
+        match self.etag.as_deref() {
+            Some(etag) if !etag.starts_with("W/") => Some(etag),
+            _ => self.last_modified.as_deref(),
+        }
+    }
+}
+
+/// The default location, next to the cached manifests
+pub(crate) fn default_dir() -> PathBuf {
+    crate::cache::Area::Downloads.dir()
+}
+
+/// Where API responses, like GitHub's release metadata, are kept
+pub(crate) fn api_dir() -> PathBuf {
+    crate::cache::Area::Api.dir()
+}
+
+impl Cache {
+    pub fn new(dir: &Path, url: &str) -> Self {
+        Cache {
+            dir: dir.to_path_buf(),
+            key: sha256::digest(url),
+        }
+    }
+
+    fn entry_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.json", self.key))
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join("blobs").join(sha256)
+    }
+
+    pub fn partial_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.part", self.key))
+    }
+
+    fn partial_validators_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.part.json", self.key))
+    }
+
+    /// Waits until no other download of this URL, in this process or another,
+    /// is writing to the cache, and keeps them waiting until the file is dropped
+    pub fn lock(&self) -> anyhow::Result<File> {
+        std::fs::create_dir_all(&self.dir)?;
+
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join(format!("{}.lock", self.key)))?;
+        file.lock()?;
+
+        Ok(file)
+    }
+
+    /// The body this URL returned last time, if it's still in the cache
+    pub fn entry(&self) -> Option<Entry> {
+        let entry: Entry = serde_json::from_slice(&std::fs::read(self.entry_path()).ok()?).ok()?;
+
+        if !self.blob_path(&entry.sha256).exists() {
+            return None;
+        }
+
+        crate::cache::touch(&self.entry_path());
+
+        Some(entry)
+    }
+
+    /// How much of an unfinished download there is, and the version it was of.
+    /// Without validators it can't be resumed safely, so it's ignored.
+    pub fn partial(&self) -> Option<(u64, Validators)> {
+        let length = std::fs::metadata(self.partial_path()).ok()?.len();
+        let validators: Validators =
+            serde_json::from_slice(&std::fs::read(self.partial_validators_path()).ok()?).ok()?;
+
+        (length > 0 && validators.if_range().is_some()).then_some((length, validators))
+    }
+
+    /// Remembers the version being downloaded, so it can be resumed
+    pub fn start(&self, validators: &Validators) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(
+            self.partial_validators_path(),
+            serde_json::to_vec(validators)?,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn discard_partial(&self) {
+        let _ = std::fs::remove_file(self.partial_path());
+        let _ = std::fs::remove_file(self.partial_validators_path());
+    }
+
+    /// Moves the finished download into the cache, under its hash
+    pub fn finish(&self, validators: Validators) -> anyhow::Result<Entry> {
+        let partial = self.partial_path();
+        let sha256 = sha256::try_digest(partial.as_path())?;
+        let blob = self.blob_path(&sha256);
+
+        std::fs::create_dir_all(self.dir.join("blobs"))?;
+        std::fs::rename(&partial, &blob)?;
+        let _ = std::fs::remove_file(self.partial_validators_path());
+
+        let entry = Entry { validators, sha256 };
+        write_atomic(&self.entry_path(), &serde_json::to_vec(&entry)?)?;
+
+        Ok(entry)
+    }
+
+    /// Stores a body received whole, like an API response
+    pub fn store(&self, validators: Validators, body: &[u8]) -> anyhow::Result<Entry> {
+        let sha256 = sha256::digest(body);
+
+        std::fs::create_dir_all(self.dir.join("blobs"))?;
+        write_atomic(&self.blob_path(&sha256), body)?;
+
+        let entry = Entry { validators, sha256 };
+        write_atomic(&self.entry_path(), &serde_json::to_vec(&entry)?)?;
+
+        Ok(entry)
+    }
+
+    pub fn read(&self, entry: &Entry) -> anyhow::Result<Vec<u8>> {
+        Ok(std::fs::read(self.blob_path(&entry.sha256))?)
+    }
+
+    /// Copies the cached body to `to`, through a temporary file like downloads
+    pub fn copy(&self, entry: &Entry, to: &Path) -> anyhow::Result<()> {
+        let partial = super::client::partial_path(to);
+
+        if let Err(err) = std::fs::copy(self.blob_path(&entry.sha256), &partial) {
+            let _ = std::fs::remove_file(&partial);
+            return Err(err.into());
+        }
+
+        std::fs::rename(&partial, to)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    #[test]
+    fn it_stores_bodies_by_hash() {
+        let tmpdir = tempdir().unwrap();
+        let cache = Cache::new(tmpdir.path(), "https://example.com/a");
+        let validators = Validators {
+            etag: Some(String::from("\"v1\"")),
+            last_modified: None,
+        };
+
+        assert_eq!(None, cache.entry());
+
+        cache.start(&validators).unwrap();
+        std::fs::write(cache.partial_path(), "body").unwrap();
+        assert_eq!(Some((4, validators.clone())), cache.partial());
+
+        let entry = cache.finish(validators).unwrap();
+        assert_eq!(sha256::digest("body"), entry.sha256);
+        assert_eq!(Some(entry.clone()), cache.entry());
+        assert_eq!(None, cache.partial());
+
+        let to = tmpdir.path().join("copy");
+        cache.copy(&entry, &to).unwrap();
+        assert_eq!("body", std::fs::read_to_string(to).unwrap());
+    }
+
+    #[test]
+    fn it_lets_one_download_of_a_url_at_a_time() {
+        let tmpdir = tempdir().unwrap();
+        let cache = Cache::new(tmpdir.path(), "https://example.com/a");
+        let other = Cache::new(tmpdir.path(), "https://example.com/b");
+
+        let lock = cache.lock().unwrap();
+        let _other = other.lock().unwrap();
+
+        let waiting = std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| {
+                let _lock = cache.lock().unwrap();
+                std::fs::exists(cache.partial_path()).unwrap()
+            });
+
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(cache.partial_path(), "body").unwrap();
+            drop(lock);
+
+            waiter.join().unwrap()
+        });
+
+        // It only got the lock once the first download was done writing
+        assert_eq!(true, waiting);
+    }
+
+    #[test]
+    fn it_only_resumes_strong_validators() {
+        let weak = Validators {
+            etag: Some(String::from("W/\"v1\"")),
+            last_modified: None,
+        };
+        let dated = Validators {
+            etag: Some(String::from("W/\"v1\"")),
+            last_modified: Some(String::from("Wed, 21 Oct 2015 07:28:00 GMT")),
+        };
+
+        assert_eq!(None, weak.if_range());
+        assert_eq!(Some("Wed, 21 Oct 2015 07:28:00 GMT"), dated.if_range());
+    }
+}
@@ -0,0 +1,581 @@
+use super::cache::{Cache, Entry, Validators};
+use super::progress::Progress;
+use crate::config::Proxy;
+use anyhow::anyhow;
+use reqwest::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, RANGE};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::Runtime;
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+const DEFAULT_CONCURRENCY: usize = 4;
+
+static CONCURRENCY: AtomicUsize = AtomicUsize::new(DEFAULT_CONCURRENCY);
+static CLIENT: OnceLock<Client> = OnceLock::new();
+static HTTP: RwLock<Option<reqwest::Client>> = RwLock::new(None);
+static PROXY: RwLock<Option<Proxy>> = RwLock::new(None);
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Downloads share one async runtime and HTTP client, so connections are reused,
+/// and at most `concurrency` of them run at once across all threads.
+struct Client {
+    runtime: Runtime,
+    permits: Arc<Semaphore>,
+}
+
+/// Limits how many downloads run at once, only effective before the first download
+pub fn set_concurrency(concurrency: usize) {
+    CONCURRENCY.store(concurrency.max(1), Ordering::Relaxed);
+}
+
+/// Serves downloads and API responses from the cache only, failing for those
+/// that aren't cached, for machines without internet
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub(crate) fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Sends every HTTP request through this proxy, instead of the one in the
+/// `HTTP(S)_PROXY` variables
+pub fn set_proxy(proxy: Option<Proxy>) {
+    *PROXY.write().unwrap() = proxy;
+
+    // Built again with the new proxy by the next request
+    *HTTP.write().unwrap() = None;
+}
+
+/// The configured proxy. Without one, reqwest uses `HTTP_PROXY`, `HTTPS_PROXY`,
+/// `ALL_PROXY` and `NO_PROXY`.
+pub(crate) fn proxy() -> anyhow::Result<Option<reqwest::Proxy>> {
+    let Some(proxy) = PROXY.read().unwrap().clone() else {
+        return Ok(None);
+    };
+
+    let no_proxy = match proxy.no_proxy {
+        Some(hosts) => reqwest::NoProxy::from_string(&hosts.join(",")),
+        None => reqwest::NoProxy::from_env(),
+    };
+
+    let proxy = reqwest::Proxy::all(&proxy.url)
+        .map_err(|err| anyhow!("Invalid proxy {}: {}", proxy.url, err))?
+        .no_proxy(no_proxy);
+
+    Ok(Some(proxy))
+}
+
+/// The configured proxy as variables for programs like git, which read them
+pub(crate) fn proxy_env() -> Vec<(&'static str, String)> {
+    PROXY
+        .read()
+        .unwrap()
+        .clone()
+        .map(proxy_vars)
+        .unwrap_or_default()
+}
+
+fn proxy_vars(proxy: Proxy) -> Vec<(&'static str, String)> {
+    let mut env = vec![
+        ("http_proxy", proxy.url.clone()),
+        ("https_proxy", proxy.url),
+    ];
+    if let Some(hosts) = proxy.no_proxy {
+        env.push(("no_proxy", hosts.join(",")));
+    }
+
+    env
+}
+
+/// Identifies comtrya to servers, some APIs like GitHub's require it
+pub(crate) fn user_agent() -> String {
+    format!("comtrya/{}", env!("CARGO_PKG_VERSION"))
+}
+
+fn client() -> anyhow::Result<&'static Client> {
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| anyhow!("Failed to create async runtime: {}", err))?;
+
+    let client = Client {
+        runtime,
+        permits: Arc::new(Semaphore::new(CONCURRENCY.load(Ordering::Relaxed))),
+    };
+
+    Ok(CLIENT.get_or_init(|| client))
+}
+
+/// The HTTP client, with the proxy configured last. Clones share connections.
+fn http() -> anyhow::Result<reqwest::Client> {
+    if let Some(http) = HTTP.read().unwrap().as_ref() {
+        return Ok(http.clone());
+    }
+
+    let mut cached = HTTP.write().unwrap();
+    let http = match proxy()? {
+        Some(proxy) => reqwest::Client::builder().proxy(proxy),
+        None => reqwest::Client::builder(),
+    }
+    .user_agent(user_agent())
+    .build()?;
+
+    Ok(cached.get_or_insert(http).clone())
+}
+
+/// Streams `url` to a temporary file next to `to`, which is renamed once complete,
+/// so an interrupted download never leaves a truncated file behind.
+pub fn download_with_headers(
+    url: &str,
+    to: &Path,
+    headers: &[(&str, String)],
+) -> anyhow::Result<()> {
+    if is_offline() {
+        return Err(anyhow!("Can't download {} offline", url));
+    }
+
+    let client = client()?;
+    let http = http()?;
+
+    client.runtime.block_on(async {
+        let _permit = client.permits.acquire().await?;
+
+        debug!("Downloading {} to {}", url, to.display());
+
+        let mut request = http.get(url);
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+
+        let mut response = request.send().await?.error_for_status()?;
+
+        let partial = partial_path(to);
+        let result = async {
+            let mut file = tokio::fs::File::create(&partial).await?;
+            let mut progress = Progress::new(url, 0, response.content_length());
+
+            // Written as it arrives, so memory use doesn't grow with the download
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).await?;
+                progress.advance(chunk.len());
+            }
+
+            file.flush().await?;
+            tokio::fs::rename(&partial, to).await?;
+
+            anyhow::Ok(())
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+
+        result
+    })
+}
+
+/// Like `download_with_headers`, through the cache in `cache_dir`. A body that's
+/// cached is revalidated with `If-None-Match` and `If-Modified-Since`, and copied
+/// from the cache when it's unchanged. An unfinished download is resumed with a
+/// `Range` request, as long as the server still has the same version.
+pub fn download_cached(
+    url: &str,
+    to: &Path,
+    headers: &[(&str, String)],
+    cache_dir: &Path,
+) -> anyhow::Result<()> {
+    let cache = Cache::new(cache_dir, url);
+    let entry = fetch_cached(url, headers, &cache)?;
+
+    cache.copy(&entry, to)
+}
+
+/// Downloads `url` into the cache in `cache_dir` only, for offline runs
+pub(crate) fn prefetch(
+    url: &str,
+    headers: &[(&str, String)],
+    cache_dir: &Path,
+) -> anyhow::Result<()> {
+    fetch_cached(url, headers, &Cache::new(cache_dir, url))?;
+
+    Ok(())
+}
+
+/// The cache entry of `url`, downloaded or revalidated first unless offline.
+/// Downloads of the same URL wait for each other, and then revalidate what the
+/// first one downloaded.
+fn fetch_cached(url: &str, headers: &[(&str, String)], cache: &Cache) -> anyhow::Result<Entry> {
+    let _lock = cache.lock()?;
+    let cached = cache.entry();
+
+    if is_offline() {
+        return cached.ok_or_else(|| offline_error(url));
+    }
+
+    let client = client()?;
+    let http = http()?;
+
+    client.runtime.block_on(async {
+        let _permit = client.permits.acquire().await?;
+
+        let request = |resume: Option<&(u64, Validators)>| {
+            let mut request = http.get(url);
+            for (name, value) in headers {
+                request = request.header(*name, value);
+            }
+
+            if let Some(entry) = cached.as_ref() {
+                if let Some(etag) = entry.validators.etag.as_deref() {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = entry.validators.last_modified.as_deref() {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+
+            if let Some((length, validators)) = resume {
+                request = request
+                    .header(RANGE, format!("bytes={length}-"))
+                    .header(IF_RANGE, validators.if_range().unwrap_or_default());
+            }
+
+            request.send()
+        };
+
+        let resume = cache.partial();
+        let mut response = request(resume.as_ref()).await?;
+
+        // The unfinished download is already complete, or the server changed
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            cache.discard_partial();
+            response = request(None).await?;
+        }
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            if let Some(entry) = cached {
+                debug!("{} is unchanged, using the cached copy", url);
+                return Ok(entry);
+            }
+        }
+
+        let mut response = response.error_for_status()?;
+        let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+        let (validators, received) = match (resumed, resume) {
+            (true, Some((length, validators))) => {
+                debug!("Resuming {} from {} bytes", url, length);
+                (validators, length)
+            }
+            _ => {
+                debug!("Downloading {}", url);
+                (Validators::from_headers(response.headers()), 0)
+            }
+        };
+
+        cache.start(&validators)?;
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(cache.partial_path())
+            .await?;
+
+        let mut progress = Progress::new(url, received, response.content_length());
+
+        // What was received so far is kept, to resume from on the next run
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
+            progress.advance(chunk.len());
+        }
+        file.flush().await?;
+        drop(file);
+
+        cache.finish(validators)
+    })
+}
+
+fn offline_error(url: &str) -> anyhow::Error {
+    anyhow!(
+        "{} isn't cached, and can't be downloaded offline. Fetch it first with `comtrya fetch`",
+        url
+    )
+}
+
+/// Fetches `url` and parses the JSON it returns, through the cache in `cache_dir`.
+/// The cached response is revalidated with its ETag, which GitHub doesn't count
+/// against the rate limit when unchanged, and used as is when the server can't be
+/// reached.
+pub(crate) fn get_json_cached<T: DeserializeOwned>(
+    url: &str,
+    cache_dir: &Path,
+) -> anyhow::Result<T> {
+    let cache = Cache::new(cache_dir, url);
+    let cached = cache.entry();
+
+    if is_offline() {
+        let entry = cached.ok_or_else(|| offline_error(url))?;
+        return Ok(serde_json::from_slice(&cache.read(&entry)?)?);
+    }
+
+    let client = client()?;
+    let http = http()?;
+
+    let body = client.runtime.block_on(async {
+        let _permit = client.permits.acquire().await?;
+
+        let mut request = http.get(url);
+        if let Some(entry) = cached.as_ref() {
+            if let Some(etag) = entry.validators.etag.as_deref() {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = entry.validators.last_modified.as_deref() {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        debug!("Fetching {}", url);
+
+        let response = match (request.send().await, cached.as_ref()) {
+            (Ok(response), _) => response,
+            (Err(err), Some(entry)) => {
+                warn!("Using the cached response of {}, {}", url, err);
+                return cache.read(entry);
+            }
+            (Err(err), None) => return Err(err.into()),
+        };
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            if let Some(entry) = cached.as_ref() {
+                debug!("{} is unchanged, using the cached response", url);
+                return cache.read(entry);
+            }
+        }
+
+        let response = response.error_for_status()?;
+        let validators = Validators::from_headers(response.headers());
+        let body = response.bytes().await?;
+
+        // Responses without validators can't be revalidated, but still serve offline runs
+        cache.store(validators, &body)?;
+
+        Ok(body.to_vec())
+    })?;
+
+    Ok(serde_json::from_slice(&body)?)
+}
+
+pub(super) fn partial_path(to: &Path) -> PathBuf {
+    let mut file_name = to.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".part");
+    to.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use tempfile::tempdir;
+
+    /// Serves `body` on every path but `/missing`, which is a 404
+    fn serve(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = [0; 1024];
+                let read = stream.read(&mut request).unwrap_or_default();
+                let request = String::from_utf8_lossy(&request[..read]);
+
+                let response = if request.starts_with("GET /missing") {
+                    String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                };
+
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+
+        format!("http://{address}")
+    }
+
+    /// Serves `body` with an ETag, honoring `If-None-Match` and `Range`, and
+    /// returns the requests it received
+    fn serve_versioned(body: &'static str) -> (String, Arc<std::sync::Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+
+        let received = requests.clone();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = [0; 1024];
+                let read = stream.read(&mut request).unwrap_or_default();
+                let request = String::from_utf8_lossy(&request[..read]).to_lowercase();
+                received.lock().unwrap().push(request.clone());
+
+                let range = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+
+                let response = if request.contains("if-none-match: \"v1\"") {
+                    String::from("HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\n")
+                } else if let Some(start) = range {
+                    format!(
+                        "HTTP/1.1 206 Partial Content\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n{}",
+                        body.len() - start,
+                        &body[start..]
+                    )
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                };
+
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+
+        (format!("http://{address}/file"), requests)
+    }
+
+    #[test]
+    fn it_revalidates_cached_downloads() {
+        let (url, requests) = serve_versioned("downloaded");
+        let cache = tempdir().unwrap();
+        let tmpdir = tempdir().unwrap();
+        let first = tmpdir.path().join("first");
+        let second = tmpdir.path().join("second");
+
+        download_cached(&url, &first, &[], cache.path()).unwrap();
+        download_cached(&url, &second, &[], cache.path()).unwrap();
+
+        assert_eq!("downloaded", std::fs::read_to_string(second).unwrap());
+        assert_eq!(
+            true,
+            requests.lock().unwrap()[1].contains("if-none-match: \"v1\"")
+        );
+    }
+
+    #[test]
+    fn it_prefetches_into_the_cache() {
+        let (url, _) = serve_versioned("downloaded");
+        let cache = tempdir().unwrap();
+
+        prefetch(&url, &[], cache.path()).unwrap();
+
+        let entry = Cache::new(cache.path(), &url).entry().unwrap();
+        assert_eq!(sha256::digest("downloaded"), entry.sha256);
+    }
+
+    #[test]
+    fn it_revalidates_cached_api_responses() {
+        let (url, requests) = serve_versioned("{\"tag_name\": \"v1\"}");
+        let cache = tempdir().unwrap();
+
+        for _ in 0..2 {
+            let release: serde_json::Value = get_json_cached(&url, cache.path()).unwrap();
+            assert_eq!("v1", release["tag_name"]);
+        }
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(2, requests.len());
+        assert_eq!(true, requests[1].contains("if-none-match: \"v1\""));
+    }
+
+    #[test]
+    fn it_resumes_unfinished_downloads() {
+        let (url, requests) = serve_versioned("downloaded");
+        let cache_dir = tempdir().unwrap();
+        let tmpdir = tempdir().unwrap();
+        let to = tmpdir.path().join("file");
+
+        let cache = Cache::new(cache_dir.path(), &url);
+        cache
+            .start(&Validators {
+                etag: Some(String::from("\"v1\"")),
+                last_modified: None,
+            })
+            .unwrap();
+        std::fs::write(cache.partial_path(), "downl").unwrap();
+
+        download_cached(&url, &to, &[], cache_dir.path()).unwrap();
+
+        assert_eq!("downloaded", std::fs::read_to_string(to).unwrap());
+        let requests = requests.lock().unwrap();
+        assert_eq!(true, requests[0].contains("range: bytes=5-"));
+        assert_eq!(true, requests[0].contains("if-range: \"v1\""));
+    }
+
+    #[test]
+    fn it_passes_the_proxy_to_programs() {
+        let vars = proxy_vars(Proxy {
+            url: String::from("http://proxy:3128"),
+            no_proxy: Some(vec![String::from("localhost"), String::from(".internal")]),
+        });
+
+        assert_eq!(
+            vars,
+            vec![
+                ("http_proxy", String::from("http://proxy:3128")),
+                ("https_proxy", String::from("http://proxy:3128")),
+                ("no_proxy", String::from("localhost,.internal")),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_downloads_concurrently() {
+        let url = serve("downloaded");
+        let tmpdir = tempdir().unwrap();
+
+        let handles: Vec<_> = (0..3)
+            .map(|i| {
+                let url = format!("{url}/{i}");
+                let to = tmpdir.path().join(format!("file-{i}"));
+                std::thread::spawn(move || download_with_headers(&url, &to, &[]).map(|_| to))
+            })
+            .collect();
+
+        for handle in handles {
+            let to = handle.join().unwrap().unwrap();
+            assert_eq!("downloaded", std::fs::read_to_string(to).unwrap());
+        }
+    }
+
+    #[test]
+    fn it_leaves_nothing_behind_on_failure() {
+        let url = serve("");
+        let tmpdir = tempdir().unwrap();
+        let to = tmpdir.path().join("missing");
+
+        assert_eq!(
+            true,
+            download_with_headers(&format!("{url}/missing"), &to, &[]).is_err()
+        );
+        assert_eq!(false, to.exists());
+        assert_eq!(false, partial_path(&to).exists());
+    }
+}
@@ -1,12 +1,51 @@
-use crate::atoms::Outcome;
+use crate::atoms::{Outcome, SideEffect};
 
 use super::super::Atom;
-use std::io::Write;
-use std::{fs::File, path::PathBuf};
+use super::{cache, client};
+use crate::secrets::{BasicAuth, Secret};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
+#[derive(Default)]
 pub struct Download {
     pub url: String,
     pub to: PathBuf,
+    pub headers: BTreeMap<String, Secret>,
+    pub basic_auth: Option<BasicAuth>,
+    pub bearer_token: Option<Secret>,
+    /// Where downloads are cached and resumed from, defaults to comtrya's cache
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Download {
+    fn cache_dir(&self) -> PathBuf {
+        self.cache_dir.clone().unwrap_or_else(cache::default_dir)
+    }
+
+    /// Secrets are only resolved right before the request is sent
+    fn request_headers(&self) -> anyhow::Result<Vec<(&str, String)>> {
+        let mut headers = vec![];
+
+        for (name, value) in self.headers.iter() {
+            headers.push((name.as_str(), value.resolve()?));
+        }
+
+        if let Some(auth) = self.basic_auth.as_ref() {
+            let credentials = format!("{}:{}", auth.username, auth.password.resolve()?);
+            headers.push((
+                "Authorization",
+                format!("Basic {}", STANDARD.encode(credentials)),
+            ));
+        }
+
+        if let Some(token) = self.bearer_token.as_ref() {
+            headers.push(("Authorization", format!("Bearer {}", token.resolve()?)));
+        }
+
+        Ok(headers)
+    }
 }
 
 impl std::fmt::Display for Download {
@@ -22,20 +61,28 @@ impl Atom for Download {
         // correct version exists; or perhaps a TTL when omitted?
 
         Ok(Outcome {
-            side_effects: vec![],
+            side_effects: vec![SideEffect::Write {
+                path: self.to.clone(),
+            }],
             should_run: !PathBuf::from(&self.to).exists(),
         })
     }
 
     fn execute(&mut self) -> anyhow::Result<()> {
-        let response = reqwest::blocking::get(&self.url)?;
-
-        let mut file = File::create(&self.to)?;
+        client::download_cached(
+            &self.url,
+            &self.to,
+            &self.request_headers()?,
+            &self.cache_dir(),
+        )
+    }
 
-        let content = response.bytes()?;
-        file.write_all(&content)?;
+    fn fetch(&self) -> anyhow::Result<()> {
+        client::prefetch(&self.url, &self.request_headers()?, &self.cache_dir())
+    }
 
-        Ok(())
+    fn managed_files(&self) -> Vec<PathBuf> {
+        vec![self.to.clone()]
     }
 }
 
@@ -53,6 +100,7 @@ mod tests {
         let mut atom = Download {
             url: String::from("https://www.google.com/images/branding/googlelogo/2x/googlelogo_color_272x92dp.png"),
             to: to_file,
+            ..Default::default()
         };
 
         assert_eq!(true, atom.plan().unwrap().should_run);
@@ -61,4 +109,38 @@ mod tests {
         assert_eq!(true, result.is_ok());
         assert_eq!(false, atom.plan().unwrap().should_run);
     }
+
+    #[test]
+    fn it_sends_credentials() {
+        std::env::set_var("COMTRYA_TEST_DOWNLOAD_TOKEN", "token");
+
+        let atom = Download {
+            url: String::from("https://example.com/artifact"),
+            headers: BTreeMap::from([(
+                String::from("X-Api-Key"),
+                Secret::Value(String::from("key")),
+            )]),
+            basic_auth: Some(BasicAuth {
+                username: String::from("user"),
+                password: Secret::Value(String::from("pass")),
+            }),
+            bearer_token: Some(Secret::Env {
+                env: String::from("COMTRYA_TEST_DOWNLOAD_TOKEN"),
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            vec![
+                ("X-Api-Key", String::from("key")),
+                ("Authorization", String::from("Basic dXNlcjpwYXNz")),
+                ("Authorization", String::from("Bearer token")),
+            ],
+            atom.request_headers().unwrap()
+        );
+        assert_eq!(
+            "HttpDownload from https://example.com/artifact to ",
+            atom.to_string()
+        );
+    }
 }
@@ -1,6 +1,15 @@
 use super::Atom;
 
+mod cache;
+mod client;
 mod download;
+mod progress;
+pub(crate) use cache::api_dir;
+pub(crate) use client::{
+    download_with_headers, get_json_cached, is_offline, proxy, proxy_env, user_agent,
+};
+pub use client::{set_concurrency, set_offline, set_proxy};
 pub use download::Download;
+pub use progress::human_size;
 
 pub trait HttpAtom: Atom {}
@@ -0,0 +1,93 @@
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// How often the progress of a download is logged
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tracks how much of a download was received, logging it every few seconds so
+/// large downloads don't look stuck. Downloads finishing sooner aren't logged.
+pub(super) struct Progress<'a> {
+    url: &'a str,
+    received: u64,
+    total: Option<u64>,
+    logged: Instant,
+}
+
+impl<'a> Progress<'a> {
+    /// `received` is what a resumed download already has, and `remaining` the
+    /// length of the response, when the server sent it
+    pub fn new(url: &'a str, received: u64, remaining: Option<u64>) -> Self {
+        Progress {
+            url,
+            received,
+            total: remaining.map(|remaining| received + remaining),
+            logged: Instant::now(),
+        }
+    }
+
+    pub fn advance(&mut self, bytes: usize) {
+        self.received += bytes as u64;
+
+        if self.logged.elapsed() >= INTERVAL {
+            self.logged = Instant::now();
+            info!(
+                received = self.received,
+                total = self.total,
+                "Downloading {}, {}",
+                self.url,
+                self.describe()
+            );
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.total {
+            Some(total) if total > 0 => format!(
+                "{} of {} ({}%)",
+                human_size(self.received),
+                human_size(total),
+                self.received * 100 / total
+            ),
+            _ => human_size(self.received),
+        }
+    }
+}
+
+/// Sizes in binary units, like `2.0 MiB`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_describes_progress() {
+        let mut progress =
+            Progress::new("https://example.com/big.iso", 512 * 1024, Some(1536 * 1024));
+        assert_eq!("512.0 KiB of 2.0 MiB (25%)", progress.describe());
+
+        progress.advance(3 * 512 * 1024);
+        assert_eq!("2.0 MiB of 2.0 MiB (100%)", progress.describe());
+
+        let progress = Progress::new("https://example.com/stream", 0, None);
+        assert_eq!("0 B", progress.describe());
+        assert_eq!("3.0 GiB", human_size(3 * 1024 * 1024 * 1024));
+    }
+}
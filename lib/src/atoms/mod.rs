@@ -1,9 +1,81 @@
 pub mod command;
 pub mod directory;
 pub mod file;
+pub mod git;
 pub mod http;
+pub mod plugin;
+pub mod script;
 
-pub enum SideEffect {}
+use crate::rollback::Undo;
+use crate::values::Value;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// A change an atom makes when it runs, shown in plans and reports
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SideEffect {
+    /// The file is created, or its contents replaced
+    Write {
+        path: PathBuf,
+    },
+    CreateDir {
+        path: PathBuf,
+    },
+    Remove {
+        path: PathBuf,
+    },
+    Link {
+        path: PathBuf,
+        target: PathBuf,
+    },
+    Chmod {
+        path: PathBuf,
+        mode: u32,
+    },
+    Chown {
+        path: PathBuf,
+        owner: Option<String>,
+        group: Option<String>,
+    },
+    /// The command line is run
+    Command {
+        command: String,
+    },
+    /// The packages are installed with this provider
+    Install {
+        provider: String,
+        packages: Vec<String>,
+    },
+}
+
+impl std::fmt::Display for SideEffect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SideEffect::Write { path } => write!(f, "write {}", path.display()),
+            SideEffect::CreateDir { path } => write!(f, "create directory {}", path.display()),
+            SideEffect::Remove { path } => write!(f, "remove {}", path.display()),
+            SideEffect::Link { path, target } => {
+                write!(f, "link {} to {}", path.display(), target.display())
+            }
+            SideEffect::Chmod { path, mode } => {
+                write!(f, "chmod {:o} {}", mode, path.display())
+            }
+            SideEffect::Chown { path, owner, group } => write!(
+                f,
+                "chown {}:{} {}",
+                owner.as_deref().unwrap_or_default(),
+                group.as_deref().unwrap_or_default(),
+                path.display()
+            ),
+            SideEffect::Command { command } => write!(f, "run `{}`", command),
+            SideEffect::Install { provider, packages } => {
+                write!(f, "install {} with {}", packages.join(", "), provider)
+            }
+        }
+    }
+}
 
 pub struct Outcome {
     pub side_effects: Vec<SideEffect>,
@@ -17,6 +89,30 @@ pub trait Atom: std::fmt::Display {
     // Apply new to old
     fn execute(&mut self) -> anyhow::Result<()>;
 
+    // Describe the change `execute` would make, as shown by `--diff`.
+    // Atoms that can't describe their change return None
+    fn diff(&self) -> Option<String> {
+        None
+    }
+
+    // Downloads what `execute` needs into comtrya's cache, without changing
+    // anything else, so it can run offline later
+    fn fetch(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    // Files whose contents this atom manages. Their checksums are
+    // recorded in the state file so drift can be detected
+    fn managed_files(&self) -> Vec<std::path::PathBuf> {
+        vec![]
+    }
+
+    // Called right before `execute` to capture what's needed to reverse it.
+    // Backups are written to `backup_dir`
+    fn prepare_undo(&self, _backup_dir: &std::path::Path) -> anyhow::Result<Option<Undo>> {
+        Ok(None)
+    }
+
     // These methods allow for finalizers to query the outcome of the Atom.
     // We'll provide default implementations to allow Atoms to opt in to
     // the queries that make sense for them
@@ -31,6 +127,22 @@ pub trait Atom: std::fmt::Display {
     fn status_code(&self) -> i32 {
         0
     }
+
+    // Facts set by this atom, stored under the `facts` context for the
+    // actions that run after it
+    fn facts(&self) -> BTreeMap<String, Value> {
+        BTreeMap::new()
+    }
+
+    // Atoms that spawn processes should give up after this long,
+    // unless they were given a timeout of their own
+    fn set_default_timeout(&mut self, _timeout: std::time::Duration) {}
+
+    // Whether the atom runs as root, or as another user, through sudo or the
+    // like. Such atoms are recorded in the audit log
+    fn privileged(&self) -> bool {
+        false
+    }
 }
 
 pub struct Echo(pub &'static str);
@@ -0,0 +1,77 @@
+use super::command::Exec;
+use super::{Atom, Outcome, SideEffect};
+use serde_json::Value;
+use std::path::Path;
+use std::time::Duration;
+
+/// A step planned by a plugin, which the plugin executes again with the step
+/// it planned on stdin. Steps the plugin marks `privileged` run it through sudo,
+/// or the like.
+pub struct PluginStep {
+    description: String,
+    exec: Exec,
+}
+
+impl PluginStep {
+    pub fn new(action: &str, plugin: &Path, request: Value) -> Self {
+        let description = request["step"]["description"]
+            .as_str()
+            .map(String::from)
+            .unwrap_or_else(|| format!("Running {action} plugin"));
+
+        PluginStep {
+            description,
+            exec: Exec {
+                command: plugin.display().to_string(),
+                arguments: vec![String::from("execute")],
+                privileged: request["step"]["privileged"].as_bool().unwrap_or(false),
+                stdin: Some(request.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for PluginStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.description)
+    }
+}
+
+impl Atom for PluginStep {
+    fn plan(&self) -> anyhow::Result<Outcome> {
+        // The plugin only plans steps that need to run
+        Ok(Outcome {
+            side_effects: vec![SideEffect::Command {
+                command: format!("{} execute", self.exec.command),
+            }],
+            should_run: true,
+        })
+    }
+
+    fn execute(&mut self) -> anyhow::Result<()> {
+        self.exec
+            .execute()
+            .map_err(|err| anyhow::anyhow!("{} {}", err, self.exec.error_message().trim()))
+    }
+
+    fn output_string(&self) -> String {
+        self.exec.output_string()
+    }
+
+    fn error_message(&self) -> String {
+        self.exec.error_message()
+    }
+
+    fn status_code(&self) -> i32 {
+        self.exec.status_code()
+    }
+
+    fn set_default_timeout(&mut self, timeout: Duration) {
+        self.exec.set_default_timeout(timeout);
+    }
+
+    fn privileged(&self) -> bool {
+        self.exec.privileged()
+    }
+}
@@ -0,0 +1,194 @@
+use super::{Atom, Outcome};
+use crate::contexts::{to_rhai, Contexts};
+use crate::values::Value;
+use anyhow::anyhow;
+use rhai::{Array, Dynamic, EvalAltResult, Map};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Runs a Rhai script with the contexts in scope
+pub struct Rhai {
+    pub script: String,
+    pub contexts: Contexts,
+    /// Relative paths given to `read_file` and `run_command` are resolved from here
+    pub dir: PathBuf,
+    output: String,
+    facts: BTreeMap<String, Value>,
+}
+
+impl Rhai {
+    pub fn new(script: String, contexts: Contexts, dir: PathBuf) -> Self {
+        Rhai {
+            script,
+            contexts,
+            dir,
+            output: String::new(),
+            facts: BTreeMap::new(),
+        }
+    }
+}
+
+fn run_command(dir: &Path, command: &str, args: Array) -> Result<Map, Box<EvalAltResult>> {
+    let args: Vec<String> = args.into_iter().map(|arg| arg.to_string()).collect();
+
+    let output = std::process::Command::new(command)
+        .args(&args)
+        .current_dir(dir)
+        .output()
+        .map_err(|err| format!("Failed to run {command}: {err}"))?;
+
+    let mut result = Map::new();
+    result.insert(
+        "stdout".into(),
+        String::from_utf8_lossy(&output.stdout).trim_end().into(),
+    );
+    result.insert(
+        "stderr".into(),
+        String::from_utf8_lossy(&output.stderr).trim_end().into(),
+    );
+    result.insert(
+        "exit_code".into(),
+        i64::from(output.status.code().unwrap_or(-1)).into(),
+    );
+
+    Ok(result)
+}
+
+// Contexts don't have booleans, so they're stored as strings
+fn to_value(value: Dynamic) -> Result<Value, Box<EvalAltResult>> {
+    if let Ok(value) = value.as_bool() {
+        return Ok(Value::from(value.to_string()));
+    }
+
+    rhai::serde::from_dynamic(&value)
+        .map_err(|_| "Facts can be strings, numbers, booleans or lists of them".into())
+}
+
+impl std::fmt::Display for Rhai {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Rhai script in {}", self.dir.display())
+    }
+}
+
+impl Atom for Rhai {
+    fn plan(&self) -> anyhow::Result<Outcome> {
+        // Scripts can do anything, so they always run
+        Ok(Outcome {
+            side_effects: vec![],
+            should_run: true,
+        })
+    }
+
+    fn execute(&mut self) -> anyhow::Result<()> {
+        let output = Rc::new(RefCell::new(String::new()));
+        let facts = Rc::new(RefCell::new(BTreeMap::new()));
+
+        let mut engine = crate::rhai_functions::engine();
+
+        let printed = output.clone();
+        engine.on_print(move |text| {
+            printed.borrow_mut().push_str(text);
+            printed.borrow_mut().push('\n');
+        });
+
+        let dir = self.dir.clone();
+        engine.register_fn(
+            "read_file",
+            move |path: &str| -> Result<String, Box<EvalAltResult>> {
+                std::fs::read_to_string(dir.join(path))
+                    .map_err(|err| format!("Failed to read {path}: {err}").into())
+            },
+        );
+
+        let dir = self.dir.clone();
+        engine.register_fn("run_command", move |command: &str| {
+            run_command(&dir, command, Array::new())
+        });
+
+        let dir = self.dir.clone();
+        engine.register_fn("run_command", move |command: &str, args: Array| {
+            run_command(&dir, command, args)
+        });
+
+        let set = facts.clone();
+        engine.register_fn(
+            "set_fact",
+            move |name: &str, value: Dynamic| -> Result<(), Box<EvalAltResult>> {
+                set.borrow_mut().insert(name.to_string(), to_value(value)?);
+                Ok(())
+            },
+        );
+
+        let result = engine.run_with_scope(&mut to_rhai(&self.contexts), &self.script);
+
+        self.output = output.take();
+        self.facts = facts.take();
+
+        result.map_err(|err| anyhow!("Script failed: {}", err))
+    }
+
+    fn output_string(&self) -> String {
+        self.output.clone()
+    }
+
+    fn facts(&self) -> BTreeMap<String, Value> {
+        self.facts.clone()
+    }
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_can_read_files_run_commands_and_set_facts() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("version"), "1.2.3\n").unwrap();
+
+        let mut contexts = Contexts::new();
+        contexts.insert(
+            String::from("user"),
+            [(String::from("username"), Value::from("me"))].into(),
+        );
+
+        let mut rhai = Rhai::new(
+            String::from(
+                r#"
+                let version = read_file("version");
+                version.trim();
+                let echo = run_command("echo", ["hello", user.username]);
+                print(echo.stdout);
+                set_fact("version", version);
+                set_fact("greeted", echo.exit_code == 0);
+                "#,
+            ),
+            contexts,
+            dir.path().to_path_buf(),
+        );
+
+        assert_eq!(true, rhai.execute().is_ok());
+        assert_eq!("hello me\n", rhai.output_string());
+        assert_eq!(
+            BTreeMap::from([
+                (String::from("greeted"), Value::from("true")),
+                (String::from("version"), Value::from("1.2.3")),
+            ]),
+            rhai.facts()
+        );
+    }
+
+    #[test]
+    fn it_fails_with_the_script_error() {
+        let mut rhai = Rhai::new(
+            String::from(r#"throw "nope""#),
+            Contexts::new(),
+            PathBuf::from("."),
+        );
+
+        assert_eq!(true, rhai.execute().is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::atoms::SideEffect;
+use crate::report::Status;
+use crate::state::now;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Where every privileged command and file modification of a run is written down,
+/// one JSON object per line. Entries are only ever appended, never rewritten.
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Mutex<File>,
+    path: PathBuf,
+    run_id: Option<String>,
+}
+
+/// A step that ran, by whom, and the manifest and action it came from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+
+    /// Who ran comtrya, before any elevation
+    pub user: String,
+    pub hostname: String,
+
+    /// The run, as known to `comtrya rollback`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+
+    pub manifest: String,
+
+    /// The directory the manifest was loaded from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest_dir: Option<PathBuf>,
+
+    pub action: String,
+    pub step: String,
+    pub privileged: bool,
+
+    #[serde(default)]
+    pub side_effects: Vec<SideEffect>,
+
+    pub status: Status,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(manifest: &str, action: &str, step: &str, privileged: bool) -> Self {
+        AuditEntry {
+            timestamp: now(),
+            user: whoami::username(),
+            hostname: gethostname::gethostname().to_string_lossy().to_string(),
+            run_id: None,
+            manifest: manifest.to_string(),
+            manifest_dir: None,
+            action: action.to_string(),
+            step: step.to_string(),
+            privileged,
+            side_effects: vec![],
+            status: Status::Applied,
+            error: None,
+        }
+    }
+
+    /// Privileged steps are audited, as are steps that change files. Commands
+    /// run as yourself are left out.
+    pub fn is_audited(privileged: bool, side_effects: &[SideEffect]) -> bool {
+        privileged
+            || side_effects
+                .iter()
+                .any(|side_effect| !matches!(side_effect, SideEffect::Command { .. }))
+    }
+}
+
+impl AuditLog {
+    /// Opens the audit log for appending, failing before anything is changed
+    /// when it can't be written
+    pub fn open(path: &Path, run_id: Option<String>) -> Result<AuditLog> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Unable to open the audit log {}", path.display()))?;
+
+        Ok(AuditLog {
+            file: Mutex::new(file),
+            path: path.to_path_buf(),
+            run_id,
+        })
+    }
+
+    /// Appends the entry as a line of its own, synced to disk before returning
+    pub fn record(&self, mut entry: AuditEntry) -> Result<()> {
+        entry.run_id = entry.run_id.or_else(|| self.run_id.clone());
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        // Written at once, so lines of manifests applied in parallel don't mix
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())
+            .and_then(|_| file.sync_data())
+            .with_context(|| format!("Unable to write the audit log {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_appends_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit").join("comtrya.jsonl");
+
+        let log = AuditLog::open(&path, Some(String::from("run-1"))).unwrap();
+        log.record(AuditEntry {
+            side_effects: vec![SideEffect::Write {
+                path: PathBuf::from("/etc/hosts"),
+            }],
+            ..AuditEntry::new("hosts", "file.copy", "Copy hosts", false)
+        })
+        .unwrap();
+        drop(log);
+
+        let log = AuditLog::open(&path, None).unwrap();
+        log.record(AuditEntry {
+            status: Status::Failed,
+            error: Some(String::from("exit code 1")),
+            ..AuditEntry::new("packages", "package.install", "apt install git", true)
+        })
+        .unwrap();
+
+        let entries: Vec<AuditEntry> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(2, entries.len());
+        assert_eq!(Some(String::from("run-1")), entries[0].run_id);
+        assert_eq!("hosts", entries[0].manifest);
+        assert_eq!(whoami::username(), entries[0].user);
+        assert_eq!(None, entries[1].run_id);
+        assert_eq!(Status::Failed, entries[1].status);
+        assert!(entries[1].privileged);
+    }
+
+    #[test]
+    fn it_leaves_out_unprivileged_commands() {
+        let command = [SideEffect::Command {
+            command: String::from("echo hi"),
+        }];
+        let write = [SideEffect::Write {
+            path: PathBuf::from("a"),
+        }];
+
+        assert!(!AuditEntry::is_audited(false, &command));
+        assert!(AuditEntry::is_audited(true, &command));
+        assert!(AuditEntry::is_audited(false, &write));
+    }
+}
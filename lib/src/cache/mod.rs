@@ -0,0 +1,449 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+/// Everything comtrya fetches is kept under one directory, `~/.cache/comtrya`
+/// on Linux, split by what it is. Entries are marked when they're used, so
+/// garbage collection removes those that haven't been used the longest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Area {
+    /// Files of `file.download`, `binary.github` and others, by their SHA-256
+    Downloads,
+    /// API responses, like GitHub's release metadata
+    Api,
+    /// Manifests fetched from git repositories and URLs
+    Manifests,
+    /// Manifest bundles pulled from OCI registries
+    Oci,
+}
+
+impl Area {
+    pub const ALL: [Area; 4] = [Area::Downloads, Area::Api, Area::Manifests, Area::Oci];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Area::Downloads => "downloads",
+            Area::Api => "api",
+            Area::Manifests => "manifests",
+            Area::Oci => "oci",
+        }
+    }
+
+    pub fn dir(&self) -> PathBuf {
+        dir().join(self.name())
+    }
+
+    /// Downloads and API responses keep their bodies once under `blobs`, for
+    /// every URL whose `<key>.json` refers to them
+    fn is_content_addressed(&self) -> bool {
+        matches!(self, Area::Downloads | Area::Api)
+    }
+}
+
+/// Limits garbage collection keeps the cache within, nothing is removed
+/// without any
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CachePolicy {
+    /// Entries not used for this many days are removed
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+
+    /// The entries used the longest ago are removed until the cache is smaller
+    #[serde(default)]
+    pub max_size_mb: Option<u64>,
+}
+
+impl CachePolicy {
+    pub fn is_empty(&self) -> bool {
+        self.max_age_days.is_none() && self.max_size_mb.is_none()
+    }
+
+    fn max_age(&self) -> Option<Duration> {
+        self.max_age_days
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+    }
+
+    fn max_size(&self) -> Option<u64> {
+        self.max_size_mb.map(|mb| mb * 1024 * 1024)
+    }
+}
+
+/// What cleaning or garbage collection removed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Removed {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+static CACHE_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Keeps what's fetched in this directory rather than comtrya's cache, as a
+/// bundle to carry to machines without internet
+pub fn set_dir(dir: Option<PathBuf>) {
+    *CACHE_DIR.write().unwrap() = dir;
+}
+
+/// Whether the cache is a bundle of `--bundle`, which is never collected
+pub fn is_bundle() -> bool {
+    CACHE_DIR.read().unwrap().is_some()
+}
+
+pub fn dir() -> PathBuf {
+    CACHE_DIR.read().unwrap().clone().unwrap_or_else(|| {
+        dirs_next::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("comtrya")
+    })
+}
+
+/// Marks an entry as used now, for garbage collection to keep it longer
+pub(crate) fn touch(path: &Path) {
+    // Directories can't be opened as files everywhere, they're then dated by
+    // their last change instead
+    let _ = std::fs::File::open(path).and_then(|file| file.set_modified(SystemTime::now()));
+}
+
+/// The disk space every area takes
+pub fn sizes() -> Vec<(Area, u64)> {
+    Area::ALL
+        .iter()
+        .map(|area| (*area, size_of(&area.dir())))
+        .collect()
+}
+
+/// Removes everything cached in these areas
+pub fn clean(areas: &[Area]) -> anyhow::Result<Removed> {
+    let mut removed = Removed::default();
+
+    for area in areas {
+        for entry in entries(&area.dir())? {
+            removed.entries += 1;
+            removed.bytes += entry.bytes;
+            remove(&entry.paths)?;
+        }
+
+        if area.is_content_addressed() {
+            removed.bytes += size_of(&area.dir().join("blobs"));
+            remove(&[area.dir().join("blobs")])?;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Removes entries older than the policy allows, then those used the longest
+/// ago until the cache fits its size
+pub fn collect(policy: &CachePolicy) -> anyhow::Result<Removed> {
+    collect_in(&dir(), policy, SystemTime::now())
+}
+
+fn collect_in(root: &Path, policy: &CachePolicy, now: SystemTime) -> anyhow::Result<Removed> {
+    let mut removed = Removed::default();
+
+    if policy.is_empty() {
+        return Ok(removed);
+    }
+
+    let mut used = vec![];
+    let mut blobs = Blobs::default();
+
+    for area in Area::ALL {
+        let dir = root.join(area.name());
+
+        for entry in entries(&dir)? {
+            if area.is_content_addressed() {
+                blobs.refer(&dir, &entry);
+            }
+
+            used.push((area, dir.clone(), entry));
+        }
+    }
+
+    for area in Area::ALL.iter().filter(|area| area.is_content_addressed()) {
+        let orphans = blobs.orphans(&root.join(area.name()))?;
+        removed.entries += orphans.len();
+        removed.bytes += orphans.iter().map(|path| size_of(path)).sum::<u64>();
+        remove(&orphans)?;
+    }
+
+    used.sort_by_key(|(_, _, entry)| entry.used);
+
+    let mut size = used.iter().map(|(_, _, entry)| entry.bytes).sum::<u64>() + blobs.bytes();
+
+    for (area, dir, entry) in used {
+        let expired = policy
+            .max_age()
+            .is_some_and(|max_age| now.duration_since(entry.used).unwrap_or_default() > max_age);
+        let too_big = policy.max_size().is_some_and(|max_size| size > max_size);
+
+        if !expired && !too_big {
+            continue;
+        }
+
+        debug!("Removing {} from the cache", entry.paths[0].display());
+        remove(&entry.paths)?;
+
+        let mut bytes = entry.bytes;
+        if area.is_content_addressed() {
+            bytes += blobs.release(&dir, &entry)?;
+        }
+
+        size = size.saturating_sub(bytes);
+        removed.entries += 1;
+        removed.bytes += bytes;
+    }
+
+    Ok(removed)
+}
+
+/// One thing in the cache, removed as a whole: a checkout or a downloaded URL
+/// with its unfinished download
+#[derive(Debug)]
+struct Entry {
+    paths: Vec<PathBuf>,
+    bytes: u64,
+    used: SystemTime,
+    /// The body the entry of a downloaded URL refers to
+    blob: Option<String>,
+}
+
+/// Entries are the files and directories in an area, those of the same URL,
+/// `<key>.json`, `<key>.part` and `<key>.part.json`, together
+fn entries(dir: &Path) -> anyhow::Result<Vec<Entry>> {
+    let mut entries: BTreeMap<String, Entry> = BTreeMap::new();
+
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err.into()),
+    };
+
+    for file in read_dir {
+        let file = file?;
+        let name = file.file_name().to_string_lossy().to_string();
+
+        if name == "blobs" {
+            continue;
+        }
+
+        let path = file.path();
+        let used = file
+            .metadata()?
+            .modified()
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let key = name.split('.').next().unwrap_or_default().to_string();
+        let blob = name
+            .strip_suffix(".json")
+            .filter(|stem| !stem.ends_with(".part"))
+            .and_then(|_| std::fs::read(&path).ok())
+            .and_then(|json| serde_json::from_slice::<serde_json::Value>(&json).ok())
+            .and_then(|json| json["sha256"].as_str().map(String::from));
+
+        let entry = entries.entry(key).or_insert(Entry {
+            paths: vec![],
+            bytes: 0,
+            used,
+            blob: None,
+        });
+
+        entry.bytes += size_of(&path);
+        entry.used = entry.used.max(used);
+        entry.blob = entry.blob.take().or(blob);
+        entry.paths.push(path);
+    }
+
+    Ok(entries.into_values().collect())
+}
+
+/// How many entries refer to each body, which is removed with the last one
+#[derive(Default)]
+struct Blobs {
+    references: BTreeMap<PathBuf, (usize, u64)>,
+}
+
+impl Blobs {
+    fn refer(&mut self, dir: &Path, entry: &Entry) {
+        if let Some(blob) = &entry.blob {
+            let path = dir.join("blobs").join(blob);
+            let bytes = size_of(&path);
+
+            self.references.entry(path).or_insert((0, bytes)).0 += 1;
+        }
+    }
+
+    /// Bodies no entry refers to, like those of entries removed by hand
+    fn orphans(&self, dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let read_dir = match std::fs::read_dir(dir.join("blobs")) {
+            Ok(read_dir) => read_dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(read_dir
+            .filter_map(Result::ok)
+            .map(|file| file.path())
+            .filter(|path| !self.references.contains_key(path))
+            .collect())
+    }
+
+    fn bytes(&self) -> u64 {
+        self.references.values().map(|(_, bytes)| bytes).sum()
+    }
+
+    /// Drops the entry's reference, returning the size of the body if it was
+    /// the last one and the body was removed
+    fn release(&mut self, dir: &Path, entry: &Entry) -> anyhow::Result<u64> {
+        let Some(blob) = &entry.blob else {
+            return Ok(0);
+        };
+
+        let path = dir.join("blobs").join(blob);
+        let Some((references, bytes)) = self.references.get_mut(&path) else {
+            return Ok(0);
+        };
+
+        *references -= 1;
+        if *references > 0 {
+            return Ok(0);
+        }
+
+        let bytes = *bytes;
+        self.references.remove(&path);
+        remove(&[path])?;
+
+        Ok(bytes)
+    }
+}
+
+fn remove(paths: &[PathBuf]) -> anyhow::Result<()> {
+    for path in paths {
+        let result = if path.is_dir() {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        };
+
+        match result {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn size_of(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn write(path: &Path, contents: &str, used: SystemTime) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(used)
+            .unwrap();
+    }
+
+    #[test]
+    fn it_removes_entries_older_than_the_policy() {
+        let tmpdir = tempdir().unwrap();
+        let now = SystemTime::now();
+        let downloads = tmpdir.path().join("downloads");
+
+        write(&downloads.join("blobs/aaa"), "body", now);
+        write(
+            &downloads.join("old.json"),
+            r#"{"sha256":"aaa"}"#,
+            now - DAY * 40,
+        );
+        write(&downloads.join("new.json"), r#"{"sha256":"aaa"}"#, now);
+        write(&downloads.join("gone.part"), "bo", now - DAY * 40);
+        write(&downloads.join("blobs/bbb"), "other", now);
+        write(
+            &downloads.join("orphan.json"),
+            r#"{"sha256":"bbb"}"#,
+            now - DAY * 40,
+        );
+
+        let policy = CachePolicy {
+            max_age_days: Some(30),
+            max_size_mb: None,
+        };
+        let removed = collect_in(tmpdir.path(), &policy, now).unwrap();
+
+        assert_eq!(3, removed.entries);
+        assert!(!downloads.join("old.json").exists());
+        assert!(!downloads.join("gone.part").exists());
+        assert!(!downloads.join("blobs/bbb").exists());
+        // Still referred to by an entry that was used lately
+        assert!(downloads.join("blobs/aaa").exists());
+        assert!(downloads.join("new.json").exists());
+    }
+
+    #[test]
+    fn it_removes_the_least_recently_used_until_it_fits() {
+        let tmpdir = tempdir().unwrap();
+        let now = SystemTime::now();
+        let manifests = tmpdir.path().join("manifests");
+        let megabyte = "x".repeat(1024 * 1024);
+
+        write(&manifests.join("first/manifest.yaml"), &megabyte, now);
+        write(&manifests.join("second/manifest.yaml"), &megabyte, now);
+        write(&manifests.join("third/manifest.yaml"), &megabyte, now);
+        for (name, used) in [("first", DAY * 3), ("second", DAY), ("third", DAY * 2)] {
+            std::fs::File::open(manifests.join(name))
+                .unwrap()
+                .set_modified(now - used)
+                .unwrap();
+        }
+
+        let policy = CachePolicy {
+            max_age_days: None,
+            max_size_mb: Some(1),
+        };
+        let removed = collect_in(tmpdir.path(), &policy, now).unwrap();
+
+        assert_eq!(2, removed.entries);
+        assert_eq!(2 * 1024 * 1024, removed.bytes);
+        assert!(manifests.join("second").exists());
+        assert!(!manifests.join("first").exists());
+        assert!(!manifests.join("third").exists());
+    }
+
+    #[test]
+    fn it_keeps_everything_without_limits() {
+        let tmpdir = tempdir().unwrap();
+        let now = SystemTime::now();
+
+        write(
+            &tmpdir.path().join("manifests/old/manifest.yaml"),
+            "",
+            now - DAY * 365,
+        );
+
+        let removed = collect_in(tmpdir.path(), &CachePolicy::default(), now).unwrap();
+
+        assert_eq!(Removed::default(), removed);
+    }
+}
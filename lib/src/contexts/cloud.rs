@@ -0,0 +1,305 @@
+use crate::contexts::{Context, ContextProvider};
+use anyhow::{anyhow, Result};
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::path::Path;
+use std::time::Duration;
+use tracing::warn;
+
+/// Where Linux describes the hardware, or what the hypervisor pretends it is
+const DMI_DIR: &str = "/sys/class/dmi/id";
+
+/// Asset tag of every Azure virtual machine
+const AZURE_ASSET_TAG: &str = "7783-7084-3265-9085-8269-3286-77";
+
+/// The cloud this machine runs in, and its instance, from the metadata service of
+/// the cloud. The metadata service is only asked once the hardware says it's a
+/// cloud VM, so other machines don't wait for it. Values are empty elsewhere.
+pub struct CloudContextProvider {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Provider {
+    Aws,
+    Gcp,
+    Azure,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Instance {
+    region: String,
+    zone: String,
+    instance_type: String,
+    instance_id: String,
+    /// `key=value`, network tags on GCP
+    tags: Vec<String>,
+}
+
+impl Provider {
+    fn name(&self) -> &'static str {
+        match self {
+            Provider::Aws => "aws",
+            Provider::Gcp => "gcp",
+            Provider::Azure => "azure",
+        }
+    }
+
+    fn detect(dmi_dir: &Path) -> Option<Provider> {
+        let read = |name: &str| {
+            std::fs::read_to_string(dmi_dir.join(name))
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default()
+        };
+
+        if read("sys_vendor") == "Amazon EC2" || read("bios_version").contains("amazon") {
+            Some(Provider::Aws)
+        } else if read("sys_vendor") == "Google" || read("product_name") == "Google Compute Engine"
+        {
+            Some(Provider::Gcp)
+        } else if read("chassis_asset_tag") == AZURE_ASSET_TAG {
+            Some(Provider::Azure)
+        } else {
+            None
+        }
+    }
+
+    fn instance(&self) -> Result<Instance> {
+        // Link-local, so never through a proxy, and quick to answer
+        let client = Client::builder()
+            .no_proxy()
+            .timeout(Duration::from_secs(2))
+            .build()?;
+
+        match self {
+            Provider::Aws => {
+                let base = "http://169.254.169.254/latest";
+
+                // IMDSv2, instances that still allow v1 answer without a token too
+                let token = client
+                    .put(format!("{base}/api/token"))
+                    .header("X-aws-ec2-metadata-token-ttl-seconds", "60")
+                    .send()
+                    .and_then(|response| response.error_for_status())
+                    .and_then(|response| response.text())
+                    .ok();
+
+                let get = |path: &str| -> Result<String> {
+                    let mut request = client.get(format!("{base}/{path}"));
+                    if let Some(token) = token.as_ref() {
+                        request = request.header("X-aws-ec2-metadata-token", token);
+                    }
+                    Ok(request.send()?.error_for_status()?.text()?)
+                };
+
+                // Only there when tags are allowed in the instance metadata
+                let tags = get("meta-data/tags/instance")
+                    .map(|keys| {
+                        keys.lines()
+                            .filter_map(|key| {
+                                let value = get(&format!("meta-data/tags/instance/{key}")).ok()?;
+                                Some(format!("{key}={value}"))
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                aws(&get("dynamic/instance-identity/document")?, tags)
+            }
+            Provider::Gcp => gcp(&client
+                .get("http://metadata.google.internal/computeMetadata/v1/instance/?recursive=true")
+                .header("Metadata-Flavor", "Google")
+                .send()?
+                .error_for_status()?
+                .text()?),
+            Provider::Azure => azure(
+                &client
+                    .get("http://169.254.169.254/metadata/instance?api-version=2021-02-01")
+                    .header("Metadata", "true")
+                    .send()?
+                    .error_for_status()?
+                    .text()?,
+            ),
+        }
+    }
+}
+
+fn string(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// The last part of GCP resource paths, `projects/1/zones/europe-west1-b`
+fn last(value: &Value) -> String {
+    string(value)
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn aws(document: &str, tags: Vec<String>) -> Result<Instance> {
+    let document: Value = serde_json::from_str(document)?;
+
+    Ok(Instance {
+        region: string(&document["region"]),
+        zone: string(&document["availabilityZone"]),
+        instance_type: string(&document["instanceType"]),
+        instance_id: string(&document["instanceId"]),
+        tags,
+    })
+}
+
+fn gcp(instance: &str) -> Result<Instance> {
+    let instance: Value = serde_json::from_str(instance)?;
+    let zone = last(&instance["zone"]);
+
+    Ok(Instance {
+        region: zone
+            .rsplit_once('-')
+            .map(|(region, _)| region.to_string())
+            .unwrap_or_default(),
+        zone,
+        instance_type: last(&instance["machineType"]),
+        instance_id: string(&instance["id"]),
+        tags: instance["tags"]
+            .as_array()
+            .map(|tags| tags.iter().map(string).collect())
+            .unwrap_or_default(),
+    })
+}
+
+fn azure(instance: &str) -> Result<Instance> {
+    let instance: Value = serde_json::from_str(instance)?;
+    let compute = instance
+        .get("compute")
+        .ok_or_else(|| anyhow!("No compute in the instance metadata"))?;
+
+    Ok(Instance {
+        region: string(&compute["location"]),
+        zone: string(&compute["zone"]),
+        instance_type: string(&compute["vmSize"]),
+        instance_id: string(&compute["vmId"]),
+        tags: compute["tagsList"]
+            .as_array()
+            .map(|tags| {
+                tags.iter()
+                    .map(|tag| format!("{}={}", string(&tag["name"]), string(&tag["value"])))
+                    .collect()
+            })
+            .unwrap_or_default(),
+    })
+}
+
+impl ContextProvider for CloudContextProvider {
+    fn get_prefix(&self) -> String {
+        String::from("cloud")
+    }
+
+    fn get_contexts(&self) -> Result<Vec<Context>> {
+        let provider = Provider::detect(Path::new(DMI_DIR));
+
+        let instance = provider
+            .and_then(|provider| {
+                provider
+                    .instance()
+                    .map_err(|err| {
+                        warn!(
+                            "Unable to read the instance metadata of {}: {}",
+                            provider.name(),
+                            err
+                        )
+                    })
+                    .ok()
+            })
+            .unwrap_or_default();
+
+        Ok(vec![
+            Context::KeyValueContext(
+                String::from("provider"),
+                provider
+                    .map(|provider| provider.name())
+                    .unwrap_or_default()
+                    .to_string()
+                    .into(),
+            ),
+            Context::KeyValueContext(String::from("region"), instance.region.into()),
+            Context::KeyValueContext(String::from("zone"), instance.zone.into()),
+            Context::KeyValueContext(String::from("instance_type"), instance.instance_type.into()),
+            Context::KeyValueContext(String::from("instance_id"), instance.instance_id.into()),
+            Context::ListContext(
+                String::from("tags"),
+                instance.tags.into_iter().map(|tag| tag.into()).collect(),
+            ),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_detects_the_cloud_from_the_hardware() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(None, Provider::detect(dir.path()));
+
+        for (file, contents, provider) in [
+            ("sys_vendor", "Amazon EC2\n", Provider::Aws),
+            ("sys_vendor", "Google\n", Provider::Gcp),
+            (
+                "chassis_asset_tag",
+                "7783-7084-3265-9085-8269-3286-77\n",
+                Provider::Azure,
+            ),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(dir.path().join(file), contents).unwrap();
+            assert_eq!(Some(provider), Provider::detect(dir.path()));
+        }
+    }
+
+    #[test]
+    fn it_reads_instance_metadata() {
+        assert_eq!(
+            Instance {
+                region: String::from("eu-west-1"),
+                zone: String::from("eu-west-1a"),
+                instance_type: String::from("t3.micro"),
+                instance_id: String::from("i-0123"),
+                tags: vec![String::from("env=prod")],
+            },
+            aws(
+                r#"{"region": "eu-west-1", "availabilityZone": "eu-west-1a", "instanceType": "t3.micro", "instanceId": "i-0123"}"#,
+                vec![String::from("env=prod")]
+            )
+            .unwrap()
+        );
+
+        assert_eq!(
+            Instance {
+                region: String::from("europe-west1"),
+                zone: String::from("europe-west1-b"),
+                instance_type: String::from("e2-medium"),
+                instance_id: String::from("4567"),
+                tags: vec![String::from("http-server")],
+            },
+            gcp(r#"{"zone": "projects/1/zones/europe-west1-b", "machineType": "projects/1/machineTypes/e2-medium", "id": 4567, "tags": ["http-server"]}"#)
+                .unwrap()
+        );
+
+        assert_eq!(
+            Instance {
+                region: String::from("westeurope"),
+                zone: String::from("1"),
+                instance_type: String::from("Standard_B2s"),
+                instance_id: String::from("abc"),
+                tags: vec![String::from("env=prod")],
+            },
+            azure(r#"{"compute": {"location": "westeurope", "zone": "1", "vmSize": "Standard_B2s", "vmId": "abc", "tagsList": [{"name": "env", "value": "prod"}]}}"#)
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use crate::contexts::{Context, ContextProvider};
+use anyhow::Result;
+
+/// The desktop environment and display server of the session comtrya runs in,
+/// so from cron or over SSH, it's `none` and `headless`
+pub struct DesktopContextProvider {}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Desktop {
+    /// gnome, kde, xfce, sway, hyprland, ... in lowercase, none without a display,
+    /// or unknown for window managers that don't say
+    environment: String,
+    /// wayland, x11, or headless
+    display: String,
+}
+
+impl Desktop {
+    fn detect(var: impl Fn(&str) -> Option<String>) -> Desktop {
+        if cfg!(target_os = "macos") {
+            return Desktop {
+                environment: String::from("aqua"),
+                display: String::from("quartz"),
+            };
+        }
+
+        if cfg!(target_family = "windows") {
+            return Desktop {
+                environment: String::from("windows"),
+                display: String::from("windows"),
+            };
+        }
+
+        let var = |name: &str| var(name).filter(|value| !value.is_empty());
+
+        // Like `ubuntu:GNOME`, the desktop it's based on comes last
+        let environment = var("XDG_CURRENT_DESKTOP")
+            .and_then(|desktops| desktops.rsplit(':').next().map(str::to_string))
+            .or_else(|| var("XDG_SESSION_DESKTOP"))
+            .or_else(|| var("DESKTOP_SESSION"))
+            .map(|desktop| match desktop.to_lowercase().as_str() {
+                "plasma" | "kde-plasma" | "plasmawayland" => String::from("kde"),
+                "x-cinnamon" => String::from("cinnamon"),
+                desktop => desktop.to_string(),
+            });
+
+        let display = match var("XDG_SESSION_TYPE").as_deref() {
+            Some("wayland") => "wayland",
+            Some("x11") => "x11",
+            _ if var("WAYLAND_DISPLAY").is_some() => "wayland",
+            _ if var("DISPLAY").is_some() => "x11",
+            _ => "headless",
+        };
+
+        Desktop {
+            environment: match (environment, display) {
+                (Some(environment), _) => environment,
+                (None, "headless") => String::from("none"),
+                // A window manager without a desktop environment
+                (None, _) => String::from("unknown"),
+            },
+            display: display.to_string(),
+        }
+    }
+}
+
+impl ContextProvider for DesktopContextProvider {
+    fn get_prefix(&self) -> String {
+        String::from("desktop")
+    }
+
+    fn get_contexts(&self) -> Result<Vec<Context>> {
+        let desktop = Desktop::detect(|name| std::env::var(name).ok());
+
+        Ok(vec![
+            Context::KeyValueContext(String::from("environment"), desktop.environment.into()),
+            Context::KeyValueContext(String::from("display"), desktop.display.into()),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn desktop(variables: &[(&str, &str)]) -> Desktop {
+        Desktop::detect(|name| {
+            variables
+                .iter()
+                .find(|(variable, _)| *variable == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn it_detects_the_desktop() {
+        assert_eq!(
+            Desktop {
+                environment: String::from("gnome"),
+                display: String::from("wayland"),
+            },
+            desktop(&[
+                ("XDG_CURRENT_DESKTOP", "ubuntu:GNOME"),
+                ("XDG_SESSION_TYPE", "wayland"),
+            ])
+        );
+
+        assert_eq!(
+            Desktop {
+                environment: String::from("kde"),
+                display: String::from("x11"),
+            },
+            desktop(&[("DESKTOP_SESSION", "plasma"), ("DISPLAY", ":0")])
+        );
+
+        assert_eq!(
+            Desktop {
+                environment: String::from("sway"),
+                display: String::from("wayland"),
+            },
+            desktop(&[
+                ("XDG_CURRENT_DESKTOP", "sway"),
+                ("WAYLAND_DISPLAY", "wayland-1")
+            ])
+        );
+
+        assert_eq!(
+            Desktop {
+                environment: String::from("none"),
+                display: String::from("headless"),
+            },
+            desktop(&[("XDG_SESSION_TYPE", "tty")])
+        );
+    }
+}
@@ -1,11 +1,15 @@
 use anyhow::Result;
 
 use super::ContextProvider;
-use crate::contexts::Context;
+use crate::{config::Config, contexts::Context};
 
-pub struct EnvContextProvider;
+/// Environment variables, only those listed in `env` of `Comtrya.yaml` when
+/// it's set. Listed variables that aren't set are empty.
+pub struct EnvContextProvider<'a> {
+    pub config: &'a Config,
+}
 
-impl ContextProvider for EnvContextProvider {
+impl ContextProvider for EnvContextProvider<'_> {
     fn get_prefix(&self) -> String {
         String::from("env")
     }
@@ -13,8 +17,18 @@ impl ContextProvider for EnvContextProvider {
     fn get_contexts(&self) -> Result<Vec<super::Context>> {
         let mut contexts = vec![];
 
-        for (key, value) in std::env::vars() {
-            contexts.push(Context::KeyValueContext(key, value.into()));
+        match self.config.env.as_ref() {
+            Some(names) => {
+                for name in names {
+                    let value = std::env::var(name).unwrap_or_default();
+                    contexts.push(Context::KeyValueContext(name.clone(), value.into()));
+                }
+            }
+            None => {
+                for (key, value) in std::env::vars() {
+                    contexts.push(Context::KeyValueContext(key, value.into()));
+                }
+            }
         }
 
         Ok(contexts)
@@ -0,0 +1,69 @@
+use anyhow::Result;
+use std::process::{Command, Stdio};
+use tracing::warn;
+
+use crate::{
+    config::{Config, Fact},
+    contexts::{Context, ContextProvider},
+};
+
+/// Facts defined in `Comtrya.yaml`, gathered from commands and files at startup
+pub struct FactsContextProvider<'a> {
+    pub config: &'a Config,
+}
+
+fn run(command: &str) -> Result<String> {
+    let mut process = if cfg!(target_family = "windows") {
+        let mut process = Command::new("cmd");
+        process.arg("/C");
+        process
+    } else {
+        let mut process = Command::new("sh");
+        process.arg("-c");
+        process
+    };
+
+    let output = process
+        .arg(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "'{}' failed with exit code {}",
+            command,
+            output.status.code().unwrap_or(-1)
+        ));
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+impl<'a> ContextProvider for FactsContextProvider<'a> {
+    fn get_prefix(&self) -> String {
+        String::from("facts")
+    }
+
+    fn get_contexts(&self) -> Result<Vec<super::Context>> {
+        let mut contexts = vec![];
+
+        for (key, fact) in self.config.facts.iter() {
+            let value = match fact {
+                Fact::Command(command) => run(command),
+                Fact::File(path) => std::fs::read_to_string(path).map_err(anyhow::Error::from),
+            };
+
+            // A fact that can't be gathered is left out, rather than failing every fact
+            match value {
+                Ok(value) => contexts.push(Context::KeyValueContext(
+                    key.to_owned(),
+                    value.trim().to_string().into(),
+                )),
+                Err(err) => warn!("Unable to gather fact '{}': {}", key, err),
+            }
+        }
+
+        Ok(contexts)
+    }
+}
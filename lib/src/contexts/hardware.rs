@@ -0,0 +1,201 @@
+use crate::contexts::{Context, ContextProvider};
+use anyhow::Result;
+use std::path::Path;
+use std::process::Command;
+
+/// The CPU, memory and graphics of this machine. Values that can't be read
+/// are empty, or 0 for numbers.
+pub struct HardwareContextProvider {}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Hardware {
+    /// musl or glibc, Linux only
+    libc: String,
+    memory_mb: i64,
+    /// nvidia, amd, intel or apple, discrete cards first
+    gpu_vendors: Vec<String>,
+}
+
+impl Hardware {
+    /// Reads everything below `root`, which is `/` outside of tests
+    fn detect(root: &Path) -> Hardware {
+        Hardware {
+            libc: libc(root),
+            memory_mb: memory_mb(root),
+            gpu_vendors: gpu_vendors(root),
+        }
+    }
+}
+
+/// The names Go, Debian and Docker use for the architecture, which is
+/// what most release downloads are named after
+fn arch_alias(arch: &str) -> &str {
+    match arch {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        arch => arch,
+    }
+}
+
+fn libc(root: &Path) -> String {
+    if !cfg!(target_os = "linux") {
+        return String::new();
+    }
+
+    let loaders = ["lib", "usr/lib", "lib64"]
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(root.join(dir)).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+
+    if loaders.iter().any(|name| name.starts_with("ld-musl-")) {
+        String::from("musl")
+    } else if loaders.iter().any(|name| name.starts_with("ld-linux")) {
+        String::from("glibc")
+    } else {
+        String::new()
+    }
+}
+
+fn memory_mb(root: &Path) -> i64 {
+    if cfg!(target_os = "macos") {
+        return sysctl("hw.memsize")
+            .parse::<i64>()
+            .map(|bytes| bytes / 1024 / 1024)
+            .unwrap_or_default();
+    }
+
+    std::fs::read_to_string(root.join("proc/meminfo"))
+        .unwrap_or_default()
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|total| {
+            total
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<i64>()
+                .ok()
+        })
+        .map(|kilobytes| kilobytes / 1024)
+        .unwrap_or_default()
+}
+
+fn gpu_vendors(root: &Path) -> Vec<String> {
+    if cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+        return vec![String::from("apple")];
+    }
+
+    let found = std::fs::read_dir(root.join("sys/class/drm"))
+        .map(|cards| {
+            cards
+                .filter_map(|card| card.ok())
+                .filter_map(|card| std::fs::read_to_string(card.path().join("device/vendor")).ok())
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+
+    // The integrated card is rarely the one that matters, so it comes last
+    [("0x10de", "nvidia"), ("0x1002", "amd"), ("0x8086", "intel")]
+        .iter()
+        .filter(|(id, _)| found.iter().any(|vendor| vendor.trim() == *id))
+        .map(|(_, vendor)| vendor.to_string())
+        .collect()
+}
+
+fn sysctl(name: &str) -> String {
+    Command::new("sysctl")
+        .args(["-n", name])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default()
+}
+
+impl ContextProvider for HardwareContextProvider {
+    fn get_prefix(&self) -> String {
+        String::from("hardware")
+    }
+
+    fn get_contexts(&self) -> Result<Vec<Context>> {
+        let hardware = Hardware::detect(Path::new("/"));
+        let arch = std::env::consts::ARCH;
+        let cpu_cores = std::thread::available_parallelism()
+            .map(|cores| cores.get() as i64)
+            .unwrap_or(1);
+
+        Ok(vec![
+            Context::KeyValueContext(String::from("arch"), arch.into()),
+            Context::KeyValueContext(String::from("arch_alias"), arch_alias(arch).into()),
+            Context::KeyValueContext(String::from("libc"), hardware.libc.into()),
+            Context::KeyValueContext(String::from("cpu_cores"), cpu_cores.into()),
+            Context::KeyValueContext(String::from("memory_mb"), hardware.memory_mb.into()),
+            Context::KeyValueContext(
+                String::from("gpu_vendor"),
+                hardware
+                    .gpu_vendors
+                    .first()
+                    .cloned()
+                    .unwrap_or_default()
+                    .into(),
+            ),
+            Context::ListContext(
+                String::from("gpu_vendors"),
+                hardware
+                    .gpu_vendors
+                    .into_iter()
+                    .map(|vendor| vendor.into())
+                    .collect(),
+            ),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_aliases_architectures() {
+        assert_eq!("amd64", arch_alias("x86_64"));
+        assert_eq!("arm64", arch_alias("aarch64"));
+        assert_eq!("riscv64", arch_alias("riscv64"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn it_reads_the_hardware() {
+        let root = tempfile::tempdir().unwrap();
+
+        for (path, contents) in [
+            ("lib/ld-musl-aarch64.so.1", ""),
+            (
+                "proc/meminfo",
+                "MemTotal:       16318412 kB\nMemFree:         1010000 kB\n",
+            ),
+            ("sys/class/drm/card0/device/vendor", "0x8086\n"),
+            ("sys/class/drm/card1/device/vendor", "0x10de\n"),
+        ] {
+            let path = root.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        assert_eq!(
+            Hardware {
+                libc: String::from("musl"),
+                memory_mb: 15935,
+                gpu_vendors: vec![String::from("nvidia"), String::from("intel")],
+            },
+            Hardware::detect(root.path())
+        );
+
+        assert_eq!(
+            Hardware::default(),
+            Hardware::detect(tempfile::tempdir().unwrap().path())
+        );
+    }
+}
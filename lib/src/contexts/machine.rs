@@ -0,0 +1,228 @@
+use crate::contexts::{Context, ContextProvider};
+use anyhow::Result;
+use std::path::Path;
+use std::process::Command;
+
+/// Whether this machine is a container, a virtual machine or real hardware,
+/// and what kind of hardware. Read from files Linux exposes, and `hw.model`
+/// on macOS; unknown values are empty.
+pub struct MachineContextProvider {}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Machine {
+    /// docker, podman, lxc, wsl, kvm, qemu, vmware, virtualbox, hyperv, xen or none
+    virtualization: String,
+    /// laptop, desktop, server, vm or container
+    chassis: String,
+}
+
+impl Machine {
+    /// Reads everything below `root`, which is `/` outside of tests
+    fn detect(root: &Path) -> Machine {
+        let read = |path: &str| {
+            std::fs::read_to_string(root.join(path))
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default()
+        };
+
+        let virtualization = container(root, &read)
+            .or_else(|| hypervisor(&read))
+            .unwrap_or("none");
+
+        let chassis = match virtualization {
+            "docker" | "podman" | "lxc" => "container",
+            "none" | "wsl" => chassis(&read("sys/class/dmi/id/chassis_type")),
+            _ => "vm",
+        };
+
+        Machine {
+            virtualization: virtualization.to_string(),
+            chassis: chassis.to_string(),
+        }
+    }
+}
+
+fn container(root: &Path, read: &dyn Fn(&str) -> String) -> Option<&'static str> {
+    let init_environ = read("proc/1/environ");
+    let container = init_environ
+        .split('\0')
+        .find_map(|variable| variable.strip_prefix("container="));
+
+    if root.join(".dockerenv").exists() {
+        Some("docker")
+    } else if root.join("run/.containerenv").exists() || container == Some("podman") {
+        Some("podman")
+    } else if matches!(container, Some("lxc") | Some("lxc-libvirt")) {
+        Some("lxc")
+    } else if read("proc/sys/kernel/osrelease")
+        .to_lowercase()
+        .contains("microsoft")
+    {
+        Some("wsl")
+    } else {
+        None
+    }
+}
+
+fn hypervisor(read: &dyn Fn(&str) -> String) -> Option<&'static str> {
+    let vendor = format!(
+        "{} {} {}",
+        read("sys/class/dmi/id/sys_vendor"),
+        read("sys/class/dmi/id/product_name"),
+        read("sys/class/dmi/id/bios_vendor")
+    );
+
+    if vendor.contains("VMware") {
+        Some("vmware")
+    } else if vendor.contains("VirtualBox") || vendor.contains("innotek") {
+        Some("virtualbox")
+    } else if vendor.contains("Microsoft Corporation") && vendor.contains("Virtual Machine") {
+        Some("hyperv")
+    } else if vendor.contains("Xen") || !read("proc/xen/capabilities").is_empty() {
+        Some("xen")
+    } else if vendor.contains("KVM") || vendor.contains("Amazon EC2") || vendor.contains("Google") {
+        Some("kvm")
+    } else if vendor.contains("QEMU") {
+        Some("qemu")
+    } else {
+        None
+    }
+}
+
+/// SMBIOS chassis types, https://www.dmtf.org/standards/smbios
+fn chassis(chassis_type: &str) -> &'static str {
+    match chassis_type.parse::<u8>() {
+        Ok(8 | 9 | 10 | 11 | 14 | 30 | 31 | 32) => "laptop",
+        Ok(3..=7 | 13 | 15 | 16 | 35 | 36) => "desktop",
+        Ok(17 | 23 | 28 | 29) => "server",
+        _ if cfg!(target_os = "macos") => mac_chassis(),
+        _ => "",
+    }
+}
+
+fn mac_chassis() -> &'static str {
+    let model = Command::new("sysctl")
+        .args(["-n", "hw.model"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default();
+
+    if model.starts_with("MacBook") {
+        "laptop"
+    } else if model.starts_with("VirtualMac") {
+        "vm"
+    } else if model.is_empty() {
+        ""
+    } else {
+        "desktop"
+    }
+}
+
+impl ContextProvider for MachineContextProvider {
+    fn get_prefix(&self) -> String {
+        String::from("machine")
+    }
+
+    fn get_contexts(&self) -> Result<Vec<Context>> {
+        let machine = Machine::detect(Path::new("/"));
+
+        Ok(vec![
+            Context::KeyValueContext(
+                String::from("virtualization"),
+                machine.virtualization.into(),
+            ),
+            Context::KeyValueContext(String::from("chassis"), machine.chassis.into()),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn machine(files: &[(&str, &str)]) -> Machine {
+        let root = tempfile::tempdir().unwrap();
+
+        for (path, contents) in files {
+            let path = root.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        Machine::detect(root.path())
+    }
+
+    #[test]
+    fn it_detects_containers() {
+        for (files, virtualization) in [
+            (vec![(".dockerenv", "")], "docker"),
+            (vec![("run/.containerenv", "")], "podman"),
+            (
+                vec![("proc/1/environ", "PATH=/bin\0container=lxc\0")],
+                "lxc",
+            ),
+        ] {
+            assert_eq!(
+                Machine {
+                    virtualization: virtualization.to_string(),
+                    chassis: String::from("container"),
+                },
+                machine(&files)
+            );
+        }
+    }
+
+    #[test]
+    fn it_detects_virtual_machines() {
+        for (vendor, virtualization) in [
+            ("QEMU", "qemu"),
+            ("VMware, Inc.", "vmware"),
+            ("innotek GmbH", "virtualbox"),
+        ] {
+            assert_eq!(
+                Machine {
+                    virtualization: virtualization.to_string(),
+                    chassis: String::from("vm"),
+                },
+                machine(&[
+                    ("sys/class/dmi/id/sys_vendor", vendor),
+                    ("sys/class/dmi/id/chassis_type", "1"),
+                ])
+            );
+        }
+
+        assert_eq!(
+            "kvm",
+            machine(&[("sys/class/dmi/id/product_name", "KVM")]).virtualization
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn it_reads_the_chassis_of_real_hardware() {
+        for (chassis_type, chassis) in [("10", "laptop"), ("3", "desktop"), ("23", "server")] {
+            assert_eq!(
+                Machine {
+                    virtualization: String::from("none"),
+                    chassis: chassis.to_string(),
+                },
+                machine(&[
+                    ("sys/class/dmi/id/sys_vendor", "LENOVO"),
+                    ("sys/class/dmi/id/chassis_type", chassis_type),
+                ])
+            );
+        }
+
+        assert_eq!(
+            Machine {
+                virtualization: String::from("wsl"),
+                chassis: String::new(),
+            },
+            machine(&[(
+                "proc/sys/kernel/osrelease",
+                "5.15.90.1-microsoft-standard-WSL2"
+            )])
+        );
+    }
+}
@@ -6,16 +6,27 @@ use tracing::{instrument, trace, warn};
 use user::UserContextProvider;
 
 use crate::{
+    atoms::Atom,
     config::Config,
     contexts::{
-        env::EnvContextProvider, os::OSContextProvider,
-        variable_include::VariableIncludeContextProvider, variables::VariablesContextProvider,
+        cloud::CloudContextProvider, desktop::DesktopContextProvider, env::EnvContextProvider,
+        facts::FactsContextProvider, hardware::HardwareContextProvider,
+        machine::MachineContextProvider, network::NetworkContextProvider, os::OSContextProvider,
+        profile::ProfileContextProvider, variable_include::VariableIncludeContextProvider,
+        variables::VariablesContextProvider,
     },
     values::Value,
 };
 
+pub mod cloud;
+pub mod desktop;
 pub mod env;
+pub mod facts;
+pub mod hardware;
+pub mod machine;
+pub mod network;
 pub mod os;
+pub mod profile;
 /// User context provider: understands the user running the command
 pub mod user;
 pub mod variable_include;
@@ -41,9 +52,16 @@ pub fn build_contexts(config: &Config) -> Contexts {
     let context_providers: Vec<Box<dyn ContextProvider>> = vec![
         Box::new(UserContextProvider {}),
         Box::new(OSContextProvider {}),
-        Box::new(EnvContextProvider {}),
+        Box::new(HardwareContextProvider {}),
+        Box::new(MachineContextProvider {}),
+        Box::new(NetworkContextProvider { config }),
+        Box::new(DesktopContextProvider {}),
+        Box::new(CloudContextProvider {}),
+        Box::new(EnvContextProvider { config }),
         Box::new(VariablesContextProvider { config }),
         Box::new(VariableIncludeContextProvider { config }),
+        Box::new(FactsContextProvider { config }),
+        Box::new(ProfileContextProvider { config }),
     ];
 
     context_providers.iter().for_each(|provider| {
@@ -89,6 +107,50 @@ pub fn build_contexts(config: &Config) -> Contexts {
     contexts
 }
 
+/// Stores the outcome of an atom under the `name` context, so later actions
+/// can use `name.stdout`, `name.stderr` and `name.exit_code`
+pub fn register(contexts: &mut Contexts, name: &str, atom: &dyn Atom) {
+    let mut values: BTreeMap<String, Value> = BTreeMap::new();
+
+    values.insert(
+        String::from("stdout"),
+        atom.output_string().trim_end().into(),
+    );
+    values.insert(
+        String::from("stderr"),
+        atom.error_message().trim_end().into(),
+    );
+    values.insert(
+        String::from("exit_code"),
+        i64::from(atom.status_code()).into(),
+    );
+
+    contexts.insert(name.to_string(), values);
+}
+
+/// Adds facts set by an atom to the `facts` context, for later actions
+pub fn set_facts(contexts: &mut Contexts, facts: BTreeMap<String, Value>) {
+    contexts
+        .entry(String::from("facts"))
+        .or_default()
+        .extend(facts);
+}
+
+/// Applies what an action registered in its own copy of the contexts, which
+/// was `before` when it started and `after` when it was done
+pub fn merge_changes(contexts: &mut Contexts, before: &Contexts, after: &Contexts) {
+    for (name, values) in after.iter() {
+        for (key, value) in values.iter() {
+            if before.get(name).and_then(|values| values.get(key)) != Some(value) {
+                contexts
+                    .entry(name.clone())
+                    .or_default()
+                    .insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
 pub fn to_tera(contexts: &Contexts) -> tera::Context {
     let mut context = tera::Context::new();
 
@@ -96,7 +158,7 @@ pub fn to_tera(contexts: &Contexts) -> tera::Context {
     context
 }
 
-pub fn to_rhai(context: &Contexts) -> rhai::Scope {
+pub fn to_rhai(context: &Contexts) -> rhai::Scope<'static> {
     let mut scope = Scope::new();
 
     context.iter().for_each(|(m, v)| {
@@ -140,6 +202,67 @@ mod test {
         assert_eq!(result, String::from("rawkode"));
     }
 
+    #[test]
+    fn it_can_register_outcomes() {
+        let engine = Engine::new();
+        let mut contexts: Contexts = BTreeMap::new();
+
+        register(
+            &mut contexts,
+            "greeting",
+            &crate::atoms::Echo("hello-world\n"),
+        );
+
+        let mut rhai_context = to_rhai(&contexts);
+
+        assert_eq!(
+            true,
+            engine
+                .eval_with_scope::<bool>(
+                    &mut rhai_context,
+                    r#"greeting.stdout == "hello-world" && greeting.exit_code == 0"#
+                )
+                .unwrap()
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn facts_context_resolves_from_commands_and_files() -> anyhow::Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        std::io::Write::write_all(&mut file, b"0123456789abcdef\n")?;
+
+        let config = Config {
+            facts: BTreeMap::from([
+                (
+                    String::from("chassis"),
+                    crate::config::Fact::Command(String::from("echo laptop")),
+                ),
+                (
+                    String::from("machine_id"),
+                    crate::config::Fact::File(file.path().display().to_string()),
+                ),
+                (
+                    String::from("broken"),
+                    crate::config::Fact::Command(String::from("exit 1")),
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let contexts = build_contexts(&config);
+        let facts = contexts.get("facts").unwrap();
+
+        assert_eq!("laptop", facts.get("chassis").unwrap().to_string());
+        assert_eq!(
+            "0123456789abcdef",
+            facts.get("machine_id").unwrap().to_string()
+        );
+        assert_eq!(None, facts.get("broken"));
+
+        Ok(())
+    }
+
     #[test]
     fn variables_context_resolves_from_config() -> anyhow::Result<()> {
         let mut variables = BTreeMap::new();
@@ -210,4 +333,66 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn it_merges_what_actions_registered() {
+        let mut before: Contexts = BTreeMap::new();
+        set_facts(
+            &mut before,
+            BTreeMap::from([(String::from("shell"), Value::from("bash"))]),
+        );
+
+        let mut first = before.clone();
+        set_facts(
+            &mut first,
+            BTreeMap::from([(String::from("shell"), Value::from("zsh"))]),
+        );
+
+        let mut second = before.clone();
+        set_facts(
+            &mut second,
+            BTreeMap::from([(String::from("editor"), Value::from("helix"))]),
+        );
+
+        let mut contexts = before.clone();
+        merge_changes(&mut contexts, &before, &first);
+        merge_changes(&mut contexts, &before, &second);
+
+        // The second action didn't change the shell back
+        assert_eq!(Some(&Value::from("zsh")), contexts["facts"].get("shell"));
+        assert_eq!(Some(&Value::from("helix")), contexts["facts"].get("editor"));
+    }
+
+    #[test]
+    fn env_context_only_includes_allowed_variables() -> anyhow::Result<()> {
+        let config = Config {
+            env: Some(vec![
+                String::from("ALLOWED_NAME"),
+                String::from("UNSET_NAME"),
+            ]),
+            ..Default::default()
+        };
+
+        std::env::set_var("ALLOWED_NAME", "Daniel Jackson");
+
+        let provider = EnvContextProvider { config: &config };
+        let values = provider
+            .get_contexts()?
+            .into_iter()
+            .map(|context| match context {
+                Context::KeyValueContext(name, value) => (name, value.to_string()),
+                Context::ListContext(name, _) => (name, String::new()),
+            })
+            .collect::<Vec<(String, String)>>();
+
+        assert_eq!(
+            values,
+            vec![
+                (String::from("ALLOWED_NAME"), String::from("Daniel Jackson")),
+                (String::from("UNSET_NAME"), String::new()),
+            ]
+        );
+
+        Ok(())
+    }
 }
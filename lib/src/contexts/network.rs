@@ -0,0 +1,156 @@
+use crate::config::Config;
+use crate::contexts::{Context, ContextProvider};
+use anyhow::Result;
+use if_addrs::Interface;
+use std::net::{IpAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+/// Public resolvers, only used to find the route out. Connecting UDP sockets
+/// sends nothing, so they aren't contacted.
+const ROUTE_V4: &str = "1.1.1.1:443";
+const ROUTE_V6: &str = "[2606:4700:4700::1111]:443";
+
+/// The interfaces and addresses of this machine, and whether it can reach the
+/// internet. `online` is only probed when `online_probe` is configured and
+/// there's a default route, and is `false` with `--offline`.
+pub struct NetworkContextProvider<'a> {
+    pub config: &'a Config,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Network {
+    interfaces: Vec<String>,
+    addresses: Vec<String>,
+}
+
+impl Network {
+    fn from_interfaces(interfaces: Vec<Interface>) -> Network {
+        let mut network = Network::default();
+
+        for interface in interfaces
+            .iter()
+            .filter(|interface| !interface.is_loopback())
+        {
+            if !network.interfaces.contains(&interface.name) {
+                network.interfaces.push(interface.name.clone());
+            }
+            network.addresses.push(interface.ip().to_string());
+        }
+
+        network
+    }
+}
+
+/// The address traffic to `probe` leaves from, which is the primary address of
+/// the default route. Connecting UDP sockets sends nothing.
+fn primary_address(bind: &str, probe: &str) -> Option<IpAddr> {
+    let socket = UdpSocket::bind(bind).ok()?;
+    socket.connect(probe).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+/// Whether any address of the probe accepts a connection
+fn is_reachable(probe: &str) -> bool {
+    probe
+        .to_socket_addrs()
+        .map(|mut addresses| {
+            addresses
+                .any(|address| TcpStream::connect_timeout(&address, Duration::from_secs(2)).is_ok())
+        })
+        .unwrap_or(false)
+}
+
+impl ContextProvider for NetworkContextProvider<'_> {
+    fn get_prefix(&self) -> String {
+        String::from("network")
+    }
+
+    fn get_contexts(&self) -> Result<Vec<Context>> {
+        let network = Network::from_interfaces(if_addrs::get_if_addrs()?);
+
+        let ipv4 = primary_address("0.0.0.0:0", ROUTE_V4);
+        let ipv6 = primary_address("[::]:0", ROUTE_V6);
+        let default_gateway = ipv4.is_some() || ipv6.is_some();
+
+        let online = default_gateway
+            && !crate::atoms::http::is_offline()
+            && self.config.online_probe.as_deref().is_none_or(is_reachable);
+
+        let address = |ip: Option<IpAddr>| ip.map(|ip| ip.to_string()).unwrap_or_default();
+
+        Ok(vec![
+            Context::KeyValueContext(String::from("ipv4"), address(ipv4).into()),
+            Context::KeyValueContext(String::from("ipv6"), address(ipv6).into()),
+            Context::KeyValueContext(
+                String::from("default_gateway"),
+                default_gateway.to_string().into(),
+            ),
+            Context::KeyValueContext(String::from("online"), online.to_string().into()),
+            Context::ListContext(
+                String::from("interfaces"),
+                network
+                    .interfaces
+                    .into_iter()
+                    .map(|name| name.into())
+                    .collect(),
+            ),
+            Context::ListContext(
+                String::from("addresses"),
+                network
+                    .addresses
+                    .into_iter()
+                    .map(|address| address.into())
+                    .collect(),
+            ),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use if_addrs::{IfAddr, Ifv4Addr};
+    use pretty_assertions::assert_eq;
+    use std::net::Ipv4Addr;
+
+    fn interface(name: &str, ip: [u8; 4]) -> Interface {
+        Interface {
+            name: name.to_string(),
+            addr: IfAddr::V4(Ifv4Addr {
+                ip: Ipv4Addr::from(ip),
+                netmask: Ipv4Addr::new(255, 255, 255, 0),
+                prefixlen: 24,
+                broadcast: None,
+            }),
+            index: None,
+            #[cfg(windows)]
+            adapter_name: String::new(),
+        }
+    }
+
+    #[test]
+    fn it_probes_addresses() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+        assert_eq!(
+            true,
+            is_reachable(&listener.local_addr().unwrap().to_string())
+        );
+        assert_eq!(false, is_reachable("not an address"));
+    }
+
+    #[test]
+    fn it_leaves_out_loopback() {
+        assert_eq!(
+            Network {
+                interfaces: vec![String::from("eth0")],
+                addresses: vec![String::from("192.168.1.2"), String::from("192.168.1.3")],
+            },
+            Network::from_interfaces(vec![
+                interface("lo", [127, 0, 0, 1]),
+                interface("eth0", [192, 168, 1, 2]),
+                interface("eth0", [192, 168, 1, 3]),
+            ])
+        );
+    }
+}
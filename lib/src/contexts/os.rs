@@ -1,4 +1,5 @@
 use crate::contexts::{Context, ContextProvider};
+use crate::rhai_functions::version_numbers;
 use anyhow::Result;
 use gethostname::gethostname;
 use os_info;
@@ -12,6 +13,8 @@ impl ContextProvider for OSContextProvider {
 
     fn get_contexts(&self) -> Result<Vec<super::Context>> {
         let osinfo = os_info::get();
+        let version = version_numbers(&osinfo.version().to_string());
+        let version_number = |index: usize| version.get(index).copied().unwrap_or_default() as i64;
 
         Ok(vec![
             Context::KeyValueContext(String::from("hostname"), gethostname().into()),
@@ -33,6 +36,8 @@ impl ContextProvider for OSContextProvider {
                 String::from("version"),
                 format!("{}", osinfo.version()).into(),
             ),
+            Context::KeyValueContext(String::from("version_major"), version_number(0).into()),
+            Context::KeyValueContext(String::from("version_minor"), version_number(1).into()),
             Context::KeyValueContext(
                 String::from("edition"),
                 String::from(osinfo.edition().unwrap_or("unknown")).into(),
@@ -0,0 +1,59 @@
+use anyhow::Result;
+
+use super::{ContextProvider, Contexts};
+use crate::{config::Config, contexts::Context};
+
+/// The profile being applied, like `work`, as `profile.name`. Empty without one.
+pub struct ProfileContextProvider<'a> {
+    pub config: &'a Config,
+}
+
+impl<'a> ContextProvider for ProfileContextProvider<'a> {
+    fn get_prefix(&self) -> String {
+        String::from("profile")
+    }
+
+    fn get_contexts(&self) -> Result<Vec<super::Context>> {
+        Ok(vec![Context::KeyValueContext(
+            String::from("name"),
+            self.config.profile.clone().unwrap_or_default().into(),
+        )])
+    }
+}
+
+/// Manifests and actions limited to profiles only apply when one of them is
+/// the active profile, while those without profiles always do
+pub fn in_profile(profiles: &[String], contexts: &Contexts) -> bool {
+    if profiles.is_empty() {
+        return true;
+    }
+
+    contexts
+        .get("profile")
+        .and_then(|profile| profile.get("name"))
+        .is_some_and(|name| profiles.contains(&name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contexts::build_contexts;
+
+    #[test]
+    fn it_only_includes_the_active_profile() {
+        let config = Config {
+            profile: Some(String::from("work")),
+            ..Default::default()
+        };
+        let contexts = build_contexts(&config);
+
+        assert!(in_profile(&[], &contexts));
+        assert!(in_profile(&[String::from("work")], &contexts));
+        assert!(!in_profile(&[String::from("personal")], &contexts));
+
+        let contexts = build_contexts(&Config::default());
+
+        assert!(in_profile(&[], &contexts));
+        assert!(!in_profile(&[String::from("work")], &contexts));
+    }
+}
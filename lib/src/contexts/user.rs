@@ -1,3 +1,4 @@
+use crate::atoms::command::{can_elevate_without_password, is_elevated, privilege};
 use crate::contexts::{Context, ContextProvider};
 use anyhow::Result;
 use dirs_next::{config_dir, data_dir, data_local_dir, document_dir, home_dir};
@@ -44,6 +45,12 @@ impl ContextProvider for UserContextProvider {
                     .map(Into::into)
                     .unwrap_or_else(|| "unknown".into()),
             ),
+            Context::KeyValueContext(String::from("is_root"), is_elevated().to_string().into()),
+            Context::KeyValueContext(String::from("privilege"), privilege().name().into()),
+            Context::KeyValueContext(
+                String::from("can_sudo"),
+                can_elevate_without_password().to_string().into(),
+            ),
         ])
     }
 }
@@ -1,8 +1,18 @@
 pub mod actions;
 pub mod atoms;
+pub mod audit;
+pub mod cache;
 pub mod config;
 pub mod contexts;
 pub mod manifests;
+pub mod notify;
+pub mod oci;
+pub mod report;
+pub mod rhai_functions;
+pub mod rollback;
+pub mod secrets;
+pub mod session;
+pub mod state;
 pub mod steps;
 pub mod tera_functions;
 mod utilities;
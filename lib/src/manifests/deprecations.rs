@@ -0,0 +1,537 @@
+use super::load::without_tags;
+use crate::atoms::file::write_atomic;
+use serde::Serialize;
+use serde_yml::libyml::error::Mark;
+use serde_yml::libyml::parser::{Event, Parser};
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use toml_edit::{ImDocument, Item as TomlItem, TableLike, Value as TomlValue};
+
+/// A name that was changed. The old one is still understood, through a serde
+/// alias, but manifests using it are warned about and `validate --fix`
+/// rewrites it.
+pub struct Deprecation {
+    /// The actions whose field was renamed, or none when an action was
+    pub actions: &'static [&'static str],
+    pub old: &'static str,
+    pub new: &'static str,
+}
+
+/// Every renamed action and field, whose old names must stay aliases
+pub const DEPRECATIONS: &[Deprecation] = &[
+    Deprecation {
+        actions: &[],
+        old: "package.installed",
+        new: "package.install",
+    },
+    Deprecation {
+        actions: &["command.run", "cmd.run"],
+        old: "sudo",
+        new: "privileged",
+    },
+];
+
+/// An old name found in a manifest, where it was found
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeprecatedName {
+    pub path: PathBuf,
+    pub line: usize,
+    /// The action of a renamed field
+    pub action: Option<String>,
+    pub old: String,
+    pub new: String,
+    #[serde(skip)]
+    column: usize,
+}
+
+impl Display for DeprecatedName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = self.path.display();
+
+        match &self.action {
+            Some(action) => write!(
+                f,
+                "{path}:{}: `{}` of {action} is deprecated, use `{}` instead",
+                self.line, self.old, self.new
+            ),
+            None => write!(
+                f,
+                "{path}:{}: {} is deprecated, use {} instead",
+                self.line, self.old, self.new
+            ),
+        }
+    }
+}
+
+/// An action, or one of its variants, which takes the action of its parent
+#[derive(Default)]
+struct Item {
+    parent: Option<usize>,
+    action: Option<String>,
+    keys: Vec<Key>,
+}
+
+struct Key {
+    name: String,
+    value: String,
+    line: usize,
+    column: usize,
+}
+
+/// The blocks of a manifest that list actions
+const BLOCKS: [&str; 5] = ["before", "actions", "after", "on_failure", "always"];
+
+/// Finds the old names in the source of a manifest, before it's rendered, in
+/// the keys of actions and their variants. Manifests that can't be parsed
+/// before they're rendered aren't looked at.
+pub fn find(path: &Path, source: &str) -> Vec<DeprecatedName> {
+    let parsed = without_tags(source);
+
+    // Evaluated manifests are generated, the old names are in their libraries
+    let items = match path.extension().and_then(OsStr::to_str) {
+        Some("yaml") | Some("yml") => yaml_items(&parsed),
+        Some("toml") => toml_items(&parsed),
+        _ => return vec![],
+    };
+
+    let action_of = |mut index: usize| loop {
+        if let Some(action) = &items[index].action {
+            return Some(action.as_str());
+        }
+        index = items[index].parent?;
+    };
+
+    let mut found = vec![];
+
+    for (index, item) in items.iter().enumerate() {
+        let action = action_of(index);
+
+        for key in item.keys.iter() {
+            for deprecation in DEPRECATIONS {
+                let (renamed, column) = match deprecation.actions.is_empty() {
+                    true => (
+                        key.name == "action" && key.value == deprecation.old,
+                        key.column
+                            + source_line(source, key.line)[key.column..]
+                                .find(deprecation.old)
+                                .unwrap_or_default(),
+                    ),
+                    false => (
+                        key.name == deprecation.old
+                            && action.is_some_and(|action| deprecation.actions.contains(&action)),
+                        key.column,
+                    ),
+                };
+
+                if renamed {
+                    found.push(DeprecatedName {
+                        path: path.to_path_buf(),
+                        line: key.line,
+                        action: (!deprecation.actions.is_empty())
+                            .then(|| action.unwrap_or_default().to_string()),
+                        old: deprecation.old.to_string(),
+                        new: deprecation.new.to_string(),
+                        column,
+                    });
+                }
+            }
+        }
+    }
+
+    found.sort_by_key(|name| (name.line, name.column));
+    found
+}
+
+/// Replaces the old names with the new ones, leaving the rest as it was
+pub fn fix(source: &str, names: &[DeprecatedName]) -> String {
+    let mut lines: Vec<String> = source.split('\n').map(String::from).collect();
+
+    // From the right, so columns of earlier names on the line stay right
+    for name in names.iter().rev() {
+        if let Some(line) = lines.get_mut(name.line - 1) {
+            if line[name.column..].starts_with(&name.old) {
+                line.replace_range(name.column..name.column + name.old.len(), &name.new);
+            }
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// The old names in every manifest in `manifest_path`
+pub fn find_files(manifest_path: &Path) -> Vec<DeprecatedName> {
+    super::load::manifest_files(manifest_path)
+        .into_iter()
+        .flat_map(|path| {
+            let source = std::fs::read_to_string(&path).unwrap_or_default();
+            find(&path, &source)
+        })
+        .collect()
+}
+
+/// Rewrites the old names in every manifest in `manifest_path`, returning
+/// those it replaced
+pub fn fix_files(manifest_path: &Path) -> anyhow::Result<Vec<DeprecatedName>> {
+    let mut fixed = vec![];
+
+    for path in super::load::manifest_files(manifest_path) {
+        let source = std::fs::read_to_string(&path)?;
+        let names = find(&path, &source);
+
+        if !names.is_empty() {
+            write_atomic(&path, fix(&source, &names).as_bytes())?;
+            fixed.extend(names);
+        }
+    }
+
+    Ok(fixed)
+}
+
+fn source_line(source: &str, line: usize) -> &str {
+    source.split('\n').nth(line - 1).unwrap_or_default()
+}
+
+/// The line, from 1, and the byte column of a position in the source
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let start = before
+        .rfind('\n')
+        .map(|index| index + 1)
+        .unwrap_or_default();
+
+    (before.matches('\n').count() + 1, offset - start)
+}
+
+/// A YAML node, with where scalars start
+enum Node {
+    Scalar(String, (usize, usize)),
+    Sequence(Vec<Node>),
+    Mapping(Vec<(Node, Node)>),
+    Alias,
+}
+
+fn yaml_tree(source: &str) -> Option<Node> {
+    let mut parser = Parser::new(Cow::Borrowed(source.as_bytes()));
+
+    loop {
+        match parser.parse_next_event().ok()? {
+            (Event::StreamStart | Event::DocumentStart, _) => continue,
+            (Event::StreamEnd | Event::DocumentEnd, _) => return None,
+            (event, mark) => return yaml_node(&mut parser, event, mark, source),
+        }
+    }
+}
+
+fn yaml_node(parser: &mut Parser, event: Event, mark: Mark, source: &str) -> Option<Node> {
+    Some(match event {
+        Event::Scalar(scalar) => {
+            // Marks count characters, columns here count bytes
+            let line = source.split('\n').nth(mark.line() as usize)?;
+            let column = line
+                .char_indices()
+                .nth(mark.column() as usize)
+                .map(|(index, _)| index)
+                .unwrap_or(line.len());
+
+            Node::Scalar(
+                String::from_utf8_lossy(&scalar.value).into_owned(),
+                (mark.line() as usize + 1, column),
+            )
+        }
+        Event::SequenceStart(_) => {
+            let mut nodes = vec![];
+            loop {
+                match parser.parse_next_event().ok()? {
+                    (Event::SequenceEnd, _) => break,
+                    (event, mark) => nodes.push(yaml_node(parser, event, mark, source)?),
+                }
+            }
+            Node::Sequence(nodes)
+        }
+        Event::MappingStart(_) => {
+            let mut pairs = vec![];
+            loop {
+                let key = match parser.parse_next_event().ok()? {
+                    (Event::MappingEnd, _) => break,
+                    (event, mark) => yaml_node(parser, event, mark, source)?,
+                };
+                let (event, mark) = parser.parse_next_event().ok()?;
+                pairs.push((key, yaml_node(parser, event, mark, source)?));
+            }
+            Node::Mapping(pairs)
+        }
+        _ => Node::Alias,
+    })
+}
+
+fn yaml_items(source: &str) -> Vec<Item> {
+    fn actions(node: &Node, parent: Option<usize>, items: &mut Vec<Item>) {
+        let Node::Sequence(nodes) = node else {
+            return;
+        };
+
+        for node in nodes {
+            let Node::Mapping(pairs) = node else {
+                continue;
+            };
+
+            let index = items.len();
+            items.push(Item {
+                parent,
+                ..Default::default()
+            });
+
+            for (key, value) in pairs {
+                let Node::Scalar(name, (line, column)) = key else {
+                    continue;
+                };
+                let value = match value {
+                    Node::Scalar(value, _) => value.as_str(),
+                    _ => "",
+                };
+
+                add_key(&mut items[index], name, value, *line, *column);
+            }
+
+            for (key, value) in pairs {
+                if matches!(key, Node::Scalar(name, _) if name == "variants") {
+                    actions(value, Some(index), items);
+                }
+            }
+        }
+    }
+
+    let mut items = vec![];
+
+    if let Some(Node::Mapping(pairs)) = yaml_tree(source) {
+        for (key, value) in pairs.iter() {
+            if matches!(key, Node::Scalar(name, _) if BLOCKS.contains(&name.as_str())) {
+                actions(value, None, &mut items);
+            }
+        }
+    }
+
+    items
+}
+
+fn toml_items(source: &str) -> Vec<Item> {
+    fn actions<'a>(
+        tables: impl Iterator<Item = &'a dyn TableLike>,
+        parent: Option<usize>,
+        items: &mut Vec<Item>,
+        source: &str,
+    ) {
+        for table in tables {
+            let index = items.len();
+            items.push(Item {
+                parent,
+                ..Default::default()
+            });
+
+            for (name, value) in table.iter() {
+                let Some(span) = table.get_key_value(name).and_then(|(key, _)| key.span()) else {
+                    continue;
+                };
+                let (line, column) = position(source, span.start);
+
+                add_key(
+                    &mut items[index],
+                    name,
+                    value.as_str().unwrap_or_default(),
+                    line,
+                    column,
+                );
+            }
+
+            if let Some(variants) = table.get("variants") {
+                actions(toml_tables(variants), Some(index), items, source);
+            }
+        }
+    }
+
+    let Ok(document) = ImDocument::parse(source) else {
+        return vec![];
+    };
+
+    let mut items = vec![];
+    for block in BLOCKS {
+        if let Some(block) = document.get(block) {
+            actions(toml_tables(block), None, &mut items, source);
+        }
+    }
+
+    items
+}
+
+/// The tables of an array of tables, or of an array of inline tables
+fn toml_tables(item: &TomlItem) -> Box<dyn Iterator<Item = &dyn TableLike> + '_> {
+    match item {
+        TomlItem::ArrayOfTables(tables) => {
+            Box::new(tables.iter().map(|table| table as &dyn TableLike))
+        }
+        TomlItem::Value(TomlValue::Array(values)) => Box::new(
+            values
+                .iter()
+                .filter_map(TomlValue::as_inline_table)
+                .map(|table| table as &dyn TableLike),
+        ),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+fn add_key(item: &mut Item, name: &str, value: &str, line: usize, column: usize) {
+    if name == "action" {
+        item.action = Some(value.to_string());
+    }
+
+    item.keys.push(Key {
+        name: name.to_string(),
+        value: value.to_string(),
+        line,
+        column,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_finds_renamed_actions_and_fields() {
+        let source = r#"
+actions:
+  - action: package.installed
+    list: [git]
+
+  - sudo: true
+    action: command.run
+    command: whoami
+    variants:
+      - where: os.name == "macos"
+        sudo: false
+
+  - action: file.copy
+    from: sudo
+    to: sudo
+"#;
+
+        let found = find(Path::new("m.yaml"), source);
+
+        assert_eq!(
+            vec![
+                (3, None),
+                (6, Some("command.run")),
+                (11, Some("command.run"))
+            ],
+            found
+                .iter()
+                .map(|name| (name.line, name.action.as_deref()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "m.yaml:6: `sudo` of command.run is deprecated, use `privileged` instead",
+            found[1].to_string()
+        );
+    }
+
+    #[test]
+    fn it_finds_old_names_in_flow_style() {
+        let source = "actions:\n  - {action: package.installed, list: [git]}\n  - action: command.run\n    variants: [{where: \"true\", sudo: true}]\n{% if true %}\n  - { action: cmd.run, sudo: true }\n{% endif %}\n";
+
+        let found = find(Path::new("m.yaml"), source);
+
+        assert_eq!(
+            "actions:\n  - {action: package.install, list: [git]}\n  - action: command.run\n    variants: [{where: \"true\", privileged: true}]\n{% if true %}\n  - { action: cmd.run, privileged: true }\n{% endif %}\n",
+            fix(source, &found)
+        );
+
+        let source = "actions = [{ action = \"package.installed\", list = [\"git\"] }]\n";
+        assert_eq!(
+            "actions = [{ action = \"package.install\", list = [\"git\"] }]\n",
+            fix(source, &find(Path::new("m.toml"), source))
+        );
+    }
+
+    #[test]
+    fn it_still_understands_old_names() {
+        // Enough of an action to deserialize
+        let fields = |action: &str| match action {
+            "package.install" => "list: [git]",
+            _ => "command: whoami",
+        };
+
+        for deprecation in DEPRECATIONS {
+            let (old, new) = match deprecation.actions {
+                [] => (
+                    format!(
+                        "- action: {}\n  {}",
+                        deprecation.old,
+                        fields(deprecation.new)
+                    ),
+                    format!(
+                        "- action: {}\n  {}",
+                        deprecation.new,
+                        fields(deprecation.new)
+                    ),
+                ),
+                [action, ..] => (
+                    format!(
+                        "- action: {action}\n  {}\n  {}: true",
+                        fields(action),
+                        deprecation.old
+                    ),
+                    format!(
+                        "- action: {action}\n  {}\n  {}: true",
+                        fields(action),
+                        deprecation.new
+                    ),
+                ),
+            };
+
+            let old: Vec<crate::actions::Actions> = serde_yml::from_str(&old).unwrap();
+            let new: Vec<crate::actions::Actions> = serde_yml::from_str(&new).unwrap();
+
+            assert_eq!(
+                serde_json::to_value(new).unwrap(),
+                serde_json::to_value(old).unwrap(),
+                "{} is no longer understood",
+                deprecation.old
+            );
+        }
+    }
+
+    #[test]
+    fn it_rewrites_old_names() {
+        let source = "actions:\n  - action: \"package.installed\"\n    list: [git]\n  - action: cmd.run\n    sudo: true # needed\n";
+
+        let fixed = fix(source, &find(Path::new("m.yaml"), source));
+
+        assert_eq!(
+            "actions:\n  - action: \"package.install\"\n    list: [git]\n  - action: cmd.run\n    privileged: true # needed\n",
+            fixed
+        );
+        assert_eq!(
+            vec![] as Vec<DeprecatedName>,
+            find(Path::new("m.yaml"), &fixed)
+        );
+    }
+
+    #[test]
+    fn it_finds_old_names_in_toml() {
+        let source = "[[actions]]\naction = \"command.run\"\ncommand = \"whoami\"\nsudo = true\n\n[[actions.variants]]\nwhere = \"true\"\nsudo = false\n";
+
+        let found = find(Path::new("m.toml"), source);
+
+        assert_eq!(
+            vec![4, 8],
+            found.iter().map(|name| name.line).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "[[actions]]\naction = \"command.run\"\ncommand = \"whoami\"\nprivileged = true\n\n[[actions.variants]]\nwhere = \"true\"\nprivileged = false\n",
+            fix(source, &found)
+        );
+    }
+}
@@ -1,20 +1,243 @@
+use super::deprecations;
+use super::roles::{self, Role};
 use super::Manifest;
 use crate::{
+    actions::interpolate::references,
     contexts::{to_tera, Contexts},
     manifests::get_manifest_name,
     tera_functions::register_functions,
 };
+use anyhow::anyhow;
 use ignore::WalkBuilder;
+use regex::{Captures, Regex};
+use serde::de::DeserializeOwned;
 use std::{
-    collections::HashMap, error::Error, ffi::OsStr, fs::canonicalize, ops::Deref, path::PathBuf,
+    collections::{HashMap, HashSet},
+    error::Error,
+    ffi::OsStr,
+    fmt::Display,
+    fs::canonicalize,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
 };
 use tera::Tera;
-use tracing::{error, span};
+use tracing::{error, span, warn};
+
+/// A manifest that couldn't be loaded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadError {
+    pub name: String,
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Manifest '{}' in file with path '{}' cannot be parsed. Reason: {}",
+            self.name,
+            self.path.display(),
+            self.reason
+        )
+    }
+}
 
 pub fn load(manifest_path: PathBuf, contexts: &Contexts) -> HashMap<String, Manifest> {
+    let (manifests, errors) = load_with_errors(manifest_path, contexts);
+
+    for err in errors {
+        error!("{err}");
+    }
+
+    manifests
+}
+
+/// Loads the manifests like `load`, but returns the manifests that couldn't be
+/// loaded instead of logging them
+pub fn load_with_errors(
+    manifest_path: PathBuf,
+    contexts: &Contexts,
+) -> (HashMap<String, Manifest>, Vec<LoadError>) {
     let mut manifests: HashMap<String, Manifest> = HashMap::new();
+    let mut errors: Vec<LoadError> = vec![];
+    let mut loaded: Vec<(PathBuf, Manifest)> = vec![];
+    let mut roles: HashMap<String, Role> = HashMap::new();
+    let mut context = to_tera(contexts);
+
+    let sources = manifest_files(&manifest_path)
+        .into_iter()
+        .map(|entry| {
+            let source = std::fs::read_to_string(&entry).unwrap_or_default();
+
+            (entry, source)
+        })
+        .collect::<Vec<_>>();
+
+    // Registered outputs only exist once their command ran, so references to
+    // them are kept as they are, and rendered when their action is planned
+    let mut outputs = sources
+        .iter()
+        .flat_map(|(entry, source)| registered(entry, source))
+        .collect::<Vec<_>>();
+
+    for name in outputs.iter() {
+        if !contexts.contains_key(name) {
+            context.insert(
+                name,
+                &["stdout", "stderr", "exit_code"]
+                    .map(|field| (field, format!("{{{{ {name}.{field} }}}}")))
+                    .into_iter()
+                    .collect::<HashMap<_, _>>(),
+            );
+        }
+    }
+
+    sources.into_iter().for_each(|(entry, source)| {
+        let span = span!(
+            tracing::Level::INFO,
+            "manifest_load",
+            manifest = entry.file_name().and_then(OsStr::to_str)
+        )
+        .entered();
+
+        for name in deprecations::find(&entry, &source) {
+            warn!(
+                file = %name.path.display(),
+                line = name.line,
+                old = name.old,
+                new = name.new,
+                "{name}"
+            );
+        }
+
+        // Roles are rendered once they're used, with their arguments.
+        // Evaluated manifests have functions for that instead.
+        let parameters = match evaluator(&entry) {
+            Some(_) => Ok(None),
+            None => roles::parameters(&source, &entry),
+        };
+
+        let manifest = match parameters {
+            Ok(Some(parameters)) => {
+                let name =
+                    get_manifest_name(&manifest_path, &entry).expect("Failed to get manifest name");
+
+                roles.insert(
+                    name,
+                    Role {
+                        path: entry,
+                        parameters,
+                    },
+                );
+                span.exit();
+
+                return;
+            }
+            Ok(None) => read(&entry, &context),
+            Err(err) => Err(anyhow!("Failed to read parameters: {err}")),
+        };
+
+        match manifest {
+            Ok(manifest) => loaded.push((entry, manifest)),
+            Err(err) => errors.push(LoadError {
+                name: get_manifest_name(&manifest_path, &entry).unwrap_or_default(),
+                path: entry,
+                reason: err.to_string(),
+            }),
+        }
+
+        span.exit();
+    });
+
+    // Imported files are snippets, rather than manifests of their own
+    let mut imported: HashSet<PathBuf> = HashSet::new();
+    let mut resolved = vec![];
+
+    for (entry, mut manifest) in loaded {
+        let name = get_manifest_name(&manifest_path, &entry).expect("Failed to get manifest name");
+
+        match resolve_imports(
+            &mut manifest,
+            &entry,
+            &context,
+            &mut vec![entry.clone()],
+            &mut imported,
+        ) {
+            Ok(()) => resolved.push((entry, name, manifest)),
+            Err(err) => errors.push(LoadError {
+                name,
+                path: entry,
+                reason: err.to_string(),
+            }),
+        }
+    }
+
+    let mut instances: HashMap<String, Manifest> = HashMap::new();
+    let mut instantiated = vec![];
+
+    for (entry, name, mut manifest) in resolved {
+        if let Err(err) = roles::instantiate(
+            &manifest.uses,
+            &roles,
+            &context,
+            &mut instances,
+            &mut vec![],
+            &mut imported,
+        )
+        .map(|dependencies| manifest.depends.extend(dependencies))
+        {
+            errors.push(LoadError {
+                name,
+                path: entry,
+                reason: err.to_string(),
+            });
+            continue;
+        }
+
+        instantiated.push((entry, name, manifest));
+    }
 
-    let mut walker = WalkBuilder::new(&manifest_path);
+    manifests.extend(instances);
+
+    for (entry, name, mut manifest) in instantiated {
+        if imported.contains(&entry) {
+            continue;
+        }
+
+        manifest.root_dir = entry.parent().map(|parent| parent.to_path_buf());
+
+        manifest.name = Some(name.clone());
+
+        manifests.insert(name, manifest);
+    }
+
+    // Evaluated manifests, and roles, can only be read for what they register
+    // once they're loaded
+    outputs.extend(
+        manifests
+            .values()
+            .flat_map(Manifest::all_actions)
+            .filter_map(|action| action.inner_ref().register().map(str::to_string)),
+    );
+    outputs.sort_unstable();
+    outputs.dedup();
+
+    for manifest in manifests.values_mut() {
+        manifest.registered = outputs.clone();
+    }
+
+    errors.sort_by(|a, b| a.path.cmp(&b.path));
+
+    (manifests, errors)
+}
+
+/// The manifest files in `manifest_path`, leaving out the files of `files`
+/// directories
+pub(super) fn manifest_files(manifest_path: &Path) -> Vec<PathBuf> {
+    let mut walker = WalkBuilder::new(manifest_path);
 
     // FIXME: get rid of all .unwrap() calls
     walker
@@ -41,6 +264,21 @@ pub fn load(manifest_path: PathBuf, contexts: &Contexts) -> HashMap<String, Mani
                     file_name.ends_with(".yaml")
                         || file_name.ends_with(".yml")
                         || file_name.ends_with(".toml")
+                        || file_name.ends_with(".jsonnet")
+                        || file_name.ends_with(".cue")
+                })
+                .unwrap_or(false)
+        })
+        // CUE modules keep their dependencies in `cue.mod`
+        .filter(|entry| {
+            !entry
+                .as_ref()
+                .ok()
+                .map(|entry| {
+                    entry
+                        .path()
+                        .components()
+                        .any(|c| c.as_os_str() == "cue.mod")
                 })
                 .unwrap_or(false)
         })
@@ -56,75 +294,462 @@ pub fn load(manifest_path: PathBuf, contexts: &Contexts) -> HashMap<String, Mani
                 })
                 .unwrap_or(false)
         })
-        .for_each(|entry| {
-            if let Ok(filename) = entry {
-                let span = span!(
-                    tracing::Level::INFO,
-                    "manifest_load",
-                    manifest = filename.file_name().to_str()
-                )
-                .entered();
-
-                let entry = canonicalize(filename.into_path()).ok().unwrap_or_default();
-                let contents =
-                    std::fs::read_to_string(entry.clone()).unwrap_or_else(|_| String::from(""));
-                let template = contents.as_str();
-
-                let mut tera = Tera::default();
-                register_functions(&mut tera);
-
-                let template = match tera.render_str(template, &to_tera(contexts)) {
-                    Ok(template) => template,
-                    Err(err) => {
-                        match err.source() {
-                            Some(err) => error!(message = err.source()),
-                            None => error!(message = err.to_string().as_str()),
-                        }
-
-                        span.exit();
-
-                        return;
-                    }
-                };
-
-                let manifest: anyhow::Result<Manifest> = match entry
-                    .extension()
-                    .and_then(OsStr::to_str)
-                {
-                    Some("yaml") | Some("yml") => {
-                        serde_yml::from_str::<Manifest>(template.deref()).map_err(anyhow::Error::from)
-                    }
-                    Some("toml") => toml::from_str::<Manifest>(template.deref()).map_err(anyhow::Error::from),
-                    _ => {
-                        error!("Unrecognized file extension for manifest");
-                        span.exit();
-
-                        return;
-                    }
-                };
-
-                match manifest {
-                    Ok(mut manifest) => {
-                        let name = get_manifest_name(&manifest_path, &entry)
-                            .expect("Failed to get manifest name");
-
-                        manifest.root_dir = entry.parent().map(|parent| parent.to_path_buf());
-
-                        manifest.name = Some(name.clone());
-
-                        manifests.insert(name, manifest);
-                    }
-                    Err(err) => {
-                        let manifest_name =
-                            get_manifest_name(&manifest_path, &entry).unwrap_or_default();
-
-                        error!("Manifest '{manifest_name}' in file with path '{}' cannot be parsed. Reason: {err}", &entry.display());
-                    }
-                }
+        .filter_map(Result::ok)
+        .map(|entry| canonicalize(entry.into_path()).ok().unwrap_or_default())
+        .collect()
+}
 
-                span.exit();
+/// Renders the manifest at `path` as a template and parses it.
+/// Jsonnet and CUE manifests are evaluated to JSON by their own tools instead.
+pub(super) fn read(path: &Path, context: &tera::Context) -> anyhow::Result<Manifest> {
+    if let Some(mut command) = evaluator(path) {
+        return evaluate(&mut command, path, context);
+    }
+
+    let contents = std::fs::read_to_string(path).unwrap_or_else(|_| String::from(""));
+
+    let mut tera = Tera::default();
+    register_functions(&mut tera);
+
+    let template = tera
+        .render_str(&keep_outputs(&contents, context), context)
+        .map_err(|err| match err.source() {
+            Some(source) => anyhow!(source.to_string()),
+            None => anyhow!(err.to_string()),
+        })?;
+
+    match path.extension().and_then(OsStr::to_str) {
+        Some("yaml") | Some("yml") => Ok(serde_yml::from_str::<Manifest>(template.deref())?),
+        Some("toml") => Ok(toml::from_str::<Manifest>(template.deref())?),
+        _ => Err(anyhow!("Unrecognized file extension for manifest")),
+    }
+}
+
+/// Wraps the references to outputs that are only registered once their command
+/// ran in `{% raw %}`, so they're kept as they're written, filters included,
+/// until their action is planned. What's in `{% raw %}` already is left alone.
+fn keep_outputs(contents: &str, context: &tera::Context) -> String {
+    static RAW: OnceLock<Regex> = OnceLock::new();
+    let raw = RAW.get_or_init(|| {
+        Regex::new(r"(?s)\{%-?\s*raw\s*-?%\}.*?\{%-?\s*endraw\s*-?%\}")
+            .expect("raw pattern is a valid regex")
+    });
+
+    // Loading puts placeholders in the context for them
+    let pending = |name: &str| {
+        context
+            .get(name)
+            .and_then(|values| values.get("stdout"))
+            .and_then(serde_json::Value::as_str)
+            == Some(format!("{{{{ {name}.stdout }}}}").as_str())
+    };
+    let keep = |text: &str| {
+        references()
+            .replace_all(text, |reference: &Captures| match pending(&reference[1]) {
+                true => format!("{{% raw %}}{}{{% endraw %}}", &reference[0]),
+                false => reference[0].to_string(),
+            })
+            .into_owned()
+    };
+
+    let mut kept = String::new();
+    let mut last = 0;
+
+    for block in raw.find_iter(contents) {
+        kept.push_str(&keep(&contents[last..block.start()]));
+        kept.push_str(block.as_str());
+        last = block.end();
+    }
+    kept.push_str(&keep(&contents[last..]));
+
+    kept
+}
+
+/// Reads a top level section of a manifest from its source, without rendering
+/// it, for what's needed before the manifest can be rendered
+pub(super) fn section<T: DeserializeOwned>(
+    source: &str,
+    path: &Path,
+    key: &str,
+) -> anyhow::Result<Option<T>> {
+    let manifest = match unrendered(source, path) {
+        Ok(manifest) => manifest,
+        // Manifests that don't have the section don't need to parse before rendering
+        Err(_) if !source.contains(key) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    manifest
+        .get(key)
+        .map(|section| serde_yml::from_value(section.clone()))
+        .transpose()
+        .map_err(Into::into)
+}
+
+/// The source of a manifest with the lines that are only a template tag, like
+/// `{% if ... %}`, blanked out, so it can be parsed before it's rendered, with
+/// both branches of a condition
+pub(super) fn without_tags(source: &str) -> String {
+    source
+        .split('\n')
+        .map(|line| {
+            let tag = line.trim();
+            match tag.starts_with("{%") && tag.ends_with("%}")
+                || tag.starts_with("{#") && tag.ends_with("#}")
+            {
+                true => "",
+                false => line,
             }
-        });
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-    manifests
+/// The source of a YAML or TOML manifest parsed as it is, without rendering
+/// it, see `without_tags`. YAML values keep expressions, like `{{ user.name }}`,
+/// as the mappings they look like.
+pub(super) fn unrendered(source: &str, path: &Path) -> anyhow::Result<serde_yml::Value> {
+    let source = without_tags(source);
+
+    match path.extension().and_then(OsStr::to_str) {
+        Some("yaml") | Some("yml") => Ok(serde_yml::from_str(&source)?),
+        Some("toml") => Ok(toml::from_str(&source)?),
+        _ => Err(anyhow!("Unrecognized file extension for manifest")),
+    }
+}
+
+/// The names outputs are registered under, by `register` on the actions of a
+/// manifest and their variants
+fn registered(path: &Path, source: &str) -> Vec<String> {
+    fn collect(actions: Option<&serde_yml::Value>, names: &mut Vec<String>) {
+        for action in actions
+            .and_then(serde_yml::Value::as_sequence)
+            .into_iter()
+            .flatten()
+        {
+            if let Some(name) = action.get("register").and_then(serde_yml::Value::as_str) {
+                names.push(name.to_string());
+            }
+            collect(action.get("variants"), names);
+        }
+    }
+
+    let Ok(manifest) = unrendered(source, path) else {
+        return vec![];
+    };
+
+    let mut names = vec![];
+    for block in ["before", "actions", "after", "on_failure", "always"] {
+        collect(manifest.get(block), &mut names);
+    }
+
+    names
+}
+
+/// The command evaluating a manifest written in a configuration language,
+/// rather than rendered as a template
+pub(super) fn evaluator(path: &Path) -> Option<Command> {
+    match path.extension().and_then(OsStr::to_str) {
+        Some("jsonnet") => {
+            let mut command = Command::new("jsonnet");
+            command.arg(path);
+            Some(command)
+        }
+        Some("cue") => {
+            let mut command = Command::new("cue");
+            command.args([OsStr::new("export"), path.as_os_str()]);
+            command.args(["--out", "json"]);
+            Some(command)
+        }
+        _ => None,
+    }
+}
+
+/// Runs the evaluator of a manifest and parses the JSON it prints. Jsonnet
+/// gets every context as an external variable, like `std.extVar('user')`.
+fn evaluate(
+    command: &mut Command,
+    path: &Path,
+    context: &tera::Context,
+) -> anyhow::Result<Manifest> {
+    if path.extension().and_then(OsStr::to_str) == Some("jsonnet") {
+        if let serde_json::Value::Object(contexts) = context.clone().into_json() {
+            for (name, value) in contexts {
+                command.arg("--ext-code").arg(format!("{name}={value}"));
+            }
+        }
+    }
+
+    if let Some(dir) = path.parent() {
+        command.current_dir(dir);
+    }
+
+    let program = command.get_program().to_string_lossy().to_string();
+    let output = command.output().map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => {
+            anyhow!("{program} needs to be installed to evaluate this manifest")
+        }
+        _ => anyhow!("Failed to run {program}: {err}"),
+    })?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(serde_json::from_slice::<Manifest>(&output.stdout)?)
+}
+
+/// Replaces the imports of a manifest with their actions, which run before
+/// the manifest's own actions. `stack` holds the files currently being
+/// imported, to catch import cycles.
+pub(super) fn resolve_imports(
+    manifest: &mut Manifest,
+    path: &Path,
+    context: &tera::Context,
+    stack: &mut Vec<PathBuf>,
+    imported: &mut HashSet<PathBuf>,
+) -> anyhow::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let mut before = vec![];
+    let mut actions = vec![];
+    let mut after = vec![];
+    let mut on_failure = vec![];
+    let mut always = vec![];
+
+    for import in std::mem::take(&mut manifest.import) {
+        let file = canonicalize(dir.join(&import))
+            .map_err(|err| anyhow!("Failed to import '{import}': {err}"))?;
+
+        if stack.contains(&file) {
+            return Err(anyhow!("Import cycle, '{import}' imports itself"));
+        }
+
+        let mut snippet =
+            read(&file, context).map_err(|err| anyhow!("Failed to import '{import}': {err}"))?;
+
+        stack.push(file.clone());
+        resolve_imports(&mut snippet, &file, context, stack, imported)?;
+        stack.pop();
+
+        imported.insert(file);
+
+        before.append(&mut snippet.before);
+        actions.append(&mut snippet.actions);
+        after.append(&mut snippet.after);
+        on_failure.append(&mut snippet.on_failure);
+        always.append(&mut snippet.always);
+    }
+
+    before.append(&mut manifest.before);
+    actions.append(&mut manifest.actions);
+    after.append(&mut manifest.after);
+    on_failure.append(&mut manifest.on_failure);
+    always.append(&mut manifest.always);
+
+    manifest.before = before;
+    manifest.actions = actions;
+    manifest.after = after;
+    manifest.on_failure = on_failure;
+    manifest.always = always;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_merges_imported_actions_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("snippets")).unwrap();
+        std::fs::write(
+            dir.path().join("snippets/docker.yaml"),
+            "import:\n  - group.yaml\nactions:\n  - action: command.run\n    command: systemctl\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("snippets/group.yaml"),
+            "actions:\n  - action: command.run\n    command: usermod\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("docker.yaml"),
+            "import:\n  - snippets/docker.yaml\nactions:\n  - action: command.run\n    command: docker\n",
+        )
+        .unwrap();
+
+        let manifests = load(dir.path().to_path_buf(), &Contexts::default());
+
+        assert_eq!(vec!["docker"], manifests.keys().collect::<Vec<_>>());
+        assert_eq!(
+            vec!["usermod", "systemctl", "docker"],
+            manifests["docker"]
+                .actions
+                .iter()
+                .map(|action| match action {
+                    crate::actions::Actions::CommandRun(action) => action.action.command.as_str(),
+                    _ => "",
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_evaluates_manifests_with_contexts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("apps.jsonnet");
+        std::fs::write(&path, "{}").unwrap();
+
+        let mut contexts = Contexts::default();
+        contexts.insert(
+            String::from("user"),
+            [(String::from("username"), "ada".into())].into(),
+        );
+
+        // Stands in for jsonnet, printing the variable it gets
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            r#"printf '{"actions":[{"action":"command.run","command":"%s"}]}' "${2%%=*}""#,
+            "sh",
+        ]);
+
+        let manifest = evaluate(&mut command, &path, &to_tera(&contexts)).unwrap();
+
+        assert_eq!(
+            vec!["--ext-code", r#"user={"username":"ada"}"#],
+            command.get_args().skip(3).collect::<Vec<_>>()
+        );
+        assert!(matches!(
+            &manifest.actions[0],
+            crate::actions::Actions::CommandRun(action) if action.action.command == "user"
+        ));
+    }
+
+    #[test]
+    fn it_names_the_missing_evaluator() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("apps.cue"), "actions: []").unwrap();
+
+        let mut command = Command::new("comtrya-missing-evaluator");
+
+        assert_eq!(
+            "comtrya-missing-evaluator needs to be installed to evaluate this manifest",
+            evaluate(
+                &mut command,
+                &dir.path().join("apps.cue"),
+                &tera::Context::new()
+            )
+            .unwrap_err()
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn it_keeps_registered_outputs_for_planning() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("node.yaml"),
+            "actions:\n  - action: command.run\n    command: node\n    register: node_version\n  - action: command.run\n    command: echo\n    args: [\"{{ node_version.stdout }}\", \"{{node_version.stdout|trim}}\", \"{% raw %}{{ other.stdout | upper }}{% endraw %}\"]\n",
+        )
+        .unwrap();
+
+        let manifests = load(dir.path().to_path_buf(), &Contexts::default());
+
+        // As they're written, filters included
+        match &manifests["node"].actions[1] {
+            crate::actions::Actions::CommandRun(action) => assert_eq!(
+                vec![
+                    "{{ node_version.stdout }}",
+                    "{{node_version.stdout|trim}}",
+                    "{{ other.stdout | upper }}"
+                ],
+                action.action.args
+            ),
+            _ => panic!("Expected a command"),
+        }
+        assert_eq!(vec!["node_version"], manifests["node"].registered);
+    }
+
+    #[test]
+    fn it_only_keeps_outputs_registered_by_actions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("setup.yaml");
+        let source = "actions:\n  - action: command.run\n    command: sh\n    args:\n      - -c\n      - |\n        register: script\n{% if true %}\n  - action: command.run\n    command: node\n    register: node_version\n{% endif %}\n";
+
+        assert_eq!(vec!["node_version"], registered(&path, source));
+    }
+
+    #[test]
+    fn it_rejects_import_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "import:\n  - b.yaml\n").unwrap();
+        std::fs::write(dir.path().join("b.yaml"), "import:\n  - a.yaml\n").unwrap();
+
+        assert_eq!(
+            0,
+            load(dir.path().to_path_buf(), &Contexts::default()).len()
+        );
+    }
+
+    #[test]
+    fn it_instantiates_roles_with_their_arguments() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("roles")).unwrap();
+        std::fs::write(
+            dir.path().join("roles/dotfile.yaml"),
+            "parameters:\n  name: ~\n  shell: sh\n\nactions:\n  - action: command.run\n    command: \"{{ params.name }}-{{ params.shell }}\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("editor.yaml"),
+            "uses:\n  - role: roles.dotfile\n    with:\n      name: nvim\n  - role: roles.dotfile\n    with:\n      name: tmux\n      shell: zsh\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("terminal.yaml"),
+            "uses:\n  - role: roles.dotfile\n    with:\n      name: tmux\n      shell: zsh\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("broken.yaml"),
+            "uses:\n  - role: roles.dotfile\n",
+        )
+        .unwrap();
+
+        let manifests = load(dir.path().to_path_buf(), &Contexts::default());
+
+        let mut names: Vec<&String> = manifests.keys().collect();
+        names.sort();
+        assert_eq!(
+            vec![
+                "editor",
+                "roles.dotfile[name=nvim]",
+                "roles.dotfile[name=tmux,shell=zsh]",
+                "terminal"
+            ],
+            names
+        );
+        assert_eq!(
+            vec![
+                "roles.dotfile[name=nvim]",
+                "roles.dotfile[name=tmux,shell=zsh]"
+            ],
+            manifests["editor"].depends
+        );
+
+        let command = |name: &str| match &manifests[name].actions[0] {
+            crate::actions::Actions::CommandRun(action) => action.action.command.clone(),
+            _ => String::new(),
+        };
+        assert_eq!("nvim-sh", command("roles.dotfile[name=nvim]"));
+        assert_eq!("tmux-zsh", command("roles.dotfile[name=tmux,shell=zsh]"));
+    }
 }
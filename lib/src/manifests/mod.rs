@@ -1,18 +1,28 @@
+pub mod deprecations;
 mod load;
-pub use load::load;
+pub use load::{load, load_with_errors, LoadError};
+mod prompts;
+pub use prompts::{prompts, Prompt};
 mod providers;
-use crate::actions::Actions;
+mod roles;
+mod select;
+use crate::actions::{Action, Actions};
+use crate::contexts::{profile::in_profile, to_rhai, Contexts};
 use petgraph::prelude::*;
 pub use providers::register_providers;
 pub use providers::ManifestProvider;
+pub use roles::RoleUse;
 use schemars::JsonSchema;
+pub use select::{select, Selector};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
-use tracing::error;
+use tracing::{debug, error};
 
 #[derive(JsonSchema, Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Manifest {
+    /// Skips the whole manifest unless the condition is true
     #[serde(default)]
     pub r#where: Option<String>,
 
@@ -22,17 +32,208 @@ pub struct Manifest {
     #[serde(default)]
     pub labels: Vec<String>,
 
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Only applies when one of these profiles is active, e.g. with `--profile work`
+    #[serde(default)]
+    pub profiles: Vec<String>,
+
     #[serde(default)]
     pub depends: Vec<String>,
 
+    /// Runs the actions one after the other, which is the default. With
+    /// `false`, they run at once, after the actions they `depends_on` or run
+    /// `when_changed` of.
+    #[serde(default)]
+    pub ordered: Option<bool>,
+
+    /// Makes this manifest a role, which other manifests can use with their own
+    /// arguments. Parameters without a default are required.
+    #[serde(default)]
+    pub parameters: BTreeMap<String, serde_json::Value>,
+
+    /// Variables asked for when they aren't set, before the manifests are rendered
+    #[serde(default)]
+    pub prompts: BTreeMap<String, Prompt>,
+
+    /// Roles this manifest depends on, with the arguments to use them with
+    #[serde(default)]
+    pub uses: Vec<RoleUse>,
+
+    /// Files whose actions run before this manifest's actions, relative to
+    /// the manifest
+    #[serde(default)]
+    pub import: Vec<String>,
+
+    /// Run before the actions, but only when any of them has changes to make
+    #[serde(default)]
+    pub before: Vec<Actions>,
+
     #[serde(default)]
     pub actions: Vec<Actions>,
 
+    /// Run after the actions succeeded, but only when any of them had changes
+    /// to make
+    #[serde(default)]
+    pub after: Vec<Actions>,
+
+    /// Run when any of the actions failed, e.g. to clean up after a partial run
+    #[serde(default)]
+    pub on_failure: Vec<Actions>,
+
+    /// Run after the actions, whether they failed or not
+    #[serde(default)]
+    pub always: Vec<Actions>,
+
     #[serde(skip)]
     pub root_dir: Option<PathBuf>,
 
     #[serde(skip)]
     pub dag_index: Option<NodeIndex<u32>>,
+
+    /// Names outputs are registered under, by the actions of every manifest
+    /// loaded with it, which the fields of actions can refer to
+    #[serde(skip)]
+    pub registered: Vec<String>,
+}
+
+impl Manifest {
+    /// An action inherits the tags of its manifest. When `tags` is not empty,
+    /// at least one of them must be present; any tag in `skip_tags` excludes
+    /// the action.
+    pub fn is_action_selected(
+        &self,
+        action: &dyn Action,
+        tags: &[String],
+        skip_tags: &[String],
+    ) -> bool {
+        let action_tags: Vec<&String> = self.tags.iter().chain(action.tags()).collect();
+
+        if action_tags.iter().any(|tag| skip_tags.contains(tag)) {
+            return false;
+        }
+
+        tags.is_empty() || action_tags.iter().any(|tag| tags.contains(tag))
+    }
+
+    /// The actions of every block
+    pub fn all_actions(&self) -> impl Iterator<Item = &Actions> {
+        [
+            &self.before,
+            &self.actions,
+            &self.after,
+            &self.on_failure,
+            &self.always,
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    /// The actions of a block grouped into batches, by their index, where the
+    /// actions of a batch can run at once once the batches before it are done.
+    /// Every action is a batch of its own when the manifest is `ordered`.
+    /// Actions can only depend on the actions of the same block.
+    pub fn batches(&self, definitions: &[Actions]) -> anyhow::Result<Vec<Vec<usize>>> {
+        for definition in definitions {
+            for id in definition.inner_ref().depends_on() {
+                if !definitions
+                    .iter()
+                    .any(|other| other.inner_ref().id() == Some(id.as_str()))
+                {
+                    return Err(anyhow::anyhow!(
+                        "Action {} depends on '{}', but no action of the same block has that id",
+                        definition,
+                        id
+                    ));
+                }
+            }
+        }
+
+        if self.ordered.unwrap_or(true) {
+            return Ok((0..definitions.len()).map(|index| vec![index]).collect());
+        }
+
+        // Only actions of the same block are waited for
+        let dependencies: Vec<Vec<usize>> = definitions
+            .iter()
+            .map(|definition| {
+                let action = definition.inner_ref();
+                let ids: Vec<&str> = action
+                    .depends_on()
+                    .iter()
+                    .map(String::as_str)
+                    .chain(action.when_changed())
+                    .collect();
+
+                definitions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, other)| other.inner_ref().id().is_some_and(|id| ids.contains(&id)))
+                    .map(|(index, _)| index)
+                    .collect()
+            })
+            .collect();
+
+        let mut done = vec![false; definitions.len()];
+        let mut batches = vec![];
+
+        while done.iter().any(|done| !done) {
+            let batch: Vec<usize> = (0..definitions.len())
+                .filter(|index| !done[*index])
+                .filter(|index| {
+                    dependencies[*index]
+                        .iter()
+                        .all(|dependency| done[*dependency])
+                })
+                .collect();
+
+            if batch.is_empty() {
+                let cycle: Vec<String> = (0..definitions.len())
+                    .filter(|index| !done[*index])
+                    .map(|index| definitions[index].to_string())
+                    .collect();
+
+                return Err(anyhow::anyhow!(
+                    "Actions depend on each other in a cycle: {}",
+                    cycle.join(", ")
+                ));
+            }
+
+            batch.iter().for_each(|index| done[*index] = true);
+            batches.push(batch);
+        }
+
+        Ok(batches)
+    }
+
+    /// Whether the manifest belongs to the active profile, or to none at all
+    pub fn is_in_profile(&self, contexts: &Contexts) -> bool {
+        in_profile(&self.profiles, contexts)
+    }
+
+    /// Checks the manifest's profiles and evaluates its `where` condition,
+    /// manifests without either always apply
+    pub fn is_applicable(&self, contexts: &Contexts) -> anyhow::Result<bool> {
+        if !self.is_in_profile(contexts) {
+            return Ok(false);
+        }
+
+        let Some(condition) = self.r#where.as_ref() else {
+            return Ok(true);
+        };
+
+        let result = crate::rhai_functions::engine()
+            .eval_with_scope::<bool>(&mut to_rhai(contexts), condition)
+            .map_err(|err| anyhow::anyhow!("'where' condition '{condition}' failed: {err}"))?;
+
+        debug!(
+            "Result of 'where' condition '{}' -> '{}'",
+            condition, result
+        );
+
+        Ok(result)
+    }
 }
 
 pub fn resolve(uri: &String) -> Option<PathBuf> {
@@ -44,10 +245,7 @@ pub fn resolve(uri: &String) -> Option<PathBuf> {
                 return path;
             }
 
-            match provider.resolve(uri.as_str()) {
-                Ok(path) => Some(path),
-                Err(_) => None,
-            }
+            provider.resolve(uri.as_str()).ok()
         });
 
     let manifest_directory = match manifest_directory {
@@ -81,6 +279,8 @@ pub fn get_manifest_name(manifest_directory: &Path, location: &Path) -> anyhow::
 
     let manifest_name = manifest_name.trim_end_matches(".yaml");
     let manifest_name = manifest_name.trim_end_matches(".yml");
+    let manifest_name = manifest_name.trim_end_matches(".jsonnet");
+    let manifest_name = manifest_name.trim_end_matches(".cue");
 
     Ok(String::from(manifest_name.trim_end_matches(".main")))
 }
@@ -90,6 +290,90 @@ pub fn get_manifest_name(manifest_directory: &Path, location: &Path) -> anyhow::
 mod test {
     use super::*;
 
+    #[test]
+    fn it_batches_unordered_actions() {
+        let actions: Vec<Actions> = serde_yml::from_str(
+            r#"
+- action: file.link
+  from: a
+  to: b
+  id: link
+- action: command.run
+  command: rebuild
+  when_changed: link
+- action: file.link
+  from: c
+  to: d
+- action: command.run
+  command: reload
+  depends_on: [rebuild]
+- action: command.run
+  command: cache
+  id: rebuild
+  depends_on: [link]
+"#,
+        )
+        .unwrap();
+
+        let mut manifest = Manifest::default();
+        assert_eq!(
+            vec![vec![0], vec![1], vec![2], vec![3], vec![4]],
+            manifest.batches(&actions).unwrap()
+        );
+
+        manifest.ordered = Some(false);
+        assert_eq!(
+            vec![vec![0, 2], vec![1, 4], vec![3]],
+            manifest.batches(&actions).unwrap()
+        );
+
+        let cycle: Vec<Actions> = serde_yml::from_str(
+            r#"
+- action: command.run
+  command: a
+  id: a
+  depends_on: [b]
+- action: command.run
+  command: b
+  id: b
+  depends_on: [a]
+"#,
+        )
+        .unwrap();
+        assert!(manifest.batches(&cycle).is_err());
+
+        let unknown: Vec<Actions> = serde_yml::from_str(
+            r#"
+- action: command.run
+  command: a
+  depends_on: [nothing]
+"#,
+        )
+        .unwrap();
+        assert!(manifest.batches(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_where_condition() {
+        let config = crate::config::Config {
+            variables: [(String::from("work"), String::from("true"))].into(),
+            ..Default::default()
+        };
+        let contexts = crate::contexts::build_contexts(&config);
+
+        let mut manifest = Manifest::default();
+        assert!(manifest.is_applicable(&contexts).unwrap());
+
+        manifest.r#where = Some(String::from("variables.work == \"true\""));
+        assert!(manifest.is_applicable(&contexts).unwrap());
+
+        manifest.r#where = Some(String::from("variables.work == \"false\""));
+        assert!(!manifest.is_applicable(&contexts).unwrap());
+
+        manifest.r#where = Some(String::from("non.existing.variable == true"));
+        assert!(manifest.is_applicable(&contexts).is_err());
+    }
+
     #[test]
     fn test_top_level_main_yaml() {
         let manifest_directory = PathBuf::from("/tmp");
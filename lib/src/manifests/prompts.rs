@@ -0,0 +1,128 @@
+use super::load::{evaluator, manifest_files, section};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::error;
+
+/// A variable that's asked for when it isn't set, e.g. the email to commit with
+#[derive(JsonSchema, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Prompt {
+    /// The question asked, defaults to the name of the variable
+    #[serde(default)]
+    pub message: Option<String>,
+
+    /// Used when the question is answered with nothing, or can't be asked
+    #[serde(default)]
+    pub default: Option<String>,
+
+    /// The only answers allowed
+    #[serde(default)]
+    pub choices: Vec<String>,
+
+    /// Doesn't show the answer as it's typed, for passwords and tokens
+    #[serde(default)]
+    pub secret: bool,
+}
+
+/// The variables the manifests in `manifest_path` ask for. Like role
+/// parameters, prompts are read before the manifests are rendered, as the
+/// answers are needed to render them.
+pub fn prompts(manifest_path: &Path) -> BTreeMap<String, Prompt> {
+    let mut prompts = BTreeMap::new();
+
+    for path in manifest_files(manifest_path) {
+        if evaluator(&path).is_some() {
+            continue;
+        }
+
+        let source = std::fs::read_to_string(&path).unwrap_or_default();
+
+        match section::<BTreeMap<String, Prompt>>(&source, &path, "prompts") {
+            Ok(Some(section)) => {
+                for (name, prompt) in section {
+                    prompts.entry(name).or_insert(prompt);
+                }
+            }
+            Ok(None) => (),
+            Err(err) => error!("Failed to read prompts of {}: {}", path.display(), err),
+        }
+    }
+
+    prompts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_reads_prompts_before_rendering() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("git.yaml"),
+            "prompts:\n  email:\n    message: Email to commit with\n  role:\n    choices: [work, personal]\n    default: personal\n\nactions:\n  - action: command.run\n    command: \"{{ variables.email }}\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("token.toml"),
+            "[prompts.token]\nsecret = true\n\n[[actions]]\naction = \"command.run\"\ncommand = \"{{ variables.token }}\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            BTreeMap::from([
+                (
+                    String::from("email"),
+                    Prompt {
+                        message: Some(String::from("Email to commit with")),
+                        ..Default::default()
+                    }
+                ),
+                (
+                    String::from("role"),
+                    Prompt {
+                        default: Some(String::from("personal")),
+                        choices: vec![String::from("work"), String::from("personal")],
+                        ..Default::default()
+                    }
+                ),
+                (
+                    String::from("token"),
+                    Prompt {
+                        secret: true,
+                        ..Default::default()
+                    }
+                ),
+            ]),
+            prompts(dir.path())
+        );
+    }
+
+    #[test]
+    fn it_reads_prompts_in_any_style() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("inline.toml"),
+            "prompts = { email = { message = \"Email\" } }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("after.toml"),
+            "[[actions]]\naction = \"command.run\"\ncommand = \"{{ variables.token }}\"\n\n[prompts.token]\nsecret = true\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("flow.yaml"),
+            "prompts: {role: {choices: [work, personal]}}\nactions: []\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec!["email", "role", "token"],
+            prompts(dir.path()).into_keys().collect::<Vec<_>>()
+        );
+    }
+}
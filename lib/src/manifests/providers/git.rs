@@ -0,0 +1,292 @@
+use super::{ManifestProvider, ManifestProviderError};
+use crate::atoms::git::{fetch_commit, git, is_commit, resolve};
+use crate::atoms::http::is_offline;
+use std::path::{Path, PathBuf};
+use tracing::{debug, error, info, warn};
+
+/// Clones manifests from a git repository, optionally pinned to a branch, tag or
+/// commit and limited to a subdirectory: `https://github.com/me/dots//workstation?ref=v2`.
+/// Repositories are cached, and only fetched again when the ref may have moved.
+#[derive(Debug)]
+pub struct GitManifestProvider {
+    pub cache_dir: PathBuf,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct GitSource {
+    repository: String,
+    subdirectory: Option<String>,
+    reference: Option<String>,
+}
+
+impl Default for GitManifestProvider {
+    fn default() -> Self {
+        GitManifestProvider {
+            cache_dir: crate::cache::Area::Manifests.dir(),
+        }
+    }
+}
+
+impl GitSource {
+    fn parse(url: &str) -> GitSource {
+        let url = url.strip_prefix("git::").unwrap_or(url);
+
+        let (url, reference) = match url.split_once("?ref=") {
+            Some((url, reference)) => (url, Some(reference.to_string())),
+            None => (url, None),
+        };
+
+        // The subdirectory follows a double slash, after the one of the scheme
+        let scheme_end = url.find("://").map(|i| i + 3).unwrap_or(0);
+        let (repository, subdirectory) = match url[scheme_end..].split_once("//") {
+            Some((repository, subdirectory)) => (
+                format!("{}{}", &url[..scheme_end], repository),
+                Some(subdirectory.trim_matches('/').to_string()),
+            ),
+            None => (url.to_string(), None),
+        };
+
+        GitSource {
+            repository,
+            subdirectory: subdirectory.filter(|s| !s.is_empty()),
+            reference,
+        }
+    }
+
+    /// A full or abbreviated commit never moves, unlike branches and tags
+    fn is_commit(&self) -> bool {
+        self.reference.as_deref().is_some_and(is_commit)
+    }
+}
+
+impl GitManifestProvider {
+    fn checkout(&self, source: &GitSource) -> anyhow::Result<PathBuf> {
+        let checkout = self
+            .cache_dir
+            .join(sha256::digest(source.repository.as_str()));
+
+        if !checkout.join(".git").exists() {
+            std::fs::create_dir_all(&checkout)?;
+            git(&checkout, &["init", "--quiet"])?;
+            git(&checkout, &["remote", "add", "origin", &source.repository])?;
+        }
+
+        let reference = source.reference.as_deref().unwrap_or("HEAD");
+
+        if source.is_commit() {
+            if let Ok(head) = git(&checkout, &["rev-parse", "HEAD"]) {
+                if resolve(&checkout, reference) == Some(head) {
+                    debug!("{} is already checked out", reference);
+                    return Ok(checkout);
+                }
+            }
+        }
+
+        if is_offline() {
+            return match git(&checkout, &["rev-parse", "HEAD"]) {
+                Ok(_) => {
+                    debug!(
+                        "Offline, using the cached checkout of {}",
+                        source.repository
+                    );
+                    Ok(checkout)
+                }
+                Err(_) => Err(anyhow::anyhow!(
+                    "{} was never fetched, and can't be offline",
+                    source.repository
+                )),
+            };
+        }
+
+        info!("Fetching {} at {}", source.repository, reference);
+
+        let fetched = match source.is_commit() {
+            true => fetch_abbreviated(&checkout, reference),
+            false => git(
+                &checkout,
+                &["fetch", "--quiet", "--depth", "1", "origin", reference],
+            )
+            .map(|_| String::from("FETCH_HEAD")),
+        };
+
+        match fetched {
+            Ok(commit) => {
+                git(&checkout, &["checkout", "--quiet", "--force", &commit])?;
+            }
+            // Keep working offline, with what was fetched before
+            Err(err) if git(&checkout, &["rev-parse", "HEAD"]).is_ok() => {
+                warn!("Using cached manifests, {}", err);
+            }
+            Err(err) => return Err(err),
+        }
+
+        Ok(checkout)
+    }
+}
+
+/// Fetches a commit, unless the checkout has it already. Servers only hand out
+/// commits by their full SHA, so abbreviated ones are looked for in the full
+/// history of the branches and tags.
+fn fetch_abbreviated(checkout: &Path, commit: &str) -> anyhow::Result<String> {
+    if let Some(found) = resolve(checkout, commit) {
+        return Ok(found);
+    }
+
+    if commit.len() == 40 {
+        fetch_commit(checkout, commit, Some(1))?;
+    } else {
+        let mut args = vec![
+            "fetch",
+            "--quiet",
+            "--tags",
+            "origin",
+            "+refs/heads/*:refs/remotes/origin/*",
+        ];
+        if git(checkout, &["rev-parse", "--is-shallow-repository"])? == "true" {
+            args.insert(2, "--unshallow");
+        }
+        git(checkout, &args)?;
+    }
+
+    resolve(checkout, commit).ok_or_else(|| anyhow::anyhow!("{} isn't a commit", commit))
+}
+
+impl ManifestProvider for GitManifestProvider {
+    fn looks_familiar(&self, url: &str) -> bool {
+        let repository = GitSource::parse(url).repository;
+
+        url.starts_with("git::")
+            || url.starts_with("git@")
+            || url.starts_with("ssh://")
+            || repository.ends_with(".git")
+            || [
+                "https://github.com/",
+                "https://gitlab.com/",
+                "https://bitbucket.org/",
+            ]
+            .iter()
+            .any(|host| repository.starts_with(host))
+    }
+
+    fn resolve(&self, url: &str) -> Result<PathBuf, ManifestProviderError> {
+        let source = GitSource::parse(url);
+
+        let checkout = self.checkout(&source).map_err(|err| {
+            error!("Failed to clone {}: {}", source.repository, err);
+            ManifestProviderError::NoResolution
+        })?;
+        crate::cache::touch(&checkout);
+
+        let path = match &source.subdirectory {
+            Some(subdirectory) => checkout.join(subdirectory),
+            None => checkout,
+        };
+
+        path.canonicalize()
+            .map_err(|_| ManifestProviderError::NoResolution)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_parses_sources() {
+        assert_eq!(
+            GitSource {
+                repository: String::from("https://github.com/me/dots"),
+                subdirectory: Some(String::from("workstation")),
+                reference: Some(String::from("v2")),
+            },
+            GitSource::parse("https://github.com/me/dots//workstation?ref=v2")
+        );
+
+        assert_eq!(
+            GitSource {
+                repository: String::from("git@github.com:me/dots.git"),
+                subdirectory: None,
+                reference: None,
+            },
+            GitSource::parse("git@github.com:me/dots.git")
+        );
+
+        let provider = GitManifestProvider::default();
+        assert_eq!(true, provider.looks_familiar("https://github.com/me/dots"));
+        assert_eq!(true, provider.looks_familiar("git::file:///srv/dots"));
+        assert_eq!(
+            false,
+            provider.looks_familiar("https://example.com/bootstrap.yaml")
+        );
+        assert_eq!(false, provider.looks_familiar("./manifests"));
+    }
+
+    #[test]
+    fn it_checks_out_refs_and_subdirectories() {
+        let remote = tempfile::tempdir().unwrap();
+        let commit = |content: &str, tag: &str| {
+            std::fs::create_dir_all(remote.path().join("workstation")).unwrap();
+            std::fs::write(remote.path().join("workstation/main.yaml"), content).unwrap();
+            git(remote.path(), &["add", "."]).unwrap();
+            git(
+                remote.path(),
+                &[
+                    "-c",
+                    "user.name=comtrya",
+                    "-c",
+                    "user.email=comtrya@example.com",
+                    "commit",
+                    "--quiet",
+                    "-m",
+                    tag,
+                ],
+            )
+            .unwrap();
+            git(remote.path(), &["tag", tag]).unwrap();
+        };
+
+        git(remote.path(), &["init", "--quiet"]).unwrap();
+        commit("v1", "v1");
+        commit("v2", "v2");
+
+        let cache = tempfile::tempdir().unwrap();
+        let provider = GitManifestProvider {
+            cache_dir: cache.path().to_path_buf(),
+        };
+        let url = |reference: &str| {
+            format!(
+                "git::file://{}//workstation?ref={reference}",
+                remote.path().display()
+            )
+        };
+
+        let path = provider.resolve(&url("v1")).unwrap();
+        assert_eq!(
+            "v1",
+            std::fs::read_to_string(path.join("main.yaml")).unwrap()
+        );
+
+        let path = provider.resolve(&url("v2")).unwrap();
+        assert_eq!(
+            "v2",
+            std::fs::read_to_string(path.join("main.yaml")).unwrap()
+        );
+
+        // Full and abbreviated commits, which the shallow checkout doesn't have yet
+        let v1 = git(remote.path(), &["rev-parse", "v1"]).unwrap();
+        for reference in [&v1[..7], v1.as_str()] {
+            let cache = tempfile::tempdir().unwrap();
+            let provider = GitManifestProvider {
+                cache_dir: cache.path().to_path_buf(),
+            };
+            provider.resolve(&url("v2")).unwrap();
+
+            let path = provider.resolve(&url(reference)).unwrap();
+            assert_eq!(
+                "v1",
+                std::fs::read_to_string(path.join("main.yaml")).unwrap()
+            );
+        }
+    }
+}
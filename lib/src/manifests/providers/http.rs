@@ -0,0 +1,253 @@
+use super::{ManifestProvider, ManifestProviderError};
+use crate::atoms::http::download_with_headers;
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tar::Archive;
+use tracing::{error, info, warn};
+
+/// Value of the Authorization header sent with manifest downloads
+pub(crate) const AUTHORIZATION_VARIABLE: &str = "COMTRYA_AUTHORIZATION";
+
+const MANIFEST_EXTENSIONS: [&str; 5] = [".yaml", ".yml", ".toml", ".jsonnet", ".cue"];
+const TARBALL_EXTENSIONS: [&str; 3] = [".tar.gz", ".tgz", ".tar"];
+
+/// Downloads a single manifest, or a tarball of manifests, over HTTP(S)
+#[derive(Debug)]
+pub struct HttpManifestProvider {
+    pub cache_dir: PathBuf,
+    /// Sent as the Authorization header, from `COMTRYA_AUTHORIZATION` by default
+    pub authorization: Option<String>,
+}
+
+impl Default for HttpManifestProvider {
+    fn default() -> Self {
+        HttpManifestProvider {
+            cache_dir: crate::cache::Area::Manifests.dir(),
+            authorization: std::env::var(AUTHORIZATION_VARIABLE).ok(),
+        }
+    }
+}
+
+fn file_name(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    path.rsplit('/').next().unwrap_or_default()
+}
+
+impl HttpManifestProvider {
+    /// Downloads next to the copy cached by the last run, which is only replaced
+    /// once the download succeeded, and used when it didn't
+    fn fetch(&self, url: &str) -> anyhow::Result<PathBuf> {
+        let dir = self.cache_dir.join(sha256::digest(url));
+        let partial = self
+            .cache_dir
+            .join(format!("{}.partial", sha256::digest(url)));
+
+        // Start over, so files removed from a tarball don't linger
+        if partial.exists() {
+            std::fs::remove_dir_all(&partial)?;
+        }
+        std::fs::create_dir_all(&partial)?;
+
+        match self.download(url, &partial) {
+            Ok(()) => {
+                if dir.exists() {
+                    std::fs::remove_dir_all(&dir)?;
+                }
+                std::fs::rename(&partial, &dir)?;
+            }
+            Err(err) if dir.exists() => {
+                let _ = std::fs::remove_dir_all(&partial);
+                warn!(
+                    "Failed to download manifests from {}, using the copy downloaded before: {}",
+                    url, err
+                );
+            }
+            Err(err) => {
+                let _ = std::fs::remove_dir_all(&partial);
+                return Err(err);
+            }
+        }
+
+        Ok(single_directory(&dir)?.unwrap_or(dir))
+    }
+
+    fn download(&self, url: &str, dir: &Path) -> anyhow::Result<()> {
+        let file_name = file_name(url);
+        let headers: Vec<(&str, String)> = self
+            .authorization
+            .iter()
+            .map(|value| ("Authorization", value.clone()))
+            .collect();
+
+        info!("Downloading manifests from {}", url);
+
+        if MANIFEST_EXTENSIONS.iter().any(|e| file_name.ends_with(e)) {
+            return download_with_headers(url, &dir.join(file_name), &headers);
+        }
+
+        let tarball = self.cache_dir.join(format!("{}.tar", sha256::digest(url)));
+        download_with_headers(url, &tarball, &headers)?;
+        let result = unpack(&tarball, dir, file_name.ends_with(".tar"));
+        std::fs::remove_file(&tarball)?;
+
+        result
+    }
+}
+
+fn unpack(tarball: &Path, dir: &Path, uncompressed: bool) -> anyhow::Result<()> {
+    let file = File::open(tarball)?;
+
+    if uncompressed {
+        Archive::new(file).unpack(dir)?;
+    } else {
+        Archive::new(GzDecoder::new(file)).unpack(dir)?;
+    }
+
+    Ok(())
+}
+
+/// Tarballs usually contain a single top-level directory, which holds the manifests
+fn single_directory(dir: &Path) -> anyhow::Result<Option<PathBuf>> {
+    let entries: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+
+    Ok(match entries.as_slice() {
+        [entry] if entry.is_dir() => Some(entry.clone()),
+        _ => None,
+    })
+}
+
+impl ManifestProvider for HttpManifestProvider {
+    fn looks_familiar(&self, url: &str) -> bool {
+        let file_name = file_name(url);
+
+        (url.starts_with("https://") || url.starts_with("http://"))
+            && MANIFEST_EXTENSIONS
+                .iter()
+                .chain(TARBALL_EXTENSIONS.iter())
+                .any(|extension| file_name.ends_with(extension))
+    }
+
+    fn resolve(&self, url: &str) -> Result<PathBuf, ManifestProviderError> {
+        self.fetch(url)
+            .and_then(|dir| Ok(dir.canonicalize()?))
+            .map_err(|err| {
+                error!("Failed to download manifests from {}: {}", url, err);
+                ManifestProviderError::NoResolution
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Serves `body`, but only to requests with the expected Authorization header
+    fn serve(body: Vec<u8>, authorization: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = [0; 4096];
+                let read = stream.read(&mut request).unwrap_or_default();
+                let request = String::from_utf8_lossy(&request[..read]).to_lowercase();
+
+                let mut response = if request.contains(&format!("authorization: {authorization}")) {
+                    format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len())
+                        .into_bytes()
+                } else {
+                    b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n".to_vec()
+                };
+                if response.starts_with(b"HTTP/1.1 200") {
+                    response.extend_from_slice(&body);
+                }
+
+                let _ = stream.write_all(&response);
+            }
+        });
+
+        format!("http://{address}")
+    }
+
+    #[test]
+    fn it_recognizes_urls() {
+        let provider = HttpManifestProvider::default();
+
+        assert_eq!(
+            true,
+            provider.looks_familiar("https://example.com/bootstrap.yaml")
+        );
+        assert_eq!(
+            true,
+            provider.looks_familiar("https://example.com/dots.tar.gz?v=2")
+        );
+        assert_eq!(false, provider.looks_familiar("https://github.com/me/dots"));
+        assert_eq!(false, provider.looks_familiar("./bootstrap.yaml"));
+    }
+
+    #[test]
+    fn it_downloads_manifests_and_tarballs() {
+        let cache = tempfile::tempdir().unwrap();
+        let provider = HttpManifestProvider {
+            cache_dir: cache.path().to_path_buf(),
+            authorization: Some(String::from("bearer secret")),
+        };
+
+        let url = serve(b"actions: []".to_vec(), "bearer secret");
+        let dir = provider.resolve(&format!("{url}/bootstrap.yaml")).unwrap();
+        assert_eq!(
+            "actions: []",
+            std::fs::read_to_string(dir.join("bootstrap.yaml")).unwrap()
+        );
+
+        let mut tarball = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(11);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tarball
+            .append_data(&mut header, "dots/main.yaml", &b"actions: []"[..])
+            .unwrap();
+        let tarball = tarball.into_inner().unwrap();
+
+        let url = serve(tarball, "bearer secret");
+        let dir = provider.resolve(&format!("{url}/dots.tar")).unwrap();
+        assert_eq!(
+            "actions: []",
+            std::fs::read_to_string(dir.join("main.yaml")).unwrap()
+        );
+
+        let url = serve(vec![], "bearer other");
+        assert_eq!(
+            Err(ManifestProviderError::NoResolution),
+            provider.resolve(&format!("{url}/bootstrap.yaml"))
+        );
+    }
+
+    #[test]
+    fn it_keeps_what_was_downloaded_when_a_download_fails() {
+        let cache = tempfile::tempdir().unwrap();
+        let provider = HttpManifestProvider {
+            cache_dir: cache.path().to_path_buf(),
+            authorization: None,
+        };
+
+        // Cached by an earlier run
+        let url = "http://127.0.0.1:1/bootstrap.yaml";
+        let dir = cache.path().join(sha256::digest(url));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("bootstrap.yaml"), "actions: []").unwrap();
+
+        let resolved = provider.resolve(url).unwrap();
+        assert_eq!(
+            "actions: []",
+            std::fs::read_to_string(resolved.join("bootstrap.yaml")).unwrap()
+        );
+    }
+}
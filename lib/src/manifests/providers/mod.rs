@@ -1,9 +1,20 @@
+mod git;
+mod http;
 mod local;
+mod oci;
+use git::GitManifestProvider;
+use http::HttpManifestProvider;
 use local::LocalManifestProvider;
+use oci::OciManifestProvider;
 use std::path::PathBuf;
 
 pub fn register_providers() -> Vec<Box<dyn ManifestProvider>> {
-    vec![Box::new(LocalManifestProvider)]
+    vec![
+        Box::new(OciManifestProvider::default()),
+        Box::new(HttpManifestProvider::default()),
+        Box::new(GitManifestProvider::default()),
+        Box::new(LocalManifestProvider),
+    ]
 }
 
 #[derive(Debug, PartialEq, Eq)]
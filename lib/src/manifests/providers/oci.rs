@@ -0,0 +1,49 @@
+use super::{ManifestProvider, ManifestProviderError};
+use crate::oci::{Reference, Registry};
+use std::path::PathBuf;
+use tracing::{error, info};
+
+/// Pulls manifest bundles pushed with `comtrya push` from an OCI registry
+#[derive(Debug)]
+pub struct OciManifestProvider {
+    pub cache_dir: PathBuf,
+}
+
+impl Default for OciManifestProvider {
+    fn default() -> Self {
+        OciManifestProvider {
+            cache_dir: crate::cache::Area::Oci.dir(),
+        }
+    }
+}
+
+impl OciManifestProvider {
+    fn fetch(&self, url: &str) -> anyhow::Result<PathBuf> {
+        let dir = self.cache_dir.join(sha256::digest(url));
+
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+
+        info!("Pulling manifests from {}", url);
+        Registry::new(Reference::parse(url)?)?.pull(&dir)?;
+
+        Ok(dir)
+    }
+}
+
+impl ManifestProvider for OciManifestProvider {
+    fn looks_familiar(&self, url: &str) -> bool {
+        url.starts_with("oci://")
+    }
+
+    fn resolve(&self, url: &str) -> Result<PathBuf, ManifestProviderError> {
+        self.fetch(url)
+            .and_then(|dir| Ok(dir.canonicalize()?))
+            .map_err(|err| {
+                error!("Failed to pull manifests from {}: {}", url, err);
+                ManifestProviderError::NoResolution
+            })
+    }
+}
@@ -0,0 +1,196 @@
+use super::load::{read, resolve_imports, section};
+use super::Manifest;
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Instantiates a role, a manifest with `parameters`, with the given arguments
+#[derive(JsonSchema, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoleUse {
+    pub role: String,
+
+    #[serde(default)]
+    pub with: BTreeMap<String, Value>,
+}
+
+/// A manifest with `parameters`, which is only rendered once it's used
+#[derive(Debug)]
+pub(super) struct Role {
+    pub path: PathBuf,
+    pub parameters: BTreeMap<String, Value>,
+}
+
+/// Reads the `parameters` of a manifest from its source. They're needed to
+/// render the manifest, so they're read before it's rendered, and can't use
+/// template expressions themselves.
+pub(super) fn parameters(
+    source: &str,
+    path: &Path,
+) -> anyhow::Result<Option<BTreeMap<String, Value>>> {
+    Ok(
+        section::<Option<BTreeMap<String, Value>>>(source, path, "parameters")?
+            .map(|parameters| parameters.unwrap_or_default()),
+    )
+}
+
+/// Instances are named after the role and their arguments, so that roles used
+/// with the same arguments by several manifests only run once
+fn instance_name(role_use: &RoleUse) -> String {
+    if role_use.with.is_empty() {
+        return role_use.role.clone();
+    }
+
+    let arguments: Vec<String> = role_use
+        .with
+        .iter()
+        .map(|(name, value)| match value {
+            Value::String(value) => format!("{name}={value}"),
+            value => format!("{name}={value}"),
+        })
+        .collect();
+
+    format!("{}[{}]", role_use.role, arguments.join(","))
+}
+
+/// Renders the roles in `uses` with their arguments, as `params`, into
+/// `instances`, and returns the names of the instances
+pub(super) fn instantiate(
+    uses: &[RoleUse],
+    roles: &HashMap<String, Role>,
+    context: &tera::Context,
+    instances: &mut HashMap<String, Manifest>,
+    stack: &mut Vec<String>,
+    imported: &mut HashSet<PathBuf>,
+) -> anyhow::Result<Vec<String>> {
+    let mut names = vec![];
+
+    for role_use in uses {
+        let role = roles
+            .get(&role_use.role)
+            .ok_or_else(|| anyhow!("Unknown role '{}'", role_use.role))?;
+
+        let name = instance_name(role_use);
+        names.push(name.clone());
+
+        if instances.contains_key(&name) {
+            continue;
+        }
+
+        if stack.contains(&name) {
+            return Err(anyhow!("Role cycle, '{name}' uses itself"));
+        }
+
+        if let Some(unknown) = role_use
+            .with
+            .keys()
+            .find(|argument| !role.parameters.contains_key(*argument))
+        {
+            return Err(anyhow!(
+                "Role '{}' has no parameter '{unknown}'",
+                role_use.role
+            ));
+        }
+
+        let mut params = role.parameters.clone();
+        params.extend(role_use.with.clone());
+
+        // Parameters without a default are required
+        if let Some((missing, _)) = params.iter().find(|(_, value)| value.is_null()) {
+            return Err(anyhow!(
+                "Role '{}' requires parameter '{missing}'",
+                role_use.role
+            ));
+        }
+
+        let mut context = context.clone();
+        context.insert("params", &params);
+
+        let mut manifest = read(&role.path, &context)
+            .map_err(|err| anyhow!("Failed to render role '{name}': {err}"))?;
+        resolve_imports(
+            &mut manifest,
+            &role.path,
+            &context,
+            &mut vec![role.path.clone()],
+            imported,
+        )?;
+
+        stack.push(name.clone());
+        let dependencies =
+            instantiate(&manifest.uses, roles, &context, instances, stack, imported)?;
+        stack.pop();
+
+        manifest.depends.extend(dependencies);
+        manifest.root_dir = role.path.parent().map(|parent| parent.to_path_buf());
+        manifest.name = Some(name.clone());
+
+        instances.insert(name, manifest);
+    }
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_reads_parameters_before_rendering() {
+        let source = "parameters:\n  name: ~\n  target: \"~/.config\"\n\nactions:\n  - action: command.run\n    command: \"{{ params.name }}\"\n{% if true %}\n{% endif %}\n";
+
+        let defaults = parameters(source, Path::new("dotfile.yaml"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Value::Null, defaults["name"]);
+        assert_eq!(Value::from("~/.config"), defaults["target"]);
+
+        let source = "[parameters]\nname = \"nvim\"\n\n[[actions]]\naction = \"command.run\"\n";
+        let defaults = parameters(source, Path::new("dotfile.toml"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Value::from("nvim"), defaults["name"]);
+
+        // Wherever the section is, in whatever style
+        let source = "[[actions]]\naction = \"command.run\"\n\n[parameters]\nname = \"nvim\"\n";
+        let defaults = parameters(source, Path::new("dotfile.toml"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Value::from("nvim"), defaults["name"]);
+
+        let source = "parameters: {name: nvim, shell: sh}\nactions:\n  - action: command.run\n    command: {{ params.name }}\n";
+        let defaults = parameters(source, Path::new("dotfile.yaml"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Value::from("sh"), defaults["shell"]);
+
+        assert_eq!(
+            None,
+            parameters("actions: []", Path::new("main.yaml")).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_names_instances_after_their_arguments() {
+        let mut role_use = RoleUse {
+            role: String::from("roles.dotfile"),
+            with: BTreeMap::new(),
+        };
+        assert_eq!("roles.dotfile", instance_name(&role_use));
+
+        role_use
+            .with
+            .insert(String::from("name"), Value::from("nvim"));
+        role_use
+            .with
+            .insert(String::from("link"), Value::from(true));
+        assert_eq!(
+            "roles.dotfile[link=true,name=nvim]",
+            instance_name(&role_use)
+        );
+    }
+}
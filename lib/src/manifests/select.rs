@@ -0,0 +1,139 @@
+use super::Manifest;
+use globset::{Glob, GlobBuilder, GlobMatcher};
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Picks a manifest by name, by glob (`apps/*`, or `apps.*`), or by label (`label:gui`)
+#[derive(Clone, Debug)]
+pub enum Selector {
+    Label(String),
+    Glob(GlobMatcher),
+}
+
+impl Selector {
+    pub fn parse(selector: &str) -> anyhow::Result<Self> {
+        if let Some(label) = selector.strip_prefix("label:") {
+            return Ok(Selector::Label(label.to_string()));
+        }
+
+        // Manifest names use dots for directories, globs may use either
+        let glob: Glob = GlobBuilder::new(&selector.replace('.', "/"))
+            .literal_separator(true)
+            .build()?;
+
+        Ok(Selector::Glob(glob.compile_matcher()))
+    }
+
+    pub fn matches(&self, name: &str, manifest: &Manifest) -> bool {
+        match self {
+            Selector::Label(label) => manifest.labels.contains(label),
+            Selector::Glob(glob) => glob.is_match(name.replace('.', "/")),
+        }
+    }
+}
+
+/// Names of the manifests matching any of `include`, or all manifests when it's empty,
+/// without those matching any of `exclude`. Dependencies aren't resolved here.
+pub fn select(
+    manifests: &HashMap<String, Manifest>,
+    include: &[String],
+    exclude: &[String],
+) -> anyhow::Result<Vec<String>> {
+    let parse = |selectors: &[String]| -> anyhow::Result<Vec<Selector>> {
+        selectors.iter().map(|s| Selector::parse(s)).collect()
+    };
+    let (include, exclude) = (parse(include)?, parse(exclude)?);
+
+    for selector in include.iter() {
+        if !manifests
+            .iter()
+            .any(|(name, manifest)| selector.matches(name, manifest))
+        {
+            warn!("No manifests match {:?}", selector);
+        }
+    }
+
+    let mut selected: Vec<String> = manifests
+        .iter()
+        .filter(|(name, manifest)| {
+            (include.is_empty() || include.iter().any(|s| s.matches(name, manifest)))
+                && !exclude.iter().any(|s| s.matches(name, manifest))
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    selected.sort();
+
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn manifests() -> HashMap<String, Manifest> {
+        [
+            ("apps.git", vec!["cli"]),
+            ("apps.firefox", vec!["gui"]),
+            ("apps.editors.vscode", vec!["gui"]),
+            ("base", vec![]),
+        ]
+        .into_iter()
+        .map(|(name, labels)| {
+            (
+                name.to_string(),
+                Manifest {
+                    labels: labels.into_iter().map(String::from).collect(),
+                    ..Default::default()
+                },
+            )
+        })
+        .collect()
+    }
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn it_selects_by_name_and_glob() {
+        let manifests = manifests();
+
+        assert_eq!(
+            strings(&["base"]),
+            select(&manifests, &strings(&["base"]), &[]).unwrap()
+        );
+        assert_eq!(
+            strings(&["apps.firefox", "apps.git"]),
+            select(&manifests, &strings(&["apps/*"]), &[]).unwrap()
+        );
+        assert_eq!(
+            strings(&["apps.editors.vscode", "apps.firefox", "apps.git"]),
+            select(&manifests, &strings(&["apps.**"]), &[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_selects_by_label_and_excludes() {
+        let manifests = manifests();
+
+        assert_eq!(
+            strings(&["apps.editors.vscode", "apps.firefox"]),
+            select(&manifests, &strings(&["label:gui"]), &[]).unwrap()
+        );
+        assert_eq!(
+            strings(&["apps.git", "base"]),
+            select(&manifests, &[], &strings(&["label:gui"])).unwrap()
+        );
+        assert_eq!(
+            strings(&["apps.firefox"]),
+            select(
+                &manifests,
+                &strings(&["label:gui"]),
+                &strings(&["apps/editors/*"])
+            )
+            .unwrap()
+        );
+    }
+}
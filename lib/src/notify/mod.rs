@@ -0,0 +1,318 @@
+use crate::atoms::http::{proxy, user_agent};
+use crate::report::{RunReport, Status};
+use crate::secrets::Secret;
+use anyhow::{anyhow, Result};
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Where to tell how a run went, for runs in the background or on other hosts
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Notify {
+    pub kind: NotifyKind,
+
+    /// Webhook of Slack, Discord or your own, or the topic of ntfy, like
+    /// `https://ntfy.sh/my-laptop`. Not used by desktop notifications.
+    #[serde(default)]
+    pub url: Option<Secret>,
+
+    #[serde(default)]
+    pub on: NotifyOn,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyKind {
+    /// With notify-send on Linux and BSD, osascript on macOS
+    Desktop,
+    /// Posts the summary as JSON
+    Webhook,
+    Slack,
+    Discord,
+    Ntfy,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyOn {
+    #[default]
+    Always,
+    /// Only when manifests failed, didn't run, or the run was cancelled
+    Failure,
+}
+
+impl std::fmt::Display for NotifyKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            NotifyKind::Desktop => "desktop",
+            NotifyKind::Webhook => "webhook",
+            NotifyKind::Slack => "slack",
+            NotifyKind::Discord => "discord",
+            NotifyKind::Ntfy => "ntfy",
+        };
+
+        write!(f, "{kind}")
+    }
+}
+
+/// What a notification says, the body of webhooks
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub title: String,
+    pub message: String,
+    pub successful: bool,
+    pub hostname: String,
+}
+
+impl Summary {
+    pub fn new(title: &str, message: &str, successful: bool) -> Self {
+        Summary {
+            title: title.to_string(),
+            message: message.to_string(),
+            successful,
+            hostname: gethostname::gethostname().to_string_lossy().to_string(),
+        }
+    }
+
+    /// Counts the manifests of the run by outcome, naming those that failed
+    /// or didn't run
+    pub fn of_run(report: &RunReport, cancelled: bool) -> Self {
+        let names = |status: Status| -> Vec<&str> {
+            report
+                .manifests
+                .iter()
+                .filter(|manifest| manifest.status == status)
+                .map(|manifest| manifest.name.as_str())
+                .collect()
+        };
+
+        let (failed, unreachable) = (names(Status::Failed), names(Status::Unreachable));
+        let successful = !cancelled && failed.is_empty() && unreachable.is_empty();
+
+        let mut parts = vec![
+            format!("{} applied", names(Status::Applied).len()),
+            format!("{} unchanged", names(Status::Unchanged).len()),
+        ];
+        if !failed.is_empty() {
+            parts.push(format!("failed: {}", failed.join(", ")));
+        }
+        if !unreachable.is_empty() {
+            parts.push(format!("didn't run: {}", unreachable.join(", ")));
+        }
+
+        let mut summary = Summary::new("", "", successful);
+        summary.title = match (cancelled, successful) {
+            (true, _) => format!("comtrya was cancelled on {}", summary.hostname),
+            (false, true) => format!("comtrya applied on {}", summary.hostname),
+            (false, false) => format!("comtrya failed on {}", summary.hostname),
+        };
+        summary.message = format!(
+            "{} in {}s",
+            parts.join(", "),
+            report.duration_ms.div_ceil(1000)
+        );
+
+        summary
+    }
+}
+
+/// Sends the summary with every notifier that wants it. A notification that
+/// can't be sent is logged, it doesn't fail the run.
+pub fn notify(notifiers: &[Notify], summary: &Summary) {
+    for notifier in notifiers
+        .iter()
+        .filter(|notifier| notifier.on == NotifyOn::Always || !summary.successful)
+    {
+        match notifier.send(summary) {
+            Ok(()) => debug!("Sent {} notification", notifier.kind),
+            Err(err) => warn!("Failed to send {} notification: {}", notifier.kind, err),
+        }
+    }
+}
+
+impl Notify {
+    fn send(&self, summary: &Summary) -> Result<()> {
+        if self.kind == NotifyKind::Desktop {
+            return desktop(summary);
+        }
+
+        let url = self
+            .url
+            .as_ref()
+            .ok_or_else(|| anyhow!("{} notifications need a url", self.kind))?
+            .resolve()?;
+
+        let client = match proxy()? {
+            Some(proxy) => Client::builder().proxy(proxy),
+            None => Client::builder(),
+        }
+        .user_agent(user_agent())
+        .timeout(Duration::from_secs(10))
+        .build()?;
+
+        let request = match self.kind {
+            NotifyKind::Ntfy => client
+                .post(url)
+                .header("Title", &summary.title)
+                .header(
+                    "Tags",
+                    if summary.successful {
+                        "white_check_mark"
+                    } else {
+                        "x"
+                    },
+                )
+                .header(
+                    "Priority",
+                    if summary.successful {
+                        "default"
+                    } else {
+                        "high"
+                    },
+                )
+                .body(summary.message.clone()),
+            kind => {
+                let body = match kind {
+                    NotifyKind::Slack => serde_json::json!({
+                        "text": format!("*{}*\n{}", summary.title, summary.message)
+                    }),
+                    NotifyKind::Discord => serde_json::json!({
+                        "content": format!("**{}**\n{}", summary.title, summary.message)
+                    }),
+                    _ => serde_json::to_value(summary)?,
+                };
+
+                client
+                    .post(url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(body.to_string())
+            }
+        };
+
+        request.send()?.error_for_status()?;
+
+        Ok(())
+    }
+}
+
+fn desktop(summary: &Summary) -> Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification {} with title {}",
+            applescript_string(&summary.message),
+            applescript_string(&summary.title)
+        ));
+        command
+    } else if cfg!(windows) {
+        return Err(anyhow!("desktop notifications aren't supported on Windows"));
+    } else {
+        let mut command = Command::new("notify-send");
+        command.args([&summary.title, &summary.message]);
+        command
+    };
+
+    let program = command.get_program().to_string_lossy().to_string();
+    let status = command.status().map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => anyhow!("{} needs to be installed", program),
+        _ => anyhow!("Failed to run {}: {}", program, err),
+    })?;
+
+    if !status.success() {
+        return Err(anyhow!("{} exited with {}", program, status));
+    }
+
+    Ok(())
+}
+
+fn applescript_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::ManifestReport;
+    use pretty_assertions::assert_eq;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn it_summarizes_runs() {
+        let mut report = RunReport::new(false);
+        report.duration_ms = 61_200;
+        for (name, status) in [
+            ("git", Status::Applied),
+            ("zsh", Status::Failed),
+            ("fonts", Status::Unchanged),
+            ("oh-my-zsh", Status::Unreachable),
+        ] {
+            report.manifests.push(ManifestReport {
+                status,
+                ..ManifestReport::new(name)
+            });
+        }
+
+        let summary = Summary::of_run(&report, false);
+        assert!(!summary.successful);
+        assert!(summary.title.starts_with("comtrya failed on "));
+        assert_eq!(
+            "1 applied, 1 unchanged, failed: zsh, didn't run: oh-my-zsh in 62s",
+            summary.message
+        );
+
+        report.manifests.truncate(1);
+        assert!(Summary::of_run(&report, false).successful);
+        assert!(!Summary::of_run(&report, true).successful);
+    }
+
+    #[test]
+    fn it_posts_to_slack_only_on_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+            }
+
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            (&stream)
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+
+            String::from_utf8(body).unwrap()
+        });
+
+        let notifiers = [Notify {
+            kind: NotifyKind::Slack,
+            url: Some(Secret::Value(url)),
+            on: NotifyOn::Failure,
+        }];
+
+        // Never sent, so the server only sees the failure
+        notify(&notifiers, &Summary::new("applied", "all good", true));
+        notify(&notifiers, &Summary::new("failed", "zsh failed", false));
+
+        assert_eq!(
+            serde_json::json!({"text": "*failed*\nzsh failed"}),
+            serde_json::from_str::<serde_json::Value>(&server.join().unwrap()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,500 @@
+use crate::atoms::http::{proxy, user_agent};
+use anyhow::{anyhow, Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::{debug, info};
+
+/// Media type of the layer holding the manifests, a gzipped tarball
+pub const LAYER_MEDIA_TYPE: &str = "application/vnd.comtrya.manifests.v1.tar+gzip";
+pub const CONFIG_MEDIA_TYPE: &str = "application/vnd.comtrya.config.v1+json";
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// Credentials used when the registry asks for them
+pub const USERNAME_VARIABLE: &str = "COMTRYA_REGISTRY_USERNAME";
+pub const PASSWORD_VARIABLE: &str = "COMTRYA_REGISTRY_PASSWORD";
+
+/// A manifest bundle in a registry, `ghcr.io/acme/baseline:1.0`, with an optional
+/// `oci://` prefix. The tag defaults to `latest`, a digest can be used instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reference {
+    pub registry: String,
+    pub repository: String,
+    pub tag: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImageManifest {
+    schema_version: u32,
+    media_type: String,
+    artifact_type: String,
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: String,
+    digest: String,
+    size: usize,
+}
+
+impl Reference {
+    pub fn parse(reference: &str) -> Result<Self> {
+        let reference = reference.strip_prefix("oci://").unwrap_or(reference);
+
+        let (registry, rest) = reference
+            .split_once('/')
+            .ok_or_else(|| anyhow!("Expected registry/repository[:tag], got {}", reference))?;
+
+        let (repository, tag) = match rest.split_once('@') {
+            Some((repository, digest)) => (repository, digest),
+            None => match rest.rsplit_once(':') {
+                Some((repository, tag)) => (repository, tag),
+                None => (rest, "latest"),
+            },
+        };
+
+        Ok(Reference {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            tag: tag.to_string(),
+        })
+    }
+
+    /// Local registries are usually served without TLS
+    fn url(&self, path: &str) -> String {
+        let scheme = if self.registry.starts_with("localhost") || self.registry.starts_with("127.")
+        {
+            "http"
+        } else {
+            "https"
+        };
+
+        format!(
+            "{}://{}/v2/{}/{}",
+            scheme, self.registry, self.repository, path
+        )
+    }
+}
+
+impl std::fmt::Display for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let separator = if self.tag.starts_with("sha256:") {
+            '@'
+        } else {
+            ':'
+        };
+        write!(
+            f,
+            "{}/{}{}{}",
+            self.registry, self.repository, separator, self.tag
+        )
+    }
+}
+
+fn digest(content: &[u8]) -> String {
+    format!("sha256:{}", sha256::digest(content))
+}
+
+/// Packs the manifests in `dir` into a gzipped tarball
+pub fn pack(dir: &Path) -> Result<Vec<u8>> {
+    let mut archive = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+    archive.follow_symlinks(true);
+    archive.append_dir_all(".", dir)?;
+
+    Ok(archive.into_inner()?.finish()?)
+}
+
+pub fn unpack(content: &[u8], dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    tar::Archive::new(GzDecoder::new(content)).unpack(dir)?;
+
+    Ok(())
+}
+
+/// Talks to an OCI distribution registry, authenticating with a bearer token when challenged
+pub struct Registry {
+    client: Client,
+    reference: Reference,
+    token: Option<String>,
+}
+
+impl Registry {
+    pub fn new(reference: Reference) -> Result<Self> {
+        Ok(Registry {
+            client: match proxy()? {
+                Some(proxy) => Client::builder().proxy(proxy),
+                None => Client::builder(),
+            }
+            .user_agent(user_agent())
+            .build()?,
+            reference,
+            token: None,
+        })
+    }
+
+    fn send(&mut self, request: impl Fn(&Client) -> RequestBuilder) -> Result<Response> {
+        let authorized = |request: RequestBuilder, token: &Option<String>| match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+
+        let response = authorized(request(&self.client), &self.token).send()?;
+
+        if response.status() != StatusCode::UNAUTHORIZED || self.token.is_some() {
+            return Ok(response);
+        }
+
+        let challenge = response
+            .headers()
+            .get(reqwest::header::WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| anyhow!("Registry refused access to {}", self.reference))?
+            .to_string();
+
+        self.token = Some(self.authenticate(&challenge)?);
+
+        Ok(authorized(request(&self.client), &self.token).send()?)
+    }
+
+    /// Exchanges the credentials, if any, for a token at the realm named by the challenge
+    fn authenticate(&self, challenge: &str) -> Result<String> {
+        let parameters = parse_challenge(challenge)
+            .ok_or_else(|| anyhow!("Unsupported authentication challenge: {}", challenge))?;
+
+        let realm = parameters
+            .get("realm")
+            .ok_or_else(|| anyhow!("Authentication challenge without realm"))?;
+
+        let mut request = self.client.get(realm).query(
+            &parameters
+                .iter()
+                .filter(|(key, _)| *key != "realm")
+                .collect::<Vec<_>>(),
+        );
+
+        if let Ok(username) = std::env::var(USERNAME_VARIABLE) {
+            request = request.basic_auth(username, std::env::var(PASSWORD_VARIABLE).ok());
+        }
+
+        #[derive(Deserialize)]
+        struct Token {
+            token: Option<String>,
+            access_token: Option<String>,
+        }
+
+        let token: Token = serde_json::from_str(&request.send()?.error_for_status()?.text()?)?;
+
+        token
+            .token
+            .or(token.access_token)
+            .ok_or_else(|| anyhow!("Registry didn't return a token"))
+    }
+
+    fn upload_blob(&mut self, content: &[u8]) -> Result<String> {
+        let digest = digest(content);
+
+        let url = self.reference.url(&format!("blobs/{digest}"));
+        let exists = self.send(|client| client.head(&url))?;
+        if exists.status().is_success() {
+            debug!("Blob {} already exists", digest);
+            return Ok(digest);
+        }
+
+        let url = self.reference.url("blobs/uploads/");
+        let upload = self.send(|client| client.post(&url))?.error_for_status()?;
+
+        let location = upload
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| anyhow!("Registry didn't return an upload location"))?;
+
+        // The location may be relative to the registry
+        let location = upload.url().join(location)?;
+
+        self.send(|client| {
+            client
+                .put(location.clone())
+                .query(&[("digest", &digest)])
+                .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+                .body(content.to_vec())
+        })?
+        .error_for_status()?;
+
+        Ok(digest)
+    }
+
+    /// Uploads the manifests in `dir`, returning the digest of the pushed OCI manifest
+    pub fn push(&mut self, dir: &Path) -> Result<String> {
+        let layer = pack(dir)?;
+        let config = b"{}".to_vec();
+
+        let manifest = ImageManifest {
+            schema_version: 2,
+            media_type: String::from(MANIFEST_MEDIA_TYPE),
+            artifact_type: String::from(CONFIG_MEDIA_TYPE),
+            config: Descriptor {
+                media_type: String::from(CONFIG_MEDIA_TYPE),
+                digest: self.upload_blob(&config)?,
+                size: config.len(),
+            },
+            layers: vec![Descriptor {
+                media_type: String::from(LAYER_MEDIA_TYPE),
+                digest: self.upload_blob(&layer)?,
+                size: layer.len(),
+            }],
+        };
+
+        let manifest = serde_json::to_vec(&manifest)?;
+
+        let url = self
+            .reference
+            .url(&format!("manifests/{}", self.reference.tag));
+        self.send(|client| {
+            client
+                .put(&url)
+                .header(reqwest::header::CONTENT_TYPE, MANIFEST_MEDIA_TYPE)
+                .body(manifest.clone())
+        })?
+        .error_for_status()
+        .with_context(|| format!("Failed to push {}", self.reference))?;
+
+        info!("Pushed {}", self.reference);
+
+        Ok(digest(&manifest))
+    }
+
+    /// Downloads the manifests into `dir`, verifying their digest
+    pub fn pull(&mut self, dir: &Path) -> Result<()> {
+        let url = self
+            .reference
+            .url(&format!("manifests/{}", self.reference.tag));
+        let manifest = self
+            .send(|client| {
+                client
+                    .get(&url)
+                    .header(reqwest::header::ACCEPT, MANIFEST_MEDIA_TYPE)
+            })?
+            .error_for_status()
+            .with_context(|| format!("Failed to pull {}", self.reference))?
+            .bytes()?;
+
+        let manifest: ImageManifest = serde_json::from_slice(&manifest)?;
+
+        let layer = manifest
+            .layers
+            .iter()
+            .find(|layer| layer.media_type == LAYER_MEDIA_TYPE)
+            .ok_or_else(|| anyhow!("{} doesn't contain comtrya manifests", self.reference))?;
+
+        let url = self.reference.url(&format!("blobs/{}", layer.digest));
+        let content = self
+            .send(|client| client.get(&url))?
+            .error_for_status()?
+            .bytes()?;
+
+        if digest(&content) != layer.digest {
+            return Err(anyhow!(
+                "Digest of {} doesn't match, expected {}",
+                self.reference,
+                layer.digest
+            ));
+        }
+
+        unpack(&content, dir)?;
+
+        info!("Pulled {}", self.reference);
+
+        Ok(())
+    }
+}
+
+/// Parses `Bearer realm="...",service="...",scope="..."`
+fn parse_challenge(challenge: &str) -> Option<HashMap<String, String>> {
+    let parameters = challenge.strip_prefix("Bearer ")?;
+
+    Some(
+        parameters
+            .split("\",")
+            .filter_map(|parameter| {
+                let (key, value) = parameter.split_once('=')?;
+                Some((key.trim().to_string(), value.trim_matches('"').to_string()))
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_parses_references() {
+        assert_eq!(
+            Reference {
+                registry: String::from("ghcr.io"),
+                repository: String::from("acme/baseline"),
+                tag: String::from("1.0"),
+            },
+            Reference::parse("oci://ghcr.io/acme/baseline:1.0").unwrap()
+        );
+
+        let reference = Reference::parse("localhost:5000/baseline").unwrap();
+        assert_eq!("latest", reference.tag);
+        assert_eq!(
+            "http://localhost:5000/v2/baseline/manifests/latest",
+            reference.url("manifests/latest")
+        );
+
+        let reference = Reference::parse("ghcr.io/acme/baseline@sha256:abc").unwrap();
+        assert_eq!("sha256:abc", reference.tag);
+        assert_eq!("ghcr.io/acme/baseline@sha256:abc", reference.to_string());
+
+        assert_eq!(true, Reference::parse("baseline").is_err());
+    }
+
+    #[test]
+    fn it_parses_challenges() {
+        let parameters = parse_challenge(
+            r#"Bearer realm="https://ghcr.io/token",service="ghcr.io",scope="repository:acme/baseline:pull""#,
+        )
+        .unwrap();
+
+        assert_eq!("https://ghcr.io/token", parameters["realm"]);
+        assert_eq!("ghcr.io", parameters["service"]);
+        assert_eq!("repository:acme/baseline:pull", parameters["scope"]);
+    }
+
+    #[test]
+    fn it_packs_and_unpacks() {
+        let source = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("apps")).unwrap();
+        std::fs::write(source.path().join("apps/git.yaml"), "actions: []").unwrap();
+
+        let target = tempfile::tempdir().unwrap();
+        unpack(&pack(source.path()).unwrap(), target.path()).unwrap();
+
+        assert_eq!(
+            "actions: []",
+            std::fs::read_to_string(target.path().join("apps/git.yaml")).unwrap()
+        );
+    }
+
+    /// A registry that keeps everything in memory, and only accepts the token it hands out
+    fn serve() -> String {
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let realm = format!("http://{address}/token");
+
+        std::thread::spawn(move || {
+            let mut stored: HashMap<String, Vec<u8>> = HashMap::new();
+
+            for stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream);
+                let mut lines = vec![];
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    lines.push(line.trim().to_lowercase());
+                }
+
+                let length = lines
+                    .iter()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .map(|length| length.parse().unwrap())
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let (method, path) = lines[0].split_once(' ').unwrap();
+                let path = path.split(' ').next().unwrap().to_string();
+                let authorized = lines.contains(&String::from("authorization: bearer secret"));
+
+                let (status, headers, content) = match (method, path.as_str()) {
+                    ("get", path) if path.starts_with("/token") => {
+                        ("200 OK", String::new(), br#"{"token":"secret"}"#.to_vec())
+                    }
+                    _ if !authorized => (
+                        "401 Unauthorized",
+                        format!("WWW-Authenticate: Bearer realm=\"{realm}\",service=\"test\"\r\n"),
+                        vec![],
+                    ),
+                    ("post", _) => (
+                        "202 Accepted",
+                        String::from("Location: /upload\r\n"),
+                        vec![],
+                    ),
+                    ("put", path) => {
+                        let key = match path.split_once("?digest=") {
+                            Some((_, digest)) => {
+                                format!("/v2/baseline/blobs/{}", digest.replace("%3a", ":"))
+                            }
+                            None => path.to_string(),
+                        };
+                        stored.insert(key, body);
+                        ("201 Created", String::new(), vec![])
+                    }
+                    (method, path) => match stored.get(path) {
+                        Some(content) if method == "get" => {
+                            ("200 OK", String::new(), content.clone())
+                        }
+                        Some(_) => ("200 OK", String::new(), vec![]),
+                        None => ("404 Not Found", String::new(), vec![]),
+                    },
+                };
+
+                let mut stream = reader.into_inner();
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    if method == "head" { 0 } else { content.len() }
+                );
+                if method != "head" {
+                    let _ = stream.write_all(&content);
+                }
+            }
+        });
+
+        address
+    }
+
+    #[test]
+    fn it_pushes_and_pulls() {
+        let address = serve();
+        let reference = Reference::parse(&format!("oci://{address}/baseline:1.0")).unwrap();
+
+        let source = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("main.yaml"), "actions: []").unwrap();
+
+        let digest = Registry::new(reference.clone())
+            .unwrap()
+            .push(source.path())
+            .unwrap();
+        assert_eq!(true, digest.starts_with("sha256:"));
+
+        let target = tempfile::tempdir().unwrap();
+        Registry::new(reference)
+            .unwrap()
+            .pull(target.path())
+            .unwrap();
+
+        assert_eq!(
+            "actions: []",
+            std::fs::read_to_string(target.path().join("main.yaml")).unwrap()
+        );
+    }
+}
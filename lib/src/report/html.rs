@@ -0,0 +1,26 @@
+use super::RunReport;
+use std::collections::HashMap;
+use tera::{Context, Tera, Value};
+
+const TEMPLATE: &str = include_str!("report.html");
+
+/// Milliseconds as seconds, e.g. 1.5s
+fn duration(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let milliseconds = value.as_u64().unwrap_or_default();
+
+    Ok(Value::from(format!("{:.1}s", milliseconds as f64 / 1000.0)))
+}
+
+impl RunReport {
+    /// Renders the report as a self-contained HTML page
+    pub fn to_html(&self) -> anyhow::Result<String> {
+        let mut tera = Tera::default();
+        tera.register_filter("duration", duration);
+        tera.add_raw_template("report.html", TEMPLATE)?;
+
+        let mut context = Context::new();
+        context.insert("report", self);
+
+        Ok(tera.render("report.html", &context)?)
+    }
+}
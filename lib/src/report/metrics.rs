@@ -0,0 +1,194 @@
+use super::{RunReport, Status};
+use crate::atoms::http::{proxy, user_agent};
+use crate::config::Metrics;
+use anyhow::{anyhow, Result};
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use std::fmt::Write;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const STATUSES: [Status; 7] = [
+    Status::Planned,
+    Status::Applied,
+    Status::Unchanged,
+    Status::Skipped,
+    Status::Failed,
+    Status::Ignored,
+    Status::Unreachable,
+];
+
+/// Label values are quoted, with backslashes, quotes and newlines escaped
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn seconds(milliseconds: u64) -> f64 {
+    milliseconds as f64 / 1000.0
+}
+
+impl RunReport {
+    /// Renders the report as Prometheus metrics, in the text format read by the
+    /// textfile collector of node_exporter and by the Pushgateway
+    pub fn to_metrics(&self) -> String {
+        let mut metrics = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+            let _ = writeln!(metrics, "# HELP {name} {help}");
+            let _ = writeln!(metrics, "# TYPE {name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(metrics, "{name}{labels} {value}");
+            }
+        };
+
+        let steps = |status: Status, manifest: Option<&str>| {
+            self.manifests
+                .iter()
+                .filter(|m| manifest.is_none() || manifest == Some(m.name.as_str()))
+                .flat_map(|m| m.actions.iter())
+                .flat_map(|action| action.steps.iter())
+                .filter(|step| step.status == status)
+                .count() as f64
+        };
+
+        metric(
+            "comtrya_run_success",
+            "gauge",
+            "Whether every manifest of the last run succeeded",
+            vec![(String::new(), f64::from(u8::from(self.is_successful())))],
+        );
+        metric(
+            "comtrya_run_dry_run",
+            "gauge",
+            "Whether the last run was a dry-run, that only planned changes",
+            vec![(String::new(), f64::from(u8::from(self.dry_run)))],
+        );
+        metric(
+            "comtrya_run_timestamp_seconds",
+            "gauge",
+            "When the last run started, since the Unix epoch",
+            vec![(String::new(), self.started_at as f64)],
+        );
+        metric(
+            "comtrya_run_duration_seconds",
+            "gauge",
+            "How long the last run took",
+            vec![(String::new(), seconds(self.duration_ms))],
+        );
+        metric(
+            "comtrya_manifests",
+            "gauge",
+            "Manifests of the last run, by status",
+            STATUSES
+                .iter()
+                .map(|status| {
+                    let count = self
+                        .manifests
+                        .iter()
+                        .filter(|manifest| manifest.status == *status)
+                        .count();
+                    (format!("{{status=\"{status}\"}}"), count as f64)
+                })
+                .collect(),
+        );
+        metric(
+            "comtrya_steps",
+            "gauge",
+            "Steps of the last run, by status, planned steps of a dry-run are drift",
+            STATUSES
+                .iter()
+                .map(|status| (format!("{{status=\"{status}\"}}"), steps(*status, None)))
+                .collect(),
+        );
+        metric(
+            "comtrya_manifest_duration_seconds",
+            "gauge",
+            "How long each manifest of the last run took",
+            self.manifests
+                .iter()
+                .map(|manifest| {
+                    (
+                        format!("{{manifest=\"{}\"}}", label(&manifest.name)),
+                        seconds(manifest.duration_ms),
+                    )
+                })
+                .collect(),
+        );
+        metric(
+            "comtrya_manifest_steps",
+            "gauge",
+            "Steps of each manifest of the last run that changed, would change, or failed",
+            self.manifests
+                .iter()
+                .flat_map(|manifest| {
+                    [Status::Applied, Status::Planned, Status::Failed]
+                        .into_iter()
+                        .map(|status| {
+                            (
+                                format!(
+                                    "{{manifest=\"{}\",status=\"{status}\"}}",
+                                    label(&manifest.name)
+                                ),
+                                steps(status, Some(&manifest.name)),
+                            )
+                        })
+                })
+                .collect(),
+        );
+
+        metrics
+    }
+}
+
+/// Writes the metrics of the run to the textfile and Pushgateway of the config.
+/// Metrics that can't be written are logged, they don't fail the run.
+pub fn write_metrics(config: &Metrics, report: &RunReport) {
+    let metrics = report.to_metrics();
+
+    if let Some(file) = config.file.as_ref() {
+        // Written atomically, as node_exporter may read the file at any time
+        match crate::atoms::file::write_atomic(std::path::Path::new(file), metrics.as_bytes()) {
+            Ok(()) => debug!("Metrics written to {}", file),
+            Err(err) => warn!("Failed to write metrics to {}: {}", file, err),
+        }
+    }
+
+    if let Some(gateway) = config.push_gateway.as_ref() {
+        match push(gateway, &config.job, &metrics) {
+            Ok(()) => debug!("Metrics pushed to {}", gateway),
+            Err(err) => warn!("Failed to push metrics to {}: {}", gateway, err),
+        }
+    }
+}
+
+/// Replaces the metrics of this job and machine on the Pushgateway
+fn push(gateway: &str, job: &str, metrics: &str) -> Result<()> {
+    let url = format!(
+        "{}/metrics/job/{}/instance/{}",
+        gateway.trim_end_matches('/'),
+        job,
+        gethostname::gethostname().to_string_lossy()
+    );
+
+    let client = match proxy()? {
+        Some(proxy) => Client::builder().proxy(proxy),
+        None => Client::builder(),
+    }
+    .user_agent(user_agent())
+    .timeout(Duration::from_secs(10))
+    .build()?;
+
+    let response = client
+        .put(url)
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(metrics.to_string())
+        .send()?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!("the Pushgateway answered {}", response.status()));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,374 @@
+mod html;
+mod metrics;
+
+use crate::atoms::SideEffect;
+use serde::{Deserialize, Serialize};
+
+pub use metrics::write_metrics;
+
+/// The outcome of a manifest, action or step during a run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Would have been applied, but this is a dry-run
+    Planned,
+    Applied,
+    /// Already in the desired state
+    Unchanged,
+    Skipped,
+    Failed,
+    /// Failed, but the action has `ignore_errors` set
+    Ignored,
+    /// Not run, because something before it failed
+    Unreachable,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            Status::Planned => "planned",
+            Status::Applied => "applied",
+            Status::Unchanged => "unchanged",
+            Status::Skipped => "skipped",
+            Status::Failed => "failed",
+            Status::Ignored => "ignored",
+            Status::Unreachable => "unreachable",
+        };
+
+        write!(f, "{status}")
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RunReport {
+    /// Identifies the run for `comtrya rollback`, not set for dry-runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+
+    pub dry_run: bool,
+
+    /// Seconds since the Unix epoch
+    #[serde(default)]
+    pub started_at: u64,
+
+    #[serde(default)]
+    pub duration_ms: u64,
+
+    pub manifests: Vec<ManifestReport>,
+
+    /// The steps that took longest, slowest first, to find what's worth caching
+    /// or running at once
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub slowest_steps: Vec<StepTiming>,
+}
+
+/// How many steps `slowest_steps` of a report has
+pub const SLOWEST_STEPS: usize = 10;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepTiming {
+    pub manifest: String,
+    pub action: String,
+    pub atom: String,
+    pub duration_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestReport {
+    pub name: String,
+    pub status: Status,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    #[serde(default)]
+    pub duration_ms: u64,
+
+    #[serde(default)]
+    pub actions: Vec<ActionReport>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActionReport {
+    pub action: String,
+    pub summary: String,
+    pub status: Status,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(default)]
+    pub duration_ms: u64,
+
+    #[serde(default)]
+    pub steps: Vec<StepReport>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StepReport {
+    pub atom: String,
+    pub status: Status,
+
+    /// Why the step was skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// What the step printed, stdout followed by stderr
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+
+    /// What the step changes, or would change in a dry-run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub side_effects: Vec<SideEffect>,
+
+    #[serde(default)]
+    pub duration_ms: u64,
+}
+
+impl RunReport {
+    pub fn new(dry_run: bool) -> Self {
+        RunReport {
+            run_id: None,
+            dry_run,
+            started_at: crate::state::now(),
+            duration_ms: 0,
+            manifests: vec![],
+            slowest_steps: vec![],
+        }
+    }
+
+    /// The steps that ran, slowest first, leaving out those that took no time
+    pub fn slowest(&self, count: usize) -> Vec<StepTiming> {
+        let mut steps = self
+            .manifests
+            .iter()
+            .flat_map(|manifest| {
+                manifest.actions.iter().flat_map(move |action| {
+                    action.steps.iter().map(move |step| StepTiming {
+                        manifest: manifest.name.clone(),
+                        action: action.summary.clone(),
+                        atom: step.atom.clone(),
+                        duration_ms: step.duration_ms,
+                    })
+                })
+            })
+            .filter(|step| step.duration_ms > 0)
+            .collect::<Vec<StepTiming>>();
+
+        steps.sort_by_key(|step| std::cmp::Reverse(step.duration_ms));
+        steps.truncate(count);
+
+        steps
+    }
+
+    pub fn is_successful(&self) -> bool {
+        self.manifests
+            .iter()
+            .all(|manifest| manifest.status != Status::Failed)
+    }
+}
+
+impl ManifestReport {
+    pub fn new(name: &str) -> Self {
+        ManifestReport {
+            name: name.to_string(),
+            status: Status::Unchanged,
+            reason: None,
+            duration_ms: 0,
+            actions: vec![],
+        }
+    }
+
+    pub fn skipped(name: &str, reason: &str) -> Self {
+        ManifestReport {
+            status: Status::Skipped,
+            reason: Some(reason.to_string()),
+            ..ManifestReport::new(name)
+        }
+    }
+
+    pub fn unreachable(name: &str, reason: &str) -> Self {
+        ManifestReport {
+            status: Status::Unreachable,
+            reason: Some(reason.to_string()),
+            ..ManifestReport::new(name)
+        }
+    }
+
+    pub fn failed(name: &str, reason: &str) -> Self {
+        ManifestReport {
+            status: Status::Failed,
+            reason: Some(reason.to_string()),
+            ..ManifestReport::new(name)
+        }
+    }
+}
+
+impl StepReport {
+    pub fn skipped(atom: String, reason: &str) -> Self {
+        StepReport {
+            status: Status::Skipped,
+            reason: Some(reason.to_string()),
+            ..StepReport::new(atom)
+        }
+    }
+
+    pub fn new(atom: String) -> Self {
+        StepReport {
+            atom,
+            status: Status::Planned,
+            reason: None,
+            error: None,
+            output: None,
+            side_effects: vec![],
+            duration_ms: 0,
+        }
+    }
+}
+
+impl ActionReport {
+    pub fn new(action: &str, summary: String) -> Self {
+        ActionReport {
+            action: action.to_string(),
+            summary,
+            status: Status::Unchanged,
+            error: None,
+            duration_ms: 0,
+            steps: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_can_serialize() {
+        let mut report = RunReport::new(true);
+        let mut manifest = ManifestReport::new("dotfiles");
+        let mut action = ActionReport::new("file.copy", String::from("Copy file from a to b"));
+
+        action.status = Status::Planned;
+        action.steps.push(StepReport {
+            side_effects: vec![SideEffect::Write {
+                path: std::path::PathBuf::from("b"),
+            }],
+            ..StepReport::new(String::from("The file b needs to be created"))
+        });
+        manifest.status = Status::Planned;
+        manifest.actions.push(action);
+        report.manifests.push(manifest);
+        report.manifests.push(ManifestReport::skipped(
+            "server",
+            "where condition was false",
+        ));
+
+        let json = serde_json::to_value(&report).unwrap();
+
+        assert_eq!(true, report.is_successful());
+        assert_eq!("planned", json["manifests"][0]["actions"][0]["status"]);
+        assert_eq!("skipped", json["manifests"][1]["status"]);
+        assert_eq!("where condition was false", json["manifests"][1]["reason"]);
+        assert_eq!(None, json["manifests"][0].get("reason"));
+        assert_eq!(
+            None,
+            json["manifests"][0]["actions"][0]["steps"][0].get("output")
+        );
+        assert_eq!(
+            serde_json::json!([{ "kind": "write", "path": "b" }]),
+            json["manifests"][0]["actions"][0]["steps"][0]["side_effects"]
+        );
+    }
+
+    #[test]
+    fn it_finds_the_slowest_steps() {
+        let mut report = RunReport::new(false);
+        let mut manifest = ManifestReport::new("dotfiles");
+        let mut action = ActionReport::new("package.install", String::from("Install packages"));
+
+        for (atom, duration_ms) in [
+            ("refresh", 2000),
+            ("install", 9000),
+            ("clean", 0),
+            ("link", 5),
+        ] {
+            action.steps.push(StepReport {
+                duration_ms,
+                ..StepReport::new(String::from(atom))
+            });
+        }
+        manifest.actions.push(action);
+        report.manifests.push(manifest);
+
+        assert_eq!(
+            vec![("install", 9000), ("refresh", 2000)],
+            report
+                .slowest(2)
+                .iter()
+                .map(|step| (step.atom.as_str(), step.duration_ms))
+                .collect::<Vec<(&str, u64)>>()
+        );
+        assert_eq!(3, report.slowest(SLOWEST_STEPS).len());
+    }
+
+    #[test]
+    fn it_can_render_html() {
+        let mut report = RunReport::new(false);
+        let mut manifest = ManifestReport::new("dotfiles");
+        let mut action = ActionReport::new("command.run", String::from("Running echo command"));
+        let mut step = StepReport::new(String::from("echo <hi>"));
+
+        step.status = Status::Failed;
+        step.output = Some(String::from("<hi>"));
+        step.duration_ms = 1500;
+        action.status = Status::Failed;
+        action.steps.push(step);
+        manifest.status = Status::Failed;
+        manifest.actions.push(action);
+        report.manifests.push(manifest);
+
+        let html = report.to_html().unwrap();
+
+        assert_eq!(true, html.starts_with("<!DOCTYPE html>"));
+        assert_eq!(true, html.contains("echo &lt;hi&gt;"));
+        assert_eq!(true, html.contains("<pre>&lt;hi&gt;</pre>"));
+        assert_eq!(true, html.contains("1.5s"));
+        assert_eq!(true, html.contains(r#"class="failed""#));
+    }
+
+    #[test]
+    fn it_can_render_metrics() {
+        let mut report = RunReport::new(true);
+        let mut manifest = ManifestReport::new("my \"dotfiles\"");
+        let mut action = ActionReport::new("file.copy", String::from("Copy file from a to b"));
+
+        action
+            .steps
+            .push(StepReport::new(String::from("b needs to be created")));
+        action
+            .steps
+            .push(StepReport::new(String::from("b needs mode 644")));
+        manifest.status = Status::Planned;
+        manifest.duration_ms = 1500;
+        manifest.actions.push(action);
+        report.manifests.push(manifest);
+
+        let metrics = report.to_metrics();
+
+        assert!(metrics.contains("# TYPE comtrya_run_success gauge\ncomtrya_run_success 1\n"));
+        assert!(metrics.contains("comtrya_run_dry_run 1\n"));
+        assert!(metrics.contains("comtrya_manifests{status=\"planned\"} 1\n"));
+        assert!(metrics.contains("comtrya_steps{status=\"planned\"} 2\n"));
+        assert!(metrics.contains("comtrya_steps{status=\"failed\"} 0\n"));
+        assert!(metrics
+            .contains("comtrya_manifest_duration_seconds{manifest=\"my \\\"dotfiles\\\"\"} 1.5\n"));
+        assert!(metrics.contains(
+            "comtrya_manifest_steps{manifest=\"my \\\"dotfiles\\\"\",status=\"planned\"} 2\n"
+        ));
+    }
+}
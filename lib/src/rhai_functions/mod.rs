@@ -0,0 +1,191 @@
+use crate::steps::initializers::{CommandSucceeds, Initializer};
+use rhai::{Array, Dynamic, Engine, EvalAltResult};
+use std::cmp::Ordering;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Environment variable, or an empty string when it isn't set
+fn env(name: &str) -> String {
+    std::env::var(name).unwrap_or_default()
+}
+
+fn file_exists(path: &str) -> bool {
+    let path = match (path.strip_prefix("~/"), dirs_next::home_dir()) {
+        (Some(path), Some(home)) => home.join(path),
+        _ => PathBuf::from(path),
+    };
+
+    path.exists()
+}
+
+/// Runs the command, whenever the condition is evaluated, also in dry-runs,
+/// `validate` and `graph`
+fn command_success(command: &str) -> bool {
+    CommandSucceeds {
+        command: command.to_string(),
+        dir: None,
+    }
+    .initialize()
+    .unwrap_or(false)
+}
+
+/// Versions are compared leniently: a leading `v` is ignored, and missing
+/// minor and patch numbers count as 0
+fn version(version: &str) -> Result<semver::Version, Box<EvalAltResult>> {
+    let version = version.trim().trim_start_matches('v');
+
+    let (core, rest) = match version.find(['-', '+']) {
+        Some(index) => version.split_at(index),
+        None => (version, ""),
+    };
+
+    let mut numbers: Vec<&str> = core.split('.').collect();
+    numbers.resize(3.max(numbers.len()), "0");
+
+    semver::Version::parse(&format!("{}{}", numbers.join("."), rest))
+        .map_err(|err| format!("Invalid version '{version}': {err}").into())
+}
+
+fn semver_ge(a: &str, b: &str) -> Result<bool, Box<EvalAltResult>> {
+    Ok(version(a)? >= version(b)?)
+}
+
+/// The numbers of distro style versions, like `22.04` or `12.7`, which aren't
+/// valid semver. Anything after them, like ` LTS`, is ignored.
+pub(crate) fn version_numbers(version: &str) -> Vec<u64> {
+    version
+        .trim()
+        .trim_start_matches('v')
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map_while(|number| number.parse::<u64>().ok())
+        .collect()
+}
+
+fn compare_versions(a: &str, b: &str) -> Result<Ordering, Box<EvalAltResult>> {
+    let (mut a_numbers, mut b_numbers) = (version_numbers(a), version_numbers(b));
+
+    if a_numbers.is_empty() || b_numbers.is_empty() {
+        return Err(format!("Can't compare versions '{a}' and '{b}'").into());
+    }
+
+    let length = a_numbers.len().max(b_numbers.len());
+    a_numbers.resize(length, 0);
+    b_numbers.resize(length, 0);
+
+    Ok(a_numbers.cmp(&b_numbers))
+}
+
+/// The version of the operating system, as in `os.version`
+fn os_version() -> &'static str {
+    static VERSION: OnceLock<String> = OnceLock::new();
+    VERSION.get_or_init(|| os_info::get().version().to_string())
+}
+
+fn version_ge(a: &str, b: &str) -> Result<bool, Box<EvalAltResult>> {
+    Ok(compare_versions(a, b)? != Ordering::Less)
+}
+
+fn version_lt(a: &str, b: &str) -> Result<bool, Box<EvalAltResult>> {
+    Ok(compare_versions(a, b)? == Ordering::Less)
+}
+
+// Dynamic values can't be compared directly. Named apart from Rhai's own
+// `contains`, which it would replace for arrays.
+fn list_contains(list: Array, item: Dynamic) -> bool {
+    list.iter()
+        .any(|value| value.type_id() == item.type_id() && value.to_string() == item.to_string())
+}
+
+pub fn register_functions(engine: &mut Engine) {
+    engine.register_fn("env", env);
+    engine.register_fn("file_exists", file_exists);
+    engine.register_fn("command_success", command_success);
+    engine.register_fn("semver_ge", semver_ge);
+    engine.register_fn("list_contains", list_contains);
+    engine.register_fn("version_ge", version_ge);
+    engine.register_fn("version_ge", |minimum: &str| {
+        version_ge(os_version(), minimum)
+    });
+    engine.register_fn("version_lt", version_lt);
+    engine.register_fn("version_lt", |maximum: &str| {
+        version_lt(os_version(), maximum)
+    });
+}
+
+/// Engine for `where` conditions and scripts, with comtrya's functions
+pub fn engine() -> Engine {
+    let mut engine = Engine::new();
+    register_functions(&mut engine);
+    engine
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn eval(condition: &str) -> bool {
+        engine().eval::<bool>(condition).unwrap()
+    }
+
+    #[test]
+    fn can_read_the_environment() {
+        std::env::set_var("COMTRYA_RHAI_TEST", "yes");
+
+        assert_eq!(true, eval(r#"env("COMTRYA_RHAI_TEST") == "yes""#));
+        assert_eq!(true, eval(r#"env("COMTRYA_RHAI_UNSET") == """#));
+    }
+
+    #[test]
+    fn can_check_files() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+
+        assert_eq!(
+            true,
+            eval(&format!(r#"file_exists("{}")"#, file.path().display()))
+        );
+        assert_eq!(false, eval(r#"file_exists("/does/not/exist")"#));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn can_check_commands() {
+        assert_eq!(true, eval(r#"command_success("true")"#));
+        assert_eq!(false, eval(r#"command_success("exit 3")"#));
+    }
+
+    #[test]
+    fn can_compare_versions() {
+        assert_eq!(true, eval(r#"semver_ge("1.10.0", "1.9.3")"#));
+        assert_eq!(true, eval(r#"semver_ge("v2", "2.0.0")"#));
+        assert_eq!(false, eval(r#"semver_ge("1.2.3-beta.1", "1.2.3")"#));
+        assert_eq!(
+            true,
+            engine().eval::<bool>(r#"semver_ge("x", "1")"#).is_err()
+        );
+    }
+
+    #[test]
+    fn can_compare_distro_versions() {
+        assert_eq!(true, eval(r#"version_ge("22.04", "20.10")"#));
+        assert_eq!(true, eval(r#"version_ge("24.04 LTS", "24.4")"#));
+        assert_eq!(false, eval(r#"version_ge("12", "12.1")"#));
+        assert_eq!(true, eval(r#"version_lt("9.3", "10")"#));
+        assert_eq!(
+            true,
+            engine()
+                .eval::<bool>(r#"version_ge("rolling", "1")"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn can_check_lists() {
+        assert_eq!(true, eval(r#"list_contains(["docker", "dev"], "dev")"#));
+        assert_eq!(false, eval(r#"list_contains(["docker", "dev"], "work")"#));
+        assert_eq!(false, eval(r#"list_contains([1, 2], "1")"#));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::atoms::{command::Exec, Atom};
+use crate::state::now;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+const JOURNAL: &str = "journal.json";
+const BACKUPS: &str = "backups";
+
+/// A single operation that reverses a change made during a run
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Undo {
+    /// The file existed before the run, put the backup back
+    RestoreFile { path: PathBuf, backup: PathBuf },
+    /// The file didn't exist before the run
+    RemoveFile { path: PathBuf },
+    /// Run a command that reverses the change, such as uninstalling packages
+    Command {
+        command: String,
+        args: Vec<String>,
+        privileged: bool,
+    },
+}
+
+/// Records how to undo each change of a run, in the order they were applied
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Journal {
+    pub run_id: String,
+
+    #[serde(default)]
+    pub entries: Vec<Undo>,
+
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+pub fn default_runs_dir() -> Option<PathBuf> {
+    dirs_next::data_local_dir().map(|dir| dir.join("comtrya").join("runs"))
+}
+
+/// Backs up `path` into `backup_dir`, so it can be restored by a rollback.
+/// When the file doesn't exist yet, undoing means removing it.
+pub fn snapshot(path: &Path, backup_dir: &Path) -> Result<Undo> {
+    if !path.is_file() {
+        return Ok(Undo::RemoveFile {
+            path: path.to_path_buf(),
+        });
+    }
+
+    std::fs::create_dir_all(backup_dir)?;
+
+    let backup = backup_dir.join(sha256::digest(path.display().to_string()));
+
+    // The first backup of a run holds the original contents
+    if !backup.exists() {
+        std::fs::copy(path, &backup)
+            .with_context(|| format!("Unable to back up {}", path.display()))?;
+    }
+
+    Ok(Undo::RestoreFile {
+        path: path.to_path_buf(),
+        backup,
+    })
+}
+
+impl Undo {
+    fn apply(&self) -> Result<()> {
+        match self {
+            Undo::RestoreFile { path, backup } => {
+                std::fs::copy(backup, path)
+                    .with_context(|| format!("Unable to restore {}", path.display()))?;
+            }
+            Undo::RemoveFile { path } => {
+                if path.is_file() {
+                    std::fs::remove_file(path)?;
+                }
+            }
+            Undo::Command {
+                command,
+                args,
+                privileged,
+            } => {
+                Exec {
+                    command: command.clone(),
+                    arguments: args.clone(),
+                    privileged: *privileged,
+                    ..Default::default()
+                }
+                .execute()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Undo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Undo::RestoreFile { path, .. } => write!(f, "Restore {}", path.display()),
+            Undo::RemoveFile { path } => write!(f, "Remove {}", path.display()),
+            Undo::Command { command, args, .. } => {
+                write!(f, "Run {} {}", command, args.join(" "))
+            }
+        }
+    }
+}
+
+impl Journal {
+    pub fn create(runs_dir: &Path) -> Result<Journal> {
+        let mut run_id = now().to_string();
+        let mut suffix = 0;
+
+        while runs_dir.join(&run_id).exists() {
+            suffix += 1;
+            run_id = format!("{}-{}", now(), suffix);
+        }
+
+        Ok(Journal {
+            dir: runs_dir.join(&run_id),
+            run_id,
+            entries: vec![],
+        })
+    }
+
+    pub fn open(runs_dir: &Path, run_id: &str) -> Result<Journal> {
+        let dir = runs_dir.join(run_id);
+        let contents = std::fs::read_to_string(dir.join(JOURNAL))
+            .with_context(|| format!("No rollback information for run {}", run_id))?;
+
+        let mut journal: Journal = serde_json::from_str(&contents)?;
+        journal.dir = dir;
+
+        Ok(journal)
+    }
+
+    /// The most recent run that recorded any changes
+    pub fn latest(runs_dir: &Path) -> Result<Option<String>> {
+        if !runs_dir.is_dir() {
+            return Ok(None);
+        }
+
+        let mut runs: Vec<(std::time::SystemTime, String)> = std::fs::read_dir(runs_dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().join(JOURNAL).is_file())
+            .filter_map(|entry| {
+                let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+                Some((modified, entry.file_name().to_string_lossy().to_string()))
+            })
+            .collect();
+
+        runs.sort();
+
+        Ok(runs.pop().map(|(_, run_id)| run_id))
+    }
+
+    pub fn backup_dir(&self) -> PathBuf {
+        self.dir.join(BACKUPS)
+    }
+
+    /// Persisted after every entry, so an interrupted run can still be rolled back
+    pub fn record(&mut self, undo: Undo) -> Result<()> {
+        self.entries.push(undo);
+
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.dir.join(JOURNAL), serde_json::to_string_pretty(&self)?)?;
+
+        Ok(())
+    }
+
+    /// Reverses the run, most recent change first
+    pub fn rollback(&self) -> Result<()> {
+        let mut failures = 0;
+
+        for undo in self.entries.iter().rev() {
+            info!("{}", undo);
+
+            if let Err(err) = undo.apply() {
+                warn!("Failed to {}: {}", undo.to_string().to_lowercase(), err);
+                failures += 1;
+            }
+        }
+
+        match failures {
+            0 => Ok(()),
+            n => Err(anyhow!(
+                "{} changes of run {} could not be undone",
+                n,
+                self.run_id
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_can_rollback() {
+        let runs = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+
+        let existing = files.path().join("existing");
+        let created = files.path().join("created");
+        std::fs::write(&existing, "original").unwrap();
+
+        let mut journal = Journal::create(runs.path()).unwrap();
+        let backup_dir = journal.backup_dir();
+
+        journal
+            .record(snapshot(&existing, &backup_dir).unwrap())
+            .unwrap();
+        std::fs::write(&existing, "changed").unwrap();
+
+        journal
+            .record(snapshot(&created, &backup_dir).unwrap())
+            .unwrap();
+        std::fs::write(&created, "new").unwrap();
+
+        assert_eq!(
+            Some(journal.run_id.clone()),
+            Journal::latest(runs.path()).unwrap()
+        );
+
+        Journal::open(runs.path(), &journal.run_id)
+            .unwrap()
+            .rollback()
+            .unwrap();
+
+        assert_eq!("original", std::fs::read_to_string(&existing).unwrap());
+        assert_eq!(false, created.exists());
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::anyhow;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A value that shouldn't be written in manifests, such as a token or password.
+/// It's read from an environment variable or a file when it's needed, so it
+/// doesn't show up in plans, reports or logs.
+#[derive(JsonSchema, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Secret {
+    /// Read from this environment variable
+    Env { env: String },
+    /// Read from this file, without its trailing newline
+    File { file: String },
+    /// Written in the manifest as is
+    Value(String),
+}
+
+impl Secret {
+    pub fn resolve(&self) -> anyhow::Result<String> {
+        match self {
+            Secret::Env { env } => {
+                std::env::var(env).map_err(|_| anyhow!("Environment variable {} isn't set", env))
+            }
+            Secret::File { file } => std::fs::read_to_string(file)
+                .map(|contents| contents.trim_end_matches(['\r', '\n']).to_string())
+                .map_err(|err| anyhow!("Unable to read secret from {}: {}", file, err)),
+            Secret::Value(value) => Ok(value.clone()),
+        }
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Secret::Env { env } => write!(f, "Secret(env: {env})"),
+            Secret::File { file } => write!(f, "Secret(file: {file})"),
+            Secret::Value(_) => write!(f, "Secret(<redacted>)"),
+        }
+    }
+}
+
+/// Credentials for HTTP basic authentication
+#[derive(JsonSchema, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: Secret,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_resolves_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("token");
+        std::fs::write(&file, "from-file\n").unwrap();
+        std::env::set_var("COMTRYA_TEST_SECRET", "from-env");
+
+        let secrets: Vec<Secret> = serde_yml::from_str(&format!(
+            "- plain\n- env: COMTRYA_TEST_SECRET\n- file: {}\n- env: COMTRYA_TEST_UNSET_SECRET\n",
+            file.display()
+        ))
+        .unwrap();
+
+        assert_eq!("plain", secrets[0].resolve().unwrap());
+        assert_eq!("from-env", secrets[1].resolve().unwrap());
+        assert_eq!("from-file", secrets[2].resolve().unwrap());
+        assert_eq!(true, secrets[3].resolve().is_err());
+        assert_eq!("Secret(<redacted>)", format!("{:?}", secrets[0]));
+    }
+}
@@ -0,0 +1,1467 @@
+use crate::actions::Actions;
+use crate::atoms::command::{may_elevate, PRIVILEGES_NOT_ALLOWED};
+use crate::atoms::SideEffect;
+use crate::audit::{AuditEntry, AuditLog};
+use crate::config::Config;
+use crate::contexts::{build_contexts, merge_changes, register, set_facts, Contexts};
+use crate::manifests::{load_with_errors, select, LoadError, Manifest};
+use crate::report::{ActionReport, ManifestReport, RunReport, Status, StepReport, SLOWEST_STEPS};
+use crate::rollback::Journal;
+use crate::state::{self, ActionState, Checkpoint, ManifestState, State};
+use crate::steps::Step;
+use anyhow::anyhow;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, span, warn};
+
+mod observer;
+pub use observer::Observer;
+
+/// Loads, plans and applies manifests. This is what `comtrya apply` runs, and
+/// what tools that embed comtrya use instead of the CLI. Manifests run after
+/// the manifests they depend on, up to `jobs` at once. So do the actions of
+/// manifests that aren't `ordered`, after the actions they depend on.
+///
+/// ```no_run
+/// use comtrya_lib::config::Config;
+/// use comtrya_lib::session::{RunOptions, Session};
+///
+/// let mut session = Session::new(Config::default());
+/// for error in session.load("./manifests") {
+///     eprintln!("{error}");
+/// }
+///
+/// let report = session.run(&RunOptions {
+///     dry_run: true,
+///     ..Default::default()
+/// })?;
+///
+/// for manifest in report.manifests {
+///     println!("{} {}", manifest.name, manifest.status);
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Session {
+    config: Config,
+    contexts: Contexts,
+    manifests: HashMap<String, Manifest>,
+    observers: Vec<Box<dyn Observer>>,
+}
+
+/// What [`Session::run`] runs, and how
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    /// Plan every step without changing the system
+    pub dry_run: bool,
+
+    /// Names, globs or labels of the manifests to run with their dependencies,
+    /// every manifest when empty
+    pub manifests: Vec<String>,
+
+    /// Manifests to leave out, unless another manifest depends on them
+    pub exclude: Vec<String>,
+
+    /// Only run manifests with this label
+    pub label: Option<String>,
+
+    /// Only run actions with one of these tags
+    pub tags: Vec<String>,
+
+    /// Skip actions with any of these tags
+    pub skip_tags: Vec<String>,
+
+    /// Continue with the remaining manifests when one fails
+    pub keep_going: bool,
+
+    /// Tell observers what each step would change in files
+    pub diff: bool,
+
+    /// Independent manifests to run at once, one when 0
+    pub jobs: usize,
+
+    /// Run one step at a time, for observers that confirm each step with a
+    /// person
+    pub interactive: bool,
+}
+
+/// What a run is recorded in, and what the runs before it did
+#[derive(Default)]
+pub struct Records {
+    /// The state of the last runs, updated with the manifests that ran to
+    /// the end
+    pub state: State,
+
+    /// What the cancelled run being resumed had done
+    pub resumed: Option<Checkpoint>,
+
+    /// Where every change is recorded, for `comtrya rollback`
+    pub journal: Option<Journal>,
+
+    /// Where privileged commands and file changes are appended
+    pub audit: Option<AuditLog>,
+}
+
+/// What [`Session::run_with`] did
+pub struct RunOutcome {
+    pub report: RunReport,
+
+    /// The state of the last runs, with the manifests of this one that ran
+    /// to the end. Unchanged by a dry-run.
+    pub state: State,
+
+    /// What this run got done, to carry on from when it was cancelled
+    pub checkpoint: Checkpoint,
+
+    pub journal: Option<Journal>,
+}
+
+/// The steps an action would run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedAction {
+    pub action: String,
+    pub summary: String,
+    pub steps: Vec<String>,
+    /// What the steps change
+    pub side_effects: Vec<SideEffect>,
+}
+
+/// What the manifests of a run share, including those running at once
+struct Run<'a> {
+    options: &'a RunOptions,
+
+    /// Contexts registered by actions, for the manifests that run after them
+    contexts: Mutex<Contexts>,
+
+    /// Ids of the actions that changed something, for `when_changed`
+    changed: Mutex<HashSet<String>>,
+
+    /// Held by actions using a package manager, which locks its database, so
+    /// only one of them runs at a time
+    packages: Mutex<()>,
+
+    previous: &'a State,
+    resumed: Option<&'a Checkpoint>,
+    journal: Option<&'a Mutex<Journal>>,
+    audit: Option<&'a AuditLog>,
+}
+
+/// What running an action did, for the report and state of its manifest
+struct ActionOutcome {
+    report: ActionReport,
+    /// Recorded in the state file, to detect drift
+    state: Option<ActionState>,
+    /// The run was cancelled before every step ran
+    cancelled: bool,
+}
+
+impl ActionOutcome {
+    fn done(report: ActionReport, state: Option<ActionState>) -> Self {
+        ActionOutcome {
+            report,
+            state,
+            cancelled: false,
+        }
+    }
+}
+
+impl Session {
+    /// A session with the contexts of the config, as the CLI resolves them
+    pub fn new(config: Config) -> Self {
+        let contexts = build_contexts(&config);
+
+        Session::with_contexts(config, contexts)
+    }
+
+    /// A session with contexts resolved by the embedder
+    pub fn with_contexts(config: Config, contexts: Contexts) -> Self {
+        Session {
+            config,
+            contexts,
+            manifests: HashMap::new(),
+            observers: vec![],
+        }
+    }
+
+    /// Has the observer told about the progress of every run
+    pub fn observe(&mut self, observer: impl Observer + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    fn notify(&self, event: impl Fn(&dyn Observer)) {
+        for observer in self.observers.iter() {
+            event(observer.as_ref());
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.observers.iter().any(|observer| observer.cancelled())
+    }
+
+    /// Loads the manifests in this directory, next to those loaded before.
+    /// Manifests that can't be parsed are left out and returned.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Vec<LoadError> {
+        let (manifests, errors) = load_with_errors(path.as_ref().to_path_buf(), &self.contexts);
+        self.manifests.extend(manifests);
+
+        errors
+    }
+
+    pub fn manifests(&self) -> &HashMap<String, Manifest> {
+        &self.manifests
+    }
+
+    pub fn contexts(&self) -> &Contexts {
+        &self.contexts
+    }
+
+    /// The selected manifests and their dependencies, dependencies first
+    pub fn order(&self, include: &[String], exclude: &[String]) -> anyhow::Result<Vec<String>> {
+        let mut names = if include.is_empty() && exclude.is_empty() {
+            self.manifests.keys().cloned().collect()
+        } else {
+            select(&self.manifests, include, exclude)?
+        };
+        names.sort();
+
+        let mut order = vec![];
+        let mut visiting = vec![];
+
+        for name in names.iter() {
+            self.visit(name, &names, &mut visiting, &mut order)?;
+        }
+
+        Ok(order)
+    }
+
+    fn visit(
+        &self,
+        name: &str,
+        selected: &[String],
+        visiting: &mut Vec<String>,
+        order: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        if order.iter().any(|done| done == name) {
+            return Ok(());
+        }
+
+        if visiting.iter().any(|parent| parent == name) {
+            return Err(anyhow!(
+                "Dependency cycle: {} -> {}",
+                visiting.join(" -> "),
+                name
+            ));
+        }
+
+        let manifest = self
+            .manifests
+            .get(name)
+            .ok_or_else(|| anyhow!("Manifest '{name}' doesn't exist"))?;
+
+        for dependency in manifest.depends.iter() {
+            let dependency = resolve_dependency(name, dependency);
+
+            if !self.manifests.contains_key(&dependency) {
+                error!(
+                    message = "Unresolved dependency",
+                    manifest = name,
+                    dependency = dependency.as_str()
+                );
+            }
+        }
+
+        visiting.push(name.to_string());
+
+        for dependency in self.dependencies(name) {
+            self.visit(&dependency, selected, visiting, order)?;
+        }
+
+        // Only orders the manifests, it doesn't add them to the run
+        for watched in self.watched(name) {
+            if selected.contains(&watched) {
+                self.visit(&watched, selected, visiting, order)?;
+            }
+        }
+
+        visiting.pop();
+        order.push(name.to_string());
+
+        Ok(())
+    }
+
+    /// The manifests this one depends on that exist. `order` reports those
+    /// that don't.
+    fn dependencies(&self, name: &str) -> Vec<String> {
+        let Some(manifest) = self.manifests.get(name) else {
+            return vec![];
+        };
+
+        let mut dependencies: Vec<String> = manifest
+            .depends
+            .iter()
+            .map(|dependency| resolve_dependency(name, dependency))
+            .filter(|dependency| self.manifests.contains_key(dependency))
+            .collect();
+        dependencies.sort();
+        dependencies.dedup();
+
+        dependencies
+    }
+
+    /// The other manifests with the ids the actions of this one refer to in
+    /// `when_changed`, which have to run first
+    fn watched(&self, name: &str) -> Vec<String> {
+        let Some(manifest) = self.manifests.get(name) else {
+            return vec![];
+        };
+
+        let defines = |manifest: &Manifest, id: &str| {
+            manifest
+                .all_actions()
+                .any(|action| action.inner_ref().id() == Some(id))
+        };
+
+        let ids: Vec<&str> = manifest
+            .all_actions()
+            .filter_map(|action| action.inner_ref().when_changed())
+            .filter(|id| !defines(manifest, id))
+            .collect();
+
+        let mut watched: Vec<String> = self
+            .manifests
+            .iter()
+            .filter(|(other, _)| other.as_str() != name)
+            .filter(|(_, other)| ids.iter().any(|id| defines(other, id)))
+            .map(|(other, _)| other.clone())
+            .collect();
+        watched.sort();
+
+        watched
+    }
+
+    /// The steps each action of the manifest would run, or `None` when its
+    /// `where` condition is false
+    pub fn plan(&self, name: &str) -> anyhow::Result<Option<Vec<PlannedAction>>> {
+        let manifest = self
+            .manifests
+            .get(name)
+            .ok_or_else(|| anyhow!("Manifest '{name}' doesn't exist"))?;
+
+        if !manifest.is_applicable(&self.contexts)? {
+            return Ok(None);
+        }
+
+        manifest
+            .actions
+            .iter()
+            .map(|definition| {
+                let action = definition.inner_ref();
+
+                let steps = pending(action.plan(manifest, &self.contexts)?);
+
+                Ok(PlannedAction {
+                    action: definition.to_string(),
+                    summary: action.summarize(),
+                    steps: steps
+                        .iter()
+                        .map(|(step, _)| step.atom.to_string())
+                        .collect(),
+                    side_effects: steps
+                        .into_iter()
+                        .flat_map(|(_, side_effects)| side_effects)
+                        .collect(),
+                })
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Some)
+    }
+
+    /// Applies the selected manifests, or plans them with `dry_run`. A failed
+    /// manifest stops the run unless `keep_going`, and the manifests depending
+    /// on it don't run. Dry-runs plan every manifest regardless.
+    pub fn run(&self, options: &RunOptions) -> anyhow::Result<RunReport> {
+        Ok(self.run_with(options, Records::default())?.report)
+    }
+
+    /// Runs like [`Session::run`], keeping what's done in the records: the
+    /// state, the journal for rollbacks and the audit log
+    pub fn run_with(&self, options: &RunOptions, records: Records) -> anyhow::Result<RunOutcome> {
+        let started = Instant::now();
+        let dry_run = options.dry_run;
+
+        let mut report = RunReport::new(dry_run);
+        report.run_id = records
+            .journal
+            .as_ref()
+            .map(|journal| journal.run_id.clone());
+
+        let mut state = records.state;
+        let previous = state.clone();
+        let journal = records.journal.map(Mutex::new);
+
+        let run = Run {
+            options,
+            contexts: Mutex::new(self.contexts.clone()),
+            changed: Mutex::new(HashSet::new()),
+            packages: Mutex::new(()),
+            previous: &previous,
+            resumed: records.resumed.as_ref(),
+            journal: journal.as_ref(),
+            audit: records.audit.as_ref(),
+        };
+
+        // What this run got done, written down when it's cancelled
+        let mut checkpoint = Checkpoint::new();
+
+        // Every manifest to run, with dependencies before the manifests depending on them
+        let mut pending = self.order(&options.manifests, &options.exclude)?;
+        let dependencies: HashMap<String, Vec<String>> = pending
+            .iter()
+            .map(|name| (name.clone(), self.dependencies(name)))
+            .collect();
+        // Manifests also wait for those setting the ids they use in `when_changed`,
+        // but don't fail along with them
+        let waits: HashMap<String, Vec<String>> = pending
+            .iter()
+            .map(|name| {
+                let watched = self
+                    .watched(name)
+                    .into_iter()
+                    .filter(|watched| pending.contains(watched));
+
+                (
+                    name.clone(),
+                    dependencies[name].iter().cloned().chain(watched).collect(),
+                )
+            })
+            .collect();
+        let jobs = options.jobs.max(1);
+
+        // Manifests that failed, or couldn't run because of a failure
+        let mut unsuccessful: HashSet<String> = HashSet::new();
+        let mut running: HashSet<String> = HashSet::new();
+        let mut done: HashSet<String> = HashSet::new();
+
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::scope(|scope| loop {
+            // Start every manifest whose dependencies are done, up to `jobs` at once
+            while running.len() < jobs {
+                let Some(position) = pending.iter().position(|name| {
+                    waits[name]
+                        .iter()
+                        .all(|dependency| done.contains(dependency))
+                }) else {
+                    break;
+                };
+
+                let name = pending.remove(position);
+                let manifest = &self.manifests[&name];
+
+                let skipped = if self.is_cancelled() {
+                    Some(ManifestReport::unreachable(&name, "the run was cancelled"))
+                } else if run
+                    .resumed
+                    .is_some_and(|resumed| resumed.is_done(&name, manifest))
+                {
+                    info!(
+                        message = "Skipping manifest, it was done before the run was cancelled",
+                        manifest = name.as_str()
+                    );
+                    checkpoint.done(&name, manifest);
+                    Some(ManifestReport::skipped(
+                        &name,
+                        "done before the run was cancelled",
+                    ))
+                } else if !options.keep_going && !options.dry_run && !unsuccessful.is_empty() {
+                    Some(ManifestReport::unreachable(
+                        &name,
+                        "an earlier manifest failed",
+                    ))
+                } else if let Some(dependency) = dependencies[&name]
+                    .iter()
+                    .find(|dependency| !options.dry_run && unsuccessful.contains(*dependency))
+                {
+                    info!(
+                        message = "Skipping manifest, dependency failed",
+                        manifest = name.as_str(),
+                        dependency = dependency.as_str()
+                    );
+                    unsuccessful.insert(name.clone());
+                    Some(ManifestReport::unreachable(
+                        &name,
+                        &format!("dependency '{dependency}' failed"),
+                    ))
+                } else {
+                    None
+                };
+
+                if let Some(manifest_report) = skipped {
+                    self.notify(|observer| observer.on_manifest_done(&manifest_report));
+                    report.manifests.push(manifest_report);
+                    done.insert(name);
+                    continue;
+                }
+
+                let actions = [
+                    &manifest.before,
+                    &manifest.actions,
+                    &manifest.after,
+                    &manifest.on_failure,
+                    &manifest.always,
+                ]
+                .iter()
+                .map(|actions| actions.len())
+                .sum();
+                self.notify(|observer| observer.on_manifest_start(&name, actions));
+
+                running.insert(name.clone());
+
+                let sender = sender.clone();
+                let run = &run;
+
+                scope.spawn(move || {
+                    let _span = span!(tracing::Level::INFO, "", manifest = name.as_str()).entered();
+
+                    let outcome = catch_panic(&name, || self.apply(manifest, &name, run));
+                    let _ = sender.send((name, outcome));
+                });
+            }
+
+            if running.is_empty() {
+                break;
+            }
+
+            // .unwrap() is safe here, a sender is kept alive by this loop, and
+            // every worker sends its outcome, even when it panicked
+            let (name, (manifest_report, manifest_state)) = receiver.recv().unwrap();
+            running.remove(&name);
+            let manifest = &self.manifests[&name];
+            done.insert(name);
+
+            self.notify(|observer| observer.on_manifest_done(&manifest_report));
+
+            match manifest_report.status {
+                Status::Failed => {
+                    unsuccessful.insert(manifest_report.name.clone());
+                }
+                Status::Applied | Status::Unchanged => {
+                    checkpoint.done(&manifest_report.name, manifest);
+                    if !dry_run {
+                        state.record(&manifest_report.name, manifest_state);
+                    }
+                }
+                Status::Skipped => {
+                    checkpoint.done(&manifest_report.name, manifest);
+                }
+                // Only manifests that were cut short come back unreachable
+                Status::Unreachable => {
+                    checkpoint
+                        .actions
+                        .insert(manifest_report.name.clone(), manifest_state.actions);
+                }
+                _ => (),
+            }
+
+            report.manifests.push(manifest_report);
+        });
+
+        report.duration_ms = elapsed_ms(started);
+        report.slowest_steps = report.slowest(SLOWEST_STEPS);
+
+        Ok(RunOutcome {
+            report,
+            state,
+            checkpoint,
+            journal: journal.map(|journal| journal.into_inner().unwrap()),
+        })
+    }
+
+    /// Runs the actions of a single manifest. Contexts registered by its
+    /// actions are shared with the manifests that run after it.
+    fn apply(&self, manifest: &Manifest, name: &str, run: &Run) -> (ManifestReport, ManifestState) {
+        let options = run.options;
+        let mut contexts = run.contexts.lock().unwrap().clone();
+
+        let started = Instant::now();
+        let mut report = ManifestReport::new(name);
+        let mut manifest_state = ManifestState {
+            applied_at: state::now(),
+            actions: vec![],
+        };
+
+        if let Some(label) = options.label.as_ref() {
+            if !manifest.labels.contains(label) {
+                info!(
+                    message = "Skipping manifest, label not found",
+                    label = label.as_str()
+                );
+                return (
+                    ManifestReport::skipped(name, &format!("label '{label}' not found")),
+                    manifest_state,
+                );
+            }
+        }
+
+        if !manifest.is_in_profile(&contexts) {
+            info!("Skipping manifest, it isn't in the active profile");
+            return (
+                ManifestReport::skipped(
+                    name,
+                    &format!("not in profiles {}", manifest.profiles.join(", ")),
+                ),
+                manifest_state,
+            );
+        }
+
+        let applicable = manifest.is_applicable(&contexts).unwrap_or_else(|err| {
+            warn!("{}", err);
+            self.notify(|observer| observer.on_error(name, &err));
+            false
+        });
+
+        if !applicable {
+            info!("Skip manifest, because 'where' conditions were false!");
+            return (
+                ManifestReport::skipped(name, "'where' condition was false"),
+                manifest_state,
+            );
+        }
+
+        // `before` and `after` only run when there's something to do
+        let changes = (!manifest.before.is_empty() || !manifest.after.is_empty())
+            && has_changes(manifest, options, &contexts);
+
+        // Set when the run is cancelled before every action ran
+        let mut cancelled = false;
+
+        // `on_failure` only runs after an action failed, `always` runs regardless
+        'blocks: for (block, definitions) in [
+            ("before", &manifest.before),
+            ("actions", &manifest.actions),
+            ("after", &manifest.after),
+            ("on_failure", &manifest.on_failure),
+            ("always", &manifest.always),
+        ] {
+            let failed = report
+                .actions
+                .iter()
+                .any(|action| action.status == Status::Failed);
+
+            let skip = match block {
+                "before" => !changes,
+                "after" => !changes || failed,
+                "on_failure" => !failed,
+                _ => false,
+            };
+
+            if skip {
+                continue;
+            }
+
+            let batches = manifest.batches(definitions).unwrap_or_else(|err| {
+                error!("{}, running them in order", err);
+                self.notify(|observer| observer.on_error(name, &err));
+                (0..definitions.len()).map(|index| vec![index]).collect()
+            });
+
+            for batch in batches
+                .iter()
+                .flat_map(|batch| batch.chunks(options.jobs.max(1)))
+            {
+                if self.is_cancelled() {
+                    cancelled = true;
+                    break 'blocks;
+                }
+
+                let apply = |index: &usize, contexts: &mut Contexts| {
+                    self.apply_action(manifest, name, &definitions[*index], run, contexts)
+                };
+
+                // Confirming asks about one step at a time
+                let outcomes: Vec<ActionOutcome> = if batch.len() == 1 || options.interactive {
+                    batch
+                        .iter()
+                        .map(|index| apply(index, &mut contexts))
+                        .collect()
+                } else {
+                    let before = contexts.clone();
+                    let span = tracing::Span::current();
+
+                    let outcomes = std::thread::scope(|scope| {
+                        let handles: Vec<_> = batch
+                            .iter()
+                            .map(|index| {
+                                let mut contexts = before.clone();
+                                let (apply, span) = (&apply, span.clone());
+
+                                scope.spawn(move || {
+                                    let _span = span.enter();
+                                    (apply(index, &mut contexts), contexts)
+                                })
+                            })
+                            .collect();
+
+                        handles
+                            .into_iter()
+                            .map(|handle| {
+                                handle
+                                    .join()
+                                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                            })
+                            .collect::<Vec<_>>()
+                    });
+
+                    outcomes
+                        .into_iter()
+                        .map(|(outcome, after)| {
+                            merge_changes(&mut contexts, &before, &after);
+                            outcome
+                        })
+                        .collect()
+                };
+
+                for outcome in outcomes {
+                    self.notify(|observer| observer.on_action_done(name, &outcome.report));
+                    cancelled |= outcome.cancelled;
+                    manifest_state.actions.extend(outcome.state);
+                    report.actions.push(outcome.report);
+                }
+            }
+        }
+
+        let successful = report
+            .actions
+            .iter()
+            .all(|action| action.status != Status::Failed);
+
+        report.status = if !successful {
+            Status::Failed
+        } else if report
+            .actions
+            .iter()
+            .any(|action| matches!(action.status, Status::Applied | Status::Planned))
+        {
+            if options.dry_run {
+                Status::Planned
+            } else {
+                Status::Applied
+            }
+        } else {
+            Status::Unchanged
+        };
+
+        report.duration_ms = elapsed_ms(started);
+
+        if cancelled {
+            report.status = Status::Unreachable;
+            report.reason = Some(String::from("the run was cancelled"));
+            warn!(duration_ms = report.duration_ms, "Cancelled");
+        } else if successful {
+            info!(
+                status = %report.status,
+                duration_ms = report.duration_ms,
+                "Completed"
+            );
+        } else {
+            error!(
+                status = %report.status,
+                duration_ms = report.duration_ms,
+                "Failed"
+            );
+        }
+
+        (report, manifest_state)
+    }
+
+    /// Runs a single action of the manifest. What it registers is added to
+    /// `contexts`, and shared with the manifests that run after it.
+    fn apply_action(
+        &self,
+        manifest: &Manifest,
+        name: &str,
+        definition: &Actions,
+        run: &Run,
+        contexts: &mut Contexts,
+    ) -> ActionOutcome {
+        let _span = span!(tracing::Level::INFO, "", action = %definition).entered();
+        let options = run.options;
+        let dry_run = options.dry_run;
+
+        // Set when the run is cancelled before every step ran
+        let mut cancelled = false;
+        let started = Instant::now();
+
+        let action = definition.inner_ref();
+
+        let _packages = definition
+            .uses_package_manager()
+            .then(|| run.packages.lock().unwrap_or_else(PoisonError::into_inner));
+
+        let mut report = ActionReport::new(&definition.to_string(), action.summarize());
+
+        if let Some(done) = run
+            .resumed
+            .and_then(|checkpoint| checkpoint.action(name, &state::fingerprint(definition)))
+        {
+            debug!("Skipping action, it was done before the run was cancelled");
+            report.status = Status::Skipped;
+            return ActionOutcome::done(report, Some(done.clone()));
+        }
+
+        if !manifest.is_action_selected(action, &options.tags, &options.skip_tags) {
+            debug!("Skipping action, filtered out by tags");
+            report.status = Status::Skipped;
+
+            // Keep what we knew about this action from previous runs
+            let fingerprint = state::fingerprint(definition);
+            let previous = run
+                .previous
+                .manifests
+                .get(name)
+                .and_then(|m| m.actions.iter().find(|a| a.fingerprint == fingerprint));
+
+            return ActionOutcome::done(report, previous.cloned());
+        }
+
+        if let Some(id) = action.when_changed() {
+            if !run.changed.lock().unwrap().contains(id) {
+                debug!("Skipping action, '{}' didn't change anything", id);
+                report.status = Status::Skipped;
+                report.duration_ms = elapsed_ms(started);
+                return ActionOutcome::done(report, None);
+            }
+        }
+
+        let retry = action.retry();
+
+        let failed = if action.ignore_errors() {
+            Status::Ignored
+        } else {
+            Status::Failed
+        };
+
+        let plan = match retry.run(|| action.plan(manifest, contexts)) {
+            Ok(steps) => steps,
+            Err(err) => {
+                info!("Action failed to get plan: {:?}", err);
+                self.notify(|observer| observer.on_error(name, &err));
+                report.status = failed;
+                report.error = Some(err.to_string());
+                report.duration_ms = elapsed_ms(started);
+                return ActionOutcome::done(report, None);
+            }
+        };
+
+        let managed_files: Vec<PathBuf> = plan
+            .iter()
+            .flat_map(|step| step.atom.managed_files())
+            .collect();
+
+        let steps = pending(plan);
+
+        if steps.is_empty() {
+            info!("nothing to be done to reconcile action");
+            report.duration_ms = elapsed_ms(started);
+            return ActionOutcome::done(report, Some(ActionState::new(definition, managed_files)));
+        }
+
+        report.status = if dry_run {
+            Status::Planned
+        } else {
+            Status::Applied
+        };
+
+        for (step, _) in steps.iter() {
+            let step = step.atom.to_string();
+            self.notify(|observer| observer.on_step_planned(name, &report.summary, &step));
+        }
+
+        let mut steps = steps.into_iter();
+
+        for (mut step, side_effects) in steps.by_ref() {
+            // The step that's running when the run is cancelled finishes
+            if self.is_cancelled() {
+                report.steps.push(StepReport {
+                    status: Status::Unreachable,
+                    side_effects,
+                    ..StepReport::new(step.atom.to_string())
+                });
+                report.status = Status::Unreachable;
+                report.error = Some(String::from("The run was cancelled"));
+                cancelled = true;
+                break;
+            }
+
+            if options.diff {
+                if let Some(diff) = step.atom.diff() {
+                    self.notify(|observer| observer.on_diff(name, &diff));
+                }
+            }
+
+            let mut step_report = StepReport {
+                side_effects,
+                ..StepReport::new(step.atom.to_string())
+            };
+
+            if step.atom.privileged() && !may_elevate(action.allow_sudo()) {
+                warn!("Skipping `{}`, it needs privileges", step_report.atom);
+                report.steps.push(StepReport {
+                    side_effects: step_report.side_effects,
+                    ..StepReport::skipped(step_report.atom, PRIVILEGES_NOT_ALLOWED)
+                });
+                continue;
+            }
+
+            if dry_run {
+                for side_effect in step_report.side_effects.iter() {
+                    info!("Would {}", side_effect);
+                }
+
+                report.steps.push(step_report);
+                continue;
+            }
+
+            let confirmed = self
+                .observers
+                .iter()
+                .all(|observer| observer.confirm_step(name, &report.summary, &step_report.atom));
+
+            if !confirmed {
+                step_report.status = Status::Skipped;
+                report.steps.push(step_report);
+                continue;
+            }
+
+            // Prepared before the step changes anything, but only recorded once
+            // it succeeded, so there's nothing to roll back for failed steps
+            let undo = run.journal.and_then(|journal| {
+                let journal = journal.lock().unwrap();
+
+                step.atom
+                    .prepare_undo(&journal.backup_dir())
+                    .unwrap_or_else(|err| {
+                        warn!("Unable to record rollback information: {}", err);
+                        None
+                    })
+            });
+
+            if let Some(timeout) = self.config.command_timeout {
+                step.atom.set_default_timeout(Duration::from_secs(timeout));
+            }
+
+            self.notify(|observer| observer.on_step_start(name, &step_report.atom));
+
+            let step_started = Instant::now();
+            let result = retry.run(|| step.atom.execute());
+            let result = result.and(step.run_always_finalizers());
+            step_report.duration_ms = elapsed_ms(step_started);
+
+            if let Some(audit) = run.audit {
+                let privileged = step.atom.privileged();
+
+                if AuditEntry::is_audited(privileged, &step_report.side_effects) {
+                    let entry = AuditEntry {
+                        manifest_dir: manifest.root_dir.clone(),
+                        side_effects: step_report.side_effects.clone(),
+                        status: match result {
+                            Ok(_) => Status::Applied,
+                            Err(_) => Status::Failed,
+                        },
+                        error: result.as_ref().err().map(|err| err.to_string()),
+                        ..AuditEntry::new(
+                            name,
+                            &definition.to_string(),
+                            &step_report.atom,
+                            privileged,
+                        )
+                    };
+
+                    if let Err(err) = audit.record(entry) {
+                        error!("{:#}", err);
+                    }
+                }
+            }
+
+            let output: Vec<String> = [step.atom.output_string(), step.atom.error_message()]
+                .iter()
+                .map(|output| output.trim().to_string())
+                .filter(|output| !output.is_empty())
+                .collect();
+            if !output.is_empty() {
+                step_report.output = Some(output.join("\n"));
+            }
+
+            if let Some(register_as) = action.register() {
+                if self.contexts.contains_key(register_as) {
+                    warn!(
+                        "Cannot register '{}', it is a built-in context",
+                        register_as
+                    );
+                } else {
+                    register(contexts, register_as, step.atom.as_ref());
+                    register(
+                        &mut run.contexts.lock().unwrap(),
+                        register_as,
+                        step.atom.as_ref(),
+                    );
+                }
+            }
+
+            let facts = step.atom.facts();
+            if !facts.is_empty() {
+                set_facts(contexts, facts.clone());
+                set_facts(&mut run.contexts.lock().unwrap(), facts);
+            }
+
+            step_report.status = Status::Applied;
+
+            if let (Ok(_), Some(journal), Some(undo)) = (&result, run.journal, undo) {
+                if let Err(err) = journal.lock().unwrap().record(undo) {
+                    warn!("Unable to record rollback information: {}", err);
+                }
+            }
+
+            if let Err(err) = result {
+                debug!("Atom failed to execute: {:?}", err);
+                self.notify(|observer| observer.on_error(name, &err));
+                step_report.status = Status::Failed;
+                step_report.error = Some(err.to_string());
+            } else if !step.do_finalizers_allow_us_to_continue() {
+                debug!("Finalizers won't allow us to continue with this action");
+                step_report.status = Status::Failed;
+                step_report.error = Some(String::from("Finalizers stopped the action"));
+            }
+
+            self.notify(|observer| observer.on_atom_executed(name, &step_report));
+
+            let stop = step_report.status == Status::Failed;
+            report.steps.push(step_report);
+
+            if stop {
+                report.status = failed;
+                break;
+            }
+        }
+
+        // Whatever is left wasn't run, because a step failed or the run was cancelled
+        report
+            .steps
+            .extend(steps.map(|(step, side_effects)| StepReport {
+                status: Status::Unreachable,
+                side_effects,
+                ..StepReport::new(step.atom.to_string())
+            }));
+
+        // Every step was skipped when confirming, or for lack of privileges
+        if matches!(report.status, Status::Applied | Status::Planned)
+            && report
+                .steps
+                .iter()
+                .all(|step| step.status == Status::Skipped)
+        {
+            report.status = Status::Skipped;
+        }
+
+        for step in report.steps.iter() {
+            debug!(
+                step = step.atom.as_str(),
+                status = %step.status,
+                duration_ms = step.duration_ms,
+                "Step {}",
+                step.status
+            );
+        }
+
+        info!(status = %report.status, "{}", action.summarize());
+
+        let changed = matches!(report.status, Status::Applied | Status::Planned);
+        if let Some(id) = action.id().filter(|_| changed) {
+            run.changed.lock().unwrap().insert(id.to_string());
+        }
+
+        let state = changed.then(|| ActionState::new(definition, managed_files));
+        report.duration_ms = elapsed_ms(started);
+
+        ActionOutcome {
+            report,
+            state,
+            cancelled,
+        }
+    }
+}
+
+/// Whether any of the selected actions of the manifest plans a step that
+/// should run
+fn has_changes(manifest: &Manifest, options: &RunOptions, contexts: &Contexts) -> bool {
+    manifest
+        .actions
+        .iter()
+        .map(|definition| definition.inner_ref())
+        .filter(|action| manifest.is_action_selected(*action, &options.tags, &options.skip_tags))
+        .any(|action| match action.plan(manifest, contexts) {
+            Ok(steps) => !pending(steps).is_empty(),
+            // Let the action report its error when it runs
+            Err(_) => true,
+        })
+}
+
+/// Runs a manifest, turning a panic into a failure, so the scheduler still
+/// hears back from its worker
+fn catch_panic(
+    name: &str,
+    apply: impl FnOnce() -> (ManifestReport, ManifestState),
+) -> (ManifestReport, ManifestState) {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(apply)).unwrap_or_else(|panic| {
+        let reason = panic
+            .downcast_ref::<&str>()
+            .map(|reason| reason.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+
+        error!("Manifest {} panicked: {}", name, reason);
+
+        (
+            ManifestReport::failed(name, &format!("panicked: {reason}")),
+            ManifestState::default(),
+        )
+    })
+}
+
+/// The steps that need to run, leaving out those already in the desired state,
+/// with what they change
+fn pending(steps: Vec<Step>) -> Vec<(Step, Vec<SideEffect>)> {
+    steps
+        .into_iter()
+        .filter(|step| step.do_initializers_allow_us_to_run())
+        .filter_map(|step| match step.atom.plan() {
+            Ok(outcome) if outcome.should_run => Some((step, outcome.side_effects)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Dependencies starting with `./` are relative to the manifest's directory
+pub fn resolve_dependency(name: &str, dependency: &str) -> String {
+    let (local_dependency_prefix, _) = name.rsplit_once('.').unwrap_or((name, ""));
+
+    dependency.replace("./", format!("{}.", local_dependency_prefix).as_str())
+}
+
+fn elapsed_ms(started: Instant) -> u64 {
+    started.elapsed().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rollback::Undo;
+    use pretty_assertions::assert_eq;
+
+    fn session(manifests: &[(&str, &str)]) -> (tempfile::TempDir, Session) {
+        let dir = tempfile::tempdir().unwrap();
+
+        for (name, contents) in manifests {
+            std::fs::write(dir.path().join(format!("{name}.yaml")), contents).unwrap();
+        }
+
+        let mut session = Session::with_contexts(Config::default(), Contexts::default());
+        assert_eq!(0, session.load(dir.path()).len());
+
+        (dir, session)
+    }
+
+    #[test]
+    fn it_orders_dependencies_first() {
+        let (_dir, session) = session(&[
+            ("app", "depends: [base]\nactions: []"),
+            ("base", "actions: []"),
+            ("tools", "depends: [app]\nactions: []"),
+        ]);
+
+        assert_eq!(
+            vec!["base", "app", "tools"],
+            session.order(&[], &[]).unwrap()
+        );
+        assert_eq!(
+            vec!["base", "app"],
+            session.order(&[String::from("app")], &[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_orders_manifests_after_the_ids_they_watch() {
+        let (_dir, session) = session(&[
+            (
+                "a",
+                "actions:\n  - action: command.run\n    command: echo\n    when_changed: theme",
+            ),
+            (
+                "b",
+                "actions:\n  - action: command.run\n    command: echo\n    id: theme",
+            ),
+        ]);
+
+        assert_eq!(vec!["b", "a"], session.order(&[], &[]).unwrap());
+        // Without adding the other manifest to the run
+        assert_eq!(vec!["a"], session.order(&[String::from("a")], &[]).unwrap());
+    }
+
+    #[test]
+    fn it_finds_cycles() {
+        let (_dir, session) = session(&[
+            ("a", "depends: [b]\nactions: []"),
+            ("b", "depends: [a]\nactions: []"),
+        ]);
+
+        assert_eq!(true, session.order(&[], &[]).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_can_plan_and_run() {
+        let output = tempfile::tempdir().unwrap();
+        let greeted = output.path().join("greeted");
+
+        let (_dir, session) = session(&[(
+            "greet",
+            &format!(
+                "actions:\n  - action: command.run\n    command: touch\n    args: [\"{}\"]\n",
+                greeted.display()
+            ),
+        )]);
+
+        let plan = session.plan("greet").unwrap().unwrap();
+        assert_eq!(1, plan.len());
+        assert_eq!(1, plan[0].steps.len());
+        assert_eq!(
+            vec![SideEffect::Command {
+                command: format!("touch {}", greeted.display())
+            }],
+            plan[0].side_effects
+        );
+
+        let report = session
+            .run(&RunOptions {
+                dry_run: true,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(Status::Planned, report.manifests[0].status);
+        assert_eq!(false, greeted.exists());
+
+        let report = session.run(&RunOptions::default()).unwrap();
+        assert_eq!(Status::Applied, report.manifests[0].status);
+        assert_eq!(true, greeted.exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_skips_privileged_steps_that_arent_allowed() {
+        let (_dir, session) = session(&[(
+            "locked",
+            "actions:\n  - action: command.run\n    command: \"true\"\n    privileged: true\n    allow_sudo: false\n",
+        )]);
+
+        let report = session
+            .run(&RunOptions {
+                dry_run: true,
+                ..Default::default()
+            })
+            .unwrap();
+        let action = &report.manifests[0].actions[0];
+
+        // Root doesn't need sudo, so nothing is skipped
+        if crate::atoms::command::is_elevated() {
+            assert_eq!(Status::Planned, action.status);
+        } else {
+            assert_eq!(Status::Skipped, action.status);
+            assert_eq!(
+                Some(String::from(PRIVILEGES_NOT_ALLOWED)),
+                action.steps[0].reason
+            );
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder(std::sync::Arc<Mutex<Vec<String>>>);
+
+    impl Observer for Recorder {
+        fn on_manifest_start(&self, manifest: &str, _actions: usize) {
+            self.0.lock().unwrap().push(format!("start {manifest}"));
+        }
+
+        fn on_manifest_done(&self, report: &ManifestReport) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("done {} {}", report.name, report.status));
+        }
+
+        fn on_step_planned(&self, manifest: &str, action: &str, _step: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("planned {manifest} {action}"));
+        }
+
+        fn on_atom_executed(&self, manifest: &str, step: &StepReport) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("executed {manifest} {}", step.status));
+        }
+
+        fn on_error(&self, manifest: &str, _error: &anyhow::Error) {
+            self.0.lock().unwrap().push(format!("error {manifest}"));
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_tells_observers() {
+        let (_dir, mut session) = session(&[(
+            "broken",
+            "actions:\n  - action: command.run\n    command: \"false\"\n",
+        )]);
+
+        let recorder = Recorder::default();
+        session.observe(recorder.clone());
+        session.run(&RunOptions::default()).unwrap();
+
+        assert_eq!(
+            vec![
+                "start broken",
+                "planned broken Running false command",
+                "error broken",
+                "executed broken failed",
+                "done broken failed",
+            ],
+            *recorder.0.lock().unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_records_the_state_of_what_ran() {
+        let (_dir, session) = session(&[
+            (
+                "done",
+                "actions:\n  - action: command.run\n    command: \"true\"\n",
+            ),
+            (
+                "broken",
+                "actions:\n  - action: command.run\n    command: \"false\"\n",
+            ),
+        ]);
+
+        let options = RunOptions {
+            keep_going: true,
+            jobs: 2,
+            ..Default::default()
+        };
+
+        let outcome = session.run_with(&options, Records::default()).unwrap();
+
+        assert_eq!(
+            vec!["done"],
+            outcome.state.manifests.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["done"],
+            outcome.checkpoint.manifests.keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn it_only_journals_steps_that_succeeded() {
+        let target = tempfile::tempdir().unwrap();
+        let copied = target.path().join("copied");
+        // Copies into directories, so this fails on the directory `a` in it
+        let directory = target.path().join("directory");
+        std::fs::create_dir_all(directory.join("a/b")).unwrap();
+
+        let (dir, session) = session(&[(
+            "copy",
+            &format!(
+                "actions:\n  - action: file.copy\n    from: a\n    to: {}\n  - action: file.copy\n    from: a\n    to: {}\n",
+                copied.display(),
+                directory.display()
+            ),
+        )]);
+        std::fs::create_dir(dir.path().join("files")).unwrap();
+        std::fs::write(dir.path().join("files/a"), "a").unwrap();
+
+        let runs = tempfile::tempdir().unwrap();
+        let records = Records {
+            journal: Some(Journal::create(runs.path()).unwrap()),
+            ..Default::default()
+        };
+
+        let outcome = session.run_with(&RunOptions::default(), records).unwrap();
+
+        assert_eq!(Status::Failed, outcome.report.manifests[0].status);
+        let mut paths: Vec<PathBuf> = outcome
+            .journal
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|undo| match undo {
+                Undo::RestoreFile { path, .. } | Undo::RemoveFile { path } => path,
+                other => panic!("Unexpected {:?}", other),
+            })
+            .collect();
+        paths.dedup();
+
+        assert_eq!(vec![copied], paths);
+    }
+
+    #[test]
+    fn it_plans_every_manifest_in_a_dry_run() {
+        let (_dir, session) = session(&[
+            (
+                "broken",
+                "actions:\n  - action: file.copy\n    from: missing\n    to: /tmp/missing\n",
+            ),
+            ("app", "depends: [broken]\nactions: []"),
+            ("tools", "actions: []"),
+        ]);
+
+        let report = session
+            .run(&RunOptions {
+                dry_run: true,
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert_eq!(
+            vec![Status::Failed, Status::Unchanged, Status::Unchanged],
+            report
+                .manifests
+                .iter()
+                .map(|manifest| manifest.status)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn it_reports_a_panicking_manifest_as_failed() {
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::scope(|scope| {
+            let sender = sender.clone();
+
+            scope.spawn(move || {
+                let outcome = catch_panic("broken", || panic!("boom"));
+                let _ = sender.send(outcome);
+            });
+        });
+
+        // The scheduler keeps a sender, so this would wait forever without one
+        let (report, _) = receiver.recv().unwrap();
+
+        assert_eq!(Status::Failed, report.status);
+        assert_eq!(Some(String::from("panicked: boom")), report.reason);
+    }
+}
@@ -0,0 +1,42 @@
+use crate::report::{ActionReport, ManifestReport, StepReport};
+
+/// Told about the progress of a [`Session`](super::Session) run, for front-ends
+/// and notifications. Every method does nothing unless implemented. Manifests
+/// and actions may run at once, so methods are called from several threads.
+pub trait Observer: Send + Sync {
+    /// The manifest is about to run, with this many actions in all its blocks
+    fn on_manifest_start(&self, _manifest: &str, _actions: usize) {}
+
+    /// The manifest finished, was skipped or couldn't run, with the outcome of
+    /// each of its actions
+    fn on_manifest_done(&self, _report: &ManifestReport) {}
+
+    /// The action has a step to run, or that would run in a dry-run
+    fn on_step_planned(&self, _manifest: &str, _action: &str, _step: &str) {}
+
+    /// What the step would change in a file, with `diff` in the run options
+    fn on_diff(&self, _manifest: &str, _diff: &str) {}
+
+    /// Whether the step may run, asked right before it does. Steps that
+    /// aren't confirmed are skipped.
+    fn confirm_step(&self, _manifest: &str, _action: &str, _step: &str) -> bool {
+        true
+    }
+
+    /// The step is about to run
+    fn on_step_start(&self, _manifest: &str, _step: &str) {}
+
+    /// The step ran, its report has the status, output and error
+    fn on_atom_executed(&self, _manifest: &str, _step: &StepReport) {}
+
+    /// The action is done, or was skipped
+    fn on_action_done(&self, _manifest: &str, _report: &ActionReport) {}
+
+    /// A `where` condition, a plan or a step failed
+    fn on_error(&self, _manifest: &str, _error: &anyhow::Error) {}
+
+    /// Stops the run once the running steps finish, like Ctrl-C does for the CLI
+    fn cancelled(&self) -> bool {
+        false
+    }
+}
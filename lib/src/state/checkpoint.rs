@@ -0,0 +1,138 @@
+use super::{manifest_fingerprint, now, ActionState};
+use crate::manifests::Manifest;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// How far a cancelled run got, so `comtrya apply --resume` can carry on from
+/// there rather than running everything again
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Seconds since the unix epoch
+    pub cancelled_at: u64,
+
+    /// Manifests that ran to the end, with their fingerprint, so they run
+    /// again when they were changed since
+    #[serde(default)]
+    pub manifests: BTreeMap<String, String>,
+
+    /// The actions done in manifests that were cut short
+    #[serde(default)]
+    pub actions: BTreeMap<String, Vec<ActionState>>,
+}
+
+/// The checkpoint next to the state file, `state.checkpoint` for `state.json`
+pub fn checkpoint_path(state_path: &Path) -> PathBuf {
+    state_path.with_extension("checkpoint")
+}
+
+impl Checkpoint {
+    pub fn new() -> Self {
+        Checkpoint {
+            cancelled_at: now(),
+            ..Default::default()
+        }
+    }
+
+    /// `None` when no run was cancelled
+    pub fn load(path: &Path) -> Result<Option<Checkpoint>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read checkpoint {}", path.display()))?;
+
+        serde_json::from_str(&contents)
+            .map(Some)
+            .with_context(|| format!("Unable to parse checkpoint {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        crate::atoms::file::write_atomic(path, serde_json::to_string_pretty(self)?.as_bytes())
+            .with_context(|| format!("Unable to write checkpoint {}", path.display()))
+    }
+
+    /// Once a run isn't cancelled, there's nothing left to resume
+    pub fn remove(path: &Path) -> Result<()> {
+        match std::fs::remove_file(path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("Unable to remove checkpoint {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Records that the manifest ran to the end, as it is now
+    pub fn done(&mut self, name: &str, manifest: &Manifest) {
+        self.manifests
+            .insert(name.to_string(), manifest_fingerprint(manifest));
+    }
+
+    /// Whether the manifest ran to the end, and wasn't changed since
+    pub fn is_done(&self, name: &str, manifest: &Manifest) -> bool {
+        self.manifests.get(name) == Some(&manifest_fingerprint(manifest))
+    }
+
+    /// The action of `manifest` with this fingerprint, when it was done
+    pub fn action(&self, manifest: &str, fingerprint: &str) -> Option<&ActionState> {
+        self.actions
+            .get(manifest)?
+            .iter()
+            .find(|action| action.fingerprint == fingerprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_remembers_what_was_done() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = checkpoint_path(&dir.path().join("state.json"));
+
+        assert_eq!(None, Checkpoint::load(&path).unwrap());
+
+        let git = Manifest {
+            depends: vec![String::from("base")],
+            ..Default::default()
+        };
+
+        let mut checkpoint = Checkpoint::new();
+        checkpoint.done("git", &git);
+        checkpoint.actions.insert(
+            String::from("zsh"),
+            vec![ActionState {
+                action: String::from("package.install"),
+                fingerprint: String::from("abc"),
+                ..Default::default()
+            }],
+        );
+        checkpoint.save(&path).unwrap();
+
+        let loaded = Checkpoint::load(&path).unwrap().unwrap();
+        assert_eq!(checkpoint, loaded);
+        assert!(loaded.is_done("git", &git));
+        assert!(!loaded.is_done("zsh", &Manifest::default()));
+
+        // Changed since, so it runs again
+        let changed = Manifest {
+            depends: vec![],
+            ..git
+        };
+        assert!(!loaded.is_done("git", &changed));
+        assert!(loaded.action("zsh", "abc").is_some());
+        assert!(loaded.action("zsh", "def").is_none());
+
+        Checkpoint::remove(&path).unwrap();
+        Checkpoint::remove(&path).unwrap();
+        assert!(!path.exists());
+    }
+}
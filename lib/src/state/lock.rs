@@ -0,0 +1,176 @@
+use super::now;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// Keeps other runs from applying at the same time, e.g. one from cron and one
+/// started by hand, for as long as it's held. The lock is the operating system's,
+/// so it's released when the lock is dropped or the process dies, whatever happens.
+#[derive(Debug)]
+pub struct RunLock {
+    file: File,
+    path: PathBuf,
+}
+
+/// Written into the lock file, to say who holds it
+#[derive(Debug, Serialize, Deserialize)]
+struct Holder {
+    pid: u32,
+    hostname: String,
+    /// Seconds since the unix epoch
+    started_at: u64,
+}
+
+impl std::fmt::Display for Holder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pid {} on {}, started {} seconds ago",
+            self.pid,
+            self.hostname,
+            now().saturating_sub(self.started_at)
+        )
+    }
+}
+
+/// The lock next to the state file, `state.lock` for `state.json`
+pub fn lock_path(state_path: &Path) -> PathBuf {
+    state_path.with_extension("lock")
+}
+
+impl RunLock {
+    /// Takes the lock at `path`, failing when another run holds it. With `force`,
+    /// the lock file is replaced, leaving a stuck run without its lock.
+    pub fn acquire(path: &Path, force: bool) -> Result<RunLock> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        if force && path.exists() {
+            warn!("Forcing the lock at {} open", path.display());
+            std::fs::remove_file(path)?;
+        }
+
+        // Never removed, so every run locks the same file
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("Failed to open the lock at {}", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => {
+                let holder = read_holder(&mut file)
+                    .map(|holder| holder.to_string())
+                    .unwrap_or_else(|| String::from("unknown"));
+
+                return Err(anyhow!(
+                    "Another run holds the lock at {} ({}). Wait for it to finish, or use --force-unlock if it's stuck",
+                    path.display(),
+                    holder
+                ));
+            }
+            // Some network filesystems can't lock files
+            Err(TryLockError::Error(err)) => {
+                warn!(
+                    "Failed to lock {}, applying without a lock: {}",
+                    path.display(),
+                    err
+                );
+            }
+        }
+
+        // A holder that's still written down never released the lock itself
+        if let Some(holder) = read_holder(&mut file) {
+            warn!("Taking over a stale lock, left by {}", holder);
+        }
+
+        let holder = Holder {
+            pid: std::process::id(),
+            hostname: gethostname::gethostname().to_string_lossy().to_string(),
+            started_at: now(),
+        };
+
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(&serde_json::to_vec(&holder)?)?;
+        file.sync_all()?;
+
+        debug!("Holding the lock at {}", path.display());
+
+        Ok(RunLock {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // Emptied rather than removed, as a run waiting to lock it may have it open
+        if let Err(err) = self.file.set_len(0) {
+            warn!(
+                "Failed to release the lock at {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+
+        let _ = self.file.unlock();
+    }
+}
+
+fn read_holder(file: &mut File) -> Option<Holder> {
+    let mut contents = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut contents).ok()?;
+
+    serde_json::from_str(&contents).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_keeps_other_runs_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = lock_path(&dir.path().join("state.json"));
+
+        let lock = RunLock::acquire(&path, false).unwrap();
+
+        let err = RunLock::acquire(&path, false).unwrap_err().to_string();
+        assert!(err.contains("Another run holds the lock"), "{err}");
+        assert!(
+            err.contains(&format!("pid {}", std::process::id())),
+            "{err}"
+        );
+
+        drop(lock);
+        assert_eq!(0, std::fs::metadata(&path).unwrap().len());
+
+        let _lock = RunLock::acquire(&path, false).unwrap();
+    }
+
+    #[test]
+    fn it_takes_over_stale_and_forced_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.lock");
+
+        // Left by a run that was killed
+        std::fs::write(&path, r#"{"pid": 1, "hostname": "here", "started_at": 0}"#).unwrap();
+        let stale = RunLock::acquire(&path, false).unwrap();
+
+        let forced = RunLock::acquire(&path, true).unwrap();
+        assert!(read_holder(&mut File::open(&path).unwrap()).is_some());
+
+        drop(stale);
+        drop(forced);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::actions::Actions;
+use crate::contexts::Contexts;
+use crate::manifests::Manifest;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+mod checkpoint;
+mod lock;
+pub use checkpoint::{checkpoint_path, Checkpoint};
+pub use lock::{lock_path, RunLock};
+
+/// What comtrya knows about the last successful apply of each manifest.
+/// Used by `comtrya status` to detect drift.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub manifests: BTreeMap<String, ManifestState>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ManifestState {
+    /// Seconds since the unix epoch
+    pub applied_at: u64,
+
+    #[serde(default)]
+    pub actions: Vec<ActionState>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionState {
+    pub action: String,
+
+    /// sha256 of the action definition, changes when the manifest is edited
+    pub fingerprint: String,
+
+    /// Managed files and the sha256 of their contents after apply
+    #[serde(default)]
+    pub files: BTreeMap<PathBuf, String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Drift {
+    FileModified { manifest: String, path: PathBuf },
+    FileRemoved { manifest: String, path: PathBuf },
+    PackagesMissing { manifest: String, action: String },
+}
+
+impl Drift {
+    pub fn manifest(&self) -> &str {
+        match self {
+            Drift::FileModified { manifest, .. }
+            | Drift::FileRemoved { manifest, .. }
+            | Drift::PackagesMissing { manifest, .. } => manifest,
+        }
+    }
+}
+
+impl std::fmt::Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Drift::FileModified { manifest, path } => write!(
+                f,
+                "{}: {} was modified outside of comtrya",
+                manifest,
+                path.display()
+            ),
+            Drift::FileRemoved { manifest, path } => {
+                write!(f, "{}: {} was removed", manifest, path.display())
+            }
+            Drift::PackagesMissing { manifest, action } => {
+                write!(
+                    f,
+                    "{}: packages of {} are no longer installed",
+                    manifest, action
+                )
+            }
+        }
+    }
+}
+
+pub fn default_state_path() -> Option<PathBuf> {
+    dirs_next::data_local_dir().map(|dir| dir.join("comtrya").join("state.json"))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn checksum(path: &Path) -> Option<String> {
+    sha256::try_digest(path).ok()
+}
+
+impl State {
+    /// A missing state file is an empty state
+    pub fn load(path: &Path) -> Result<State> {
+        if !path.exists() {
+            return Ok(State::default());
+        }
+
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read state file {}", path.display()))?;
+
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Unable to parse state file {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Unable to write state file {}", path.display()))
+    }
+
+    pub fn record(&mut self, manifest: &str, state: ManifestState) {
+        self.manifests.insert(manifest.to_string(), state);
+    }
+
+    /// Compares the recorded state with the system, without changing anything.
+    /// Package actions are re-planned against the manifests to find packages
+    /// that were removed.
+    pub fn drift(&self, manifests: &HashMap<String, Manifest>, contexts: &Contexts) -> Vec<Drift> {
+        let mut drift = vec![];
+
+        for (name, manifest_state) in self.manifests.iter() {
+            for action_state in manifest_state.actions.iter() {
+                for (path, recorded) in action_state.files.iter() {
+                    match checksum(path) {
+                        None => drift.push(Drift::FileRemoved {
+                            manifest: name.clone(),
+                            path: path.clone(),
+                        }),
+                        Some(current) if current.ne(recorded) => drift.push(Drift::FileModified {
+                            manifest: name.clone(),
+                            path: path.clone(),
+                        }),
+                        Some(_) => (),
+                    }
+                }
+            }
+
+            let Some(manifest) = manifests.get(name) else {
+                debug!("Manifest {} is no longer available, skipping", name);
+                continue;
+            };
+
+            for action in manifest.actions.iter() {
+                if !matches!(action, Actions::PackageInstall(_)) {
+                    continue;
+                }
+
+                let fingerprint = fingerprint(action);
+
+                if !manifest_state
+                    .actions
+                    .iter()
+                    .any(|state| state.fingerprint == fingerprint)
+                {
+                    continue;
+                }
+
+                let steps = match action.inner_ref().plan(manifest, contexts) {
+                    Ok(steps) => steps,
+                    Err(err) => {
+                        warn!("Unable to plan {} for drift detection: {}", action, err);
+                        continue;
+                    }
+                };
+
+                if steps
+                    .iter()
+                    .any(|step| step.atom.plan().map(|o| o.should_run).unwrap_or(false))
+                {
+                    drift.push(Drift::PackagesMissing {
+                        manifest: name.clone(),
+                        action: action.inner_ref().summarize(),
+                    });
+                }
+            }
+        }
+
+        drift
+    }
+}
+
+pub fn fingerprint(action: &Actions) -> String {
+    sha256::digest(serde_json::to_string(action).unwrap_or_default())
+}
+
+/// Changes when anything in the rendered manifest does
+pub fn manifest_fingerprint(manifest: &Manifest) -> String {
+    sha256::digest(serde_json::to_string(manifest).unwrap_or_default())
+}
+
+impl ActionState {
+    pub fn new(action: &Actions, files: Vec<PathBuf>) -> Self {
+        ActionState {
+            action: action.to_string(),
+            fingerprint: fingerprint(action),
+            files: files
+                .into_iter()
+                .filter_map(|path| checksum(&path).map(|sum| (path, sum)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_detects_file_drift() {
+        let dir = tempfile::tempdir().unwrap();
+        let managed = dir.path().join("managed");
+        let removed = dir.path().join("removed");
+        std::fs::write(&managed, "hello").unwrap();
+        std::fs::write(&removed, "hello").unwrap();
+
+        let actions: Vec<Actions> = serde_yml::from_str(
+            r#"
+- action: file.copy
+  from: a
+  to: b
+"#,
+        )
+        .unwrap();
+
+        let mut state = State::default();
+        state.record(
+            "dotfiles",
+            ManifestState {
+                applied_at: now(),
+                actions: vec![ActionState::new(
+                    &actions[0],
+                    vec![managed.clone(), removed.clone()],
+                )],
+            },
+        );
+
+        assert_eq!(0, state.drift(&HashMap::new(), &Contexts::default()).len());
+
+        std::fs::write(&managed, "changed").unwrap();
+        std::fs::remove_file(&removed).unwrap();
+
+        assert_eq!(
+            vec![
+                Drift::FileModified {
+                    manifest: String::from("dotfiles"),
+                    path: managed,
+                },
+                Drift::FileRemoved {
+                    manifest: String::from("dotfiles"),
+                    path: removed,
+                },
+            ],
+            state.drift(&HashMap::new(), &Contexts::default())
+        );
+    }
+
+    #[test]
+    fn it_can_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("state.json");
+
+        assert_eq!(0, State::load(&path).unwrap().manifests.len());
+
+        let mut state = State::default();
+        state.record("dotfiles", ManifestState::default());
+        state.save(&path).unwrap();
+
+        assert_eq!(
+            true,
+            State::load(&path)
+                .unwrap()
+                .manifests
+                .contains_key("dotfiles")
+        );
+    }
+}
@@ -6,6 +6,8 @@ pub use output_contains::OutputContains;
 #[allow(dead_code)]
 pub enum FlowControl {
     StopIf(Box<dyn Finalizer>),
+    /// Runs after the step's atom, even when it failed, to clean up after it
+    Always(Box<dyn Atom>),
 }
 
 /// Finalizers allow us to store data within the manifests KV store,
@@ -0,0 +1,57 @@
+use super::Initializer;
+use std::process::{Command, Stdio};
+
+/// Runs a command through the system shell, true when it exits successfully
+#[derive(Clone, Debug)]
+pub struct CommandSucceeds {
+    pub command: String,
+    pub dir: Option<String>,
+}
+
+impl Initializer for CommandSucceeds {
+    fn initialize(&self) -> anyhow::Result<bool> {
+        let mut command = if cfg!(target_family = "windows") {
+            let mut command = Command::new("cmd");
+            command.arg("/C");
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            command
+        };
+
+        command
+            .arg(&self.command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+
+        if let Some(dir) = &self.dir {
+            command.current_dir(dir);
+        }
+
+        Ok(command.status()?.success())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn it_reports_the_exit_status() {
+        let initializer = CommandSucceeds {
+            command: String::from("true"),
+            dir: None,
+        };
+        assert_eq!(true, initializer.initialize().unwrap());
+
+        let initializer = CommandSucceeds {
+            command: String::from("exit 3"),
+            dir: None,
+        };
+        assert_eq!(false, initializer.initialize().unwrap());
+    }
+}
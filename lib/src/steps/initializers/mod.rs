@@ -1,6 +1,9 @@
 mod command_found;
 pub use command_found::CommandFound;
 
+mod command_succeeds;
+pub use command_succeeds::CommandSucceeds;
+
 mod file_exists;
 pub use file_exists::FileExists;
 
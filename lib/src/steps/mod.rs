@@ -14,7 +14,7 @@ impl Step {
     pub fn do_initializers_allow_us_to_run(&self) -> bool {
         self.initializers
             .iter()
-            .fold(true, |_, flow_control| match flow_control {
+            .all(|flow_control| match flow_control {
                 initializers::FlowControl::Ensure(i) => {
                     match i.initialize() {
                         Ok(should) => should,
@@ -51,7 +51,10 @@ impl Step {
     pub fn do_finalizers_allow_us_to_continue(&self) -> bool {
         self.finalizers
             .iter()
-            .fold(true, |_, flow_control| match flow_control {
+            .all(|flow_control| match flow_control {
+                // Cleanups don't decide whether the action continues
+                finalizers::FlowControl::Always(_) => true,
+
                 finalizers::FlowControl::StopIf(i) => {
                     match i.finalize(self.atom.as_ref()) {
                         Ok(true) => {
@@ -71,6 +74,31 @@ impl Step {
                 }
             })
     }
+
+    /// Runs the `Always` finalizers, whether the atom succeeded or not. Every one
+    /// of them runs, and the first error is returned.
+    pub fn run_always_finalizers(&mut self) -> anyhow::Result<()> {
+        let mut result = Ok(());
+
+        for flow_control in self.finalizers.iter_mut() {
+            let finalizers::FlowControl::Always(atom) = flow_control else {
+                continue;
+            };
+
+            let outcome = match atom.plan() {
+                Ok(outcome) if outcome.should_run => atom.execute(),
+                Ok(_) => Ok(()),
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = outcome {
+                error!("Failed to clean up with {}: {}", atom, err);
+                result = result.and(Err(err));
+            }
+        }
+
+        result
+    }
 }
 
 #[cfg(test)]
@@ -138,6 +166,17 @@ mod tests {
         };
 
         assert_eq!(false, step.do_initializers_allow_us_to_run());
+
+        let step = Step {
+            atom: Box::new(EchoAtom("hello-world")),
+            initializers: vec![
+                InitializerFlowControl::SkipIf(Box::new(EchoInitializer(true))),
+                InitializerFlowControl::SkipIf(Box::new(EchoInitializer(false))),
+            ],
+            finalizers: vec![],
+        };
+
+        assert_eq!(false, step.do_initializers_allow_us_to_run());
     }
 
     #[test]
@@ -192,6 +231,32 @@ mod tests {
         assert_eq!(false, step.do_finalizers_allow_us_to_continue());
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn always_finalizers_run_after_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let leftover = dir.path().join("leftover");
+        std::fs::write(&leftover, "").unwrap();
+
+        let mut step = Step {
+            atom: Box::new(crate::atoms::command::Exec {
+                command: String::from("false"),
+                ..Default::default()
+            }),
+            initializers: vec![],
+            finalizers: vec![FinalizerFlowControl::Always(Box::new(
+                crate::atoms::file::Remove {
+                    target: leftover.clone(),
+                },
+            ))],
+        };
+
+        assert_eq!(true, step.atom.execute().is_err());
+        assert_eq!(true, step.do_finalizers_allow_us_to_continue());
+        assert_eq!(true, step.run_always_finalizers().is_ok());
+        assert_eq!(false, leftover.exists());
+    }
+
     #[test]
     fn finalizers_that_error_block_execution() {
         let step = Step {
@@ -0,0 +1,53 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// Values assigned to keys that look like credentials are masked in diffs
+const SECRET_PATTERN: &str =
+    r"(?i)(password|passwd|passphrase|secret|token|api[_-]?key|private[_-]?key)(\W*\s*[:=]\s*)\S.*";
+
+pub fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    let old_lines: Vec<String> = old.split_inclusive('\n').map(redact).collect();
+    let new_lines: Vec<String> = new.split_inclusive('\n').map(redact).collect();
+
+    difflib::unified_diff(&old_lines, &new_lines, path, path, "", "", 3)
+        .into_iter()
+        .map(|line| {
+            let line = line.trim_end_matches(['\t', '\n']);
+            format!("{}\n", line)
+        })
+        .collect()
+}
+
+pub fn redact(line: &str) -> String {
+    static SECRETS: OnceLock<Regex> = OnceLock::new();
+
+    SECRETS
+        .get_or_init(|| Regex::new(SECRET_PATTERN).expect("secret pattern is a valid regex"))
+        .replace_all(line, "${1}${2}<redacted>")
+        .trim_end_matches('\n')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_can_diff() {
+        let diff = unified_diff("a.txt", "one\ntwo\n", "one\nthree\n");
+
+        assert_eq!(
+            "--- a.txt\n+++ a.txt\n@@ -1,2 +1,2 @@\n one\n-two\n+three\n",
+            diff
+        );
+        assert_eq!("", unified_diff("a.txt", "same\n", "same\n"));
+    }
+
+    #[test]
+    fn it_redacts_secrets() {
+        assert_eq!("password: <redacted>", redact("password: hunter2\n"));
+        assert_eq!("export API_KEY=<redacted>", redact("export API_KEY=abc123"));
+        assert_eq!("name = \"comtrya\"", redact("name = \"comtrya\""));
+    }
+}
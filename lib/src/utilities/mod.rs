@@ -1,4 +1,4 @@
-use which;
+pub mod diff;
 
 pub fn get_binary_path(binary: &str) -> Result<String, anyhow::Error> {
     let binary = which::which(String::from(binary))?
@@ -7,3 +7,15 @@ pub fn get_binary_path(binary: &str) -> Result<String, anyhow::Error> {
 
     Ok(binary)
 }
+
+/// Stdout of a command that lists something, empty when it fails, like asdf
+/// does for tools without a plugin
+pub fn list_output(command: &str, args: &[&str]) -> String {
+    std::process::Command::new(command)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+        .unwrap_or_default()
+}
@@ -281,6 +281,14 @@ impl PartialOrd for NumberVariant {
     }
 }
 
+impl From<i64> for Value {
+    fn from(from: i64) -> Self {
+        Value::Number(Number {
+            inner: NumberVariant::Signed(from),
+        })
+    }
+}
+
 impl From<String> for Value {
     fn from(from: String) -> Self {
         Value::String(from)
@@ -317,17 +325,20 @@ impl<T: Into<Value>> From<Vec<T>> for Value {
     }
 }
 
-impl ToString for Value {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Value::Null => "null".to_string(),
-            Value::String(string) => string.to_owned(),
-            Value::Number(number) => number.to_string(),
-            Value::List(list) => list
-                .iter()
-                .map(|value| value.to_string())
-                .collect::<Vec<String>>()
-                .join(","),
+            Value::Null => write!(f, "null"),
+            Value::String(string) => write!(f, "{}", string),
+            Value::Number(number) => write!(f, "{}", number),
+            Value::List(list) => write!(
+                f,
+                "{}",
+                list.iter()
+                    .map(|value| value.to_string())
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
         }
     }
 }